@@ -0,0 +1,330 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::history::{Event, Operation, Outcome};
+
+/// A way in which a history contradicts a consistent, durable log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Anomaly {
+    /// A read that started after a write was acknowledged covered its
+    /// offset without returning it
+    LostWrite {
+        offset: u64,
+        produce: usize,
+        consume: usize,
+    },
+
+    /// Two different records were seen at the same offset
+    ConflictingRecords {
+        offset: u64,
+        first: usize,
+        second: usize,
+    },
+
+    /// The same record was seen at two offsets
+    DuplicateRecord {
+        offsets: (u64, u64),
+        events: (usize, usize),
+    },
+
+    /// A read saw a lower high watermark than a request that finished
+    /// before it started: an earlier read's watermark, or one past an
+    /// acknowledged write
+    HighWatermarkRegressed {
+        consume: usize,
+        high_watermark: u64,
+        floor: u64,
+    },
+
+    /// A write was acknowledged at a lower offset than a write that
+    /// finished before it started
+    WritesReordered { earlier: usize, later: usize },
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Anomaly::LostWrite { offset, produce, consume } => write!(
+                f,
+                "write {} acknowledged at offset {} was not there for read {}",
+                produce, offset, consume
+            ),
+            Anomaly::ConflictingRecords { offset, first, second } => write!(
+                f,
+                "events {} and {} saw different records at offset {}",
+                first, second, offset
+            ),
+            Anomaly::DuplicateRecord { offsets, events } => write!(
+                f,
+                "events {} and {} saw the same record at offsets {} and {}",
+                events.0, events.1, offsets.0, offsets.1
+            ),
+            Anomaly::HighWatermarkRegressed { consume, high_watermark, floor } => write!(
+                f,
+                "read {} saw high watermark {} after {} was established",
+                consume, high_watermark, floor
+            ),
+            Anomaly::WritesReordered { earlier, later } => write!(
+                f,
+                "write {} finished before write {} started but got a higher offset",
+                earlier, later
+            ),
+        }
+    }
+}
+
+/// Check a history against the guarantees of a replicated log
+///
+/// Acknowledged writes must survive, every offset holds one record, and
+/// high watermarks and write offsets respect the real-time order of
+/// requests. Writes that timed out or failed may or may not have happened,
+/// so they only have to be consistent with whatever reads saw.
+pub fn check(events: &[Event]) -> Vec<Anomaly> {
+    let mut anomalies = Vec::new();
+    check_records(events, &mut anomalies);
+    check_acknowledged(events, &mut anomalies);
+    check_real_time_order(events, &mut anomalies);
+    anomalies
+}
+
+/// Records seen by an event, with their offsets
+fn records(event: &Event) -> Vec<(u64, &[u8])> {
+    match (&event.operation, event.outcome()) {
+        (Operation::Produce { value }, Some(Outcome::Produced { offset })) => {
+            vec![(*offset, value.as_slice())]
+        }
+        (_, Some(Outcome::Consumed { records, .. })) => records
+            .iter()
+            .map(|(offset, value)| (*offset, value.as_slice()))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn check_records(events: &[Event], anomalies: &mut Vec<Anomaly>) {
+    let mut by_offset: BTreeMap<u64, (&[u8], usize)> = BTreeMap::new();
+    let mut by_value: BTreeMap<&[u8], (u64, usize)> = BTreeMap::new();
+
+    for event in events {
+        for (offset, value) in records(event) {
+            let (seen, first) = *by_offset.entry(offset).or_insert((value, event.id));
+            if seen != value {
+                anomalies.push(Anomaly::ConflictingRecords {
+                    offset,
+                    first,
+                    second: event.id,
+                });
+            }
+
+            let (seen_at, first) = *by_value.entry(value).or_insert((offset, event.id));
+            if seen_at != offset {
+                anomalies.push(Anomaly::DuplicateRecord {
+                    offsets: (seen_at, offset),
+                    events: (first, event.id),
+                });
+            }
+        }
+    }
+}
+
+fn check_acknowledged(events: &[Event], anomalies: &mut Vec<Anomaly>) {
+    for produce in events {
+        let (Operation::Produce { value }, Some(Outcome::Produced { offset })) =
+            (&produce.operation, produce.outcome())
+        else {
+            continue;
+        };
+
+        for consume in events.iter().filter(|consume| produce.precedes(consume)) {
+            let (Operation::Consume { from }, Some(Outcome::Consumed { records, end, .. })) =
+                (&consume.operation, consume.outcome())
+            else {
+                continue;
+            };
+            if !(*from..*end).contains(offset) {
+                continue;
+            }
+            if !records.iter().any(|(at, found)| at == offset && found == value) {
+                anomalies.push(Anomaly::LostWrite {
+                    offset: *offset,
+                    produce: produce.id,
+                    consume: consume.id,
+                });
+            }
+        }
+    }
+}
+
+fn check_real_time_order(events: &[Event], anomalies: &mut Vec<Anomaly>) {
+    // Walk invocations and completions in the order they happened,
+    // tracking what every request finished so far established
+    let mut timeline: Vec<(u64, bool, &Event)> = Vec::new();
+    for event in events {
+        timeline.push((event.invoked, false, event));
+        if let Some((completed, _)) = &event.completed {
+            timeline.push((*completed, true, event));
+        }
+    }
+    timeline.sort_by_key(|(at, _, _)| *at);
+
+    let mut high_watermark = 0;
+    let mut last_write: Option<(u64, usize)> = None;
+    let mut floors = BTreeMap::new();
+
+    for (_, completed, event) in timeline {
+        if !completed {
+            floors.insert(event.id, (high_watermark, last_write));
+            continue;
+        }
+
+        let (floor, earlier_write) = floors[&event.id];
+        match event.outcome() {
+            Some(Outcome::Produced { offset }) => {
+                if let Some((earlier_offset, earlier)) = earlier_write {
+                    if *offset <= earlier_offset {
+                        anomalies.push(Anomaly::WritesReordered {
+                            earlier,
+                            later: event.id,
+                        });
+                    }
+                }
+                high_watermark = high_watermark.max(offset + 1);
+                if last_write.is_none_or(|(last, _)| *offset > last) {
+                    last_write = Some((*offset, event.id));
+                }
+            }
+            Some(Outcome::Consumed { high_watermark: seen, .. }) => {
+                if *seen < floor {
+                    anomalies.push(Anomaly::HighWatermarkRegressed {
+                        consume: event.id,
+                        high_watermark: *seen,
+                        floor,
+                    });
+                }
+                high_watermark = high_watermark.max(*seen);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::History;
+
+    fn produce(history: &History, value: &str, offset: u64) -> usize {
+        let id = history.invoke(1, Operation::Produce { value: value.into() });
+        history.complete(id, Outcome::Produced { offset });
+        id
+    }
+
+    fn consume(history: &History, from: u64, records: &[&str]) -> usize {
+        let id = history.invoke(2, Operation::Consume { from });
+        let records: Vec<(u64, Vec<u8>)> = records
+            .iter()
+            .enumerate()
+            .map(|(i, value)| (from + i as u64, value.as_bytes().to_vec()))
+            .collect();
+        let high_watermark = from + records.len() as u64;
+        history.complete(id, Outcome::Consumed {
+            records,
+            end: high_watermark,
+            high_watermark,
+        });
+        id
+    }
+
+    #[test]
+    fn test_consistent_history() {
+        let history = History::new();
+        produce(&history, "a", 0);
+        let pending = history.invoke(3, Operation::Produce { value: b"c".to_vec() });
+        produce(&history, "b", 1);
+        consume(&history, 0, &["a", "b"]);
+        history.complete(pending, Outcome::Timeout);
+        consume(&history, 1, &["b", "c"]);
+
+        assert_eq!(check(&history.events()), Vec::new());
+    }
+
+    #[test]
+    fn test_detects_lost_write() {
+        let history = History::new();
+        let write = produce(&history, "a", 0);
+        let read = consume(&history, 0, &["x"]);
+
+        let anomalies = check(&history.events());
+        assert!(anomalies.contains(&Anomaly::LostWrite {
+            offset: 0,
+            produce: write,
+            consume: read,
+        }));
+    }
+
+    #[test]
+    fn test_detects_missing_write() {
+        let history = History::new();
+        produce(&history, "a", 0);
+        let write = produce(&history, "b", 1);
+        produce(&history, "c", 2);
+        let id = history.invoke(2, Operation::Consume { from: 0 });
+        history.complete(id, Outcome::Consumed {
+            records: vec![(0, b"a".to_vec()), (2, b"c".to_vec())],
+            end: 3,
+            high_watermark: 3,
+        });
+
+        let anomalies = check(&history.events());
+        assert_eq!(
+            anomalies,
+            vec![Anomaly::LostWrite {
+                offset: 1,
+                produce: write,
+                consume: id,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_detects_conflicting_and_duplicate_records() {
+        let history = History::new();
+        let first = consume(&history, 0, &["a", "b"]);
+        let second = consume(&history, 0, &["a", "c", "b"]);
+
+        let anomalies = check(&history.events());
+        assert!(anomalies.contains(&Anomaly::ConflictingRecords { offset: 1, first, second }));
+        assert!(anomalies.contains(&Anomaly::DuplicateRecord {
+            offsets: (1, 2),
+            events: (first, second),
+        }));
+    }
+
+    #[test]
+    fn test_detects_regressed_high_watermark() {
+        let history = History::new();
+        produce(&history, "a", 0);
+        produce(&history, "b", 1);
+        let read = consume(&history, 0, &["a"]);
+
+        let anomalies = check(&history.events());
+        assert_eq!(
+            anomalies,
+            vec![Anomaly::HighWatermarkRegressed {
+                consume: read,
+                high_watermark: 1,
+                floor: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_detects_reordered_writes() {
+        let history = History::new();
+        let earlier = produce(&history, "a", 1);
+        let later = produce(&history, "b", 0);
+
+        let anomalies = check(&history.events());
+        assert_eq!(anomalies, vec![Anomaly::WritesReordered { earlier, later }]);
+    }
+}
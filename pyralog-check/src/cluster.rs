@@ -0,0 +1,295 @@
+use bytes::Bytes;
+use parking_lot::Mutex;
+use pyralog::replication::{ReplicaHandler, ReplicaRequest, ReplicaResponse};
+use pyralog::PyralogConfig;
+use pyralog::PyralogServer;
+use pyralog_core::{LogId, LogOffset, PartitionId, Result, PyralogError};
+use pyralog_protocol::api::{
+    AckMode, ConsumeRequest, CreateLogRequest, ProduceRecord, ProduceRequest, ProtocolHandler,
+};
+use pyralog_sim::SimNetwork;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use tempfile::TempDir;
+use tokio::task::AbortHandle;
+
+type Nodes = Arc<Mutex<BTreeMap<u64, LogNode>>>;
+
+/// The one partition the clients write to
+const PARTITION: PartitionId = PartitionId(0);
+
+/// Copies of the partition; at least three, so a write quorum survives a
+/// replica that is down
+const REPLICATION_FACTOR: u32 = 3;
+
+struct LogNode {
+    server: Arc<PyralogServer>,
+
+    /// Requests the node is serving, aborted if it crashes
+    requests: Vec<AbortHandle>,
+    up: bool,
+
+    /// Times the node was restarted, which varies its Raft seed
+    restarts: u64,
+}
+
+/// Pyralog servers on a `SimNetwork`, serving one single-partition log
+///
+/// Requests go through each server's `ProtocolHandler`, so records are
+/// sequenced in epochs and written to `LogStorage` as in production, while
+/// the servers' Raft and replica traffic crosses the simulated network. The
+/// partition has `REPLICATION_FACTOR` replicas and a write is acknowledged
+/// once a write quorum of them holds it. Nodes can be crashed, losing
+/// whatever they had not flushed, and restarted from their data
+/// directories.
+pub struct LogCluster {
+    seed: u64,
+    node_ids: Vec<u64>,
+    log_id: LogId,
+    dir: TempDir,
+    network: Arc<SimNetwork>,
+    nodes: Nodes,
+}
+
+impl LogCluster {
+    /// Start the servers, create the log once they have elected a metadata
+    /// leader and wait for its partition to have a leader
+    ///
+    /// Each server's Raft is seeded from `seed`, its node ID and its
+    /// restarts, so a run on a seeded network can be replayed.
+    pub async fn start(nodes: u64, network: Arc<SimNetwork>, seed: u64) -> Result<Arc<Self>> {
+        let cluster = Arc::new(Self {
+            seed,
+            node_ids: (1..=nodes).collect(),
+            log_id: LogId::new("check", "events"),
+            dir: TempDir::new().map_err(|e| PyralogError::IoError(e.to_string()))?,
+            network,
+            nodes: Nodes::default(),
+        });
+        for &node_id in &cluster.node_ids {
+            cluster.start_node(node_id).await?;
+        }
+
+        let request = CreateLogRequest {
+            log_id: cluster.log_id.clone(),
+            partition_count: 1,
+            replication_factor: REPLICATION_FACTOR,
+        };
+        let mut created = false;
+        for _ in 0..100 {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            if created && !cluster.leaders().is_empty() {
+                return Ok(cluster);
+            }
+            for &node_id in &cluster.node_ids {
+                if created {
+                    break;
+                }
+                let request = request.clone();
+                created = cluster.call(node_id, |server| async move { server.create_log(request).await }).await.is_ok();
+            }
+        }
+        Err(PyralogError::LeaderNotAvailable)
+    }
+
+    pub fn node_ids(&self) -> &[u64] {
+        &self.node_ids
+    }
+
+    pub fn network(&self) -> &Arc<SimNetwork> {
+        &self.network
+    }
+
+    async fn start_node(&self, node_id: u64) -> Result<()> {
+        let mut config = PyralogConfig::default();
+        config.node.node_id = node_id;
+        config.node.cluster_nodes = self.node_ids.clone();
+        config.node.data_dir = self.dir.path().join(format!("node-{}", node_id));
+        let restarts = self.nodes.lock().get(&node_id).map_or(0, |node| node.restarts + 1);
+        config.node.seed = Some(self.seed ^ (node_id << 32) ^ restarts);
+        // Every node places partitions itself; a shared seed must make
+        // them agree
+        config.replication.seed = Some(self.seed);
+
+        let transport = Arc::new(self.network.transport(node_id));
+        let server = Arc::new(PyralogServer::with_transport(config, transport.clone(), transport).await?);
+        self.network.register(node_id, server.cluster().raft_handler());
+        self.network.register_replica(
+            node_id,
+            Arc::new(NodeReplica {
+                node_id,
+                nodes: Arc::clone(&self.nodes),
+            }),
+        );
+        server.run();
+
+        self.nodes.lock().insert(
+            node_id,
+            LogNode {
+                server,
+                requests: Vec::new(),
+                up: true,
+                restarts,
+            },
+        );
+        Ok(())
+    }
+
+    /// Serve a request on `node_id` as a task that a crash aborts
+    async fn call<T, F, Fut>(&self, node_id: u64, request: F) -> Result<T>
+    where
+        F: FnOnce(Arc<PyralogServer>) -> Fut,
+        Fut: Future<Output = Result<T>> + Send + 'static,
+        T: Send + 'static,
+    {
+        call(&self.nodes, node_id, request).await
+    }
+
+    /// Running nodes that believe they lead the partition
+    pub fn leaders(&self) -> Vec<u64> {
+        self.nodes
+            .lock()
+            .iter()
+            .filter(|(_, node)| node.up && node.server.cluster().is_partition_leader(&self.log_id, PARTITION))
+            .map(|(&node_id, _)| node_id)
+            .collect()
+    }
+
+    /// Nodes that are running
+    pub fn up_nodes(&self) -> Vec<u64> {
+        self.nodes
+            .lock()
+            .iter()
+            .filter(|(_, node)| node.up)
+            .map(|(&node_id, _)| node_id)
+            .collect()
+    }
+
+    /// Nodes that have crashed and not been restarted
+    pub fn down_nodes(&self) -> Vec<u64> {
+        self.nodes
+            .lock()
+            .iter()
+            .filter(|(_, node)| !node.up)
+            .map(|(&node_id, _)| node_id)
+            .collect()
+    }
+
+    /// Produce a record through `node_id` and return its offset
+    ///
+    /// Resolves once a write quorum of the partition's replicas holds the
+    /// record.
+    pub async fn produce(&self, node_id: u64, value: Vec<u8>) -> Result<u64> {
+        let request = ProduceRequest {
+            log_id: self.log_id.clone(),
+            partition: Some(PARTITION),
+            records: vec![ProduceRecord {
+                key: None,
+                value: Bytes::from(value),
+                headers: Vec::new(),
+            }],
+            acks: AckMode::Leader,
+        };
+        let response = self.call(node_id, |server| async move { server.produce(request).await }).await?;
+        Ok(response.base_offset.as_u64())
+    }
+
+    /// Consume up to `max` records from `from` on `node_id`
+    ///
+    /// Returns the records, the offset just past the range that was read
+    /// and the high watermark it was read at. Gap markers are left out of
+    /// the records but count towards the range.
+    pub async fn consume(&self, node_id: u64, from: u64, max: usize) -> Result<(Vec<(u64, Vec<u8>)>, u64, u64)> {
+        let request = ConsumeRequest {
+            log_id: self.log_id.clone(),
+            partition: PARTITION,
+            offset: LogOffset::new(from),
+            max_records: max,
+            max_bytes: usize::MAX,
+        };
+        let response = self.call(node_id, |server| async move { server.consume(request).await }).await?;
+
+        let end = response
+            .records
+            .last()
+            .map_or(from, |record| record.offset.as_u64() + 1);
+        let records = response
+            .records
+            .into_iter()
+            .filter(|record| record.gap_kind().is_none())
+            .map(|record| (record.offset.as_u64(), record.value.to_vec()))
+            .collect();
+        Ok((records, end, response.high_watermark.as_u64()))
+    }
+
+    /// Stop a node as if it lost power
+    ///
+    /// Requests it was serving are abandoned and records it had not
+    /// flushed are lost.
+    pub fn crash(&self, node_id: u64) {
+        if let Some(node) = self.nodes.lock().get_mut(&node_id).filter(|node| node.up) {
+            for request in node.requests.drain(..) {
+                request.abort();
+            }
+            node.server.cluster().shutdown();
+            node.up = false;
+        }
+        self.network.crash(node_id);
+    }
+
+    /// Start a crashed node again from its data directory
+    pub async fn restart(&self, node_id: u64) -> Result<()> {
+        if self.nodes.lock().get(&node_id).is_some_and(|node| node.up) {
+            return Ok(());
+        }
+        self.start_node(node_id).await
+    }
+
+    pub async fn shutdown(&self) -> Result<()> {
+        let servers: Vec<Arc<PyralogServer>> = self
+            .nodes
+            .lock()
+            .values()
+            .filter(|node| node.up)
+            .map(|node| Arc::clone(&node.server))
+            .collect();
+        for server in servers {
+            server.shutdown().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Serve a request on `node_id` as a task that a crash aborts
+async fn call<T, F, Fut>(nodes: &Nodes, node_id: u64, request: F) -> Result<T>
+where
+    F: FnOnce(Arc<PyralogServer>) -> Fut,
+    Fut: Future<Output = Result<T>> + Send + 'static,
+    T: Send + 'static,
+{
+    let down = || PyralogError::NetworkError(format!("Node {} is down", node_id));
+
+    let task = {
+        let mut nodes = nodes.lock();
+        let node = nodes.get_mut(&node_id).filter(|node| node.up).ok_or_else(down)?;
+        let task = tokio::spawn(request(Arc::clone(&node.server)));
+        node.requests.retain(|request| !request.is_finished());
+        node.requests.push(task.abort_handle());
+        task
+    };
+    task.await.map_err(|_| down())?
+}
+
+/// Replica traffic for a node, served by whichever of its servers is up
+struct NodeReplica {
+    node_id: u64,
+    nodes: Nodes,
+}
+
+#[async_trait::async_trait]
+impl ReplicaHandler for NodeReplica {
+    async fn handle(&self, request: ReplicaRequest) -> Result<ReplicaResponse> {
+        call(&self.nodes, self.node_id, |server| async move { server.handle(request).await }).await
+    }
+}
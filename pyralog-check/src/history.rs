@@ -0,0 +1,109 @@
+use parking_lot::Mutex;
+use std::sync::Arc;
+
+/// A client request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    /// Append a record with this value
+    Produce { value: Vec<u8> },
+
+    /// Read records starting at an offset
+    Consume { from: u64 },
+}
+
+/// How a request ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The record was acknowledged at this offset
+    Produced { offset: u64 },
+
+    /// Records read with their offsets, the offset just past the range
+    /// that was read, and the high watermark at the time
+    ///
+    /// Offsets in the range without a record held gap markers.
+    Consumed {
+        records: Vec<(u64, Vec<u8>)>,
+        end: u64,
+        high_watermark: u64,
+    },
+
+    /// No answer in time; a write may or may not have taken effect
+    Timeout,
+
+    /// Rejected with an error; a write may still have taken effect, e.g.
+    /// when its leader stepped down after appending it
+    Failed(String),
+}
+
+/// One request and its outcome
+///
+/// `invoked` and `completed` come from a single counter shared by the whole
+/// history, so they order events across clients the way a wall clock
+/// would.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub id: usize,
+    pub client: u64,
+    pub operation: Operation,
+    pub invoked: u64,
+    pub completed: Option<(u64, Outcome)>,
+}
+
+impl Event {
+    /// Whether this request finished before `other` was sent
+    pub fn precedes(&self, other: &Event) -> bool {
+        self.completed
+            .as_ref()
+            .is_some_and(|(completed, _)| *completed < other.invoked)
+    }
+
+    pub fn outcome(&self) -> Option<&Outcome> {
+        self.completed.as_ref().map(|(_, outcome)| outcome)
+    }
+}
+
+/// Records the requests of concurrent clients
+#[derive(Default)]
+pub struct History {
+    inner: Mutex<Recorded>,
+}
+
+#[derive(Default)]
+struct Recorded {
+    clock: u64,
+    events: Vec<Event>,
+}
+
+impl History {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Record that a client sent a request; returns its ID
+    pub fn invoke(&self, client: u64, operation: Operation) -> usize {
+        let mut inner = self.inner.lock();
+        inner.clock += 1;
+        let id = inner.events.len();
+        let invoked = inner.clock;
+        inner.events.push(Event {
+            id,
+            client,
+            operation,
+            invoked,
+            completed: None,
+        });
+        id
+    }
+
+    /// Record the outcome of a request
+    pub fn complete(&self, id: usize, outcome: Outcome) {
+        let mut inner = self.inner.lock();
+        inner.clock += 1;
+        let completed = inner.clock;
+        inner.events[id].completed = Some((completed, outcome));
+    }
+
+    pub fn events(&self) -> Vec<Event> {
+        self.inner.lock().events.clone()
+    }
+}
@@ -0,0 +1,19 @@
+//! Pyralog Check - Consistency checking of client histories
+//!
+//! Records what concurrent clients asked of a log and what they were told,
+//! including timeouts, while in-process Pyralog servers on a simulated
+//! network are partitioned, crashed and restarted. Requests are served by
+//! the servers' protocol handlers, so the history covers the real
+//! sequencing, epoch and storage paths. The history is then checked for lost
+//! acknowledged writes, offsets holding two records, regressing high
+//! watermarks and writes ordered against real time.
+
+pub mod history;
+pub mod checker;
+pub mod cluster;
+pub mod workload;
+
+pub use history::{Event, History, Operation, Outcome};
+pub use checker::{check, Anomaly};
+pub use cluster::LogCluster;
+pub use workload::{run, CheckReport, WorkloadConfig};
@@ -0,0 +1,285 @@
+use pyralog_core::{Result, PyralogError};
+use pyralog_sim::{NetworkFaults, SimNetwork};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{sleep, timeout};
+
+use crate::checker::{check, Anomaly};
+use crate::cluster::LogCluster;
+use crate::history::{Event, History, Operation, Outcome};
+
+/// How long a client waits for an answer before giving up
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Most records returned by one consume
+const MAX_RECORDS: usize = 32;
+
+/// Shape of one checked run
+#[derive(Debug, Clone)]
+pub struct WorkloadConfig {
+    /// Drives every random choice of the run
+    pub seed: u64,
+
+    /// Number of servers in the cluster
+    pub nodes: u64,
+
+    /// Number of concurrent clients
+    pub clients: u64,
+
+    /// Requests sent by each client
+    pub operations: usize,
+
+    /// Probability that a message is lost while faults are injected
+    pub drop_rate: f64,
+}
+
+impl WorkloadConfig {
+    /// A configuration with cluster size and loss drawn from `seed`
+    pub fn random(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        Self {
+            seed,
+            nodes: *[3, 5].choose(&mut rng).unwrap(),
+            clients: rng.gen_range(2..=5),
+            operations: 50,
+            drop_rate: rng.gen_range(0.0..0.1),
+        }
+    }
+}
+
+/// A history and what the checker found in it
+#[derive(Debug, Clone)]
+pub struct CheckReport {
+    pub seed: u64,
+    pub events: Vec<Event>,
+    pub anomalies: Vec<Anomaly>,
+}
+
+impl CheckReport {
+    /// Writes acknowledged with an offset
+    pub fn acknowledged(&self) -> usize {
+        self.events
+            .iter()
+            .filter(|event| matches!(event.outcome(), Some(Outcome::Produced { .. })))
+            .count()
+    }
+
+    /// Fail with every anomaly if there were any
+    pub fn into_result(self) -> Result<Self> {
+        if self.anomalies.is_empty() {
+            return Ok(self);
+        }
+        let anomalies: Vec<String> = self.anomalies.iter().map(ToString::to_string).collect();
+        Err(PyralogError::ConsensusError(format!(
+            "seed {}: {}",
+            self.seed,
+            anomalies.join("; ")
+        )))
+    }
+}
+
+/// Run clients against a log cluster under faults, then check the history
+///
+/// Runs on its own paused-clock runtime, a seeded network and servers
+/// whose Raft is seeded, so a seed always gives the same history. Once the
+/// clients are done every fault is healed and a final read of the whole
+/// log is added, which any lost write shows up in.
+pub fn run(config: WorkloadConfig) -> Result<CheckReport> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .start_paused(true)
+        .build()
+        .map_err(|e| PyralogError::IoError(e.to_string()))?;
+
+    runtime.block_on(async move {
+        let faults = NetworkFaults {
+            drop_rate: config.drop_rate,
+            ..NetworkFaults::default()
+        };
+        let network = SimNetwork::new(config.seed, faults);
+        let cluster = LogCluster::start(config.nodes, network, config.seed).await?;
+        let history = History::new();
+
+        let clients: Vec<_> = (0..config.clients)
+            .map(|client| {
+                let seed = config.seed ^ ((client + 1) << 48);
+                tokio::spawn(run_client(
+                    Arc::clone(&cluster),
+                    Arc::clone(&history),
+                    client,
+                    seed,
+                    config.operations,
+                ))
+            })
+            .collect();
+        let nemesis = tokio::spawn(run_nemesis(Arc::clone(&cluster), config.seed));
+
+        for client in clients {
+            client.await.map_err(|e| PyralogError::IoError(e.to_string()))??;
+        }
+        nemesis.abort();
+
+        heal(&cluster).await?;
+        final_read(&cluster, &history).await?;
+        cluster.shutdown().await?;
+
+        let events = history.events();
+        let anomalies = check(&events);
+        Ok(CheckReport {
+            seed: config.seed,
+            events,
+            anomalies,
+        })
+    })
+}
+
+async fn run_client(
+    cluster: Arc<LogCluster>,
+    history: Arc<History>,
+    client: u64,
+    seed: u64,
+    operations: usize,
+) -> Result<()> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut high_watermark = 0;
+
+    for n in 0..operations {
+        sleep(Duration::from_millis(rng.gen_range(1..=50))).await;
+
+        // Requests go to a node that believes it leads, if any
+        let node_id = match cluster.leaders().choose(&mut rng) {
+            Some(&leader) => leader,
+            None => *cluster.node_ids().choose(&mut rng).unwrap(),
+        };
+
+        if rng.gen_bool(0.6) {
+            let value = format!("{}-{}", client, n).into_bytes();
+            let id = history.invoke(client, Operation::Produce { value: value.clone() });
+            let outcome = match timeout(REQUEST_TIMEOUT, cluster.produce(node_id, value)).await {
+                Ok(Ok(offset)) => Outcome::Produced { offset },
+                Ok(Err(e)) => Outcome::Failed(e.to_string()),
+                Err(_) => Outcome::Timeout,
+            };
+            history.complete(id, outcome);
+        } else {
+            let from = rng.gen_range(0..=high_watermark);
+            let id = history.invoke(client, Operation::Consume { from });
+            let outcome = match timeout(REQUEST_TIMEOUT, cluster.consume(node_id, from, MAX_RECORDS)).await {
+                Ok(Ok((records, end, seen))) => {
+                    high_watermark = high_watermark.max(seen);
+                    Outcome::Consumed {
+                        records,
+                        end,
+                        high_watermark: seen,
+                    }
+                }
+                Ok(Err(e)) => Outcome::Failed(e.to_string()),
+                Err(_) => Outcome::Timeout,
+            };
+            history.complete(id, outcome);
+        }
+    }
+    Ok(())
+}
+
+/// Partition, crash and restart nodes until aborted
+async fn run_nemesis(cluster: Arc<LogCluster>, seed: u64) -> Result<()> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let network = Arc::clone(cluster.network());
+
+    loop {
+        sleep(Duration::from_millis(rng.gen_range(100..=400))).await;
+
+        match rng.gen_range(0..4) {
+            0 => {
+                let mut node_ids = cluster.node_ids().to_vec();
+                node_ids.shuffle(&mut rng);
+                let split = rng.gen_range(1..node_ids.len());
+                let (a, b) = node_ids.split_at(split);
+                network.partition(&[a.to_vec(), b.to_vec()]);
+            }
+            1 => network.heal(),
+            2 => {
+                if let Some(&node_id) = cluster.up_nodes().choose(&mut rng) {
+                    cluster.crash(node_id);
+                }
+            }
+            _ => {
+                if let Some(&node_id) = cluster.down_nodes().choose(&mut rng) {
+                    cluster.restart(node_id).await?;
+                }
+            }
+        }
+    }
+}
+
+async fn heal(cluster: &LogCluster) -> Result<()> {
+    cluster.network().heal();
+    cluster.network().set_drop_rate(0.0);
+    for node_id in cluster.down_nodes() {
+        cluster.restart(node_id).await?;
+    }
+    Ok(())
+}
+
+/// Read the whole log once the cluster has recovered
+async fn final_read(cluster: &LogCluster, history: &History) -> Result<()> {
+    for _ in 0..100 {
+        sleep(Duration::from_millis(100)).await;
+        let Some(&node_id) = cluster.leaders().first() else {
+            continue;
+        };
+
+        let id = history.invoke(0, Operation::Consume { from: 0 });
+        match timeout(REQUEST_TIMEOUT, cluster.consume(node_id, 0, usize::MAX)).await {
+            Ok(Ok((records, end, high_watermark))) => {
+                history.complete(id, Outcome::Consumed {
+                    records,
+                    end,
+                    high_watermark,
+                });
+                return Ok(());
+            }
+            Ok(Err(e)) => history.complete(id, Outcome::Failed(e.to_string())),
+            Err(_) => history.complete(id, Outcome::Timeout),
+        }
+    }
+    Err(PyralogError::Timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(name: &str) -> Option<u64> {
+        std::env::var(name).ok().and_then(|value| value.parse().ok())
+    }
+
+    #[test]
+    fn test_same_seed_same_history() {
+        let first = run(WorkloadConfig::random(42)).unwrap();
+        let second = run(WorkloadConfig::random(42)).unwrap();
+
+        assert_eq!(first.events, second.events);
+        assert!(first.acknowledged() > 0);
+    }
+
+    /// Check `PYRALOG_SIM_SCHEDULES` histories starting at
+    /// `PYRALOG_SIM_SEED`; a failure names the seed that reproduces it
+    #[test]
+    fn test_histories_are_consistent() {
+        let schedules = env("PYRALOG_SIM_SCHEDULES").unwrap_or(4);
+        let first_seed = env("PYRALOG_SIM_SEED").unwrap_or(0);
+
+        for seed in first_seed..first_seed + schedules {
+            let report = run(WorkloadConfig::random(seed)).unwrap();
+            assert!(report.acknowledged() > 0);
+            if let Err(e) = report.into_result() {
+                panic!("{}", e);
+            }
+        }
+    }
+}
@@ -20,12 +20,47 @@ impl Default for ElectionTimeoutConfig {
 impl ElectionTimeoutConfig {
     /// Generate a random election timeout
     pub fn generate_timeout(&self) -> Duration {
-        let mut rng = rand::thread_rng();
+        self.generate_timeout_with(&mut rand::thread_rng())
+    }
+
+    /// Generate a random election timeout from the given source
+    pub fn generate_timeout_with(&self, rng: &mut impl Rng) -> Duration {
         let timeout_ms = rng.gen_range(self.min_ms..=self.max_ms);
         Duration::from_millis(timeout_ms)
     }
 }
 
+/// Safeguards against disruptive elections and stale leaders
+#[derive(Debug, Clone)]
+pub struct LeadershipConfig {
+    /// Ask peers whether they would vote before starting an election, so a
+    /// node that cannot win does not bump the term of the whole cluster
+    pub pre_vote: bool,
+
+    /// Step down as leader after an election timeout without hearing from
+    /// a majority, and ignore vote requests while a leader is known
+    pub check_quorum: bool,
+
+    /// Let the leader serve reads on a lease while a majority has
+    /// acknowledged it within the minimum election timeout
+    pub lease: bool,
+
+    /// Upper bound on the relative clock rate difference between nodes;
+    /// the lease is shortened by this fraction
+    pub max_clock_drift: f64,
+}
+
+impl Default for LeadershipConfig {
+    fn default() -> Self {
+        Self {
+            pre_vote: true,
+            check_quorum: true,
+            lease: false,
+            max_clock_drift: 0.1,
+        }
+    }
+}
+
 /// Heartbeat interval (should be << election timeout)
 pub const HEARTBEAT_INTERVAL_MS: u64 = 50;
 
@@ -8,8 +8,24 @@ pub mod state;
 pub mod log;
 pub mod rpc;
 pub mod election;
+pub mod state_machine;
+pub mod snapshot;
+pub mod transport;
+pub mod membership;
+pub mod multi_raft;
+pub mod pipeline;
 
 pub use raft::{RaftNode, RaftConfig};
 pub use state::{NodeState, NodeRole};
-pub use rpc::{AppendEntriesRequest, AppendEntriesResponse, VoteRequest, VoteResponse};
+pub use membership::Membership;
+pub use multi_raft::{GroupId, MultiRaft};
+pub use state_machine::{AppliedEntry, StateMachine};
+pub use rpc::{
+    AppendEntriesRequest, AppendEntriesResponse, GroupRequest, InstallSnapshotRequest, InstallSnapshotResponse,
+    RaftRequest, RaftResponse, ReadIndexRequest, ReadIndexResponse, TimeoutNowRequest,
+    TimeoutNowResponse, VoteRequest, VoteResponse,
+};
+pub use pipeline::PipelineConfig;
+pub use snapshot::{SnapshotConfig, SnapshotMeta};
+pub use transport::{ChannelNetwork, ChannelTransport, RaftHandler, RaftTransport, TcpTransport};
 
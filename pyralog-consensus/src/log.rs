@@ -1,64 +1,424 @@
 use pyralog_core::{Result, PyralogError};
-use parking_lot::RwLock;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
-use std::fs::{File, OpenOptions};
-use std::io::{Read, Write, Seek, SeekFrom};
 
-use crate::state::{PersistentState, LogEntry};
+use crate::state::LogEntry;
+
+/// Default size at which a new log segment is started
+pub const DEFAULT_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+const HARD_STATE_FILE: &str = "hard_state";
+const SEGMENT_EXTENSION: &str = "raftlog";
+const RECORD_HEADER_SIZE: usize = 8; // length u32 + crc32 u32
+
+/// Term and vote, which must be durable before a node answers RPCs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HardState {
+    pub current_term: u64,
+    pub voted_for: Option<u64>,
+}
+
+struct Segment {
+    first_index: u64,
+    path: PathBuf,
+    /// Byte position of each entry, the entry at `first_index` first
+    positions: Vec<u64>,
+    size: u64,
+}
+
+impl Segment {
+    fn next_index(&self) -> u64 {
+        self.first_index + self.positions.len() as u64
+    }
+}
+
+struct Segments {
+    segments: Vec<Segment>,
+    /// Append handle of the last segment
+    active: Option<File>,
+}
 
 /// Persistent log storage for Raft
+///
+/// The term and vote live in a small file that is replaced atomically.
+/// Entries are appended to segment files named after their first index;
+/// each entry is stored as `[len: u32][crc32: u32][bincode entry]`. A new
+/// segment is started once the active one reaches the segment size, so
+/// compaction deletes whole files and truncation only touches the tail.
 pub struct RaftLog {
-    path: PathBuf,
-    file: RwLock<File>,
+    dir: PathBuf,
+    segment_size: u64,
+    inner: Mutex<Segments>,
 }
 
 impl RaftLog {
-    pub fn open(path: PathBuf) -> Result<Self> {
-        let file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(&path)
-            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+    /// Open the log in `dir`; `load` must be called before appending
+    pub fn open(dir: PathBuf, segment_size: u64) -> Result<Self> {
+        fs::create_dir_all(&dir).map_err(|e| PyralogError::StorageError(e.to_string()))?;
 
         Ok(Self {
-            path,
-            file: RwLock::new(file),
+            dir,
+            segment_size,
+            inner: Mutex::new(Segments {
+                segments: Vec::new(),
+                active: None,
+            }),
         })
     }
 
-    /// Save persistent state to disk
-    pub fn save_state(&self, state: &PersistentState) -> Result<()> {
-        let data = bincode::serialize(state)
+    /// Load the hard state and all entries from disk
+    ///
+    /// A record torn by a crash at the end of the last segment is cut off;
+    /// corruption anywhere else is an error.
+    pub fn load(&self) -> Result<(HardState, Vec<LogEntry>)> {
+        let hard_state = self.load_hard_state()?;
+
+        let mut paths: Vec<(u64, PathBuf)> = fs::read_dir(&self.dir)
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == SEGMENT_EXTENSION))
+            .filter_map(|path| {
+                let first_index = path.file_stem()?.to_str()?.parse().ok()?;
+                Some((first_index, path))
+            })
+            .collect();
+        paths.sort();
+
+        let mut inner = self.inner.lock();
+        inner.segments.clear();
+        inner.active = None;
+
+        let mut entries = Vec::new();
+        let count = paths.len();
+
+        for (i, (first_index, path)) in paths.into_iter().enumerate() {
+            let is_last = i + 1 == count;
+            let (segment_entries, positions, valid_len) = read_segment(&path)?;
+
+            let size = fs::metadata(&path)
+                .map_err(|e| PyralogError::StorageError(e.to_string()))?
+                .len();
+            if valid_len < size {
+                if !is_last {
+                    return Err(PyralogError::StorageError(format!(
+                        "Corrupt Raft log segment {}",
+                        path.display()
+                    )));
+                }
+                truncate_file(&path, valid_len)?;
+            }
+
+            for (offset, entry) in segment_entries.iter().enumerate() {
+                if entry.index != first_index + offset as u64 {
+                    return Err(PyralogError::StorageError(format!(
+                        "Raft log segment {} is out of sequence",
+                        path.display()
+                    )));
+                }
+            }
+            if entries
+                .last()
+                .is_some_and(|last: &LogEntry| last.index + 1 != first_index)
+            {
+                return Err(PyralogError::StorageError(format!(
+                    "Gap in Raft log before segment {}",
+                    path.display()
+                )));
+            }
+
+            entries.extend(segment_entries);
+            inner.segments.push(Segment {
+                first_index,
+                path,
+                positions,
+                size: valid_len,
+            });
+        }
+
+        if let Some(segment) = inner.segments.last() {
+            inner.active = Some(open_append(&segment.path)?);
+        }
+
+        Ok((hard_state, entries))
+    }
+
+    /// Durably replace the term and vote
+    pub fn save_hard_state(&self, hard_state: &HardState) -> Result<()> {
+        let data = bincode::serialize(hard_state)
             .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
 
-        let mut file = self.file.write();
-        file.seek(SeekFrom::Start(0))
+        let path = self.dir.join(HARD_STATE_FILE);
+        let temp_path = path.with_extension("tmp");
+
+        let mut file = File::create(&temp_path)
             .map_err(|e| PyralogError::StorageError(e.to_string()))?;
         file.write_all(&data)
+            .and_then(|_| file.sync_all())
+            .and_then(|_| fs::rename(&temp_path, &path))
             .map_err(|e| PyralogError::StorageError(e.to_string()))?;
-        file.sync_all()
-            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+
+        sync_dir(&self.dir)
+    }
+
+    /// Durably append entries following the last stored entry
+    pub fn append(&self, entries: &[LogEntry]) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let mut inner = self.inner.lock();
+
+        for entry in entries {
+            let roll = inner
+                .segments
+                .last()
+                .is_none_or(|segment| segment.size >= self.segment_size);
+            if roll {
+                self.roll_segment(&mut inner, entry.index)?;
+            }
+
+            let record = encode_entry(entry)?;
+            inner
+                .active
+                .as_mut()
+                .expect("active segment")
+                .write_all(&record)
+                .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+
+            let segment = inner.segments.last_mut().expect("active segment");
+            segment.positions.push(segment.size);
+            segment.size += record.len() as u64;
+        }
+
+        inner
+            .active
+            .as_ref()
+            .expect("active segment")
+            .sync_data()
+            .map_err(|e| PyralogError::StorageError(e.to_string()))
+    }
+
+    /// Index of the last stored entry; 0 if there is none
+    pub fn last_index(&self) -> u64 {
+        self.inner
+            .lock()
+            .segments
+            .last()
+            .map_or(0, |segment| segment.next_index() - 1)
+    }
+
+    /// Remove the entry at `index` and all that follow it
+    pub fn truncate_suffix(&self, index: u64) -> Result<()> {
+        let mut inner = self.inner.lock();
+        inner.active = None;
+
+        while let Some(segment) = inner.segments.last() {
+            if segment.first_index < index {
+                break;
+            }
+            fs::remove_file(&segment.path)
+                .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+            inner.segments.pop();
+        }
+
+        if let Some(segment) = inner.segments.last_mut() {
+            if index < segment.next_index() {
+                let keep = (index - segment.first_index) as usize;
+                segment.size = segment.positions[keep];
+                segment.positions.truncate(keep);
+                truncate_file(&segment.path, segment.size)?;
+            }
+            let active = open_append(&segment.path)?;
+            inner.active = Some(active);
+        }
+
+        sync_dir(&self.dir)
+    }
+
+    /// Delete segments holding only entries up to and including `index`
+    ///
+    /// Segments are removed whole, so some compacted entries may remain
+    /// on disk; they are skipped when the log is loaded after a snapshot.
+    pub fn compact_prefix(&self, index: u64) -> Result<()> {
+        let mut inner = self.inner.lock();
+
+        // The last segment is kept so appends continue in sequence
+        while inner.segments.len() > 1 && inner.segments[1].first_index <= index + 1 {
+            let segment = inner.segments.remove(0);
+            fs::remove_file(&segment.path)
+                .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+        }
 
         Ok(())
     }
 
-    /// Load persistent state from disk
-    pub fn load_state(&self) -> Result<PersistentState> {
-        let mut file = self.file.write();
-        file.seek(SeekFrom::Start(0))
-            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+    fn roll_segment(&self, inner: &mut Segments, first_index: u64) -> Result<()> {
+        if let Some(active) = inner.active.take() {
+            active
+                .sync_data()
+                .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+        }
 
-        let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer)
+        let path = self
+            .dir
+            .join(format!("{:020}.{}", first_index, SEGMENT_EXTENSION));
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)
             .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+        sync_dir(&self.dir)?;
 
-        if buffer.is_empty() {
-            return Ok(PersistentState::default());
+        inner.segments.push(Segment {
+            first_index,
+            path,
+            positions: Vec::new(),
+            size: 0,
+        });
+        inner.active = Some(file);
+
+        Ok(())
+    }
+
+    fn load_hard_state(&self) -> Result<HardState> {
+        match fs::read(self.dir.join(HARD_STATE_FILE)) {
+            Ok(data) => bincode::deserialize(&data)
+                .map_err(|e| PyralogError::SerializationError(e.to_string())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HardState::default()),
+            Err(e) => Err(PyralogError::StorageError(e.to_string())),
         }
+    }
+}
+
+fn encode_entry(entry: &LogEntry) -> Result<Vec<u8>> {
+    let data = bincode::serialize(entry)
+        .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+
+    let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + data.len());
+    record.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    record.extend_from_slice(&crc32fast::hash(&data).to_le_bytes());
+    record.extend_from_slice(&data);
+    Ok(record)
+}
+
+/// Read the valid records of a segment
+///
+/// Returns the entries, their positions, and the length of the valid prefix.
+fn read_segment(path: &Path) -> Result<(Vec<LogEntry>, Vec<u64>, u64)> {
+    let mut contents = Vec::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut contents))
+        .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+
+    let mut entries = Vec::new();
+    let mut positions = Vec::new();
+    let mut position = 0;
 
-        bincode::deserialize(&buffer)
-            .map_err(|e| PyralogError::SerializationError(e.to_string()))
+    while position + RECORD_HEADER_SIZE <= contents.len() {
+        let len = u32::from_le_bytes(contents[position..position + 4].try_into().unwrap()) as usize;
+        let crc = u32::from_le_bytes(contents[position + 4..position + 8].try_into().unwrap());
+
+        let start = position + RECORD_HEADER_SIZE;
+        let data = match contents.get(start..start + len) {
+            Some(data) if crc32fast::hash(data) == crc => data,
+            _ => break,
+        };
+        let entry: LogEntry = match bincode::deserialize(data) {
+            Ok(entry) => entry,
+            Err(_) => break,
+        };
+
+        positions.push(position as u64);
+        entries.push(entry);
+        position = start + len;
     }
+
+    Ok((entries, positions, position as u64))
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .append(true)
+        .open(path)
+        .map_err(|e| PyralogError::StorageError(e.to_string()))
+}
+
+fn truncate_file(path: &Path, len: u64) -> Result<()> {
+    let file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+    file.set_len(len)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| PyralogError::StorageError(e.to_string()))
+}
+
+fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|e| PyralogError::StorageError(e.to_string()))
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn entries(term: u64, range: std::ops::Range<u64>) -> Vec<LogEntry> {
+        range
+            .map(|index| LogEntry::new(term, index, format!("entry-{}", index).into_bytes()))
+            .collect()
+    }
+
+    fn indexes(entries: &[LogEntry]) -> Vec<(u64, u64)> {
+        entries.iter().map(|entry| (entry.index, entry.term)).collect()
+    }
+
+    #[test]
+    fn test_segments_truncate_and_compact() {
+        let dir = TempDir::new().unwrap();
+        let log = RaftLog::open(dir.path().to_path_buf(), 128).unwrap();
+        log.load().unwrap();
+
+        log.save_hard_state(&HardState { current_term: 2, voted_for: Some(3) }).unwrap();
+        log.append(&entries(1, 1..21)).unwrap();
+        assert!(log.inner.lock().segments.len() > 2);
+
+        // Replace a conflicting suffix
+        log.truncate_suffix(15).unwrap();
+        log.append(&entries(2, 15..18)).unwrap();
+        log.compact_prefix(10).unwrap();
+
+        let (hard_state, loaded) = log.load().unwrap();
+        assert_eq!(hard_state, HardState { current_term: 2, voted_for: Some(3) });
+        assert!(loaded.first().unwrap().index <= 11);
+        let tail: Vec<_> = loaded.into_iter().filter(|entry| entry.index > 10).collect();
+        let mut expected = entries(1, 11..15);
+        expected.extend(entries(2, 15..18));
+        assert_eq!(indexes(&tail), indexes(&expected));
+    }
+
+    #[test]
+    fn test_torn_tail_is_discarded() {
+        let dir = TempDir::new().unwrap();
+        let log = RaftLog::open(dir.path().to_path_buf(), DEFAULT_SEGMENT_SIZE).unwrap();
+        log.load().unwrap();
+        log.append(&entries(1, 1..4)).unwrap();
+
+        // Simulate a crash in the middle of writing a record
+        let path = dir.path().join(format!("{:020}.{}", 1, SEGMENT_EXTENSION));
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[42, 0, 0, 0, 1, 2]).unwrap();
+
+        let (_, loaded) = log.load().unwrap();
+        assert_eq!(indexes(&loaded), indexes(&entries(1, 1..4)));
+
+        log.append(&entries(1, 4..5)).unwrap();
+        let (_, loaded) = log.load().unwrap();
+        assert_eq!(loaded.len(), 4);
+    }
+}
@@ -0,0 +1,76 @@
+use pyralog_core::{Result, PyralogError};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The nodes that make up a Raft group
+///
+/// Membership changes one node at a time: every change is a configuration
+/// entry in the log, and a node uses the latest configuration in its log as
+/// soon as the entry is appended, whether or not it has committed. Any two
+/// consecutive configurations then share a majority, so the old and new
+/// configurations can never elect separate leaders.
+///
+/// Learners receive the log like voters but take no part in elections or
+/// commit decisions, so they can catch up or sit behind a slow link without
+/// holding back commits.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Membership {
+    /// Nodes that vote in elections and count towards commit quorums
+    pub voters: BTreeSet<u64>,
+
+    /// Nodes that only replicate the log
+    pub learners: BTreeSet<u64>,
+
+    /// Internal cluster addresses of members, where known
+    pub addresses: BTreeMap<u64, String>,
+}
+
+impl Membership {
+    pub fn new(voters: impl IntoIterator<Item = u64>) -> Self {
+        Self {
+            voters: voters.into_iter().collect(),
+            learners: BTreeSet::new(),
+            addresses: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_voter(&self, node_id: u64) -> bool {
+        self.voters.contains(&node_id)
+    }
+
+    pub fn is_learner(&self, node_id: u64) -> bool {
+        self.learners.contains(&node_id)
+    }
+
+    /// Number of voters that form a majority
+    pub fn quorum(&self) -> usize {
+        self.voters.len() / 2 + 1
+    }
+
+    /// Members other than `node_id` that the leader replicates to, in
+    /// ascending order
+    pub fn peers(&self, node_id: u64) -> Vec<u64> {
+        self.voters
+            .union(&self.learners)
+            .copied()
+            .filter(|&peer| peer != node_id)
+            .collect()
+    }
+
+    /// Voters other than `node_id`, in ascending order
+    pub fn voting_peers(&self, node_id: u64) -> Vec<u64> {
+        self.voters
+            .iter()
+            .copied()
+            .filter(|&peer| peer != node_id)
+            .collect()
+    }
+
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        bincode::serialize(self).map_err(|e| PyralogError::SerializationError(e.to_string()))
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        bincode::deserialize(data).map_err(|e| PyralogError::SerializationError(e.to_string()))
+    }
+}
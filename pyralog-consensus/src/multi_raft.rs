@@ -0,0 +1,517 @@
+use async_trait::async_trait;
+use parking_lot::{Mutex, RwLock};
+use pyralog_core::{Result, PyralogError};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::oneshot;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::sleep;
+
+use crate::election::heartbeat_interval;
+use crate::raft::{RaftConfig, RaftNode};
+use crate::rpc::{GroupRequest, RaftRequest, RaftResponse};
+use crate::state_machine::StateMachine;
+use crate::transport::{RaftHandler, RaftTransport};
+
+/// Identifies a Raft group within a multi-Raft node
+pub type GroupId = u64;
+
+/// Idle leaders are ticked once every this many heartbeat intervals, which
+/// still keeps followers well inside the minimum election timeout
+const IDLE_TICKS: u64 = 2;
+
+/// Hosts many Raft groups on one node
+///
+/// Groups share the node's transport. Requests that groups send to the
+/// same peer at the same time travel in one `Batch`, so a node leading a
+/// thousand partitions sends each peer one heartbeat message per interval
+/// rather than a thousand. A batch is answered once all of its requests
+/// are, so requests that can wait on other nodes or the disk (reads
+/// confirming leadership, snapshot chunks) are sent on their own instead.
+/// A single ticker drives the timers of every group instead of each group
+/// running its own, and leaders with nothing left to replicate are ticked
+/// less often.
+pub struct MultiRaft {
+    node_id: u64,
+    batcher: Arc<Batcher>,
+    groups: RwLock<BTreeMap<GroupId, Arc<RaftNode>>>,
+    ticker: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl MultiRaft {
+    pub fn new(node_id: u64, transport: Arc<dyn RaftTransport>) -> Arc<Self> {
+        Arc::new(Self {
+            node_id,
+            batcher: Arc::new(Batcher {
+                transport,
+                outboxes: Mutex::new(BTreeMap::new()),
+            }),
+            groups: RwLock::new(BTreeMap::new()),
+            ticker: Mutex::new(None),
+        })
+    }
+
+    pub fn node_id(&self) -> u64 {
+        self.node_id
+    }
+
+    /// Start ticking the hosted groups
+    pub fn start(self: &Arc<Self>) {
+        let runtime = Arc::clone(self);
+        let ticker = tokio::spawn(async move {
+            runtime.run_ticker().await;
+        });
+        if let Some(previous) = self.ticker.lock().replace(ticker) {
+            previous.abort();
+        }
+    }
+
+    /// Stop ticking and shut down every group
+    pub fn shutdown(&self) {
+        if let Some(ticker) = self.ticker.lock().take() {
+            ticker.abort();
+        }
+        for group in self.groups.read().values() {
+            group.shutdown();
+        }
+    }
+
+    /// Open a group and start it on this node
+    ///
+    /// `config.data_dir` must be distinct for every group.
+    pub async fn create_group(
+        &self,
+        group: GroupId,
+        config: RaftConfig,
+        state_machine: Box<dyn StateMachine>,
+    ) -> Result<Arc<RaftNode>> {
+        if self.groups.read().contains_key(&group) {
+            return Err(PyralogError::ConsensusError(format!("Raft group {} already exists", group)));
+        }
+
+        let transport = Arc::new(GroupTransport {
+            group,
+            batcher: Arc::clone(&self.batcher),
+        });
+        let node = Arc::new(RaftNode::new(config, transport, state_machine).await?);
+        Arc::clone(&node).start_ticked().await?;
+
+        self.groups.write().insert(group, Arc::clone(&node));
+        Ok(node)
+    }
+
+    /// Shut down a group and stop routing its requests
+    pub fn remove_group(&self, group: GroupId) {
+        if let Some(node) = self.groups.write().remove(&group) {
+            node.shutdown();
+        }
+    }
+
+    pub fn group(&self, group: GroupId) -> Option<Arc<RaftNode>> {
+        self.groups.read().get(&group).cloned()
+    }
+
+    pub fn groups(&self) -> Vec<GroupId> {
+        self.groups.read().keys().copied().collect()
+    }
+
+    async fn run_ticker(self: Arc<Self>) {
+        let mut ticks: u64 = 0;
+        loop {
+            sleep(heartbeat_interval()).await;
+            ticks += 1;
+
+            let groups: Vec<Arc<RaftNode>> = self.groups.read().values().cloned().collect();
+            for group in groups {
+                if group.is_quiescent() && !ticks.is_multiple_of(IDLE_TICKS) {
+                    continue;
+                }
+                group.tick();
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl RaftHandler for MultiRaft {
+    async fn handle(&self, request: RaftRequest) -> Result<RaftResponse> {
+        let RaftRequest::Batch(requests) = request else {
+            return Err(PyralogError::ConsensusError(
+                "Requests to a multi-Raft node must name their group".to_string(),
+            ));
+        };
+
+        // Groups handle their requests concurrently, so the batch takes as
+        // long as its slowest request
+        let mut handlers = JoinSet::new();
+        let count = requests.len();
+        for (position, GroupRequest { group, request }) in requests.into_iter().enumerate() {
+            let node = self.group(group);
+            handlers.spawn(async move {
+                let response = match node {
+                    Some(node) => node.handle(request).await.map_err(|e| e.to_string()),
+                    None => Err(format!("Unknown Raft group {}", group)),
+                };
+                (position, response)
+            });
+        }
+
+        let mut responses: Vec<std::result::Result<RaftResponse, String>> =
+            vec![Err("Request was not handled".to_string()); count];
+        while let Some(handled) = handlers.join_next().await {
+            let (position, response) =
+                handled.map_err(|e| PyralogError::ConsensusError(e.to_string()))?;
+            responses[position] = response;
+        }
+
+        Ok(RaftResponse::Batch(responses))
+    }
+}
+
+/// A request waiting to be sent in the next batch to a peer
+struct Queued {
+    request: GroupRequest,
+    reply: oneshot::Sender<Result<RaftResponse>>,
+}
+
+#[derive(Default)]
+struct Outbox {
+    queued: Vec<Queued>,
+
+    /// Whether a flush has been scheduled for the queued requests
+    flushing: bool,
+}
+
+/// Coalesces the requests of all groups into one batch per peer
+struct Batcher {
+    transport: Arc<dyn RaftTransport>,
+    outboxes: Mutex<BTreeMap<u64, Outbox>>,
+}
+
+impl Batcher {
+    async fn send(self: &Arc<Self>, target: u64, request: GroupRequest) -> Result<RaftResponse> {
+        let (reply, response) = oneshot::channel();
+        let schedule = {
+            let mut outboxes = self.outboxes.lock();
+            let outbox = outboxes.entry(target).or_default();
+            outbox.queued.push(Queued { request, reply });
+            !std::mem::replace(&mut outbox.flushing, true)
+        };
+
+        if schedule {
+            let batcher = Arc::clone(self);
+            tokio::spawn(async move {
+                batcher.flush(target).await;
+            });
+        }
+
+        response
+            .await
+            .map_err(|_| PyralogError::NetworkError(format!("Request to node {} was dropped", target)))?
+    }
+
+    /// Send a request in a batch of its own, without waiting for others
+    async fn send_alone(&self, target: u64, request: GroupRequest) -> Result<RaftResponse> {
+        match self.transport.send(target, RaftRequest::Batch(vec![request])).await? {
+            RaftResponse::Batch(mut responses) if responses.len() == 1 => responses
+                .pop()
+                .expect("one response")
+                .map_err(PyralogError::ConsensusError),
+            _ => Err(PyralogError::ConsensusError(format!(
+                "Node {} answered a batch with the wrong response",
+                target
+            ))),
+        }
+    }
+
+    async fn flush(&self, target: u64) {
+        // Let the other groups woken by the same tick queue their requests
+        tokio::task::yield_now().await;
+
+        let queued = {
+            let mut outboxes = self.outboxes.lock();
+            let outbox = outboxes.entry(target).or_default();
+            outbox.flushing = false;
+            std::mem::take(&mut outbox.queued)
+        };
+        let (requests, replies): (Vec<_>, Vec<_>) = queued
+            .into_iter()
+            .map(|queued| (queued.request, queued.reply))
+            .unzip();
+
+        let count = requests.len();
+        match self.transport.send(target, RaftRequest::Batch(requests)).await {
+            Ok(RaftResponse::Batch(responses)) if responses.len() == count => {
+                for (reply, response) in replies.into_iter().zip(responses) {
+                    reply.send(response.map_err(PyralogError::ConsensusError)).ok();
+                }
+            }
+            Ok(_) => {
+                for reply in replies {
+                    reply
+                        .send(Err(PyralogError::ConsensusError(format!(
+                            "Node {} answered a batch with the wrong response",
+                            target
+                        ))))
+                        .ok();
+                }
+            }
+            Err(e) => {
+                for reply in replies {
+                    reply.send(Err(e.clone())).ok();
+                }
+            }
+        }
+    }
+}
+
+/// Transport of one group, sending through its node's batcher
+struct GroupTransport {
+    group: GroupId,
+    batcher: Arc<Batcher>,
+}
+
+#[async_trait]
+impl RaftTransport for GroupTransport {
+    async fn send(&self, target: u64, request: RaftRequest) -> Result<RaftResponse> {
+        let slow = matches!(request, RaftRequest::ReadIndex(_) | RaftRequest::InstallSnapshot(_));
+        let request = GroupRequest {
+            group: self.group,
+            request,
+        };
+        if slow {
+            return self.batcher.send_alone(target, request).await;
+        }
+        self.batcher.send(target, request).await
+    }
+
+    fn update_peers(&self, addresses: &BTreeMap<u64, String>) {
+        self.batcher.transport.update_peers(addresses);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::election::{ElectionTimeoutConfig, LeadershipConfig};
+    use crate::pipeline::PipelineConfig;
+    use crate::rpc::{ReadIndexRequest, ReadIndexResponse, TimeoutNowRequest, TimeoutNowResponse};
+    use crate::snapshot::SnapshotConfig;
+    use crate::transport::{ChannelNetwork, ChannelTransport};
+    use bytes::Bytes;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    const GROUPS: u64 = 8;
+
+    struct Discard;
+
+    impl StateMachine for Discard {
+        fn apply(&mut self, _index: u64, _data: &[u8]) {}
+
+        fn snapshot(&self) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+
+        fn restore(&mut self, _snapshot: &[u8]) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Counts node-level messages and the group requests they carry
+    struct Counting {
+        inner: ChannelTransport,
+        messages: Arc<AtomicUsize>,
+        requests: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl RaftTransport for Counting {
+        async fn send(&self, target: u64, request: RaftRequest) -> Result<RaftResponse> {
+            if let RaftRequest::Batch(requests) = &request {
+                self.messages.fetch_add(1, Ordering::Relaxed);
+                self.requests.fetch_add(requests.len(), Ordering::Relaxed);
+            }
+            self.inner.send(target, request).await
+        }
+    }
+
+    struct Cluster {
+        network: Arc<ChannelNetwork>,
+        nodes: BTreeMap<u64, Arc<MultiRaft>>,
+        messages: Arc<AtomicUsize>,
+        requests: Arc<AtomicUsize>,
+        _dir: TempDir,
+    }
+
+    async fn start_cluster() -> Cluster {
+        let network = ChannelNetwork::new();
+        let dir = TempDir::new().unwrap();
+        let messages = Arc::new(AtomicUsize::new(0));
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let mut nodes = BTreeMap::new();
+        for node_id in 1..=3 {
+            let transport = Arc::new(Counting {
+                inner: network.transport(node_id),
+                messages: Arc::clone(&messages),
+                requests: Arc::clone(&requests),
+            });
+            let node = MultiRaft::new(node_id, transport);
+            network.register(node_id, node.clone());
+
+            for group in 0..GROUPS {
+                let config = RaftConfig {
+                    node_id,
+                    cluster_nodes: vec![1, 2, 3],
+                    data_dir: dir.path().join(format!("group-{}", group)),
+                    election_timeout: ElectionTimeoutConfig::default(),
+                    leadership: LeadershipConfig::default(),
+                    snapshot: SnapshotConfig::default(),
+                    pipeline: PipelineConfig::default(),
+                    seed: None,
+                };
+                node.create_group(group, config, Box::new(Discard)).await.unwrap();
+            }
+            node.start();
+            nodes.insert(node_id, node);
+        }
+
+        Cluster {
+            network,
+            nodes,
+            messages,
+            requests,
+            _dir: dir,
+        }
+    }
+
+    /// Leader of every group, among the nodes still connected
+    async fn wait_for_leaders(cluster: &Cluster, excluded: Option<u64>) -> BTreeMap<GroupId, (u64, u64)> {
+        for _ in 0..100 {
+            let mut leaders = BTreeMap::new();
+            for (&node_id, node) in &cluster.nodes {
+                if Some(node_id) == excluded {
+                    continue;
+                }
+                for group in node.groups() {
+                    if let Some(term) = node.group(group).unwrap().leader_term() {
+                        leaders.insert(group, (node_id, term));
+                    }
+                }
+            }
+            if leaders.len() == GROUPS as usize {
+                return leaders;
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+        panic!("not every group elected a leader");
+    }
+
+    #[tokio::test]
+    async fn test_groups_elect_leaders_and_batch_heartbeats() {
+        let cluster = start_cluster().await;
+        let leaders = wait_for_leaders(&cluster, None).await;
+
+        for (&group, &(node_id, _)) in &leaders {
+            let leader = cluster.nodes[&node_id].group(group).unwrap();
+            leader.propose(Bytes::from(format!("group {}", group))).await.unwrap();
+        }
+
+        // Let the groups go idle, then count a second of heartbeats
+        sleep(Duration::from_millis(500)).await;
+        let messages = cluster.messages.load(Ordering::Relaxed);
+        let requests = cluster.requests.load(Ordering::Relaxed);
+        sleep(Duration::from_secs(1)).await;
+        let messages = cluster.messages.load(Ordering::Relaxed) - messages;
+        let requests = cluster.requests.load(Ordering::Relaxed) - requests;
+
+        // Each leader node sends every peer one batch per tick, however many
+        // groups it leads
+        assert!(requests >= messages * 2, "{} requests in {} messages", requests, messages);
+        for (&group, &(node_id, _)) in &leaders {
+            assert!(cluster.nodes[&node_id].group(group).unwrap().is_quiescent(), "group {}", group);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failover_elects_new_partition_leaders() {
+        let cluster = start_cluster().await;
+        let leaders = wait_for_leaders(&cluster, None).await;
+
+        // Take down the node leading the most groups
+        let mut led: BTreeMap<u64, usize> = BTreeMap::new();
+        for &(node_id, _) in leaders.values() {
+            *led.entry(node_id).or_default() += 1;
+        }
+        let failed = led.iter().max_by_key(|(_, &count)| count).map(|(&node_id, _)| node_id).unwrap();
+        cluster.nodes[&failed].shutdown();
+        cluster.network.disconnect(failed);
+
+        let new_leaders = wait_for_leaders(&cluster, Some(failed)).await;
+        for (group, &(node_id, term)) in &new_leaders {
+            let (old_leader, old_term) = leaders[group];
+            assert_ne!(node_id, failed);
+            if old_leader == failed {
+                assert!(term > old_term, "group {} kept term {}", group, term);
+            }
+        }
+    }
+
+    /// Answers every request at once, recording the batches it was sent
+    #[derive(Default)]
+    struct Recording {
+        batches: Mutex<Vec<Vec<GroupId>>>,
+    }
+
+    #[async_trait]
+    impl RaftTransport for Recording {
+        async fn send(&self, _target: u64, request: RaftRequest) -> Result<RaftResponse> {
+            let RaftRequest::Batch(requests) = request else {
+                panic!("unbatched request");
+            };
+            self.batches.lock().push(requests.iter().map(|request| request.group).collect());
+
+            let responses = requests
+                .into_iter()
+                .map(|request| match request.request {
+                    RaftRequest::ReadIndex(_) => Ok(RaftResponse::ReadIndex(ReadIndexResponse {
+                        term: 1,
+                        read_index: Some(0),
+                    })),
+                    _ => Ok(RaftResponse::TimeoutNow(TimeoutNowResponse { term: 1 })),
+                })
+                .collect();
+            Ok(RaftResponse::Batch(responses))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reads_are_not_batched_with_heartbeats() {
+        let recording = Arc::new(Recording::default());
+        let batcher = Arc::new(Batcher {
+            transport: recording.clone(),
+            outboxes: Mutex::new(BTreeMap::new()),
+        });
+        let transport = |group| GroupTransport {
+            group,
+            batcher: Arc::clone(&batcher),
+        };
+
+        let timeout_now = RaftRequest::TimeoutNow(TimeoutNowRequest { term: 1, leader_id: 1 });
+        let read_index = RaftRequest::ReadIndex(ReadIndexRequest { term: 1 });
+        let (heartbeats, reads) = (transport(0), transport(1));
+        let (first, second, read) = tokio::join!(
+            heartbeats.send(2, timeout_now.clone()),
+            heartbeats.send(2, timeout_now),
+            reads.send(2, read_index),
+        );
+        assert!(first.is_ok() && second.is_ok());
+        assert!(matches!(read.unwrap(), RaftResponse::ReadIndex(_)));
+
+        let mut batches = recording.batches.lock().clone();
+        batches.sort();
+        assert_eq!(batches, vec![vec![0, 0], vec![1]]);
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::VecDeque;
+
+/// How much the leader sends to a follower without waiting for answers
+#[derive(Debug, Clone)]
+pub struct PipelineConfig {
+    /// AppendEntries requests a follower may have unanswered at once
+    pub max_in_flight: usize,
+
+    /// Entry payload bytes a follower may have unanswered at once
+    pub max_in_flight_bytes: usize,
+
+    /// Maximum entries carried by one AppendEntries request
+    pub max_entries: usize,
+
+    /// Maximum entry payload bytes carried by one AppendEntries request;
+    /// a single larger entry is still sent on its own
+    pub max_bytes: usize,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            max_in_flight: 8,
+            max_in_flight_bytes: 8 * 1024 * 1024,
+            max_entries: 1024,
+            max_bytes: 1024 * 1024,
+        }
+    }
+}
+
+/// Requests sent to one follower that have not been answered yet
+///
+/// A follower starts out probing: one request at a time until a response
+/// shows where its log matches the leader's. From then on requests are
+/// pipelined up to the window. A rejected or lost request ends the
+/// pipeline; the leader rewinds and probes again.
+#[derive(Debug, Clone, Default)]
+pub struct Inflight {
+    /// Last index and payload bytes of each unanswered request, oldest first
+    requests: VecDeque<(u64, usize)>,
+
+    /// Payload bytes of the unanswered requests
+    bytes: usize,
+
+    /// Whether the follower's log is known to match the entries sent so
+    /// far, so requests may go out before earlier ones are answered
+    replicating: bool,
+
+    /// Whether a snapshot is being sent in place of entries
+    snapshot: bool,
+}
+
+impl Inflight {
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty() && !self.snapshot
+    }
+
+    /// Whether another request may be sent now
+    pub fn can_send(&self, config: &PipelineConfig) -> bool {
+        if self.snapshot {
+            return false;
+        }
+        if !self.replicating {
+            return self.requests.is_empty();
+        }
+        self.requests.len() < config.max_in_flight.max(1) && self.bytes < config.max_in_flight_bytes
+    }
+
+    /// Record a request ending at `last_index` with `bytes` of entries
+    pub fn sent(&mut self, last_index: u64, bytes: usize) {
+        self.requests.push_back((last_index, bytes));
+        self.bytes += bytes;
+    }
+
+    /// Record the answer to a request ending at `last_index`
+    ///
+    /// Returns false if the request is no longer tracked because the
+    /// window was reset after it was sent.
+    pub fn answered(&mut self, last_index: u64) -> bool {
+        let position = self.requests.iter().position(|&(index, _)| index == last_index);
+        match position.and_then(|position| self.requests.remove(position)) {
+            Some((_, bytes)) => {
+                self.bytes -= bytes;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The follower accepted a request, so later ones can be pipelined
+    pub fn replicate(&mut self) {
+        self.replicating = true;
+    }
+
+    /// Forget every unanswered request and go back to probing
+    pub fn reset(&mut self) {
+        self.requests.clear();
+        self.bytes = 0;
+        self.replicating = false;
+    }
+
+    pub fn start_snapshot(&mut self) {
+        self.reset();
+        self.snapshot = true;
+    }
+
+    pub fn finish_snapshot(&mut self) {
+        self.snapshot = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window() {
+        let config = PipelineConfig {
+            max_in_flight: 2,
+            max_in_flight_bytes: 100,
+            ..PipelineConfig::default()
+        };
+        let mut inflight = Inflight::default();
+
+        // Probing allows a single request
+        inflight.sent(5, 10);
+        assert!(!inflight.can_send(&config));
+        assert!(inflight.answered(5));
+        inflight.replicate();
+
+        inflight.sent(6, 10);
+        assert!(inflight.can_send(&config));
+        inflight.sent(7, 10);
+        assert!(!inflight.can_send(&config));
+
+        // Out of order answers free their own slot
+        assert!(inflight.answered(7));
+        assert!(inflight.can_send(&config));
+
+        // Bytes limit the window as well
+        inflight.sent(8, 200);
+        assert!(!inflight.can_send(&config));
+        assert!(inflight.answered(8));
+
+        // A reset drops unanswered requests and returns to probing
+        inflight.reset();
+        assert!(!inflight.answered(6));
+        assert!(inflight.is_empty());
+        inflight.sent(6, 10);
+        assert!(!inflight.can_send(&config));
+    }
+}
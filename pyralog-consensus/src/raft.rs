@@ -1,99 +1,633 @@
+use async_trait::async_trait;
 use bytes::Bytes;
 use pyralog_core::{Result, PyralogError, LogOffset};
-use parking_lot::RwLock;
-use std::collections::HashMap;
+use parking_lot::{Mutex, RwLock};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Instant;
-use tokio::sync::mpsc;
-use tokio::time::{sleep, Duration};
+use std::time::Duration;
+use tokio::sync::{broadcast, oneshot, watch, Notify};
+use tokio::task::JoinSet;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Instant};
 
-use crate::election::{ElectionTimeoutConfig, heartbeat_interval};
-use crate::log::RaftLog;
-use crate::rpc::{AppendEntriesRequest, AppendEntriesResponse, VoteRequest, VoteResponse};
-use crate::state::{LogEntry, NodeRole, NodeState};
+use crate::election::{ElectionTimeoutConfig, LeadershipConfig, heartbeat_interval};
+use crate::log::{HardState, RaftLog, DEFAULT_SEGMENT_SIZE};
+use crate::membership::Membership;
+use crate::rpc::{
+    AppendEntriesRequest, AppendEntriesResponse, InstallSnapshotRequest, InstallSnapshotResponse,
+    RaftRequest, RaftResponse, ReadIndexRequest, ReadIndexResponse, TimeoutNowRequest,
+    TimeoutNowResponse, VoteRequest, VoteResponse,
+};
+use crate::snapshot::{SnapshotConfig, SnapshotMeta, SnapshotStore};
+use crate::pipeline::{Inflight, PipelineConfig};
+use crate::state::{EntryKind, LogEntry, NodeRole, NodeState};
+use crate::state_machine::{AppliedEntry, StateMachine};
+use crate::transport::{RaftHandler, RaftTransport};
+
+/// Maximum committed entries applied under one state machine lock
+const MAX_ENTRIES_PER_APPLY: usize = 256;
+
+/// Applied entries buffered for slow subscribers before they lag
+const WATCHER_CAPACITY: usize = 1024;
 
 #[derive(Debug, Clone)]
 pub struct RaftConfig {
     pub node_id: u64,
+    /// Voters of a new cluster; a node joining an existing cluster starts
+    /// with none and learns the membership from the leader
     pub cluster_nodes: Vec<u64>,
     pub data_dir: PathBuf,
     pub election_timeout: ElectionTimeoutConfig,
+    pub leadership: LeadershipConfig,
+    pub snapshot: SnapshotConfig,
+    pub pipeline: PipelineConfig,
+    /// Seed for election timeouts; a fixed seed makes a node's timing
+    /// reproducible under a paused tokio clock
+    pub seed: Option<u64>,
 }
 
 pub struct RaftNode {
     config: RaftConfig,
     state: Arc<RwLock<NodeState>>,
     log: Arc<RaftLog>,
+    /// Held while writing to `log`, so writes made after the state lock is
+    /// released still happen in log order
+    log_io: Mutex<()>,
     last_heartbeat: Arc<RwLock<Instant>>,
-    peers: HashMap<u64, PeerConnection>,
+    transport: Arc<dyn RaftTransport>,
+    pending: Mutex<BTreeMap<u64, PendingProposal>>,
+    /// Proposals waiting to be appended together in the next batch
+    proposals: Mutex<Vec<(Bytes, oneshot::Sender<Result<LogOffset>>)>>,
+    replicate: Notify,
+    state_machine: Mutex<Box<dyn StateMachine>>,
+    committed: Notify,
+    applied: watch::Sender<u64>,
+    watchers: broadcast::Sender<AppliedEntry>,
+    snapshots: SnapshotStore,
+    incoming_snapshot: Mutex<Option<IncomingSnapshot>>,
+    campaign: Notify,
+    transfer_progress: Notify,
+    acked: Notify,
+    rng: Mutex<StdRng>,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
+    /// When the election timer of a ticked node next fires, and the
+    /// timeout it was set with
+    election_timer: Mutex<(Instant, Duration)>,
+}
+
+/// A snapshot being received from the leader in chunks
+struct IncomingSnapshot {
+    meta: SnapshotMeta,
+    data: Vec<u8>,
 }
 
-struct PeerConnection {
-    node_id: u64,
-    // In production, this would hold actual network connections
+/// A proposal waiting for its entry to commit
+struct PendingProposal {
+    term: u64,
+    done: oneshot::Sender<Result<LogOffset>>,
 }
 
 impl RaftNode {
-    pub async fn new(config: RaftConfig) -> Result<Self> {
-        let log_path = config.data_dir.join(format!("raft-{}.log", config.node_id));
-        let log = Arc::new(RaftLog::open(log_path)?);
+    pub async fn new(
+        config: RaftConfig,
+        transport: Arc<dyn RaftTransport>,
+        mut state_machine: Box<dyn StateMachine>,
+    ) -> Result<Self> {
+        let log_dir = config.data_dir.join(format!("raft-{}", config.node_id));
+        let log = Arc::new(RaftLog::open(log_dir, DEFAULT_SEGMENT_SIZE)?);
 
-        let persistent_state = log.load_state()?;
+        let (hard_state, entries) = log.load()?;
         let mut state = NodeState::new(config.node_id);
-        state.persistent = persistent_state;
+        state.persistent.current_term = hard_state.current_term;
+        state.persistent.voted_for = hard_state.voted_for;
 
-        let mut peers = HashMap::new();
-        for &peer_id in &config.cluster_nodes {
-            if peer_id != config.node_id {
-                peers.insert(peer_id, PeerConnection { node_id: peer_id });
-            }
+        // Resume from the latest snapshot
+        let snapshots =
+            SnapshotStore::new(config.data_dir.join(format!("raft-{}.snapshot", config.node_id)));
+        if let Some((meta, data)) = snapshots.load()? {
+            state_machine.restore(&data)?;
+            state.persistent.snapshot_index = meta.last_included_index;
+            state.persistent.snapshot_term = meta.last_included_term;
+            state.persistent.snapshot_membership = meta.membership;
+            state.volatile.commit_index = meta.last_included_index;
+            state.volatile.last_applied = meta.last_included_index;
+        } else {
+            state.persistent.snapshot_membership =
+                Membership::new(config.cluster_nodes.iter().copied());
+        }
+        let last_applied = state.volatile.last_applied;
+
+        // Segments are compacted whole, so the log may still hold entries
+        // the snapshot covers
+        let snapshot_index = state.persistent.snapshot_index;
+        state.persistent.log = entries
+            .into_iter()
+            .filter(|entry| entry.index > snapshot_index)
+            .collect();
+        if state
+            .persistent
+            .log
+            .first()
+            .is_some_and(|entry| entry.index != snapshot_index + 1)
+        {
+            return Err(PyralogError::StorageError(
+                "Raft log does not continue from the snapshot".to_string(),
+            ));
         }
+        state.volatile.persisted_index = state.last_log_index();
+
+        state.refresh_membership();
+        transport.update_peers(&state.membership.addresses);
+
+        let rng = match config.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
 
         Ok(Self {
             config,
             state: Arc::new(RwLock::new(state)),
             log,
+            log_io: Mutex::new(()),
             last_heartbeat: Arc::new(RwLock::new(Instant::now())),
-            peers,
+            transport,
+            pending: Mutex::new(BTreeMap::new()),
+            proposals: Mutex::new(Vec::new()),
+            replicate: Notify::new(),
+            state_machine: Mutex::new(state_machine),
+            committed: Notify::new(),
+            applied: watch::channel(last_applied).0,
+            watchers: broadcast::channel(WATCHER_CAPACITY).0,
+            snapshots,
+            incoming_snapshot: Mutex::new(None),
+            campaign: Notify::new(),
+            transfer_progress: Notify::new(),
+            acked: Notify::new(),
+            rng: Mutex::new(rng),
+            tasks: Mutex::new(Vec::new()),
+            election_timer: Mutex::new((Instant::now(), Duration::ZERO)),
         })
     }
 
     /// Start the Raft node
     pub async fn start(self: Arc<Self>) -> Result<()> {
+        let mut tasks = self.tasks.lock();
+
         // Start election timer
         let node_clone = Arc::clone(&self);
-        tokio::spawn(async move {
+        tasks.push(tokio::spawn(async move {
             node_clone.run_election_timer().await;
-        });
+        }));
 
         // Start heartbeat timer (if leader)
         let node_clone = Arc::clone(&self);
-        tokio::spawn(async move {
+        tasks.push(tokio::spawn(async move {
             node_clone.run_heartbeat_timer().await;
-        });
+        }));
+
+        // Apply committed entries to the state machine
+        let node_clone = Arc::clone(&self);
+        tasks.push(tokio::spawn(async move {
+            node_clone.run_apply_loop().await;
+        }));
+
+        Ok(())
+    }
+
+    /// Stop the timers and the apply loop started by `start`
+    ///
+    /// The node stops campaigning, replicating and applying; RPCs already
+    /// in flight may still complete. A new node can then be opened on the
+    /// same data directory, as after a crash.
+    pub fn shutdown(&self) {
+        for task in self.tasks.lock().drain(..) {
+            task.abort();
+        }
+    }
+
+    /// Start the node with its timers driven by `tick`
+    ///
+    /// Used when many groups share a node: instead of two timer tasks per
+    /// group, one runtime calls `tick` every heartbeat interval.
+    pub async fn start_ticked(self: Arc<Self>) -> Result<()> {
+        self.reset_election_timer();
+
+        let mut tasks = self.tasks.lock();
+
+        let node_clone = Arc::clone(&self);
+        tasks.push(tokio::spawn(async move {
+            node_clone.run_events().await;
+        }));
+
+        let node_clone = Arc::clone(&self);
+        tasks.push(tokio::spawn(async move {
+            node_clone.run_apply_loop().await;
+        }));
 
         Ok(())
     }
 
+    /// Advance the timers of a node started with `start_ticked` by one
+    /// heartbeat interval
+    ///
+    /// A leader heartbeats its peers; any other voter campaigns once it has
+    /// not heard from a leader within its election timeout.
+    pub fn tick(self: &Arc<Self>) {
+        if self.is_leader() {
+            self.heartbeat();
+            return;
+        }
+
+        let (deadline, timeout) = *self.election_timer.lock();
+        if Instant::now() < deadline {
+            return;
+        }
+        self.reset_election_timer();
+        if self.election_due(timeout) {
+            self.spawn_election(false);
+        }
+    }
+
+    fn reset_election_timer(&self) {
+        let timeout = self.next_election_timeout();
+        *self.election_timer.lock() = (Instant::now() + timeout, timeout);
+    }
+
+    /// Whether this node leads and every peer has the whole log, so that
+    /// heartbeats only keep the leadership alive
+    pub fn is_quiescent(&self) -> bool {
+        let state = self.state.read();
+        let last_log_index = state.last_log_index();
+        state.volatile.commit_index == last_log_index
+            && state.leader.as_ref().is_some_and(|leader| {
+                leader.in_flight.values().all(Inflight::is_empty)
+                    && leader.match_index.values().all(|&index| index == last_log_index)
+            })
+    }
+
     /// Propose a value to be committed
+    ///
+    /// Resolves once the entry is committed, that is durable on a majority
+    /// of the cluster. Fails with `NotLeader` if this node is not the leader
+    /// or loses leadership before the entry commits.
+    ///
+    /// Proposals made while an earlier batch is being written are appended
+    /// together, with a single write to the log, and replicated in the same
+    /// AppendEntries requests.
     pub async fn propose(&self, value: Bytes) -> Result<LogOffset> {
-        let mut state = self.state.write();
+        let (done, committed) = oneshot::channel();
+        self.proposals.lock().push((value, done));
+
+        // Let proposals made at the same time join the batch; whichever
+        // proposer runs first appends them all, the rest find nothing left
+        tokio::task::yield_now().await;
+        self.append_proposals();
+
+        committed
+            .await
+            .unwrap_or_else(|_| Err(PyralogError::not_leader(self.state.read().leader_hint())))
+    }
+
+    /// Append every queued proposal to the log as one batch
+    fn append_proposals(&self) {
+        {
+            let mut state = self.state.write();
+            let proposals = std::mem::take(&mut *self.proposals.lock());
+            if proposals.is_empty() {
+                return;
+            }
+
+            let (values, senders): (Vec<_>, Vec<_>) = proposals
+                .into_iter()
+                .map(|(value, done)| (value.to_vec(), done))
+                .unzip();
+            let appended = if state.role == NodeRole::Leader {
+                self.append_to_log_as_leader(&mut state, EntryKind::Normal, values)
+            } else {
+                Err(PyralogError::not_leader(state.leader_hint()))
+            };
+
+            match appended {
+                Ok(first_index) => self.await_commit(&mut state, first_index, senders),
+                Err(e) => {
+                    for done in senders {
+                        done.send(Err(e.clone())).ok();
+                    }
+                    return;
+                }
+            }
+        }
+
+        // Followers receive the batch while it is written locally
+        self.replicate.notify_one();
+        self.persist_log();
+    }
+
+    /// Add a voter to the cluster
+    ///
+    /// The new node should be started without `cluster_nodes` so that it
+    /// learns the membership from the leader instead of campaigning on its
+    /// own. Resolves once the configuration entry commits.
+    pub async fn add_voter(&self, node_id: u64, address: String) -> Result<()> {
+        self.change_membership(|membership| {
+            if membership.is_learner(node_id) {
+                return Err(PyralogError::InvalidRequest(format!(
+                    "Node {} is a learner; promote it instead",
+                    node_id
+                )));
+            }
+            if !membership.voters.insert(node_id) {
+                return Err(PyralogError::InvalidRequest(format!(
+                    "Node {} is already a voter",
+                    node_id
+                )));
+            }
+            membership.addresses.insert(node_id, address);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Add a learner to the cluster
+    ///
+    /// Learners receive every entry but neither vote nor count towards
+    /// commits. Like a new voter, the node should be started without
+    /// `cluster_nodes`. Resolves once the configuration entry commits.
+    pub async fn add_learner(&self, node_id: u64, address: String) -> Result<()> {
+        self.change_membership(|membership| {
+            if membership.is_voter(node_id) || !membership.learners.insert(node_id) {
+                return Err(PyralogError::InvalidRequest(format!(
+                    "Node {} is already a member",
+                    node_id
+                )));
+            }
+            membership.addresses.insert(node_id, address);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Turn a learner into a voter
+    ///
+    /// Fails unless the learner has replicated everything committed so far,
+    /// so that promoting it cannot stall commits while it catches up.
+    pub async fn promote_learner(&self, node_id: u64) -> Result<()> {
+        {
+            let state = self.state.read();
+            let matched = state
+                .leader
+                .as_ref()
+                .and_then(|leader| leader.match_index.get(&node_id).copied())
+                .unwrap_or(0);
+            if state.role == NodeRole::Leader
+                && state.membership.is_learner(node_id)
+                && matched < state.volatile.commit_index
+            {
+                return Err(PyralogError::ConsensusError(format!(
+                    "Learner {} has not caught up",
+                    node_id
+                )));
+            }
+        }
+
+        self.change_membership(|membership| {
+            if !membership.learners.remove(&node_id) {
+                return Err(PyralogError::InvalidRequest(format!(
+                    "Node {} is not a learner",
+                    node_id
+                )));
+            }
+            membership.voters.insert(node_id);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Remove a learner from the cluster
+    pub async fn remove_learner(&self, node_id: u64) -> Result<()> {
+        self.change_membership(|membership| {
+            if !membership.learners.remove(&node_id) {
+                return Err(PyralogError::InvalidRequest(format!(
+                    "Node {} is not a learner",
+                    node_id
+                )));
+            }
+            membership.addresses.remove(&node_id);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Remove a voter from the cluster
+    ///
+    /// A leader that removes itself keeps leading until the change commits
+    /// and then steps down. Resolves once the configuration entry commits.
+    pub async fn remove_voter(&self, node_id: u64) -> Result<()> {
+        self.change_membership(|membership| {
+            if !membership.voters.remove(&node_id) {
+                return Err(PyralogError::InvalidRequest(format!(
+                    "Node {} is not a voter",
+                    node_id
+                )));
+            }
+            if membership.voters.is_empty() {
+                return Err(PyralogError::InvalidRequest(
+                    "Cannot remove the last voter".to_string(),
+                ));
+            }
+            membership.addresses.remove(&node_id);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Current cluster membership, including uncommitted changes
+    pub fn membership(&self) -> Membership {
+        self.state.read().membership.clone()
+    }
+
+    /// Hand leadership over to another voter
+    ///
+    /// Proposals are refused while the target is brought up to date; it is
+    /// then told to start an election right away, which it wins because its
+    /// log is as complete as the leader's. Resolves once this node has
+    /// stepped down. If the target has not taken over within an election
+    /// timeout the transfer is abandoned and proposals are accepted again.
+    pub async fn transfer_leadership(&self, target: u64) -> Result<()> {
+        let term = {
+            let mut state = self.state.write();
+
+            if state.role != NodeRole::Leader {
+                return Err(PyralogError::not_leader(state.leader_hint()));
+            }
+            if target == state.node_id || !state.membership.is_voter(target) {
+                return Err(PyralogError::InvalidRequest(format!(
+                    "Node {} is not a voter that can take over",
+                    target
+                )));
+            }
+
+            let leader = state.leader.as_mut().expect("leader state");
+            if leader.transferee.is_some() {
+                return Err(PyralogError::ConsensusError(
+                    "A leadership transfer is already in progress".to_string(),
+                ));
+            }
+            leader.transferee = Some(target);
+            state.persistent.current_term
+        };
+        let _transfer = TransferGuard { node: self, term };
+
+        let deadline = Instant::now()
+            + Duration::from_millis(self.config.election_timeout.max_ms);
+        let mut timeout_sent = false;
 
-        if state.role != NodeRole::Leader {
-            return Err(PyralogError::NotLeader(None));
+        while Instant::now() < deadline {
+            let caught_up = {
+                let state = self.state.read();
+                let leader = match &state.leader {
+                    Some(leader) if state.persistent.current_term == term => leader,
+                    // Stepped down, normally because the target won its election
+                    _ => return Ok(()),
+                };
+                leader.match_index.get(&target).copied() == Some(state.last_log_index())
+            };
+
+            if caught_up && !timeout_sent {
+                let request = TimeoutNowRequest {
+                    term,
+                    leader_id: self.config.node_id,
+                };
+                let sent = tokio::time::timeout_at(
+                    deadline,
+                    self.transport.send(target, RaftRequest::TimeoutNow(request)),
+                )
+                .await;
+                if let Ok(Ok(RaftResponse::TimeoutNow(response))) = sent {
+                    self.observe_term(response.term);
+                    timeout_sent = true;
+                    continue;
+                }
+            } else {
+                self.replicate.notify_one();
+            }
+
+            // Wait for the target to catch up or take over, retrying an
+            // unreachable one after a heartbeat interval
+            let wait = deadline.min(Instant::now() + heartbeat_interval());
+            tokio::time::timeout_at(wait, self.transfer_progress.notified())
+                .await
+                .ok();
+        }
+
+        Err(PyralogError::Timeout)
+    }
+
+    /// Propose a configuration entry with one change to the membership
+    ///
+    /// Only one change may be in progress at a time, and not before the
+    /// leader has committed an entry of its own term; otherwise two
+    /// configurations without a common majority could both be in use.
+    async fn change_membership(
+        &self,
+        change: impl FnOnce(&mut Membership) -> Result<()>,
+    ) -> Result<()> {
+        let committed = {
+            let mut state = self.state.write();
+
+            if state.role != NodeRole::Leader {
+                return Err(PyralogError::not_leader(state.leader_hint()));
+            }
+
+            let commit_index = state.volatile.commit_index;
+            if state.membership_index > commit_index
+                || state.term_at(commit_index) != Some(state.persistent.current_term)
+            {
+                return Err(PyralogError::ConsensusError(
+                    "A membership change is already in progress".to_string(),
+                ));
+            }
+
+            let mut membership = state.membership.clone();
+            change(&mut membership)?;
+            self.append_as_leader(&mut state, EntryKind::Membership, membership.encode()?)?
+        };
+
+        self.replicate.notify_one();
+        self.persist_log();
+
+        committed
+            .await
+            .unwrap_or_else(|_| Err(PyralogError::not_leader(self.state.read().leader_hint())))
+            .map(|_| ())
+    }
+
+    /// Append an entry to the leader's log and wait for it to commit
+    fn append_as_leader(
+        &self,
+        state: &mut NodeState,
+        kind: EntryKind,
+        data: Vec<u8>,
+    ) -> Result<oneshot::Receiver<Result<LogOffset>>> {
+        let index = self.append_to_log_as_leader(state, kind, vec![data])?;
+        let (done, committed) = oneshot::channel();
+        self.await_commit(state, index, vec![done]);
+        Ok(committed)
+    }
+
+    /// Append entries to the leader's in-memory log
+    ///
+    /// `persist_log` writes them to disk once the state lock is released;
+    /// the leader only counts itself towards their commit after that.
+    /// Returns the index of the first entry.
+    fn append_to_log_as_leader(
+        &self,
+        state: &mut NodeState,
+        kind: EntryKind,
+        batch: Vec<Vec<u8>>,
+    ) -> Result<u64> {
+        // Clients retry against the node taking over
+        if let Some(target) = state.leader.as_ref().and_then(|leader| leader.transferee) {
+            return Err(PyralogError::NotLeader(Some(target)));
         }
 
         let term = state.persistent.current_term;
-        let index = state.last_log_index() + 1;
-        let entry = LogEntry::new(term, index, value.to_vec());
+        let first_index = state.last_log_index() + 1;
+        let entries: Vec<LogEntry> = batch
+            .into_iter()
+            .zip(first_index..)
+            .map(|(data, index)| LogEntry { term, index, kind, data })
+            .collect();
+        state.persistent.log.extend(entries);
+
+        // The new configuration also decides when this entry commits
+        if kind == EntryKind::Membership {
+            self.refresh_membership(state);
+        }
+
+        Ok(first_index)
+    }
 
-        state.persistent.log.push(entry);
-        self.log.save_state(&state.persistent)?;
+    /// Resolve `done` once the entries from `first_index` on commit, one
+    /// sender per entry
+    fn await_commit(
+        &self,
+        state: &mut NodeState,
+        first_index: u64,
+        done: Vec<oneshot::Sender<Result<LogOffset>>>,
+    ) {
+        let term = state.persistent.current_term;
+        self.pending.lock().extend(
+            (first_index..)
+                .zip(done)
+                .map(|(index, done)| (index, PendingProposal { term, done })),
+        );
 
-        // In production, replicate to followers here
-        
-        Ok(LogOffset::new(index))
+        // A single-node cluster commits as soon as the entries are durable
+        self.advance_commit(state);
     }
 
     /// Handle AppendEntries RPC
@@ -101,11 +635,11 @@ impl RaftNode {
         &self,
         request: AppendEntriesRequest,
     ) -> Result<AppendEntriesResponse> {
+        // Held until the entries are durable, so requests are acknowledged
+        // in order and never ahead of the disk
+        let _io = self.log_io.lock();
         let mut state = self.state.write();
 
-        // Update last heartbeat
-        *self.last_heartbeat.write() = Instant::now();
-
         // Reply false if term < currentTerm
         if request.term < state.persistent.current_term {
             return Ok(AppendEntriesResponse {
@@ -115,63 +649,96 @@ impl RaftNode {
             });
         }
 
+        // Update last heartbeat
+        *self.last_heartbeat.write() = Instant::now();
+
         // If RPC request or response contains term T > currentTerm:
         // set currentTerm = T, convert to follower
-        if request.term > state.persistent.current_term {
-            state.become_follower(request.term);
+        // A candidate that learns of a leader for its term steps down as well
+        if request.term > state.persistent.current_term || state.role != NodeRole::Follower {
+            self.step_down(&mut state, request.term, Some(request.leader_id))?;
         }
+        state.leader_id = Some(request.leader_id);
 
-        // Reply false if log doesn't contain an entry at prevLogIndex
-        // whose term matches prevLogTerm
-        if request.prev_log_index > 0 {
-            if request.prev_log_index as usize > state.persistent.log.len() {
-                return Ok(AppendEntriesResponse {
-                    term: state.persistent.current_term,
-                    success: false,
-                    match_index: None,
-                });
-            }
+        // Entries up to the snapshot are committed and known to match
+        let mut request = request;
+        let snapshot_index = state.persistent.snapshot_index;
+        if request.prev_log_index < snapshot_index {
+            request.entries.retain(|entry| entry.index > snapshot_index);
+            request.prev_log_index = snapshot_index;
+            request.prev_log_term = state.persistent.snapshot_term;
+        }
 
-            let prev_entry = &state.persistent.log[request.prev_log_index as usize - 1];
-            if prev_entry.term != request.prev_log_term {
-                // Delete conflicting entry and all that follow it
-                state.persistent.log.truncate(request.prev_log_index as usize);
-                self.log.save_state(&state.persistent)?;
-                
-                return Ok(AppendEntriesResponse {
-                    term: state.persistent.current_term,
-                    success: false,
-                    match_index: None,
-                });
-            }
+        // Reply false if log doesn't contain an entry at prevLogIndex
+        // whose term matches prevLogTerm. The hint lets the leader skip
+        // past the end of a shorter log in one step.
+        if state.term_at(request.prev_log_index) != Some(request.prev_log_term) {
+            return Ok(AppendEntriesResponse {
+                term: state.persistent.current_term,
+                success: false,
+                match_index: Some(
+                    state
+                        .last_log_index()
+                        .min(request.prev_log_index.saturating_sub(1)),
+                ),
+            });
         }
 
+        let last_new_index = request.prev_log_index + request.entries.len() as u64;
+
         // Append any new entries not already in the log
+        let mut conflict = None;
+        let mut new_entries = Vec::new();
         for entry in request.entries {
-            if entry.index as usize > state.persistent.log.len() {
-                state.persistent.log.push(entry);
-            } else {
-                // If an existing entry conflicts with a new one, delete it and all that follow
-                if state.persistent.log[entry.index as usize - 1].term != entry.term {
-                    state.persistent.log.truncate(entry.index as usize - 1);
-                    state.persistent.log.push(entry);
+            if new_entries.is_empty() {
+                match state.term_at(entry.index) {
+                    Some(term) if term == entry.term => continue,
+                    // If an existing entry conflicts with a new one, delete it and all that follow
+                    Some(_) => conflict = Some(entry.index),
+                    None => {}
                 }
             }
+            new_entries.push(entry);
+        }
+
+        let reconfigured = conflict.is_some()
+            || new_entries.iter().any(|entry| entry.kind == EntryKind::Membership);
+        if let Some(index) = conflict {
+            state.truncate_from(index);
+        }
+        state.persistent.log.extend(new_entries);
+
+        // Configuration entries take effect as soon as they are in the log
+        if reconfigured {
+            self.refresh_membership(&mut state);
         }
 
-        self.log.save_state(&state.persistent)?;
+        // Entries must be durable before they are acknowledged; the disk is
+        // written without blocking other users of the state
+        drop(state);
+        self.write_log()?;
+        let mut state = self.state.write();
+        if request.term != state.persistent.current_term {
+            return Ok(AppendEntriesResponse {
+                term: state.persistent.current_term,
+                success: false,
+                match_index: None,
+            });
+        }
 
         // If leaderCommit > commitIndex, set commitIndex = min(leaderCommit, index of last new entry)
         if request.leader_commit > state.volatile.commit_index {
             state.volatile.commit_index = request
                 .leader_commit
-                .min(state.last_log_index());
+                .min(last_new_index)
+                .max(state.volatile.commit_index);
+            self.committed.notify_one();
         }
 
         Ok(AppendEntriesResponse {
             term: state.persistent.current_term,
             success: true,
-            match_index: Some(state.last_log_index()),
+            match_index: Some(last_new_index),
         })
     }
 
@@ -187,10 +754,38 @@ impl RaftNode {
             });
         }
 
+        let log_up_to_date = request.last_log_term > state.last_log_term()
+            || (request.last_log_term == state.last_log_term()
+                && request.last_log_index >= state.last_log_index());
+
+        // A pre-vote changes neither the term nor the vote
+        if request.pre_vote {
+            return Ok(VoteResponse {
+                term: state.persistent.current_term,
+                vote_granted: request.term > state.persistent.current_term
+                    && log_up_to_date
+                    && !self.has_leader_contact(&state),
+            });
+        }
+
+        // While a leader is known to be alive, candidates are ignored so a
+        // node rejoining after a partition cannot depose it
+        let leadership = &self.config.leadership;
+        if (leadership.check_quorum || leadership.lease)
+            && !request.leadership_transfer
+            && request.term > state.persistent.current_term
+            && self.has_leader_contact(&state)
+        {
+            return Ok(VoteResponse {
+                term: state.persistent.current_term,
+                vote_granted: false,
+            });
+        }
+
         // If RPC request contains term T > currentTerm:
         // set currentTerm = T, convert to follower
         if request.term > state.persistent.current_term {
-            state.become_follower(request.term);
+            self.step_down(&mut state, request.term, None)?;
         }
 
         // Grant vote if:
@@ -199,13 +794,9 @@ impl RaftNode {
         let can_vote = state.persistent.voted_for.is_none()
             || state.persistent.voted_for == Some(request.candidate_id);
 
-        let log_up_to_date = request.last_log_term > state.last_log_term()
-            || (request.last_log_term == state.last_log_term()
-                && request.last_log_index >= state.last_log_index());
-
         if can_vote && log_up_to_date {
             state.persistent.voted_for = Some(request.candidate_id);
-            self.log.save_state(&state.persistent)?;
+            self.save_hard_state(&state)?;
             *self.last_heartbeat.write() = Instant::now();
 
             Ok(VoteResponse {
@@ -220,108 +811,1800 @@ impl RaftNode {
         }
     }
 
+    /// Handle TimeoutNow RPC
+    ///
+    /// A voter that is still following the sender starts an election
+    /// immediately instead of waiting for its election timeout.
+    pub async fn handle_timeout_now(&self, request: TimeoutNowRequest) -> Result<TimeoutNowResponse> {
+        let state = self.state.read();
+
+        if request.term == state.persistent.current_term
+            && state.role == NodeRole::Follower
+            && state.membership.is_voter(state.node_id)
+        {
+            self.campaign.notify_one();
+        }
+
+        Ok(TimeoutNowResponse {
+            term: state.persistent.current_term,
+        })
+    }
+
     /// Check if this node is the leader
     pub fn is_leader(&self) -> bool {
         self.state.read().role == NodeRole::Leader
     }
 
     /// Get the current leader ID
+    ///
+    /// Followers learn it from the leader's requests; it is unknown while
+    /// an election is under way.
     pub fn leader_id(&self) -> Option<u64> {
+        self.state.read().leader_id
+    }
+
+    /// Term of this node's leadership, if it is the leader
+    pub fn leader_term(&self) -> Option<u64> {
         let state = self.state.read();
-        if state.role == NodeRole::Leader {
-            Some(state.node_id)
-        } else {
-            None
+        (state.role == NodeRole::Leader).then_some(state.persistent.current_term)
+    }
+
+    /// Check if this node is the leader and holds a lease
+    ///
+    /// The lease lasts for the minimum election timeout, shortened by the
+    /// maximum clock drift, from the oldest request acknowledged by a
+    /// majority. No other leader can be elected while it lasts, so reads may
+    /// be served locally. Voters elect a leadership transfer's target
+    /// regardless, so the lease ends once a transfer starts. Always false
+    /// unless leases are enabled.
+    pub fn has_lease(&self) -> bool {
+        if !self.config.leadership.lease {
+            return false;
+        }
+
+        let state = self.state.read();
+        if state.leader.as_ref().is_some_and(|leader| leader.transferee.is_some()) {
+            return false;
         }
+
+        let lease = self
+            .min_election_timeout()
+            .mul_f64(1.0 - self.config.leadership.max_clock_drift);
+        Self::quorum_contact(&state).is_some_and(|contact| contact.elapsed() < lease)
     }
 
-    /// Get the committed offset
-    pub fn committed_offset(&self) -> LogOffset {
-        LogOffset::new(self.state.read().volatile.commit_index)
+    /// Wait until this node has applied every entry committed before the call
+    ///
+    /// The leader confirms it still leads with a round of heartbeats, or its
+    /// lease, and takes its commit index as the read index. Followers ask
+    /// the leader for the read index. Reads of the state machine made after
+    /// this returns are linearizable. Returns the read index.
+    pub async fn read_index(&self) -> Result<u64> {
+        let index = if self.is_leader() {
+            self.confirm_read_index().await?
+        } else {
+            self.forward_read_index().await?
+        };
+
+        // A node cut off after learning the read index never catches up
+        let timeout = Duration::from_millis(self.config.election_timeout.max_ms);
+        tokio::time::timeout(timeout, self.wait_applied(index))
+            .await
+            .map_err(|_| PyralogError::Timeout)?;
+        Ok(index)
     }
 
-    /// Run the election timer
-    async fn run_election_timer(self: Arc<Self>) {
-        loop {
-            let timeout = self.config.election_timeout.generate_timeout();
-            sleep(timeout).await;
+    /// Handle ReadIndex RPC
+    pub async fn handle_read_index(&self, request: ReadIndexRequest) -> Result<ReadIndexResponse> {
+        self.observe_term(request.term);
+        let read_index = self.confirm_read_index().await.ok();
 
-            let last_heartbeat = *self.last_heartbeat.read();
-            let elapsed = last_heartbeat.elapsed();
+        Ok(ReadIndexResponse {
+            term: self.state.read().persistent.current_term,
+            read_index,
+        })
+    }
+
+    /// Confirm this node still leads and return its read index
+    async fn confirm_read_index(&self) -> Result<u64> {
+        let acked = self.acked.notified();
+        tokio::pin!(acked);
+        acked.as_mut().enable();
+
+        let start = Instant::now();
+        let (term, read_index) = {
+            let state = self.state.read();
+            let leader = match &state.leader {
+                Some(leader) => leader,
+                None => return Err(PyralogError::not_leader(state.leader_hint())),
+            };
+            // Until an entry of its own term commits, the leader may not
+            // know the latest commit index
+            let read_index = state.volatile.commit_index.max(leader.term_start);
+            (state.persistent.current_term, read_index)
+        };
+
+        if self.has_lease() {
+            return Ok(read_index);
+        }
+
+        // Only heartbeats sent after the read started confirm leadership
+        self.replicate.notify_one();
+        let deadline = Instant::now()
+            + Duration::from_millis(self.config.election_timeout.max_ms);
 
-            let role = self.state.read().role;
-            
-            // Start election if we're a follower or candidate and haven't heard from leader
-            if role != NodeRole::Leader && elapsed >= timeout {
-                self.start_election().await;
+        loop {
+            {
+                let state = self.state.read();
+                if state.role != NodeRole::Leader || state.persistent.current_term != term {
+                    return Err(PyralogError::not_leader(state.leader_hint()));
+                }
+                if Self::quorum_contact(&state).is_some_and(|contact| contact >= start) {
+                    return Ok(read_index);
+                }
             }
+
+            tokio::time::timeout_at(deadline, acked.as_mut())
+                .await
+                .map_err(|_| PyralogError::Timeout)?;
+            acked.set(self.acked.notified());
+            acked.as_mut().enable();
         }
     }
 
-    /// Run the heartbeat timer (for leaders)
-    async fn run_heartbeat_timer(self: Arc<Self>) {
-        loop {
-            sleep(heartbeat_interval()).await;
+    /// Ask the leader for a read index
+    async fn forward_read_index(&self) -> Result<u64> {
+        let (term, leader_id) = {
+            let state = self.state.read();
+            (state.persistent.current_term, state.leader_id)
+        };
+        let leader_id = leader_id.ok_or(PyralogError::LeaderNotAvailable)?;
 
-            if self.is_leader() {
-                self.send_heartbeats().await;
+        let request = RaftRequest::ReadIndex(ReadIndexRequest { term });
+        match self.transport.send(leader_id, request).await? {
+            RaftResponse::ReadIndex(ReadIndexResponse {
+                read_index: Some(index),
+                ..
+            }) => Ok(index),
+            RaftResponse::ReadIndex(response) => {
+                self.observe_term(response.term);
+                Err(PyralogError::LeaderNotAvailable)
             }
+            _ => Err(PyralogError::ConsensusError(
+                "Unexpected response to ReadIndex".to_string(),
+            )),
         }
     }
 
-    /// Start a new election
-    async fn start_election(&self) {
-        let mut state = self.state.write();
-        state.become_candidate();
-        
-        let term = state.persistent.current_term;
-        let last_log_index = state.last_log_index();
-        let last_log_term = state.last_log_term();
-        let candidate_id = state.node_id;
-        
-        drop(state);
-
-        self.log.save_state(&self.state.read().persistent).ok();
+    /// Latest time by which a majority of voters had answered the leader
+    ///
+    /// Each answer counts from when the request it answered was sent; the
+    /// leader itself counts as answering now.
+    fn quorum_contact(state: &NodeState) -> Option<Instant> {
+        let leader = state.leader.as_ref()?;
+        let now = Instant::now();
 
-        // Vote for self
-        let mut votes = 1;
-        let majority = (self.config.cluster_nodes.len() / 2) + 1;
+        let mut contacts: Vec<Instant> = state
+            .membership
+            .voters
+            .iter()
+            .filter_map(|&voter| {
+                if voter == state.node_id {
+                    Some(now)
+                } else {
+                    leader.last_contact.get(&voter).copied()
+                }
+            })
+            .collect();
 
-        // Request votes from all peers
-        // In production, this would send actual RPC requests
-        // For now, we'll simulate winning the election if we're the first node
-        if candidate_id == self.config.cluster_nodes[0] {
-            votes = majority;
+        let quorum = state.membership.quorum();
+        if contacts.len() < quorum {
+            return None;
         }
+        contacts.sort_unstable_by(|a, b| b.cmp(a));
+        Some(contacts[quorum - 1])
+    }
+
+    /// Get the committed offset
+    ///
+    /// This is local state; use `read_index` first when it must be current.
+    pub fn committed_offset(&self) -> LogOffset {
+        LogOffset::new(self.state.read().volatile.commit_index)
+    }
+
+    /// Inspect the node's state under its lock, for tests and diagnostics
+    pub fn with_state<R>(&self, f: impl FnOnce(&NodeState) -> R) -> R {
+        f(&self.state.read())
+    }
 
-        if votes >= majority {
+    /// Handle InstallSnapshot RPC
+    ///
+    /// Chunks must arrive in order; once the last one is received the
+    /// snapshot replaces the state machine and the log it covers.
+    pub async fn handle_install_snapshot(
+        &self,
+        request: InstallSnapshotRequest,
+    ) -> Result<InstallSnapshotResponse> {
+        let term = {
             let mut state = self.state.write();
-            if state.role == NodeRole::Candidate && state.persistent.current_term == term {
-                state.become_leader(self.config.cluster_nodes.len());
-                self.log.save_state(&state.persistent).ok();
+
+            // Reply immediately if term < currentTerm
+            if request.term < state.persistent.current_term {
+                return Ok(InstallSnapshotResponse {
+                    term: state.persistent.current_term,
+                    success: false,
+                });
             }
-        }
-    }
 
-    /// Send heartbeats to all followers
-    async fn send_heartbeats(&self) {
-        let state = self.state.read();
-        
-        if state.role != NodeRole::Leader {
-            return;
-        }
+            *self.last_heartbeat.write() = Instant::now();
 
-        let request = AppendEntriesRequest {
-            term: state.persistent.current_term,
-            leader_id: state.node_id,
-            prev_log_index: state.last_log_index(),
-            prev_log_term: state.last_log_term(),
-            entries: Vec::new(), // Heartbeat has no entries
-            leader_commit: state.volatile.commit_index,
+            if request.term > state.persistent.current_term || state.role != NodeRole::Follower {
+                self.step_down(&mut state, request.term, Some(request.leader_id))?;
+            }
+            state.leader_id = Some(request.leader_id);
+
+            state.persistent.current_term
         };
 
-        // In production, send to all peers
-        // For now, this is a placeholder
+        let meta = SnapshotMeta {
+            last_included_index: request.last_included_index,
+            last_included_term: request.last_included_term,
+            membership: request.membership,
+        };
+
+        let snapshot = {
+            let mut incoming = self.incoming_snapshot.lock();
+
+            // Create a new snapshot if first chunk (offset is 0)
+            if request.offset == 0 {
+                *incoming = Some(IncomingSnapshot { meta: meta.clone(), data: Vec::new() });
+            }
+
+            match incoming.as_mut() {
+                Some(snapshot)
+                    if snapshot.meta == meta && snapshot.data.len() as u64 == request.offset =>
+                {
+                    snapshot.data.extend_from_slice(&request.data);
+                }
+                _ => {
+                    *incoming = None;
+                    return Ok(InstallSnapshotResponse { term, success: false });
+                }
+            }
+
+            if !request.done {
+                return Ok(InstallSnapshotResponse { term, success: true });
+            }
+
+            incoming.take().expect("snapshot in progress")
+        };
+
+        self.install_snapshot(snapshot.meta, snapshot.data)?;
+
+        Ok(InstallSnapshotResponse { term, success: true })
+    }
+
+    /// Snapshot the state machine and compact the log it covers
+    ///
+    /// Returns the index of the last entry included in the snapshot.
+    pub fn snapshot(&self) -> Result<u64> {
+        let state_machine = self.state_machine.lock();
+        self.take_snapshot(state_machine.as_ref())
+    }
+
+    /// Index of the last entry applied to the state machine
+    pub fn last_applied(&self) -> u64 {
+        *self.applied.borrow()
+    }
+
+    /// Wait until the entry at `index` has been applied locally
+    pub async fn wait_applied(&self, index: u64) {
+        let mut applied = self.applied.subscribe();
+        // The sender lives as long as the node, so this only ends once applied
+        applied.wait_for(|&last_applied| last_applied >= index).await.ok();
+    }
+
+    /// Subscribe to entries as they are applied to the state machine
+    ///
+    /// Only entries applied after subscribing are delivered. A subscriber
+    /// that falls too far behind sees `RecvError::Lagged`.
+    pub fn subscribe(&self) -> broadcast::Receiver<AppliedEntry> {
+        self.watchers.subscribe()
+    }
+
+    /// Apply committed entries whenever the commit index moves
+    async fn run_apply_loop(self: Arc<Self>) {
+        loop {
+            self.committed.notified().await;
+            self.apply_committed();
+        }
+    }
+
+    /// Apply entries up to the commit index in log order
+    fn apply_committed(&self) {
+        loop {
+            let mut state_machine = self.state_machine.lock();
+
+            let entries = {
+                let state = self.state.read();
+                let next = state.volatile.last_applied + 1;
+                let count = state.volatile.commit_index.saturating_sub(next - 1) as usize;
+                state.entries_from(next, count.min(MAX_ENTRIES_PER_APPLY))
+            };
+
+            if entries.is_empty() {
+                let (last_applied, snapshot_index) = {
+                    let state = self.state.read();
+                    (state.volatile.last_applied, state.persistent.snapshot_index)
+                };
+                if last_applied - snapshot_index >= self.config.snapshot.threshold {
+                    // A failed snapshot is retried after the next apply
+                    self.take_snapshot(state_machine.as_ref()).ok();
+                }
+                return;
+            }
+
+            for entry in entries {
+                let index = entry.index;
+
+                if entry.kind == EntryKind::Normal {
+                    state_machine.apply(index, &entry.data);
+
+                    // Having no subscribers is not an error
+                    self.watchers
+                        .send(AppliedEntry {
+                            index,
+                            term: entry.term,
+                            data: Bytes::from(entry.data),
+                        })
+                        .ok();
+                }
+
+                self.state.write().volatile.last_applied = index;
+                self.applied.send_replace(index);
+            }
+        }
+    }
+
+    /// Snapshot the state machine up to `last_applied`
+    ///
+    /// The caller holds the state machine lock, so nothing is applied while
+    /// the snapshot is taken.
+    fn take_snapshot(&self, state_machine: &dyn StateMachine) -> Result<u64> {
+        let (index, term, membership) = {
+            let state = self.state.read();
+            let index = state.volatile.last_applied;
+            if index <= state.persistent.snapshot_index {
+                return Ok(state.persistent.snapshot_index);
+            }
+            let term = state.term_at(index).expect("applied entry is in the log");
+            (index, term, state.membership_at(index).1)
+        };
+
+        let meta = SnapshotMeta {
+            last_included_index: index,
+            last_included_term: term,
+            membership: membership.clone(),
+        };
+        self.snapshots.save(meta, state_machine.snapshot()?)?;
+
+        {
+            let mut state = self.state.write();
+            state.compact_through(index, term);
+            state.persistent.snapshot_membership = membership;
+        }
+        self.log.compact_prefix(index)?;
+
+        Ok(index)
+    }
+
+    /// Replace local state with a snapshot received from the leader
+    fn install_snapshot(&self, meta: SnapshotMeta, data: Vec<u8>) -> Result<()> {
+        let mut state_machine = self.state_machine.lock();
+
+        // Ignore snapshots older than what has already been applied
+        if meta.last_included_index <= self.state.read().volatile.last_applied {
+            return Ok(());
+        }
+
+        let _io = self.log_io.lock();
+        let mut state = self.state.write();
+
+        // A log that disagrees with the snapshot is discarded before the
+        // snapshot is saved, so a restart never pairs them
+        let index = meta.last_included_index;
+        let kept = state.term_at(index) == Some(meta.last_included_term);
+        if !kept {
+            self.log.truncate_suffix(0)?;
+        }
+
+        let term = meta.last_included_term;
+        let membership = meta.membership.clone();
+        state_machine.restore(&data)?;
+        self.snapshots.save(meta, data)?;
+
+        state.compact_through(index, term);
+        state.persistent.snapshot_membership = membership;
+        self.refresh_membership(&mut state);
+        if kept {
+            self.log.compact_prefix(index)?;
+        }
+        state.volatile.commit_index = state.volatile.commit_index.max(index);
+        state.volatile.last_applied = index;
+        self.applied.send_replace(index);
+
+        Ok(())
+    }
+
+    /// Run the election timer
+    async fn run_election_timer(self: Arc<Self>) {
+        loop {
+            let timeout = self.next_election_timeout();
+            tokio::select! {
+                biased;
+                _ = sleep(timeout) => {}
+                _ = self.campaign.notified() => {
+                    // The leader is handing over leadership
+                    self.spawn_election(true);
+                    continue;
+                }
+            }
+
+            if self.election_due(timeout) {
+                self.spawn_election(false);
+            }
+        }
+    }
+
+    /// Run the replication and heartbeat loop (for leaders)
+    async fn run_heartbeat_timer(self: Arc<Self>) {
+        loop {
+            // Wake early when new entries are waiting to be replicated
+            tokio::select! {
+                biased;
+                _ = sleep(heartbeat_interval()) => {}
+                _ = self.replicate.notified() => {}
+            }
+
+            self.heartbeat();
+        }
+    }
+
+    /// React to leadership transfers and new entries between ticks
+    async fn run_events(self: Arc<Self>) {
+        loop {
+            tokio::select! {
+                biased;
+                _ = self.campaign.notified() => self.spawn_election(true),
+                _ = self.replicate.notified() => {
+                    if self.is_leader() {
+                        self.replicate_to_peers();
+                    }
+                }
+            }
+        }
+    }
+
+    fn next_election_timeout(&self) -> Duration {
+        self.config
+            .election_timeout
+            .generate_timeout_with(&mut *self.rng.lock())
+    }
+
+    /// Whether a follower or candidate has gone `timeout` without hearing
+    /// from a leader and should campaign
+    ///
+    /// Nodes outside the membership never campaign.
+    fn election_due(&self, timeout: Duration) -> bool {
+        let elapsed = self.last_heartbeat.read().elapsed();
+        let state = self.state.read();
+        state.role != NodeRole::Leader && state.membership.is_voter(state.node_id) && elapsed >= timeout
+    }
+
+    fn spawn_election(self: &Arc<Self>, leadership_transfer: bool) {
+        let node = Arc::clone(self);
+        tokio::spawn(async move {
+            node.start_election(leadership_transfer).await;
+        });
+    }
+
+    /// Replicate to every peer and check the quorum, if leader
+    fn heartbeat(self: &Arc<Self>) {
+        if self.is_leader() {
+            self.replicate_to_peers();
+            if self.config.leadership.check_quorum {
+                self.check_quorum();
+            }
+        }
+    }
+
+    /// Start a new election
+    ///
+    /// Requests votes from all peers concurrently and becomes leader once a
+    /// majority has granted its vote. A response with a newer term turns the
+    /// candidate back into a follower. With pre-vote enabled the term is only
+    /// incremented once a majority has said it would vote for this node.
+    /// Neither check applies when the leader handed over leadership.
+    async fn start_election(self: &Arc<Self>, leadership_transfer: bool) {
+        if !leadership_transfer && self.config.leadership.pre_vote && !self.pre_vote().await {
+            return;
+        }
+
+        let (request, voters, majority) = {
+            let mut state = self.state.write();
+            if !state.membership.is_voter(state.node_id)
+                || (!leadership_transfer && self.has_leader_contact(&state))
+            {
+                return;
+            }
+            let (previous_term, previous_vote) =
+                (state.persistent.current_term, state.persistent.voted_for);
+            state.become_candidate();
+            *self.last_heartbeat.write() = Instant::now();
+
+            if self.save_hard_state(&state).is_err() {
+                // Without a durable vote for ourselves we must not campaign,
+                // nor act on a term and vote that were never saved
+                state.role = NodeRole::Follower;
+                state.persistent.current_term = previous_term;
+                state.persistent.voted_for = previous_vote;
+                return;
+            }
+
+            let request = VoteRequest {
+                term: state.persistent.current_term,
+                candidate_id: state.node_id,
+                last_log_index: state.last_log_index(),
+                last_log_term: state.last_log_term(),
+                pre_vote: false,
+                leadership_transfer,
+            };
+            (request, state.membership.voting_peers(state.node_id), state.membership.quorum())
+        };
+        let term = request.term;
+
+        if !self.collect_votes(request, &voters, majority).await {
+            return;
+        }
+
+        let became_leader = {
+            let mut state = self.state.write();
+            if state.role == NodeRole::Candidate && state.persistent.current_term == term {
+                let peers = state.membership.peers(state.node_id);
+                state.become_leader(&peers);
+
+                // Commit a no-op so entries from earlier terms commit too
+                let noop = LogEntry::noop(term, state.last_log_index() + 1);
+                state.persistent.log.push(noop);
+                true
+            } else {
+                false
+            }
+        };
+
+        if became_leader {
+            // Assert leadership before followers time out
+            self.replicate_to_peers();
+            self.persist_log();
+        }
+    }
+
+    /// Ask peers whether they would vote for this node in the next term
+    async fn pre_vote(&self) -> bool {
+        let (request, voters, majority) = {
+            let state = self.state.read();
+            if !state.membership.is_voter(state.node_id) || self.has_leader_contact(&state) {
+                return false;
+            }
+
+            let request = VoteRequest {
+                term: state.persistent.current_term + 1,
+                candidate_id: state.node_id,
+                last_log_index: state.last_log_index(),
+                last_log_term: state.last_log_term(),
+                pre_vote: true,
+                leadership_transfer: false,
+            };
+            (request, state.membership.voting_peers(state.node_id), state.membership.quorum())
+        };
+
+        self.collect_votes(request, &voters, majority).await
+    }
+
+    /// Send a vote request to `peers` and wait for a majority to grant it
+    ///
+    /// The candidate's own vote counts towards `majority`. Returns false if
+    /// the majority cannot be reached or a peer reports a newer term.
+    async fn collect_votes(&self, request: VoteRequest, peers: &[u64], majority: usize) -> bool {
+        // Vote for self
+        let mut votes = 1;
+
+        let mut requests = JoinSet::new();
+        for &peer in peers {
+            let transport = Arc::clone(&self.transport);
+            let request = RaftRequest::Vote(request.clone());
+            requests.spawn(async move { transport.send(peer, request).await });
+        }
+
+        while votes < majority {
+            let response = match requests.join_next().await {
+                Some(Ok(Ok(RaftResponse::Vote(response)))) => response,
+                Some(_) => continue,
+                None => return false,
+            };
+
+            if !self.observe_term(response.term) {
+                return false;
+            }
+
+            // Pre-votes are answered from the voter's own, older term
+            if response.vote_granted && (request.pre_vote || response.term == request.term) {
+                votes += 1;
+            }
+        }
+
+        true
+    }
+
+    /// Check whether this node has heard from a live leader
+    ///
+    /// A leader counts as live until the minimum election timeout has passed
+    /// since its last message; a leader always considers itself live.
+    fn has_leader_contact(&self, state: &NodeState) -> bool {
+        state.role == NodeRole::Leader
+            || (state.leader_id.is_some()
+                && self.last_heartbeat.read().elapsed() < self.min_election_timeout())
+    }
+
+    fn min_election_timeout(&self) -> Duration {
+        Duration::from_millis(self.config.election_timeout.min_ms)
+    }
+
+    /// Step down if a majority has not answered within an election timeout
+    fn check_quorum(&self) {
+        let mut state = self.state.write();
+        let timeout = Duration::from_millis(self.config.election_timeout.max_ms);
+
+        let leader = match &state.leader {
+            Some(leader) => leader,
+            None => return,
+        };
+        if leader.elected_at.elapsed() < timeout {
+            return;
+        }
+
+        let active = state
+            .membership
+            .voters
+            .iter()
+            .filter(|&&voter| {
+                voter == state.node_id
+                    || leader
+                        .last_contact
+                        .get(&voter)
+                        .is_some_and(|sent| sent.elapsed() < timeout)
+            })
+            .count();
+
+        if active < state.membership.quorum() {
+            self.resign(&mut state);
+        }
+    }
+
+    /// Send AppendEntries to every peer with room in its window
+    ///
+    /// Peers that are up to date receive an empty heartbeat.
+    fn replicate_to_peers(self: &Arc<Self>) {
+        let peers = {
+            let state = self.state.read();
+            state.membership.peers(state.node_id)
+        };
+        for peer in peers {
+            self.replicate_to(peer);
+        }
+    }
+
+    /// Send the entries a peer is missing, starting at its `next_index`
+    ///
+    /// Requests are pipelined: `next_index` moves past the entries sent
+    /// right away, so the next request carries the entries after them
+    /// without waiting for an answer, until the peer's window is full.
+    fn replicate_to(self: &Arc<Self>, peer: u64) {
+        while self.send_append(peer) {}
+    }
+
+    /// Send one AppendEntries request to a peer if its window has room
+    ///
+    /// Returns true if entries remain to be sent after this request.
+    fn send_append(self: &Arc<Self>, peer: u64) -> bool {
+        let (request, more) = {
+            let mut state = self.state.write();
+
+            if state.role != NodeRole::Leader {
+                return false;
+            }
+
+            let leader = state.leader.as_ref().expect("leader state");
+            let inflight = leader.in_flight.get(&peer);
+            if inflight.is_some_and(|inflight| !inflight.can_send(&self.config.pipeline)) {
+                return false;
+            }
+            let next_index = leader.next_index.get(&peer).copied().unwrap_or(1);
+
+            // Entries the peer needs were compacted away; send the snapshot
+            if next_index <= state.persistent.snapshot_index {
+                let leader = state.leader.as_mut().unwrap();
+                leader.in_flight.entry(peer).or_default().start_snapshot();
+                drop(state);
+
+                let node = Arc::clone(self);
+                tokio::spawn(async move {
+                    let installed = node.send_snapshot(peer).await;
+                    node.handle_snapshot_sent(peer, installed);
+                });
+                return false;
+            }
+
+            let prev_log_index = next_index - 1;
+            let pipeline = &self.config.pipeline;
+            let request = AppendEntriesRequest {
+                term: state.persistent.current_term,
+                leader_id: state.node_id,
+                prev_log_index,
+                prev_log_term: state.term_at(prev_log_index).unwrap_or(0),
+                entries: state.entries_within(next_index, pipeline.max_entries, pipeline.max_bytes),
+                leader_commit: state.volatile.commit_index,
+            };
+
+            let last_index = prev_log_index + request.entries.len() as u64;
+            let bytes = request.entries.iter().map(|entry| entry.data.len()).sum();
+            let leader = state.leader.as_mut().unwrap();
+            leader.in_flight.entry(peer).or_default().sent(last_index, bytes);
+            leader.next_index.insert(peer, last_index + 1);
+            let more = last_index < state.last_log_index();
+            (request, more)
+        };
+
+        let node = Arc::clone(self);
+        let sent = Instant::now();
+        tokio::spawn(async move {
+            let result = node
+                .transport
+                .send(peer, RaftRequest::AppendEntries(request.clone()))
+                .await;
+            let response = match result {
+                Ok(RaftResponse::AppendEntries(response)) => Some(response),
+                _ => None,
+            };
+
+            if node.handle_append_response(peer, &request, sent, response) {
+                node.replicate_to(peer);
+            }
+        });
+
+        more
+    }
+
+    /// Stream the latest snapshot to a peer in chunks
+    ///
+    /// Returns the index covered by the snapshot once the peer installed it.
+    async fn send_snapshot(&self, peer: u64) -> Option<u64> {
+        let (term, leader_id) = {
+            let state = self.state.read();
+            (state.persistent.current_term, state.node_id)
+        };
+
+        let (meta, data) = self.snapshots.load().ok()??;
+        let chunk_size = self.config.snapshot.chunk_size.max(1);
+        let mut offset = 0;
+
+        loop {
+            let end = (offset + chunk_size).min(data.len());
+            let request = InstallSnapshotRequest {
+                term,
+                leader_id,
+                last_included_index: meta.last_included_index,
+                last_included_term: meta.last_included_term,
+                membership: meta.membership.clone(),
+                offset: offset as u64,
+                data: data[offset..end].to_vec(),
+                done: end == data.len(),
+            };
+
+            let sent = Instant::now();
+            let response = match self
+                .transport
+                .send(peer, RaftRequest::InstallSnapshot(request))
+                .await
+            {
+                Ok(RaftResponse::InstallSnapshot(response)) => response,
+                _ => return None,
+            };
+
+            if !self.observe_term(response.term) {
+                return None;
+            }
+
+            {
+                let mut state = self.state.write();
+                if state.persistent.current_term == term {
+                    if let Some(leader) = state.leader.as_mut() {
+                        leader.record_contact(peer, sent);
+                    }
+                }
+            }
+
+            if !response.success {
+                return None;
+            }
+
+            if end == data.len() {
+                return Some(meta.last_included_index);
+            }
+            offset = end;
+        }
+    }
+
+    /// Update a peer's progress after a snapshot transfer
+    fn handle_snapshot_sent(&self, peer: u64, installed: Option<u64>) {
+        let mut state = self.state.write();
+
+        let leader = match state.leader.as_mut() {
+            Some(leader) => leader,
+            None => return,
+        };
+        leader.in_flight.entry(peer).or_default().finish_snapshot();
+
+        if let Some(index) = installed {
+            let match_index = leader.match_index.entry(peer).or_insert(0);
+            *match_index = (*match_index).max(index);
+            let match_index = *match_index;
+            leader.next_index.insert(peer, match_index + 1);
+            self.advance_commit(&mut state);
+        }
+    }
+
+    /// Update a peer's progress from its AppendEntries response
+    ///
+    /// Responses may arrive out of order. Acknowledged entries only ever
+    /// move `match_index` forward; a rejected or lost request rewinds
+    /// `next_index` and drops the requests sent after it, which the peer
+    /// cannot accept either. Returns true if more entries should be sent to
+    /// the peer right away.
+    fn handle_append_response(
+        &self,
+        peer: u64,
+        request: &AppendEntriesRequest,
+        sent: Instant,
+        response: Option<AppendEntriesResponse>,
+    ) -> bool {
+        let mut state = self.state.write();
+
+        let last_index = request.prev_log_index + request.entries.len() as u64;
+        let current = state.persistent.current_term == request.term;
+        let tracked = match state.leader.as_mut() {
+            Some(leader) if current => leader.in_flight.entry(peer).or_default().answered(last_index),
+            _ => false,
+        };
+
+        let response = match response {
+            Some(response) => response,
+            None => {
+                // Resend from the last acknowledged entry on the next heartbeat
+                if let Some(leader) = state.leader.as_mut().filter(|_| tracked) {
+                    let match_index = leader.match_index.get(&peer).copied().unwrap_or(0);
+                    leader.next_index.insert(peer, match_index + 1);
+                    leader.in_flight.entry(peer).or_default().reset();
+                }
+                return false;
+            }
+        };
+
+        if response.term > state.persistent.current_term {
+            // Stays a follower of its old term if the new one cannot be saved
+            self.step_down(&mut state, response.term, None).ok();
+            return false;
+        }
+
+        if state.role != NodeRole::Leader || state.persistent.current_term != request.term {
+            return false;
+        }
+
+        let last_log_index = state.last_log_index();
+        let leader = state.leader.as_mut().expect("leader state");
+        leader.record_contact(peer, sent);
+        self.acked.notify_waiters();
+
+        if response.success {
+            let match_index = leader.match_index.entry(peer).or_insert(0);
+            *match_index = (*match_index).max(last_index);
+            let match_index = *match_index;
+            let next_index = leader.next_index.entry(peer).or_insert(1);
+            *next_index = (*next_index).max(match_index + 1);
+            let next_index = *next_index;
+            if tracked {
+                leader.in_flight.entry(peer).or_default().replicate();
+            }
+            if leader.transferee == Some(peer) {
+                self.transfer_progress.notify_one();
+            }
+
+            self.advance_commit(&mut state);
+            next_index <= last_log_index
+        } else if tracked {
+            // Back off, jumping to the end of the follower's log when it is shorter
+            let match_index = leader.match_index.get(&peer).copied().unwrap_or(0);
+            let mut backoff = request.prev_log_index;
+            if let Some(hint) = response.match_index {
+                backoff = backoff.min(hint + 1);
+            }
+            leader.next_index.insert(peer, backoff.max(match_index + 1).max(1));
+            leader.in_flight.entry(peer).or_default().reset();
+            true
+        } else {
+            // The window was reset after this request was sent
+            false
+        }
+    }
+
+    /// Advance the commit index to the highest entry stored on a majority
+    ///
+    /// Only entries from the current term are committed by counting
+    /// replicas; earlier entries commit along with them. The majority is
+    /// taken over the voters of the latest configuration, which need not
+    /// include the leader itself.
+    fn advance_commit(&self, state: &mut NodeState) {
+        let leader = match &state.leader {
+            Some(leader) => leader,
+            None => return,
+        };
+
+        let mut matched: Vec<u64> = state
+            .membership
+            .voters
+            .iter()
+            .map(|&voter| {
+                if voter == state.node_id {
+                    state.volatile.persisted_index
+                } else {
+                    leader.match_index.get(&voter).copied().unwrap_or(0)
+                }
+            })
+            .collect();
+        if matched.is_empty() {
+            return;
+        }
+        matched.sort_unstable_by(|a, b| b.cmp(a));
+
+        let candidate = matched[state.membership.quorum() - 1];
+
+        if candidate > state.volatile.commit_index
+            && state.term_at(candidate) == Some(state.persistent.current_term)
+        {
+            state.volatile.commit_index = candidate;
+            self.resolve_pending(state);
+            self.committed.notify_one();
+        }
+
+        // A leader removed from the cluster leads until its removal commits
+        if !state.membership.is_voter(state.node_id)
+            && state.membership_index <= state.volatile.commit_index
+        {
+            self.resign(state);
+        }
+    }
+
+    /// Pick up the latest configuration in the log
+    fn refresh_membership(&self, state: &mut NodeState) {
+        if !state.refresh_membership() {
+            return;
+        }
+
+        self.transport.update_peers(&state.membership.addresses);
+
+        // Start replicating to new members from the end of the log
+        let next_index = state.last_log_index() + 1;
+        if let Some(leader) = state.leader.as_mut() {
+            for peer in state.membership.peers(state.node_id) {
+                leader.next_index.entry(peer).or_insert(next_index);
+                leader.match_index.entry(peer).or_insert(0);
+            }
+        }
+    }
+
+    /// Complete proposals whose entries are now committed
+    fn resolve_pending(&self, state: &NodeState) {
+        let commit_index = state.volatile.commit_index;
+        let mut pending = self.pending.lock();
+
+        while let Some(entry) = pending.first_entry() {
+            if *entry.key() > commit_index {
+                break;
+            }
+
+            let (index, proposal) = entry.remove_entry();
+            let result = if state.term_at(index) == Some(proposal.term) {
+                Ok(LogOffset::new(index))
+            } else {
+                // Overwritten by another leader's entry
+                Err(PyralogError::not_leader(state.leader_hint()))
+            };
+            proposal.done.send(result).ok();
+        }
+    }
+
+    /// Become a follower in `term`, failing proposals that can no longer be
+    /// confirmed
+    ///
+    /// `leader_id` is the leader of `term` when the caller heard from it;
+    /// failed proposals are redirected there. A new term is made durable
+    /// first. If that fails the node stays a follower of its old term, so it
+    /// never answers in a term, or casts a vote in it, that it could forget
+    /// in a crash.
+    fn step_down(&self, state: &mut NodeState, term: u64, leader_id: Option<u64>) -> Result<()> {
+        let was_leader = state.role == NodeRole::Leader;
+        let (previous_term, previous_vote) =
+            (state.persistent.current_term, state.persistent.voted_for);
+
+        state.become_follower(term);
+        let saved = if term > previous_term {
+            self.save_hard_state(state)
+        } else {
+            Ok(())
+        };
+        match saved {
+            Ok(()) if leader_id.is_some() => state.leader_id = leader_id,
+            Ok(()) => {}
+            Err(_) => {
+                state.persistent.current_term = previous_term;
+                state.persistent.voted_for = previous_vote;
+            }
+        }
+
+        if was_leader {
+            let hint = state.leader_hint();
+            for (_, proposal) in std::mem::take(&mut *self.pending.lock()) {
+                proposal.done.send(Err(PyralogError::not_leader(hint.clone()))).ok();
+            }
+            self.transfer_progress.notify_one();
+            self.acked.notify_waiters();
+        }
+
+        saved
+    }
+
+    /// Become a follower in the current term, failing proposals that can no
+    /// longer be confirmed
+    fn resign(&self, state: &mut NodeState) {
+        let term = state.persistent.current_term;
+        // Nothing needs saving without a new term
+        self.step_down(state, term, None).ok();
+    }
+
+    /// Write the leader's newly appended entries to disk
+    ///
+    /// If they cannot be written the node gives up leadership; the entries
+    /// stay in memory and are written before it acknowledges anything as a
+    /// follower.
+    fn persist_log(&self) {
+        let _io = self.log_io.lock();
+        let written = self.write_log();
+
+        let mut state = self.state.write();
+        if written.is_err() {
+            self.resign(&mut state);
+            return;
+        }
+        self.advance_commit(&mut state);
+    }
+
+    /// Bring the log on disk up to date with the in-memory log
+    ///
+    /// The caller holds `log_io`, but not the state lock, which is only
+    /// taken briefly before and after the disk is written.
+    fn write_log(&self) -> Result<()> {
+        let (persisted, entries) = {
+            let state = self.state.read();
+            let persisted = state.volatile.persisted_index;
+            (persisted, state.entries_from(persisted + 1, usize::MAX))
+        };
+
+        // Entries removed from memory since they were written
+        if self.log.last_index() > persisted {
+            self.log.truncate_suffix(persisted + 1)?;
+        }
+        self.log.append(&entries)?;
+
+        if let Some(last) = entries.last() {
+            let mut state = self.state.write();
+            if state.term_at(last.index) == Some(last.term) {
+                state.volatile.persisted_index = state.volatile.persisted_index.max(last.index);
+            }
+        }
+        Ok(())
+    }
+
+    /// Durably store the current term and vote
+    fn save_hard_state(&self, state: &NodeState) -> Result<()> {
+        self.log.save_hard_state(&HardState {
+            current_term: state.persistent.current_term,
+            voted_for: state.persistent.voted_for,
+        })
+    }
+
+    /// Step down if a peer reports a newer term
+    ///
+    /// Returns false if this node had to become a follower.
+    fn observe_term(&self, term: u64) -> bool {
+        let mut state = self.state.write();
+        if term <= state.persistent.current_term {
+            return true;
+        }
+
+        // Stays a follower of its old term if the new one cannot be saved
+        self.step_down(&mut state, term, None).ok();
+        false
+    }
+}
+
+/// Ends a leadership transfer however `transfer_leadership` returns, even
+/// if it is cancelled, so the leader takes proposals again
+struct TransferGuard<'a> {
+    node: &'a RaftNode,
+    term: u64,
+}
+
+impl Drop for TransferGuard<'_> {
+    fn drop(&mut self) {
+        let mut state = self.node.state.write();
+        if state.persistent.current_term == self.term {
+            if let Some(leader) = state.leader.as_mut() {
+                leader.transferee = None;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl RaftHandler for RaftNode {
+    async fn handle(&self, request: RaftRequest) -> Result<RaftResponse> {
+        match request {
+            RaftRequest::AppendEntries(request) => self
+                .handle_append_entries(request)
+                .await
+                .map(RaftResponse::AppendEntries),
+            RaftRequest::Vote(request) => self
+                .handle_vote_request(request)
+                .await
+                .map(RaftResponse::Vote),
+            RaftRequest::InstallSnapshot(request) => self
+                .handle_install_snapshot(request)
+                .await
+                .map(RaftResponse::InstallSnapshot),
+            RaftRequest::TimeoutNow(request) => self
+                .handle_timeout_now(request)
+                .await
+                .map(RaftResponse::TimeoutNow),
+            RaftRequest::ReadIndex(request) => self
+                .handle_read_index(request)
+                .await
+                .map(RaftResponse::ReadIndex),
+            RaftRequest::Batch(_) => Err(PyralogError::ConsensusError(
+                "Batched requests must be sent to a multi-Raft node".to_string(),
+            )),
+            RaftRequest::Peer(_) => Err(PyralogError::ConsensusError(
+                "Requests of other services must be sent to the node serving them".to_string(),
+            )),
+        }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{ChannelNetwork, TcpTransport};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tempfile::TempDir;
+    use tokio::time::Duration;
+
+    /// Records applied values in order
+    #[derive(Default)]
+    struct Recorder(Vec<Vec<u8>>);
+
+    impl StateMachine for Recorder {
+        fn apply(&mut self, _index: u64, data: &[u8]) {
+            self.0.push(data.to_vec());
+        }
+
+        fn snapshot(&self) -> Result<Vec<u8>> {
+            bincode::serialize(&self.0).map_err(|e| PyralogError::SerializationError(e.to_string()))
+        }
+
+        fn restore(&mut self, snapshot: &[u8]) -> Result<()> {
+            self.0 = bincode::deserialize(snapshot)
+                .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+            Ok(())
+        }
+    }
+
+    fn node_config(dir: &TempDir, node_id: u64, cluster_nodes: Vec<u64>) -> RaftConfig {
+        RaftConfig {
+            node_id,
+            cluster_nodes,
+            data_dir: dir.path().to_path_buf(),
+            election_timeout: ElectionTimeoutConfig::default(),
+            leadership: LeadershipConfig {
+                lease: true,
+                ..LeadershipConfig::default()
+            },
+            snapshot: SnapshotConfig {
+                threshold: 8,
+                chunk_size: 16,
+            },
+            pipeline: PipelineConfig::default(),
+            seed: None,
+        }
+    }
+
+    async fn start_node(
+        network: &Arc<ChannelNetwork>,
+        dir: &TempDir,
+        node_id: u64,
+        cluster_nodes: Vec<u64>,
+    ) -> Arc<RaftNode> {
+        let config = node_config(dir, node_id, cluster_nodes);
+        let node = Arc::new(
+            RaftNode::new(config, Arc::new(network.transport(node_id)), Box::<Recorder>::default())
+                .await
+                .unwrap(),
+        );
+        network.register(node_id, node.clone());
+        Arc::clone(&node).start().await.unwrap();
+        node
+    }
+
+    async fn start_cluster(network: &Arc<ChannelNetwork>, dir: &TempDir) -> Vec<Arc<RaftNode>> {
+        let cluster_nodes = vec![1, 2, 3];
+        let mut nodes = Vec::new();
+
+        for &node_id in &cluster_nodes {
+            nodes.push(start_node(network, dir, node_id, cluster_nodes.clone()).await);
+        }
+
+        nodes
+    }
+
+    async fn wait_for_leader(nodes: &[Arc<RaftNode>], exclude: Option<u64>) -> u64 {
+        for _ in 0..100 {
+            sleep(Duration::from_millis(50)).await;
+            let leaders: Vec<_> = nodes
+                .iter()
+                .filter(|node| Some(node.config.node_id) != exclude && node.is_leader())
+                .collect();
+            if leaders.len() == 1 {
+                return leaders[0].config.node_id;
+            }
+        }
+        panic!("no leader elected");
+    }
+
+    #[tokio::test]
+    async fn test_election_and_failover() {
+        let dir = TempDir::new().unwrap();
+        let network = ChannelNetwork::new();
+        let nodes = start_cluster(&network, &dir).await;
+
+        let leader = wait_for_leader(&nodes, None).await;
+        let term = nodes[leader as usize - 1].state.read().persistent.current_term;
+
+        // Isolate the leader; the remaining majority elects a new one
+        network.disconnect(leader);
+        let new_leader = wait_for_leader(&nodes, Some(leader)).await;
+        assert_ne!(new_leader, leader);
+        assert!(nodes[new_leader as usize - 1].state.read().persistent.current_term > term);
+    }
+
+    #[tokio::test]
+    async fn test_followers_redirect_to_leader() {
+        let dir = TempDir::new().unwrap();
+        let network = ChannelNetwork::new();
+        let nodes = start_cluster(&network, &dir).await;
+
+        let leader = wait_for_leader(&nodes, None).await;
+        sleep(heartbeat_interval() * 2).await;
+
+        for follower in nodes.iter().filter(|node| !node.is_leader()) {
+            assert_eq!(follower.leader_id(), Some(leader));
+            match follower.propose(Bytes::from("redirected")).await {
+                Err(PyralogError::NotLeader(Some(hint))) => assert_eq!(hint, leader),
+                other => panic!("expected a redirect, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_propose_commits_on_majority() {
+        let dir = TempDir::new().unwrap();
+        let network = ChannelNetwork::new();
+        let nodes = start_cluster(&network, &dir).await;
+
+        let leader = wait_for_leader(&nodes, None).await;
+        let follower = nodes.iter().find(|node| !node.is_leader()).unwrap();
+        assert!(matches!(
+            follower.propose(Bytes::from("rejected")).await,
+            Err(PyralogError::NotLeader(_))
+        ));
+
+        // One follower down still leaves a majority
+        let down = follower.config.node_id;
+        network.disconnect(down);
+
+        let leader_node = &nodes[leader as usize - 1];
+        let first = leader_node.propose(Bytes::from("a")).await.unwrap();
+        let second = leader_node.propose(Bytes::from("b")).await.unwrap();
+        assert_eq!(second.as_u64(), first.as_u64() + 1);
+        assert!(leader_node.committed_offset() >= second);
+
+        // The surviving follower holds both entries
+        let other = nodes
+            .iter()
+            .find(|node| node.config.node_id != leader && node.config.node_id != down)
+            .unwrap();
+        let state = other.state.read();
+        assert_eq!(state.entry(second.as_u64()).unwrap().data, b"b".to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_proposals_are_batched() {
+        let dir = TempDir::new().unwrap();
+        let network = ChannelNetwork::new();
+        let nodes = start_cluster(&network, &dir).await;
+
+        let leader = wait_for_leader(&nodes, None).await;
+        let leader_node = Arc::clone(&nodes[leader as usize - 1]);
+
+        let mut proposals = tokio::task::JoinSet::new();
+        for i in 0..2000 {
+            let node = Arc::clone(&leader_node);
+            proposals.spawn(async move { node.propose(Bytes::from(format!("entry-{}", i))).await });
+        }
+        let mut offsets = Vec::new();
+        while let Some(result) = proposals.join_next().await {
+            offsets.push(result.unwrap().unwrap().as_u64());
+        }
+
+        // Every proposal got its own entry, with no gaps between them
+        offsets.sort_unstable();
+        offsets.dedup();
+        assert_eq!(offsets.len(), 2000);
+        let last = *offsets.last().unwrap();
+        assert_eq!(last - offsets[0], 1999);
+
+        for _ in 0..100 {
+            if nodes.iter().all(|node| node.committed_offset().as_u64() >= last) {
+                return;
+            }
+            sleep(Duration::from_millis(20)).await;
+        }
+        panic!("followers did not catch up");
+    }
+
+    /// Tracks how many AppendEntries with entries a node handles at once
+    struct CountInFlight {
+        node: Arc<RaftNode>,
+        active: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl RaftHandler for CountInFlight {
+        async fn handle(&self, request: RaftRequest) -> Result<RaftResponse> {
+            let RaftRequest::AppendEntries(append) = &request else {
+                return self.node.handle(request).await;
+            };
+            if append.entries.is_empty() {
+                return self.node.handle(request).await;
+            }
+
+            let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(active, Ordering::SeqCst);
+            sleep(Duration::from_millis(5)).await;
+            let response = self.node.handle(request).await;
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            response
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_over_tcp() {
+        let dir = TempDir::new().unwrap();
+        let cluster_nodes = vec![1, 2, 3];
+        let addresses: BTreeMap<u64, String> = cluster_nodes
+            .iter()
+            .map(|&node_id| {
+                let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+                (node_id, listener.local_addr().unwrap().to_string())
+            })
+            .collect();
+
+        let mut nodes = Vec::new();
+        let mut handlers = Vec::new();
+        for &node_id in &cluster_nodes {
+            let transport = TcpTransport::new(addresses.clone(), Duration::from_secs(1));
+            let config = node_config(&dir, node_id, cluster_nodes.clone());
+            let node = Arc::new(
+                RaftNode::new(config, Arc::new(transport), Box::<Recorder>::default())
+                    .await
+                    .unwrap(),
+            );
+            let handler = Arc::new(CountInFlight {
+                node: Arc::clone(&node),
+                active: AtomicUsize::new(0),
+                peak: AtomicUsize::new(0),
+            });
+            let address = addresses[&node_id].clone();
+            let serving: Arc<dyn RaftHandler> = handler.clone();
+            tokio::spawn(async move { TcpTransport::serve(&address, serving).await });
+            handlers.push(handler);
+            nodes.push(node);
+        }
+        sleep(Duration::from_millis(50)).await;
+        for node in &nodes {
+            Arc::clone(node).start().await.unwrap();
+        }
+
+        let leader = Arc::clone(&nodes[wait_for_leader(&nodes, None).await as usize - 1]);
+        let mut proposals = JoinSet::new();
+        for i in 0..500 {
+            let node = Arc::clone(&leader);
+            proposals.spawn(async move {
+                // Spread out so entries go out in several requests
+                sleep(Duration::from_millis(i % 50)).await;
+                node.propose(Bytes::from(format!("entry-{}", i))).await
+            });
+        }
+        let mut last = 0;
+        while let Some(result) = proposals.join_next().await {
+            last = last.max(result.unwrap().unwrap().as_u64());
+        }
+
+        for node in &nodes {
+            tokio::time::timeout(Duration::from_secs(5), node.wait_applied(last))
+                .await
+                .unwrap();
+        }
+        // Followers had more than one request in flight on their connection
+        assert!(handlers.iter().any(|handler| handler.peak.load(Ordering::SeqCst) > 1));
+        for node in &nodes {
+            node.shutdown();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_committed_entries_are_applied_in_order() {
+        let dir = TempDir::new().unwrap();
+        let network = ChannelNetwork::new();
+        let nodes = start_cluster(&network, &dir).await;
+
+        let leader = &nodes[wait_for_leader(&nodes, None).await as usize - 1];
+        let mut watcher = leader.subscribe();
+
+        let mut last = LogOffset::new(0);
+        for value in ["a", "b", "c"] {
+            last = leader.propose(Bytes::from(value)).await.unwrap();
+        }
+
+        let applied = watcher.recv().await.unwrap();
+        assert_eq!(applied.data, Bytes::from("a"));
+
+        for node in &nodes {
+            tokio::time::timeout(Duration::from_secs(5), node.wait_applied(last.as_u64()))
+                .await
+                .unwrap();
+            let snapshot = node.state_machine.lock().snapshot().unwrap();
+            let values: Vec<Vec<u8>> = bincode::deserialize(&snapshot).unwrap();
+            assert_eq!(values, vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lagging_follower_catches_up_from_snapshot() {
+        let dir = TempDir::new().unwrap();
+        let network = ChannelNetwork::new();
+        let nodes = start_cluster(&network, &dir).await;
+
+        let leader = &nodes[wait_for_leader(&nodes, None).await as usize - 1];
+        let lagging = nodes.iter().find(|node| !node.is_leader()).unwrap();
+        network.disconnect(lagging.config.node_id);
+
+        let mut last = LogOffset::new(0);
+        for i in 0..20 {
+            last = leader.propose(Bytes::from(format!("value-{}", i))).await.unwrap();
+        }
+
+        // The leader compacted the entries the lagging follower is missing
+        tokio::time::timeout(Duration::from_secs(5), leader.wait_applied(last.as_u64()))
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(100)).await;
+        assert!(leader.state.read().persistent.snapshot_index > 0);
+        assert!(lagging.state.read().last_log_index() < leader.state.read().first_log_index());
+
+        network.register(lagging.config.node_id, lagging.clone());
+        tokio::time::timeout(Duration::from_secs(5), lagging.wait_applied(last.as_u64()))
+            .await
+            .unwrap();
+
+        let expected = leader.state_machine.lock().snapshot().unwrap();
+        assert_eq!(lagging.state_machine.lock().snapshot().unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_partitioned_follower_does_not_disrupt_leader() {
+        let dir = TempDir::new().unwrap();
+        let network = ChannelNetwork::new();
+        let nodes = start_cluster(&network, &dir).await;
+
+        let leader = wait_for_leader(&nodes, None).await;
+        let term = nodes[leader as usize - 1].state.read().persistent.current_term;
+
+        // Pre-votes fail while partitioned, so the term does not move
+        let follower = nodes.iter().find(|node| !node.is_leader()).unwrap();
+        network.disconnect(follower.config.node_id);
+        sleep(Duration::from_secs(1)).await;
+        assert_eq!(follower.state.read().persistent.current_term, term);
+
+        network.register(follower.config.node_id, follower.clone());
+        sleep(Duration::from_millis(500)).await;
+        assert!(nodes[leader as usize - 1].is_leader());
+        assert_eq!(nodes[leader as usize - 1].state.read().persistent.current_term, term);
+    }
+
+    #[tokio::test]
+    async fn test_isolated_leader_steps_down_and_loses_lease() {
+        let dir = TempDir::new().unwrap();
+        let network = ChannelNetwork::new();
+        let nodes = start_cluster(&network, &dir).await;
+
+        let leader = &nodes[wait_for_leader(&nodes, None).await as usize - 1];
+        sleep(Duration::from_millis(100)).await;
+        assert!(leader.has_lease());
+        assert!(nodes.iter().all(|node| node.is_leader() || !node.has_lease()));
+
+        // CheckQuorum makes the leader give up without hearing a newer term
+        network.disconnect(leader.config.node_id);
+        sleep(Duration::from_millis(200)).await;
+        assert!(!leader.has_lease());
+        sleep(Duration::from_millis(500)).await;
+        assert!(!leader.is_leader());
+    }
+
+    #[tokio::test]
+    async fn test_transfer_leadership() {
+        let dir = TempDir::new().unwrap();
+        let network = ChannelNetwork::new();
+        let nodes = start_cluster(&network, &dir).await;
+
+        let leader = &nodes[wait_for_leader(&nodes, None).await as usize - 1];
+        let last = leader.propose(Bytes::from("a")).await.unwrap();
+        let target = nodes.iter().find(|node| !node.is_leader()).unwrap();
+
+        assert!(matches!(
+            leader.transfer_leadership(leader.config.node_id).await,
+            Err(PyralogError::InvalidRequest(_))
+        ));
+        leader.transfer_leadership(target.config.node_id).await.unwrap();
+        assert!(!leader.is_leader());
+
+        // The target takes over well within an election timeout
+        for _ in 0..50 {
+            if target.is_leader() {
+                break;
+            }
+            sleep(Duration::from_millis(2)).await;
+        }
+        assert!(target.is_leader());
+        assert!(target.committed_offset() >= last);
+        target.propose(Bytes::from("b")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_transfer_to_unreachable_target_times_out() {
+        let dir = TempDir::new().unwrap();
+        let network = ChannelNetwork::new();
+        let nodes = start_cluster(&network, &dir).await;
+
+        let leader = Arc::clone(&nodes[wait_for_leader(&nodes, None).await as usize - 1]);
+        leader.propose(Bytes::from("a")).await.unwrap();
+        sleep(heartbeat_interval() * 2).await;
+        assert!(leader.has_lease());
+
+        // Caught up, but TimeoutNow cannot reach it
+        let target = nodes.iter().find(|node| !node.is_leader()).unwrap().config.node_id;
+        network.disconnect(target);
+
+        let transfer = {
+            let leader = Arc::clone(&leader);
+            tokio::spawn(async move { leader.transfer_leadership(target).await })
+        };
+        sleep(Duration::from_millis(20)).await;
+        assert!(!leader.has_lease());
+
+        let result = tokio::time::timeout(Duration::from_secs(5), transfer)
+            .await
+            .expect("transfer did not give up")
+            .unwrap();
+        assert!(matches!(result, Err(PyralogError::Timeout)));
+
+        // The remaining majority still follows, and proposals are taken again
+        assert!(leader.is_leader());
+        leader.propose(Bytes::from("b")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_read_index() {
+        let dir = TempDir::new().unwrap();
+        let network = ChannelNetwork::new();
+        let nodes = start_cluster(&network, &dir).await;
+
+        let leader = &nodes[wait_for_leader(&nodes, None).await as usize - 1];
+        let last = leader.propose(Bytes::from("a")).await.unwrap();
+
+        // Reads on any node see the committed write once the read index is applied
+        for node in &nodes {
+            let index = node.read_index().await.unwrap();
+            assert!(index >= last.as_u64());
+            assert!(node.last_applied() >= index);
+        }
+
+        // An isolated leader cannot confirm leadership once its lease runs out
+        network.disconnect(leader.config.node_id);
+        sleep(Duration::from_millis(200)).await;
+        assert!(leader.read_index().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_learner_is_promoted_once_caught_up() {
+        let dir = TempDir::new().unwrap();
+        let network = ChannelNetwork::new();
+        let leader = start_node(&network, &dir, 1, vec![1]).await;
+        let learner = start_node(&network, &dir, 2, Vec::new()).await;
+        wait_for_leader(std::slice::from_ref(&leader), None).await;
+        leader.propose(Bytes::from("a")).await.unwrap();
+
+        leader.add_learner(2, "127.0.0.1:9002".to_string()).await.unwrap();
+        let last = leader.propose(Bytes::from("b")).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), learner.wait_applied(last.as_u64()))
+            .await
+            .unwrap();
+        assert!(learner.membership().is_learner(2));
+
+        // Commits do not wait for the learner
+        network.disconnect(2);
+        let last = tokio::time::timeout(
+            Duration::from_millis(100),
+            leader.propose(Bytes::from("c")),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(matches!(
+            leader.promote_learner(2).await,
+            Err(PyralogError::ConsensusError(_))
+        ));
+
+        network.register(2, learner.clone());
+        tokio::time::timeout(Duration::from_secs(5), learner.wait_applied(last.as_u64()))
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(100)).await;
+        leader.promote_learner(2).await.unwrap();
+        assert_eq!(leader.membership().voters, [1, 2].into_iter().collect());
+        assert!(!learner.is_leader());
+
+        // The promoted node now counts towards the quorum
+        network.disconnect(2);
+        assert!(tokio::time::timeout(
+            Duration::from_millis(200),
+            leader.propose(Bytes::from("d")),
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_add_and_remove_voters() {
+        let dir = TempDir::new().unwrap();
+        let network = ChannelNetwork::new();
+        let mut nodes = start_cluster(&network, &dir).await;
+        let leader = Arc::clone(&nodes[wait_for_leader(&nodes, None).await as usize - 1]);
+
+        // Wait for the leader's no-op to commit before changing membership
+        let first = leader.propose(Bytes::from("a")).await.unwrap();
+
+        // A joining node starts without a membership and never campaigns
+        let joining = start_node(&network, &dir, 4, Vec::new()).await;
+        assert!(joining.membership().voters.is_empty());
+        leader.add_voter(4, "127.0.0.1:9004".to_string()).await.unwrap();
+        assert!(matches!(
+            leader.add_voter(4, "127.0.0.1:9004".to_string()).await,
+            Err(PyralogError::InvalidRequest(_))
+        ));
+
+        let last = leader.propose(Bytes::from("b")).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), joining.wait_applied(last.as_u64()))
+            .await
+            .unwrap();
+        assert_eq!(joining.membership().voters, (1..=4).collect());
+        assert_eq!(
+            joining.state.read().entry(first.as_u64()).unwrap().data,
+            b"a".to_vec()
+        );
+        nodes.push(joining);
+
+        // Remove the leader itself; it steps down once the change commits
+        let removed = leader.config.node_id;
+        leader.remove_voter(removed).await.unwrap();
+        assert!(!leader.is_leader());
+
+        let new_leader = wait_for_leader(&nodes, Some(removed)).await;
+        let new_leader = &nodes[new_leader as usize - 1];
+        assert!(!new_leader.membership().is_voter(removed));
+        new_leader.propose(Bytes::from("c")).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_restart_recovers_log_and_snapshot() {
+        let dir = TempDir::new().unwrap();
+        let config = RaftConfig {
+            node_id: 1,
+            cluster_nodes: vec![1],
+            data_dir: dir.path().to_path_buf(),
+            election_timeout: ElectionTimeoutConfig::default(),
+            leadership: LeadershipConfig {
+                lease: true,
+                ..LeadershipConfig::default()
+            },
+            snapshot: SnapshotConfig {
+                threshold: 8,
+                chunk_size: 16,
+            },
+            pipeline: PipelineConfig::default(),
+            seed: None,
+        };
+
+        let start = |network: Arc<ChannelNetwork>| {
+            let config = config.clone();
+            async move {
+                let node = Arc::new(
+                    RaftNode::new(config, Arc::new(network.transport(1)), Box::<Recorder>::default())
+                        .await
+                        .unwrap(),
+                );
+                Arc::clone(&node).start().await.unwrap();
+                node
+            }
+        };
+
+        let node = start(ChannelNetwork::new()).await;
+        wait_for_leader(std::slice::from_ref(&node), None).await;
+        let mut last = LogOffset::new(0);
+        for i in 0..12 {
+            last = node.propose(Bytes::from(format!("value-{}", i))).await.unwrap();
+        }
+        node.wait_applied(last.as_u64()).await;
+        let expected = node.state_machine.lock().snapshot().unwrap();
+        let term = node.state.read().persistent.current_term;
+        drop(node);
+
+        // The snapshot plus the entries after it rebuild the same state
+        let node = start(ChannelNetwork::new()).await;
+        assert!(node.state.read().persistent.snapshot_index > 0);
+        assert!(node.state.read().persistent.current_term >= term);
+        wait_for_leader(std::slice::from_ref(&node), None).await;
+        tokio::time::timeout(Duration::from_secs(5), node.wait_applied(last.as_u64()))
+            .await
+            .unwrap();
+        assert_eq!(node.state_machine.lock().snapshot().unwrap(), expected);
+    }
+}
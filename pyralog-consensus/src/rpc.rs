@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use crate::membership::Membership;
 use crate::state::LogEntry;
 
 /// AppendEntries RPC request
@@ -50,6 +51,14 @@ pub struct VoteRequest {
     
     /// Term of candidate's last log entry
     pub last_log_term: u64,
+    
+    /// Ask whether the vote would be granted, without changing any state.
+    /// `term` is then the term the candidate would campaign in.
+    pub pre_vote: bool,
+    
+    /// The leader asked the candidate to take over, so voters must not
+    /// ignore it for having heard from that leader recently
+    pub leadership_transfer: bool,
 }
 
 /// RequestVote RPC response
@@ -62,3 +71,119 @@ pub struct VoteResponse {
     pub vote_granted: bool,
 }
 
+
+/// InstallSnapshot RPC request, carrying one chunk of a snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallSnapshotRequest {
+    /// Leader's term
+    pub term: u64,
+    
+    /// So follower can redirect clients
+    pub leader_id: u64,
+    
+    /// The snapshot replaces all entries up through and including this index
+    pub last_included_index: u64,
+    
+    /// Term of last_included_index
+    pub last_included_term: u64,
+    
+    /// Membership in effect at last_included_index
+    pub membership: Membership,
+    
+    /// Byte offset where the chunk is positioned in the snapshot
+    pub offset: u64,
+    
+    /// Raw bytes of the snapshot chunk
+    pub data: Vec<u8>,
+    
+    /// True if this is the last chunk
+    pub done: bool,
+}
+
+/// InstallSnapshot RPC response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallSnapshotResponse {
+    /// Current term, for leader to update itself
+    pub term: u64,
+    
+    /// False if the chunk did not continue the snapshot being received
+    pub success: bool,
+}
+
+/// TimeoutNow RPC request, asking an up-to-date follower to start an
+/// election immediately so leadership moves to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeoutNowRequest {
+    /// Leader's term
+    pub term: u64,
+    
+    /// Leader handing over leadership
+    pub leader_id: u64,
+}
+
+/// TimeoutNow RPC response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeoutNowResponse {
+    /// Current term, for leader to update itself
+    pub term: u64,
+}
+
+/// ReadIndex RPC request, sent by a follower serving a linearizable read
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadIndexRequest {
+    /// Follower's term
+    pub term: u64,
+}
+
+/// ReadIndex RPC response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadIndexResponse {
+    /// Current term, for follower to update itself
+    pub term: u64,
+    
+    /// Commit index once leadership was confirmed; `None` if the receiver
+    /// could not confirm it is the leader
+    pub read_index: Option<u64>,
+}
+
+/// A request for one Raft group hosted on a multi-Raft node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupRequest {
+    pub group: u64,
+    pub request: RaftRequest,
+}
+
+/// A Raft RPC sent from one peer to another
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RaftRequest {
+    AppendEntries(AppendEntriesRequest),
+    Vote(VoteRequest),
+    InstallSnapshot(InstallSnapshotRequest),
+    TimeoutNow(TimeoutNowRequest),
+    ReadIndex(ReadIndexRequest),
+    
+    /// Requests for several groups that share the receiving node
+    Batch(Vec<GroupRequest>),
+
+    /// A request of another service between the same nodes, such as log
+    /// replication, sharing Raft's connections; encoded and answered by
+    /// that service
+    Peer(Vec<u8>),
+}
+
+/// Response to a `RaftRequest`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum RaftResponse {
+    AppendEntries(AppendEntriesResponse),
+    Vote(VoteResponse),
+    InstallSnapshot(InstallSnapshotResponse),
+    TimeoutNow(TimeoutNowResponse),
+    ReadIndex(ReadIndexResponse),
+    
+    /// Responses to a `Batch`, in request order; each either the group's
+    /// response or its error message
+    Batch(Vec<std::result::Result<RaftResponse, String>>),
+
+    /// Answer to a `Peer` request, encoded by the service it was for
+    Peer(Vec<u8>),
+}
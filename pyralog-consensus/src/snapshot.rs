@@ -0,0 +1,84 @@
+use pyralog_core::{Result, PyralogError};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::membership::Membership;
+
+/// When to snapshot and how to ship snapshots to followers
+#[derive(Debug, Clone)]
+pub struct SnapshotConfig {
+    /// Applied entries since the last snapshot that trigger a new one
+    pub threshold: u64,
+
+    /// Size of the chunks a snapshot is sent to followers in
+    pub chunk_size: usize,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 10_000,
+            chunk_size: 1024 * 1024,
+        }
+    }
+}
+
+/// Position in the log covered by a snapshot
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotMeta {
+    /// Index of the last entry included in the snapshot
+    pub last_included_index: u64,
+
+    /// Term of the last entry included in the snapshot
+    pub last_included_term: u64,
+
+    /// Membership in effect at the last included entry
+    pub membership: Membership,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile {
+    meta: SnapshotMeta,
+    data: Vec<u8>,
+}
+
+/// Durable storage for the latest state machine snapshot
+///
+/// A new snapshot is written to a temporary file and renamed over the old
+/// one, so a crash leaves either the previous or the new snapshot intact.
+pub struct SnapshotStore {
+    path: PathBuf,
+}
+
+impl SnapshotStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Replace the stored snapshot
+    pub fn save(&self, meta: SnapshotMeta, data: Vec<u8>) -> Result<()> {
+        let contents = bincode::serialize(&SnapshotFile { meta, data })
+            .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+
+        let temp_path = self.path.with_extension("snapshot.tmp");
+        fs::write(&temp_path, contents)
+            .and_then(|_| fs::File::open(&temp_path)?.sync_all())
+            .and_then(|_| fs::rename(&temp_path, &self.path))
+            .map_err(|e| PyralogError::StorageError(e.to_string()))
+    }
+
+    /// Load the stored snapshot, if any
+    pub fn load(&self) -> Result<Option<(SnapshotMeta, Vec<u8>)>> {
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(PyralogError::StorageError(e.to_string())),
+        };
+
+        let file: SnapshotFile = bincode::deserialize(&contents)
+            .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+
+        Ok(Some((file.meta, file.data)))
+    }
+}
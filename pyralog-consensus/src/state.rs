@@ -1,5 +1,11 @@
-use pyralog_core::LogOffset;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tokio::time::Instant;
+
+use pyralog_core::LeaderHint;
+
+use crate::membership::Membership;
+use crate::pipeline::Inflight;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum NodeRole {
@@ -17,8 +23,17 @@ pub struct PersistentState {
     /// Candidate ID that received vote in current term
     pub voted_for: Option<u64>,
     
-    /// Log entries
+    /// Log entries following the snapshot
     pub log: Vec<LogEntry>,
+    
+    /// Index of the last entry covered by the snapshot
+    pub snapshot_index: u64,
+    
+    /// Term of the last entry covered by the snapshot
+    pub snapshot_term: u64,
+    
+    /// Membership in effect at the end of the snapshot
+    pub snapshot_membership: Membership,
 }
 
 impl Default for PersistentState {
@@ -27,6 +42,9 @@ impl Default for PersistentState {
             current_term: 0,
             voted_for: None,
             log: Vec::new(),
+            snapshot_index: 0,
+            snapshot_term: 0,
+            snapshot_membership: Membership::default(),
         }
     }
 }
@@ -39,6 +57,10 @@ pub struct VolatileState {
     
     /// Index of highest log entry applied to state machine
     pub last_applied: u64,
+    
+    /// Index of highest log entry written durably to disk; entries after
+    /// it are only in memory
+    pub persisted_index: u64,
 }
 
 impl Default for VolatileState {
@@ -46,6 +68,7 @@ impl Default for VolatileState {
         Self {
             commit_index: 0,
             last_applied: 0,
+            persisted_index: 0,
         }
     }
 }
@@ -53,20 +76,46 @@ impl Default for VolatileState {
 /// Volatile state on leaders
 #[derive(Debug, Clone)]
 pub struct LeaderState {
-    /// For each server, index of next log entry to send
-    pub next_index: Vec<u64>,
+    /// For each peer, index of next log entry to send
+    pub next_index: BTreeMap<u64, u64>,
+    
+    /// For each peer, index of highest log entry known to be replicated
+    pub match_index: BTreeMap<u64, u64>,
+    
+    /// For each peer, requests awaiting a response
+    pub in_flight: BTreeMap<u64, Inflight>,
     
-    /// For each server, index of highest log entry known to be replicated
-    pub match_index: Vec<u64>,
+    /// For each peer, when the latest request it answered was sent
+    pub last_contact: BTreeMap<u64, Instant>,
+    
+    /// When this node became leader
+    pub elected_at: Instant,
+    
+    /// Follower leadership is being handed to; proposals are refused meanwhile
+    pub transferee: Option<u64>,
+    
+    /// Index of the first entry of this leader's term
+    pub term_start: u64,
 }
 
 impl LeaderState {
-    pub fn new(cluster_size: usize, last_log_index: u64) -> Self {
+    pub fn new(peers: &[u64], last_log_index: u64) -> Self {
         Self {
-            next_index: vec![last_log_index + 1; cluster_size],
-            match_index: vec![0; cluster_size],
+            next_index: peers.iter().map(|&peer| (peer, last_log_index + 1)).collect(),
+            match_index: peers.iter().map(|&peer| (peer, 0)).collect(),
+            in_flight: BTreeMap::new(),
+            last_contact: BTreeMap::new(),
+            elected_at: Instant::now(),
+            transferee: None,
+            term_start: last_log_index + 1,
         }
     }
+
+    /// Record that a peer answered a request sent at `sent`
+    pub fn record_contact(&mut self, peer: u64, sent: Instant) {
+        let contact = self.last_contact.entry(peer).or_insert(sent);
+        *contact = (*contact).max(sent);
+    }
 }
 
 /// Complete node state
@@ -76,6 +125,15 @@ pub struct NodeState {
     pub persistent: PersistentState,
     pub volatile: VolatileState,
     pub leader: Option<LeaderState>,
+    
+    /// Leader of the current term, once heard from
+    pub leader_id: Option<u64>,
+    
+    /// Latest membership in the log, in effect as soon as it is appended
+    pub membership: Membership,
+    
+    /// Index of the entry that set `membership`
+    pub membership_index: u64,
 }
 
 impl NodeState {
@@ -86,13 +144,23 @@ impl NodeState {
             persistent: PersistentState::default(),
             volatile: VolatileState::default(),
             leader: None,
+            leader_id: None,
+            membership: Membership::default(),
+            membership_index: 0,
         }
     }
 
     pub fn become_follower(&mut self, term: u64) {
         self.role = NodeRole::Follower;
-        self.persistent.current_term = term;
-        self.persistent.voted_for = None;
+        if term > self.persistent.current_term {
+            // The vote is only reset when entering a new term
+            self.persistent.current_term = term;
+            self.persistent.voted_for = None;
+            self.leader_id = None;
+        }
+        if self.leader_id == Some(self.node_id) {
+            self.leader_id = None;
+        }
         self.leader = None;
     }
 
@@ -101,16 +169,153 @@ impl NodeState {
         self.persistent.current_term += 1;
         self.persistent.voted_for = Some(self.node_id);
         self.leader = None;
+        self.leader_id = None;
     }
 
-    pub fn become_leader(&mut self, cluster_size: usize) {
+    pub fn become_leader(&mut self, peers: &[u64]) {
         self.role = NodeRole::Leader;
-        let last_log_index = self.persistent.log.len() as u64;
-        self.leader = Some(LeaderState::new(cluster_size, last_log_index));
+        let last_log_index = self.last_log_index();
+        self.leader = Some(LeaderState::new(peers, last_log_index));
+        self.leader_id = Some(self.node_id);
+    }
+
+    /// Where a request this node cannot serve should go instead
+    ///
+    /// Only the node ID is known here; callers that know client addresses
+    /// add the leader's.
+    pub fn leader_hint(&self) -> Option<LeaderHint> {
+        self.leader_id
+            .filter(|&leader_id| leader_id != self.node_id)
+            .map(LeaderHint::new)
     }
 
     pub fn last_log_index(&self) -> u64 {
-        self.persistent.log.len().saturating_sub(1) as u64
+        self.persistent
+            .log
+            .last()
+            .map(|entry| entry.index)
+            .unwrap_or(self.persistent.snapshot_index)
+    }
+
+    /// Index of the first entry still held in the log
+    pub fn first_log_index(&self) -> u64 {
+        self.persistent.snapshot_index + 1
+    }
+
+    /// Get the entry at a log index (indexes start at 1)
+    ///
+    /// Returns `None` for entries compacted into the snapshot.
+    pub fn entry(&self, index: u64) -> Option<&LogEntry> {
+        index
+            .checked_sub(self.first_log_index())
+            .and_then(|position| self.persistent.log.get(position as usize))
+    }
+
+    /// Term of the entry at a log index, with index 0 having term 0
+    ///
+    /// The last index covered by the snapshot still has a known term.
+    pub fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.persistent.snapshot_index {
+            return Some(self.persistent.snapshot_term);
+        }
+        self.entry(index).map(|entry| entry.term)
+    }
+
+    /// Entries starting at `index`, at most `max` of them
+    pub fn entries_from(&self, index: u64, max: usize) -> Vec<LogEntry> {
+        let start = index.saturating_sub(self.first_log_index()) as usize;
+        self.persistent
+            .log
+            .iter()
+            .skip(start)
+            .take(max)
+            .cloned()
+            .collect()
+    }
+
+    /// Entries from `index` on, up to `max` of them and `max_bytes` of
+    /// payload, but always at least one if there is any
+    pub fn entries_within(&self, index: u64, max: usize, max_bytes: usize) -> Vec<LogEntry> {
+        let start = index.saturating_sub(self.first_log_index()) as usize;
+        let mut bytes = 0;
+        self.persistent
+            .log
+            .iter()
+            .skip(start)
+            .take(max.max(1))
+            .take_while(|entry| {
+                bytes += entry.data.len();
+                bytes <= max_bytes || bytes == entry.data.len()
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Remove the entry at `index` and all that follow it
+    ///
+    /// Entries already written to disk from `index` on have to be removed
+    /// there too before anything else is written.
+    pub fn truncate_from(&mut self, index: u64) {
+        let len = index.saturating_sub(self.first_log_index()) as usize;
+        self.persistent.log.truncate(len);
+        self.volatile.persisted_index = self.volatile.persisted_index.min(index.saturating_sub(1));
+    }
+
+    /// Drop the entries covered by a snapshot ending at `index`
+    ///
+    /// Entries after the snapshot are kept if the log agrees with it at
+    /// `index`; otherwise the whole log is discarded. Returns whether the
+    /// entries after the snapshot were kept.
+    pub fn compact_through(&mut self, index: u64, term: u64) -> bool {
+        if index <= self.persistent.snapshot_index {
+            return true;
+        }
+
+        // The snapshot is durable, so everything it covers counts as written
+        let kept = self.term_at(index) == Some(term);
+        if kept {
+            let count = (index - self.persistent.snapshot_index) as usize;
+            self.persistent.log.drain(..count);
+            self.volatile.persisted_index = self.volatile.persisted_index.max(index);
+        } else {
+            self.persistent.log.clear();
+            self.volatile.persisted_index = index;
+        }
+
+        self.persistent.snapshot_index = index;
+        self.persistent.snapshot_term = term;
+        kept
+    }
+
+    /// Membership in effect at `index`
+    ///
+    /// Falls back to the snapshot membership when no configuration entry
+    /// at or before `index` is left in the log.
+    pub fn membership_at(&self, index: u64) -> (u64, Membership) {
+        let configuration = self
+            .persistent
+            .log
+            .iter()
+            .rev()
+            .filter(|entry| entry.index <= index && entry.kind == EntryKind::Membership)
+            .find_map(|entry| Some((entry.index, Membership::decode(&entry.data).ok()?)));
+
+        configuration.unwrap_or_else(|| {
+            (self.persistent.snapshot_index, self.persistent.snapshot_membership.clone())
+        })
+    }
+
+    /// Recompute the membership after the log changed
+    ///
+    /// Returns true if the membership differs from before.
+    pub fn refresh_membership(&mut self) -> bool {
+        let (index, membership) = self.membership_at(self.last_log_index());
+        self.membership_index = index;
+        if membership == self.membership {
+            return false;
+        }
+        self.membership = membership;
+        true
     }
 
     pub fn last_log_term(&self) -> u64 {
@@ -118,20 +323,38 @@ impl NodeState {
             .log
             .last()
             .map(|entry| entry.term)
-            .unwrap_or(0)
+            .unwrap_or(self.persistent.snapshot_term)
     }
 }
 
+/// What a log entry carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntryKind {
+    /// A proposed value
+    Normal,
+    
+    /// Appended by a new leader so entries from earlier terms can commit
+    NoOp,
+    
+    /// A new cluster membership, encoded with `Membership::encode`
+    Membership,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogEntry {
     pub term: u64,
     pub index: u64,
+    pub kind: EntryKind,
     pub data: Vec<u8>,
 }
 
 impl LogEntry {
     pub fn new(term: u64, index: u64, data: Vec<u8>) -> Self {
-        Self { term, index, data }
+        Self { term, index, kind: EntryKind::Normal, data }
+    }
+
+    pub fn noop(term: u64, index: u64) -> Self {
+        Self { term, index, kind: EntryKind::NoOp, data: Vec::new() }
     }
 }
 
@@ -0,0 +1,27 @@
+use bytes::Bytes;
+use pyralog_core::Result;
+
+/// Application state replicated through the Raft log
+///
+/// `RaftNode` applies every committed entry exactly once and in log order,
+/// so all replicas reach the same state. `apply` must be deterministic:
+/// invalid commands are handled inside the state machine rather than
+/// reported back, because every replica has to make the same decision.
+pub trait StateMachine: Send + Sync {
+    /// Apply a committed entry
+    fn apply(&mut self, index: u64, data: &[u8]);
+
+    /// Serialize the current state
+    fn snapshot(&self) -> Result<Vec<u8>>;
+
+    /// Replace the current state with a snapshot
+    fn restore(&mut self, snapshot: &[u8]) -> Result<()>;
+}
+
+/// A committed entry that has been applied to the state machine
+#[derive(Debug, Clone)]
+pub struct AppliedEntry {
+    pub index: u64,
+    pub term: u64,
+    pub data: Bytes,
+}
@@ -0,0 +1,480 @@
+use async_trait::async_trait;
+use pyralog_core::{Result, PyralogError};
+use parking_lot::RwLock;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, Duration};
+
+use crate::rpc::{RaftRequest, RaftResponse};
+
+/// Largest Raft message accepted from a peer
+const MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Sends Raft RPCs to peers
+#[async_trait]
+pub trait RaftTransport: Send + Sync {
+    /// Send a request to a peer and wait for its response
+    async fn send(&self, target: u64, request: RaftRequest) -> Result<RaftResponse>;
+
+    /// Learn the addresses of members added through a membership change
+    fn update_peers(&self, _addresses: &BTreeMap<u64, String>) {}
+}
+
+/// Handles Raft RPCs received from peers
+#[async_trait]
+pub trait RaftHandler: Send + Sync {
+    async fn handle(&self, request: RaftRequest) -> Result<RaftResponse>;
+}
+
+/// Raft transport over TCP
+///
+/// Each peer is reached at its internal cluster address. Messages are
+/// bincode-encoded and prefixed with a little-endian `u32` length. One
+/// connection per peer is kept open and re-established after errors;
+/// requests on it carry an id, so many can be in flight at once and their
+/// responses may arrive in any order.
+pub struct TcpTransport {
+    addresses: RwLock<BTreeMap<u64, String>>,
+    connections: RwLock<BTreeMap<u64, Arc<ConnectionSlot>>>,
+    request_timeout: Duration,
+}
+
+impl TcpTransport {
+    pub fn new(addresses: BTreeMap<u64, String>, request_timeout: Duration) -> Self {
+        Self {
+            addresses: RwLock::new(addresses),
+            connections: RwLock::new(BTreeMap::new()),
+            request_timeout,
+        }
+    }
+
+    /// Accept Raft RPCs on `address` and pass them to `handler`
+    pub async fn serve(address: &str, handler: Arc<dyn RaftHandler>) -> Result<()> {
+        let listener = TcpListener::bind(address)
+            .await
+            .map_err(|e| PyralogError::NetworkError(e.to_string()))?;
+
+        loop {
+            let (socket, _) = listener
+                .accept()
+                .await
+                .map_err(|e| PyralogError::NetworkError(e.to_string()))?;
+
+            let handler = Arc::clone(&handler);
+            tokio::spawn(async move {
+                // Errors only end this connection; the peer reconnects
+                serve_connection(socket, handler).await.ok();
+            });
+        }
+    }
+
+    /// The open connection to `target`, connecting first if there is none
+    async fn connection(&self, target: u64) -> Result<Arc<Connection>> {
+        let slot = Arc::clone(
+            self.connections
+                .write()
+                .entry(target)
+                .or_insert_with(|| Arc::new(Mutex::new(None))),
+        );
+        let mut slot = slot.lock().await;
+
+        if let Some(connection) = slot.as_ref().filter(|connection| !connection.is_closed()) {
+            return Ok(Arc::clone(connection));
+        }
+
+        let address = self
+            .addresses
+            .read()
+            .get(&target)
+            .cloned()
+            .ok_or_else(|| PyralogError::NetworkError(format!("Unknown peer {}", target)))?;
+        let socket = TcpStream::connect(&address)
+            .await
+            .map_err(|e| PyralogError::NetworkError(e.to_string()))?;
+        socket.set_nodelay(true).ok();
+
+        let connection = Arc::new(Connection::new(socket));
+        *slot = Some(Arc::clone(&connection));
+        Ok(connection)
+    }
+}
+
+#[async_trait]
+impl RaftTransport for TcpTransport {
+    async fn send(&self, target: u64, request: RaftRequest) -> Result<RaftResponse> {
+        timeout(self.request_timeout, async {
+            self.connection(target).await?.exchange(&request).await
+        })
+        .await
+        .map_err(|_| PyralogError::Timeout)?
+    }
+
+    fn update_peers(&self, addresses: &BTreeMap<u64, String>) {
+        let mut known = self.addresses.write();
+        for (&node_id, address) in addresses {
+            if known.insert(node_id, address.clone()).as_ref() != Some(address) {
+                // Reconnect to the new address on the next request
+                self.connections.write().remove(&node_id);
+            }
+        }
+    }
+}
+
+type PeerResponse = std::result::Result<RaftResponse, String>;
+
+/// Connection to a peer, locked while it is being established
+type ConnectionSlot = Mutex<Option<Arc<Connection>>>;
+
+/// Requests sent on a connection and not answered yet, by request id
+type Pending = parking_lot::Mutex<HashMap<u64, oneshot::Sender<PeerResponse>>>;
+
+/// Connection to a peer shared by every request sent to it
+///
+/// Frames are written by one task, so a request that times out or is
+/// cancelled never leaves a partly written frame behind, and read by
+/// another, which hands each response to the request with its id. A
+/// response to a request that was given up on is dropped.
+struct Connection {
+    outgoing: mpsc::UnboundedSender<Vec<u8>>,
+    pending: Arc<Pending>,
+    closed: Arc<AtomicBool>,
+    next_id: AtomicU64,
+    tasks: [JoinHandle<()>; 2],
+}
+
+impl Connection {
+    fn new(socket: TcpStream) -> Self {
+        let (mut reader, mut writer) = socket.into_split();
+        let (outgoing, mut frames) = mpsc::unbounded_channel::<Vec<u8>>();
+        let pending = Arc::new(Pending::default());
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let write_task = {
+            let (pending, closed) = (Arc::clone(&pending), Arc::clone(&closed));
+            tokio::spawn(async move {
+                while let Some(frame) = frames.recv().await {
+                    if writer.write_all(&frame).await.is_err() {
+                        break;
+                    }
+                }
+                close(&pending, &closed);
+            })
+        };
+        let read_task = {
+            let (pending, closed) = (Arc::clone(&pending), Arc::clone(&closed));
+            tokio::spawn(async move {
+                while let Ok(Some((id, response))) =
+                    read_message::<_, (u64, PeerResponse)>(&mut reader).await
+                {
+                    if let Some(reply) = pending.lock().remove(&id) {
+                        reply.send(response).ok();
+                    }
+                }
+                close(&pending, &closed);
+            })
+        };
+
+        Self {
+            outgoing,
+            pending,
+            closed,
+            next_id: AtomicU64::new(0),
+            tasks: [write_task, read_task],
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    async fn exchange(&self, request: &RaftRequest) -> Result<RaftResponse> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let frame = encode(&(id, request))?;
+
+        let (reply, response) = oneshot::channel();
+        self.pending.lock().insert(id, reply);
+        let _pending = PendingGuard { pending: &self.pending, id };
+
+        // Checked after registering, so a connection closing now either
+        // fails this request here or drops its reply
+        if self.is_closed() || self.outgoing.send(frame).is_err() {
+            return Err(PyralogError::NetworkError("Connection closed".to_string()));
+        }
+
+        response
+            .await
+            .map_err(|_| PyralogError::NetworkError("Connection closed by peer".to_string()))?
+            .map_err(PyralogError::ConsensusError)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Fail every pending request and keep the connection from being reused
+fn close(pending: &Pending, closed: &AtomicBool) {
+    closed.store(true, Ordering::SeqCst);
+    pending.lock().clear();
+}
+
+/// Forgets a request that is answered, fails or is given up on
+struct PendingGuard<'a> {
+    pending: &'a Pending,
+    id: u64,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.pending.lock().remove(&self.id);
+    }
+}
+
+/// Handle the requests of one peer connection concurrently
+///
+/// Responses are written in the order they complete, tagged with the id of
+/// their request.
+async fn serve_connection(socket: TcpStream, handler: Arc<dyn RaftHandler>) -> Result<()> {
+    socket.set_nodelay(true).ok();
+    let (mut reader, mut writer) = socket.into_split();
+    let (outgoing, mut frames) = mpsc::unbounded_channel::<Vec<u8>>();
+
+    let write_task = tokio::spawn(async move {
+        while let Some(frame) = frames.recv().await {
+            if writer.write_all(&frame).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    let result = async {
+        while let Some((id, request)) = read_message::<_, (u64, RaftRequest)>(&mut reader).await? {
+            let handler = Arc::clone(&handler);
+            let outgoing = outgoing.clone();
+            tokio::spawn(async move {
+                let response: PeerResponse = handler.handle(request).await.map_err(|e| e.to_string());
+                if let Ok(frame) = encode(&(id, response)) {
+                    outgoing.send(frame).ok();
+                }
+            });
+        }
+        Ok(())
+    }
+    .await;
+
+    // Requests still being handled hold senders, so their responses are
+    // written before the writer stops
+    drop(outgoing);
+    write_task.await.ok();
+    result
+}
+
+async fn read_message<R, T>(reader: &mut R) -> Result<Option<T>>
+where
+    R: AsyncRead + Unpin,
+    T: serde::de::DeserializeOwned,
+{
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(PyralogError::NetworkError(e.to_string())),
+    }
+
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(PyralogError::NetworkError(format!("Raft message too large: {} bytes", len)));
+    }
+
+    let mut payload = vec![0u8; len];
+    reader
+        .read_exact(&mut payload)
+        .await
+        .map_err(|e| PyralogError::NetworkError(e.to_string()))?;
+
+    bincode::deserialize(&payload)
+        .map(Some)
+        .map_err(|e| PyralogError::SerializationError(e.to_string()))
+}
+
+/// Encode a message as a length-prefixed frame
+fn encode<T: serde::Serialize>(message: &T) -> Result<Vec<u8>> {
+    let payload = bincode::serialize(message)
+        .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+struct Envelope {
+    request: RaftRequest,
+    reply: oneshot::Sender<Result<RaftResponse>>,
+}
+
+/// In-process network connecting Raft nodes through channels
+///
+/// Used in tests to run a cluster inside one process. Nodes can be
+/// disconnected to simulate crashes and partitions.
+#[derive(Default)]
+pub struct ChannelNetwork {
+    nodes: RwLock<BTreeMap<u64, mpsc::UnboundedSender<Envelope>>>,
+}
+
+impl ChannelNetwork {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Attach a node so it receives the requests addressed to it
+    pub fn register(&self, node_id: u64, handler: Arc<dyn RaftHandler>) {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Envelope>();
+
+        tokio::spawn(async move {
+            while let Some(envelope) = rx.recv().await {
+                let handler = Arc::clone(&handler);
+                tokio::spawn(async move {
+                    let response = handler.handle(envelope.request).await;
+                    envelope.reply.send(response).ok();
+                });
+            }
+        });
+
+        self.nodes.write().insert(node_id, tx);
+    }
+
+    /// Detach a node; requests to it fail until it is registered again
+    pub fn disconnect(&self, node_id: u64) {
+        self.nodes.write().remove(&node_id);
+    }
+
+    /// Transport for sending requests from `node_id`
+    pub fn transport(self: &Arc<Self>, node_id: u64) -> ChannelTransport {
+        ChannelTransport {
+            node_id,
+            network: Arc::clone(self),
+        }
+    }
+
+    fn is_connected(&self, node_id: u64) -> bool {
+        self.nodes.read().contains_key(&node_id)
+    }
+}
+
+/// Transport of a single node on a `ChannelNetwork`
+pub struct ChannelTransport {
+    node_id: u64,
+    network: Arc<ChannelNetwork>,
+}
+
+#[async_trait]
+impl RaftTransport for ChannelTransport {
+    async fn send(&self, target: u64, request: RaftRequest) -> Result<RaftResponse> {
+        if !self.network.is_connected(self.node_id) {
+            return Err(PyralogError::NetworkError(format!("Node {} is disconnected", self.node_id)));
+        }
+
+        let sender = self
+            .network
+            .nodes
+            .read()
+            .get(&target)
+            .cloned()
+            .ok_or_else(|| PyralogError::NetworkError(format!("Node {} is unreachable", target)))?;
+
+        let (reply, response) = oneshot::channel();
+        sender
+            .send(Envelope { request, reply })
+            .map_err(|_| PyralogError::NetworkError(format!("Node {} is unreachable", target)))?;
+
+        response
+            .await
+            .map_err(|_| PyralogError::NetworkError(format!("Node {} dropped the request", target)))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::{TimeoutNowRequest, TimeoutNowResponse};
+    use tokio::time::Instant;
+
+    /// Answers `TimeoutNow` with the request's term, the first one late
+    struct EchoTerm;
+
+    #[async_trait]
+    impl RaftHandler for EchoTerm {
+        async fn handle(&self, request: RaftRequest) -> Result<RaftResponse> {
+            let RaftRequest::TimeoutNow(request) = request else {
+                return Err(PyralogError::InvalidRequest("Expected TimeoutNow".to_string()));
+            };
+            if request.term == 1 {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+            }
+            Ok(RaftResponse::TimeoutNow(TimeoutNowResponse { term: request.term }))
+        }
+    }
+
+    fn timeout_now(term: u64) -> RaftRequest {
+        RaftRequest::TimeoutNow(TimeoutNowRequest { term, leader_id: 1 })
+    }
+
+    /// Serve `EchoTerm` on a free local port and return its address
+    async fn serve_echo_term() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let serving = address.clone();
+        tokio::spawn(async move { TcpTransport::serve(&serving, Arc::new(EchoTerm)).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        address
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_response_is_not_read_by_next_request() {
+        let address = serve_echo_term().await;
+        let transport = TcpTransport::new(
+            BTreeMap::from([(2, address)]),
+            Duration::from_millis(100),
+        );
+        assert!(matches!(transport.send(2, timeout_now(1)).await, Err(PyralogError::Timeout)));
+
+        // The late answer to term 1 must not be taken for this one
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        match transport.send(2, timeout_now(2)).await.unwrap() {
+            RaftResponse::TimeoutNow(response) => assert_eq!(response.term, 2),
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_to_a_peer_are_answered_out_of_order() {
+        let address = serve_echo_term().await;
+        let transport = TcpTransport::new(BTreeMap::from([(2, address)]), Duration::from_secs(1));
+
+        // The slow first request does not hold up the second
+        let start = Instant::now();
+        let slow = transport.send(2, timeout_now(1));
+        let fast = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let response = transport.send(2, timeout_now(2)).await;
+            (response, Instant::now())
+        };
+        let (slow, (fast, answered)) = tokio::join!(slow, fast);
+
+        assert!(answered - start < Duration::from_millis(200));
+        assert!(matches!(fast.unwrap(), RaftResponse::TimeoutNow(r) if r.term == 2));
+        assert!(matches!(slow.unwrap(), RaftResponse::TimeoutNow(r) if r.term == 1));
+        assert_eq!(transport.connections.read().len(), 1);
+    }
+}
@@ -64,6 +64,9 @@ impl EpochOffset {
         offset: 0,
     };
 
+    /// Records one epoch can address
+    pub const EPOCH_CAPACITY: u64 = u32::MAX as u64 + 1;
+
     pub fn new(epoch: Epoch, offset: u32) -> Self {
         EpochOffset { epoch, offset }
     }
@@ -80,11 +83,15 @@ impl EpochOffset {
         (self.epoch.0 << 32) | (self.offset as u64)
     }
 
-    pub fn next(&self) -> Self {
-        EpochOffset {
+    /// The following LSN in the same epoch
+    ///
+    /// Returns `None` once the epoch's 32-bit offsets are exhausted; the
+    /// sequencer must roll to a new epoch instead.
+    pub fn next(&self) -> Option<Self> {
+        Some(EpochOffset {
             epoch: self.epoch,
-            offset: self.offset.saturating_add(1),
-        }
+            offset: self.offset.checked_add(1)?,
+        })
     }
 
     pub fn is_valid(&self) -> bool {
@@ -113,7 +120,8 @@ pub struct EpochMetadata {
     /// Whether this epoch is sealed (no more writes)
     pub sealed: bool,
     
-    /// Last known offset in this epoch
+    /// Last offset written in this epoch; None until it is sealed, and
+    /// for an epoch sealed without any records
     pub last_known_offset: Option<u32>,
 }
 
@@ -129,9 +137,11 @@ impl EpochMetadata {
     }
 
     /// Seal this epoch (no more writes allowed)
-    pub fn seal(&mut self, last_offset: u32) {
+    ///
+    /// `last_offset` is None if the epoch holds no records.
+    pub fn seal(&mut self, last_offset: Option<u32>) {
         self.sealed = true;
-        self.last_known_offset = Some(last_offset);
+        self.last_known_offset = last_offset;
     }
 
     /// Check if this epoch can accept writes
@@ -141,7 +151,11 @@ impl EpochMetadata {
 }
 
 /// Epoch store for tracking epoch metadata
-#[derive(Debug, Clone)]
+///
+/// Epochs are kept in increasing order, and their start offsets are
+/// expected not to decrease, so lookups by epoch or offset are binary
+/// searches.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EpochStore {
     epochs: Vec<EpochMetadata>,
 }
@@ -165,18 +179,43 @@ impl EpochStore {
         epoch
     }
 
+    /// Record an epoch allocated elsewhere, e.g. through consensus
+    ///
+    /// Epochs only ever increase; an epoch not above the current one is
+    /// ignored and false returned.
+    pub fn record_epoch(&mut self, metadata: EpochMetadata) -> bool {
+        if self
+            .current_epoch()
+            .is_some_and(|current| metadata.current_epoch <= current)
+        {
+            return false;
+        }
+
+        self.epochs.push(metadata);
+        true
+    }
+
+    /// Every epoch recorded so far, oldest first
+    pub fn epochs(&self) -> &[EpochMetadata] {
+        &self.epochs
+    }
+
     /// Get metadata for an epoch
     pub fn get_epoch(&self, epoch: Epoch) -> Option<&EpochMetadata> {
-        self.epochs
-            .iter()
-            .find(|e| e.current_epoch == epoch)
+        let index = self.position(epoch)?;
+        Some(&self.epochs[index])
     }
 
     /// Get mutable metadata for an epoch
     pub fn get_epoch_mut(&mut self, epoch: Epoch) -> Option<&mut EpochMetadata> {
+        let index = self.position(epoch)?;
+        Some(&mut self.epochs[index])
+    }
+
+    fn position(&self, epoch: Epoch) -> Option<usize> {
         self.epochs
-            .iter_mut()
-            .find(|e| e.current_epoch == epoch)
+            .binary_search_by_key(&epoch, |e| e.current_epoch)
+            .ok()
     }
 
     /// Get the current (latest) epoch
@@ -185,7 +224,7 @@ impl EpochStore {
     }
 
     /// Seal an epoch
-    pub fn seal_epoch(&mut self, epoch: Epoch, last_offset: u32) -> bool {
+    pub fn seal_epoch(&mut self, epoch: Epoch, last_offset: Option<u32>) -> bool {
         if let Some(metadata) = self.get_epoch_mut(epoch) {
             metadata.seal(last_offset);
             true
@@ -196,19 +235,21 @@ impl EpochStore {
 
     /// Find which epoch contains a global offset
     pub fn epoch_for_offset(&self, global_offset: u64) -> Option<Epoch> {
-        for metadata in self.epochs.iter().rev() {
-            if global_offset >= metadata.start_offset {
-                return Some(metadata.current_epoch);
-            }
-        }
-        None
+        let count = self
+            .epochs
+            .partition_point(|metadata| metadata.start_offset <= global_offset);
+        count
+            .checked_sub(1)
+            .map(|index| self.epochs[index].current_epoch)
     }
 
     /// Convert global offset to epoch offset
+    ///
+    /// Returns `None` if the offset lies beyond what its epoch can address.
     pub fn to_epoch_offset(&self, global_offset: u64) -> Option<EpochOffset> {
         let epoch = self.epoch_for_offset(global_offset)?;
         let metadata = self.get_epoch(epoch)?;
-        let offset_in_epoch = (global_offset - metadata.start_offset) as u32;
+        let offset_in_epoch = u32::try_from(global_offset - metadata.start_offset).ok()?;
         Some(EpochOffset::new(epoch, offset_in_epoch))
     }
 
@@ -217,6 +258,22 @@ impl EpochStore {
         let metadata = self.get_epoch(epoch_offset.epoch)?;
         Some(metadata.start_offset + epoch_offset.offset as u64)
     }
+
+    /// Drop sealed epochs whose records all lie below `low_watermark`
+    ///
+    /// Retention has removed those records, so their epochs can no longer
+    /// be read. The current epoch is always kept. Returns the number of
+    /// epochs dropped.
+    pub fn compact(&mut self, low_watermark: u64) -> usize {
+        let removable = self
+            .epochs
+            .windows(2)
+            .take_while(|pair| pair[0].sealed && pair[1].start_offset <= low_watermark)
+            .count();
+
+        self.epochs.drain(..removable);
+        removable
+    }
 }
 
 impl Default for EpochStore {
@@ -237,6 +294,41 @@ mod tests {
         assert_eq!(e1.next(), e2);
     }
 
+    #[test]
+    fn test_epoch_offset_exhaustion() {
+        let last = EpochOffset::new(Epoch::new(2), u32::MAX);
+        assert_eq!(last.next(), None);
+        assert_eq!(
+            EpochOffset::new(Epoch::new(2), 7).next(),
+            Some(EpochOffset::new(Epoch::new(2), 8))
+        );
+
+        let mut store = EpochStore::new();
+        store.start_epoch(1, 0);
+        assert_eq!(store.to_epoch_offset(EpochOffset::EPOCH_CAPACITY), None);
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut store = EpochStore::new();
+        for start in [0, 100, 200, 300] {
+            let epoch = store.start_epoch(1, start);
+            if start < 300 {
+                store.seal_epoch(epoch, Some(99));
+            }
+        }
+
+        // Epoch 2 still holds records at 150
+        assert_eq!(store.compact(150), 1);
+        assert_eq!(store.epoch_for_offset(50), None);
+        assert_eq!(store.epoch_for_offset(150), Some(Epoch::new(2)));
+
+        // The current epoch survives even when everything is below the mark
+        assert_eq!(store.compact(1000), 2);
+        assert_eq!(store.current_epoch(), Some(Epoch::new(4)));
+        assert_eq!(store.get_epoch(Epoch::new(4)).unwrap().start_offset, 300);
+    }
+
     #[test]
     fn test_epoch_offset() {
         let eo = EpochOffset::new(Epoch::new(5), 100);
@@ -278,10 +370,26 @@ mod tests {
         
         assert!(store.get_epoch(epoch).unwrap().can_write());
         
-        store.seal_epoch(epoch, 999);
+        store.seal_epoch(epoch, Some(999));
         
         assert!(!store.get_epoch(epoch).unwrap().can_write());
         assert_eq!(store.get_epoch(epoch).unwrap().last_known_offset, Some(999));
     }
+
+    #[test]
+    fn test_record_epoch() {
+        let mut store = EpochStore::new();
+
+        assert!(store.record_epoch(EpochMetadata::new(Epoch::new(3), 1, 0)));
+        assert!(store.record_epoch(EpochMetadata::new(Epoch::new(7), 2, 500)));
+
+        // Stale or repeated epochs are refused
+        assert!(!store.record_epoch(EpochMetadata::new(Epoch::new(7), 3, 600)));
+        assert!(!store.record_epoch(EpochMetadata::new(Epoch::new(5), 3, 600)));
+
+        assert_eq!(store.current_epoch(), Some(Epoch::new(7)));
+        assert_eq!(store.get_epoch(Epoch::new(7)).unwrap().sequencer_node, 2);
+        assert_eq!(store.epochs().len(), 2);
+    }
 }
 
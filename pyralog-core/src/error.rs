@@ -1,8 +1,12 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use thiserror::Error;
 
+use crate::epoch::Epoch;
+
 pub type Result<T> = std::result::Result<T, PyralogError>;
 
-#[derive(Error, Debug, Clone)]
+#[derive(Error, Debug, Clone, Serialize, Deserialize)]
 pub enum PyralogError {
     #[error("Log not found: {0}")]
     LogNotFound(String),
@@ -48,6 +52,68 @@ pub enum PyralogError {
 
     #[error("IO error: {0}")]
     IoError(String),
+
+    #[error("Epoch {0} is sealed")]
+    EpochSealed(Epoch),
+
+    /// `NotLeader` with the leader's client address
+    #[error("Not leader: current leader is {0}")]
+    NotLeaderHinted(LeaderHint),
+}
+
+impl PyralogError {
+    /// Error for a request sent to a node that does not lead
+    ///
+    /// Only a hint carrying an address needs `NotLeaderHinted`; a bare node
+    /// ID keeps the encoding older clients decode.
+    pub fn not_leader(hint: Option<LeaderHint>) -> Self {
+        match hint {
+            Some(hint) if hint.address.is_some() => PyralogError::NotLeaderHinted(hint),
+            hint => PyralogError::NotLeader(hint.map(|hint| hint.node_id)),
+        }
+    }
+
+    /// Where to retry a request that failed with `NotLeader` or
+    /// `NotLeaderHinted`
+    pub fn leader_hint(&self) -> Option<LeaderHint> {
+        match self {
+            PyralogError::NotLeader(leader) => leader.map(LeaderHint::new),
+            PyralogError::NotLeaderHinted(hint) => Some(hint.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Where to retry a request sent to a node that does not lead
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeaderHint {
+    pub node_id: u64,
+
+    /// Client address of the leader, if the node that answered knows it
+    pub address: Option<String>,
+}
+
+impl LeaderHint {
+    pub fn new(node_id: u64) -> Self {
+        Self {
+            node_id,
+            address: None,
+        }
+    }
+
+    pub fn with_address(mut self, address: impl Into<String>) -> Self {
+        self.address = Some(address.into());
+        self
+    }
+}
+
+impl fmt::Display for LeaderHint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.address {
+            Some(address) => write!(f, "node {} at {}", self.node_id, address),
+            None => write!(f, "node {}", self.node_id),
+        }
+    }
 }
 
 impl From<std::io::Error> for PyralogError {
@@ -56,3 +122,33 @@ impl From<std::io::Error> for PyralogError {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variant_tags_are_stable() {
+        // Errors travel inside responses, so new variants go at the end
+        let tag = |error: PyralogError| bincode::serialize(&error).unwrap()[..4].to_vec();
+        assert_eq!(tag(PyralogError::Timeout), 12u32.to_le_bytes());
+        assert_eq!(tag(PyralogError::IoError(String::new())), 14u32.to_le_bytes());
+        assert_eq!(tag(PyralogError::EpochSealed(Epoch::new(1))), 15u32.to_le_bytes());
+        assert_eq!(tag(PyralogError::NotLeaderHinted(LeaderHint::new(2))), 16u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_not_leader_keeps_its_encoding() {
+        // Encoded as before hints carried addresses
+        let encoded = bincode::serialize(&PyralogError::not_leader(Some(LeaderHint::new(2)))).unwrap();
+        let mut expected = 10u32.to_le_bytes().to_vec();
+        expected.push(1);
+        expected.extend_from_slice(&2u64.to_le_bytes());
+        assert_eq!(encoded, expected);
+
+        let hint = LeaderHint::new(2).with_address("10.0.0.2:9092");
+        let error = PyralogError::not_leader(Some(hint.clone()));
+        let decoded: PyralogError = bincode::deserialize(&bincode::serialize(&error).unwrap()).unwrap();
+        assert_eq!(decoded.leader_hint(), Some(hint));
+    }
+}
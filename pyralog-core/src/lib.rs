@@ -10,12 +10,14 @@ pub mod sequencer;
 pub mod record;
 pub mod partition;
 pub mod traits;
+pub mod merkle;
 
-pub use error::{PyralogError, Result};
-pub use log::{LogId, LogMetadata};
+pub use error::{LeaderHint, PyralogError, Result};
+pub use log::{LogId, LogMetadata, LogConfig, RetentionPolicy};
 pub use offset::{LogOffset, OffsetRange};
 pub use epoch::{Epoch, EpochOffset, EpochMetadata, EpochStore};
-pub use sequencer::Sequencer;
-pub use record::{Record, RecordBatch, RecordHeader};
+pub use sequencer::{EpochLog, MemoryEpochLog, Sequencer};
+pub use record::{GapKind, Record, RecordBatch, RecordHeader, GAP_HEADER};
 pub use partition::{Partition, PartitionId};
+pub use merkle::{InclusionProof, MerkleRoot};
 
@@ -48,6 +48,12 @@ pub struct LogConfig {
     
     /// Enable tiered storage
     pub tiered_storage_enabled: bool,
+
+    /// Header keys to maintain secondary indexes on
+    pub indexed_headers: Vec<String>,
+
+    /// Maintain an index of the latest record per key
+    pub key_index_enabled: bool,
 }
 
 impl Default for LogConfig {
@@ -57,6 +63,8 @@ impl Default for LogConfig {
             flush_interval_ms: 1000,           // 1 second
             compression_enabled: true,
             tiered_storage_enabled: false,
+            indexed_headers: Vec::new(),
+            key_index_enabled: false,
         }
     }
 }
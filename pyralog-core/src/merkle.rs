@@ -0,0 +1,228 @@
+use serde::{Deserialize, Serialize};
+
+use crate::offset::LogOffset;
+use crate::record::Record;
+
+/// A BLAKE3 digest
+pub type Hash = [u8; 32];
+
+/// Chain hash before the first segment of a partition
+pub const GENESIS_CHAIN: Hash = [0u8; 32];
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+const CHAIN_PREFIX: u8 = 0x02;
+
+/// Hash a stored record as a Merkle leaf
+pub fn leaf_hash(data: &[u8]) -> Hash {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[LEAF_PREFIX]);
+    hasher.update(data);
+    *hasher.finalize().as_bytes()
+}
+
+/// Hash two child nodes into their parent
+pub fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    *hasher.finalize().as_bytes()
+}
+
+/// Chain a segment root onto the roots of all previous segments
+pub fn chain_hash(prev_chain: &Hash, segment_root: &Hash) -> Hash {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[CHAIN_PREFIX]);
+    hasher.update(prev_chain);
+    hasher.update(segment_root);
+    *hasher.finalize().as_bytes()
+}
+
+/// Largest power of two strictly less than `n` (n > 1)
+fn split_point(n: usize) -> usize {
+    let mut k = 1;
+    while k << 1 < n {
+        k <<= 1;
+    }
+    k
+}
+
+/// Merkle tree hash over a list of leaf hashes
+///
+/// Uses the RFC 6962 tree shape, so the root of any prefix of a segment is
+/// well defined and proofs stay valid as the segment grows.
+pub fn merkle_root(leaves: &[Hash]) -> Hash {
+    match leaves.len() {
+        0 => *blake3::hash(&[]).as_bytes(),
+        1 => leaves[0],
+        n => {
+            let k = split_point(n);
+            node_hash(&merkle_root(&leaves[..k]), &merkle_root(&leaves[k..]))
+        }
+    }
+}
+
+/// Audit path for the leaf at `index`, deepest sibling first
+pub fn audit_path(index: usize, leaves: &[Hash]) -> Vec<Hash> {
+    let n = leaves.len();
+    if n <= 1 {
+        return Vec::new();
+    }
+
+    let k = split_point(n);
+    if index < k {
+        let mut path = audit_path(index, &leaves[..k]);
+        path.push(merkle_root(&leaves[k..]));
+        path
+    } else {
+        let mut path = audit_path(index - k, &leaves[k..]);
+        path.push(merkle_root(&leaves[..k]));
+        path
+    }
+}
+
+/// Recompute a tree root from a leaf and its audit path
+pub fn root_from_path(index: u64, tree_size: u64, leaf: Hash, path: &[Hash]) -> Option<Hash> {
+    if index >= tree_size {
+        return None;
+    }
+    if tree_size == 1 {
+        return path.is_empty().then_some(leaf);
+    }
+
+    let (sibling, rest) = path.split_last()?;
+    let k = split_point(tree_size as usize) as u64;
+    if index < k {
+        let left = root_from_path(index, k, leaf, rest)?;
+        Some(node_hash(&left, sibling))
+    } else {
+        let right = root_from_path(index - k, tree_size - k, leaf, rest)?;
+        Some(node_hash(sibling, &right))
+    }
+}
+
+/// Chained Merkle root of a partition covering every record up to `offset`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleRoot {
+    /// Last offset covered by this root
+    pub offset: LogOffset,
+
+    /// Chain hash over all segment roots up to `offset`
+    pub hash: Hash,
+}
+
+/// Proof that a record is included under a `MerkleRoot`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InclusionProof {
+    /// Offset of the proven record
+    pub offset: LogOffset,
+
+    /// Position of the record within its segment tree
+    pub leaf_index: u64,
+
+    /// Number of leaves of the segment tree the path was computed against
+    pub tree_size: u64,
+
+    /// Sibling hashes from the leaf up to the segment root
+    pub path: Vec<Hash>,
+
+    /// Chain hash of all segments before the record's segment
+    pub prev_chain: Hash,
+
+    /// Roots of the segments after the record's segment, up to the root offset
+    pub later_segment_roots: Vec<Hash>,
+
+    /// Offset of the root this proof is against
+    pub root_offset: LogOffset,
+}
+
+impl InclusionProof {
+    /// Compute the chained root implied by this proof for a leaf hash
+    pub fn chained_root(&self, leaf: Hash) -> Option<Hash> {
+        let segment_root = root_from_path(self.leaf_index, self.tree_size, leaf, &self.path)?;
+
+        let chain = self
+            .later_segment_roots
+            .iter()
+            .fold(chain_hash(&self.prev_chain, &segment_root), |chain, root| {
+                chain_hash(&chain, root)
+            });
+
+        Some(chain)
+    }
+
+    /// Verify the stored encoding of a record against a trusted root
+    pub fn verify(&self, record_bytes: &[u8], root: &MerkleRoot) -> bool {
+        root.offset == self.root_offset
+            && self.chained_root(leaf_hash(record_bytes)) == Some(root.hash)
+    }
+
+    /// Verify a record against a trusted root
+    pub fn verify_record(&self, record: &Record, root: &MerkleRoot) -> bool {
+        if record.offset != self.offset {
+            return false;
+        }
+
+        match bincode::serialize(record) {
+            Ok(bytes) => self.verify(&bytes, root),
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(n: usize) -> Vec<Hash> {
+        (0..n).map(|i| leaf_hash(format!("record-{}", i).as_bytes())).collect()
+    }
+
+    #[test]
+    fn test_audit_path_roundtrip() {
+        for n in 1..20 {
+            let leaves = leaves(n);
+            let root = merkle_root(&leaves);
+
+            for (i, leaf) in leaves.iter().enumerate() {
+                let path = audit_path(i, &leaves);
+                assert_eq!(root_from_path(i as u64, n as u64, *leaf, &path), Some(root));
+            }
+        }
+    }
+
+    #[test]
+    fn test_tampered_leaf_fails() {
+        let leaves = leaves(7);
+        let root = merkle_root(&leaves);
+        let path = audit_path(3, &leaves);
+
+        let forged = leaf_hash(b"forged");
+        assert_ne!(root_from_path(3, 7, forged, &path), Some(root));
+    }
+
+    #[test]
+    fn test_chained_proof() {
+        let first = leaves(4);
+        let second = leaves(3);
+        let prev_chain = chain_hash(&GENESIS_CHAIN, &merkle_root(&first));
+        let root = MerkleRoot {
+            offset: LogOffset::new(6),
+            hash: chain_hash(&prev_chain, &merkle_root(&second)),
+        };
+
+        let proof = InclusionProof {
+            offset: LogOffset::new(5),
+            leaf_index: 1,
+            tree_size: 3,
+            path: audit_path(1, &second),
+            prev_chain,
+            later_segment_roots: Vec::new(),
+            root_offset: LogOffset::new(6),
+        };
+
+        assert!(proof.verify(b"record-1", &root));
+        assert!(!proof.verify(b"record-2", &root));
+    }
+}
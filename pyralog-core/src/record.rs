@@ -6,7 +6,7 @@ use crate::offset::LogOffset;
 use crate::epoch::Epoch;
 
 /// A single log record
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Record {
     /// Unique offset of this record in the log
     pub offset: LogOffset,
@@ -52,10 +52,68 @@ impl Record {
     pub fn size_bytes(&self) -> usize {
         self.key.as_ref().map_or(0, |k| k.len()) + self.value.len()
     }
+
+    /// Marker for an offset that holds no client data
+    ///
+    /// The kind is carried in the reserved `GAP_HEADER`, so markers are
+    /// stored in the same encoding as every other record.
+    pub fn gap(kind: GapKind) -> Self {
+        Record::new(None, Bytes::new()).with_headers(vec![RecordHeader::new(
+            GAP_HEADER.to_string(),
+            Bytes::from_static(kind.as_str().as_bytes()),
+        )])
+    }
+
+    /// The kind of gap this record marks, or None for client data
+    pub fn gap_kind(&self) -> Option<GapKind> {
+        let header = self.headers.iter().find(|header| header.key == GAP_HEADER)?;
+        GapKind::parse(&header.value)
+    }
+
+    /// Check whether the record carries the reserved `GAP_HEADER`
+    ///
+    /// Only epoch recovery writes markers; appends of client data must not
+    /// carry the header, or readers would skip them.
+    pub fn has_gap_header(&self) -> bool {
+        self.headers.iter().any(|header| header.key == GAP_HEADER)
+    }
+}
+
+/// Header carried by gap markers, reserved for epoch recovery
+pub const GAP_HEADER: &str = "pyralog.gap";
+
+/// Why an offset holds no client data
+///
+/// Written by epoch recovery so readers can tell a record that was never
+/// durably written from one they have yet to receive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GapKind {
+    /// The record was lost before being acknowledged; skip it
+    Hole,
+
+    /// The epoch ends here; continue with the next epoch
+    Bridge,
+}
+
+impl GapKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            GapKind::Hole => "hole",
+            GapKind::Bridge => "bridge",
+        }
+    }
+
+    fn parse(value: &[u8]) -> Option<Self> {
+        match value {
+            b"hole" => Some(GapKind::Hole),
+            b"bridge" => Some(GapKind::Bridge),
+            _ => None,
+        }
+    }
 }
 
 /// Record header for metadata
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct RecordHeader {
     pub key: String,
     pub value: Bytes,
@@ -1,69 +1,235 @@
-use crate::epoch::{Epoch, EpochStore};
+use crate::epoch::{Epoch, EpochOffset, EpochStore};
+use crate::error::{PyralogError, Result};
+use crate::log::LogId;
 use crate::partition::PartitionId;
+use async_trait::async_trait;
 use parking_lot::RwLock;
 use std::collections::HashMap;
 use std::sync::Arc;
 
+/// Where a sequencer allocates and seals epochs
+///
+/// Epochs are only unique if every sequencer of a partition allocates them
+/// from the same place. In a cluster that is a replicated, durable record,
+/// such as a Raft group, so a node taking over can never reuse an epoch and
+/// a restarted node recovers the partition's epoch history.
+#[async_trait]
+pub trait EpochLog: Send + Sync {
+    /// Allocate the partition's next epoch to `sequencer_node`
+    ///
+    /// Resolves once the epoch is durable.
+    async fn start_epoch(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        sequencer_node: u64,
+        start_offset: u64,
+    ) -> Result<Epoch>;
+
+    /// Mark an epoch as closed to writes, ending at `last_offset`, or
+    /// holding nothing if it is None
+    async fn seal_epoch(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        epoch: Epoch,
+        last_offset: Option<u32>,
+    ) -> Result<()>;
+
+    /// Drop sealed epochs of the partition whose records all lie below
+    /// `low_watermark`; returns how many were dropped
+    async fn compact_epochs(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        low_watermark: u64,
+    ) -> Result<usize>;
+
+    /// The partition's epochs as currently known to this node
+    fn epochs(&self, log_id: &LogId, partition: PartitionId) -> Option<EpochStore>;
+}
+
+/// Epochs held in process memory
+///
+/// Sequencers sharing one `MemoryEpochLog` get unique epochs, but nothing
+/// survives a restart. For tests and single-process setups.
+#[derive(Clone, Default)]
+pub struct MemoryEpochLog {
+    partitions: Arc<RwLock<HashMap<(LogId, PartitionId), EpochStore>>>,
+}
+
+impl MemoryEpochLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl EpochLog for MemoryEpochLog {
+    async fn start_epoch(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        sequencer_node: u64,
+        start_offset: u64,
+    ) -> Result<Epoch> {
+        let mut partitions = self.partitions.write();
+        let store = partitions.entry((log_id.clone(), partition)).or_default();
+        Ok(store.start_epoch(sequencer_node, start_offset))
+    }
+
+    async fn seal_epoch(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        epoch: Epoch,
+        last_offset: Option<u32>,
+    ) -> Result<()> {
+        let sealed = self
+            .partitions
+            .write()
+            .get_mut(&(log_id.clone(), partition))
+            .is_some_and(|store| store.seal_epoch(epoch, last_offset));
+        if !sealed {
+            return Err(PyralogError::InvalidRequest(format!(
+                "Unknown epoch {} of {} partition {}",
+                epoch, log_id, partition
+            )));
+        }
+        Ok(())
+    }
+
+    async fn compact_epochs(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        low_watermark: u64,
+    ) -> Result<usize> {
+        Ok(self
+            .partitions
+            .write()
+            .get_mut(&(log_id.clone(), partition))
+            .map_or(0, |store| store.compact(low_watermark)))
+    }
+
+    fn epochs(&self, log_id: &LogId, partition: PartitionId) -> Option<EpochStore> {
+        self.partitions.read().get(&(log_id.clone(), partition)).cloned()
+    }
+}
+
 /// Sequencer manages epoch numbers and record ordering for partitions
-/// 
+///
 /// Inspired by LogDevice's sequencer concept. The sequencer is responsible for:
 /// - Assigning epoch numbers to partitions
 /// - Sealing old epochs during failover
 /// - Ensuring no duplicate writes during recovery
+///
+/// Epochs are allocated and sealed through an `EpochLog`, which the
+/// sequencers of every node share.
 #[derive(Clone)]
 pub struct Sequencer {
     node_id: u64,
-    partition_epochs: Arc<RwLock<HashMap<PartitionId, EpochStore>>>,
+    epochs: Arc<dyn EpochLog>,
 }
 
 impl Sequencer {
-    pub fn new(node_id: u64) -> Self {
-        Self {
-            node_id,
-            partition_epochs: Arc::new(RwLock::new(HashMap::new())),
-        }
+    pub fn new(node_id: u64, epochs: Arc<dyn EpochLog>) -> Self {
+        Self { node_id, epochs }
     }
 
     /// Activate sequencer for a partition (become leader)
-    pub fn activate(&self, partition: PartitionId, start_offset: u64) -> Epoch {
-        let mut epochs = self.partition_epochs.write();
-        let store = epochs.entry(partition).or_insert_with(EpochStore::new);
-        store.start_epoch(self.node_id, start_offset)
+    pub async fn activate(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        start_offset: u64,
+    ) -> Result<Epoch> {
+        self.epochs
+            .start_epoch(log_id, partition, self.node_id, start_offset)
+            .await
     }
 
     /// Get current epoch for a partition
-    pub fn current_epoch(&self, partition: PartitionId) -> Option<Epoch> {
-        self.partition_epochs
-            .read()
-            .get(&partition)
+    pub fn current_epoch(&self, log_id: &LogId, partition: PartitionId) -> Option<Epoch> {
+        self.epochs
+            .epochs(log_id, partition)
             .and_then(|store| store.current_epoch())
     }
 
     /// Seal an epoch (during failover or graceful shutdown)
-    pub fn seal_epoch(&self, partition: PartitionId, epoch: Epoch, last_offset: u32) -> bool {
-        self.partition_epochs
-            .write()
-            .get_mut(&partition)
-            .map(|store| store.seal_epoch(epoch, last_offset))
-            .unwrap_or(false)
+    pub async fn seal_epoch(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        epoch: Epoch,
+        last_offset: Option<u32>,
+    ) -> Result<()> {
+        self.epochs.seal_epoch(log_id, partition, epoch, last_offset).await
+    }
+
+    /// Epoch to write `count` records in, the first at global offset
+    /// `next_offset`
+    ///
+    /// Offsets within an epoch are 32 bits wide. When the records would
+    /// not fit in `epoch`, it is sealed after its last record and a new
+    /// epoch starts at `next_offset`.
+    pub async fn reserve(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        epoch: Epoch,
+        next_offset: u64,
+        count: u64,
+    ) -> Result<Epoch> {
+        if count > EpochOffset::EPOCH_CAPACITY {
+            return Err(PyralogError::InvalidRequest(format!(
+                "{} records do not fit in one epoch",
+                count
+            )));
+        }
+
+        let start_offset = self
+            .get_epoch_store(log_id, partition)
+            .and_then(|store| store.get_epoch(epoch).map(|metadata| metadata.start_offset))
+            .ok_or_else(|| {
+                PyralogError::InvalidRequest(format!(
+                    "Unknown epoch {} of {} partition {}",
+                    epoch, log_id, partition
+                ))
+            })?;
+        let used = next_offset.saturating_sub(start_offset);
+        if used + count <= EpochOffset::EPOCH_CAPACITY {
+            return Ok(epoch);
+        }
+
+        let last_offset = used.checked_sub(1).map(|last_offset| last_offset as u32);
+        self.seal_epoch(log_id, partition, epoch, last_offset).await?;
+        self.activate(log_id, partition, next_offset).await
+    }
+
+    /// Drop sealed epochs of a partition that retention has moved past
+    pub async fn compact_epochs(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        low_watermark: u64,
+    ) -> Result<usize> {
+        self.epochs.compact_epochs(log_id, partition, low_watermark).await
     }
 
     /// Check if sequencer can write to this epoch
-    pub fn can_write(&self, partition: PartitionId, epoch: Epoch) -> bool {
-        self.partition_epochs
-            .read()
-            .get(&partition)
-            .and_then(|store| store.get_epoch(epoch))
-            .map(|metadata| metadata.can_write())
-            .unwrap_or(false)
+    ///
+    /// Only the node the epoch was allocated to may write to it.
+    pub fn can_write(&self, log_id: &LogId, partition: PartitionId, epoch: Epoch) -> bool {
+        self.epochs
+            .epochs(log_id, partition)
+            .and_then(|store| store.get_epoch(epoch).cloned())
+            .is_some_and(|metadata| metadata.sequencer_node == self.node_id && metadata.can_write())
     }
 
     /// Get the epoch store for a partition
-    pub fn get_epoch_store(&self, partition: PartitionId) -> Option<EpochStore> {
-        self.partition_epochs
-            .read()
-            .get(&partition)
-            .cloned()
+    pub fn get_epoch_store(&self, log_id: &LogId, partition: PartitionId) -> Option<EpochStore> {
+        self.epochs.epochs(log_id, partition)
     }
 }
 
@@ -71,45 +237,73 @@ impl Sequencer {
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_sequencer_activation() {
-        let sequencer = Sequencer::new(1);
+    #[tokio::test]
+    async fn test_sequencer_activation() {
+        let sequencer = Sequencer::new(1, Arc::new(MemoryEpochLog::new()));
+        let log_id = LogId::new("default", "events");
         let partition = PartitionId::new(0);
 
-        let epoch = sequencer.activate(partition, 0);
+        let epoch = sequencer.activate(&log_id, partition, 0).await.unwrap();
         assert_eq!(epoch, Epoch::FIRST);
-        assert_eq!(sequencer.current_epoch(partition), Some(epoch));
+        assert_eq!(sequencer.current_epoch(&log_id, partition), Some(epoch));
     }
 
-    #[test]
-    fn test_epoch_sealing() {
-        let sequencer = Sequencer::new(1);
+    #[tokio::test]
+    async fn test_epoch_sealing() {
+        let sequencer = Sequencer::new(1, Arc::new(MemoryEpochLog::new()));
+        let log_id = LogId::new("default", "events");
         let partition = PartitionId::new(0);
 
-        let epoch = sequencer.activate(partition, 0);
-        assert!(sequencer.can_write(partition, epoch));
+        let epoch = sequencer.activate(&log_id, partition, 0).await.unwrap();
+        assert!(sequencer.can_write(&log_id, partition, epoch));
 
-        sequencer.seal_epoch(partition, epoch, 999);
-        assert!(!sequencer.can_write(partition, epoch));
+        sequencer.seal_epoch(&log_id, partition, epoch, Some(999)).await.unwrap();
+        assert!(!sequencer.can_write(&log_id, partition, epoch));
     }
 
-    #[test]
-    fn test_failover() {
-        let sequencer1 = Sequencer::new(1);
-        let sequencer2 = Sequencer::new(2);
+    #[tokio::test]
+    async fn test_failover() {
+        let epochs = Arc::new(MemoryEpochLog::new());
+        let sequencer1 = Sequencer::new(1, epochs.clone());
+        let sequencer2 = Sequencer::new(2, epochs);
+        let log_id = LogId::new("default", "events");
         let partition = PartitionId::new(0);
 
         // Node 1 activates
-        let epoch1 = sequencer1.activate(partition, 0);
+        let epoch1 = sequencer1.activate(&log_id, partition, 0).await.unwrap();
         assert_eq!(epoch1, Epoch::FIRST);
 
         // Node 1 seals its epoch
-        sequencer1.seal_epoch(partition, epoch1, 999);
+        sequencer1.seal_epoch(&log_id, partition, epoch1, Some(999)).await.unwrap();
 
         // Node 2 activates with new epoch
-        let epoch2 = sequencer2.activate(partition, 1000);
+        let epoch2 = sequencer2.activate(&log_id, partition, 1000).await.unwrap();
         assert_eq!(epoch2, Epoch::new(2));
         assert!(epoch2 > epoch1);
+
+        // Both see the same history, and only node 2 may write
+        assert_eq!(sequencer1.current_epoch(&log_id, partition), Some(epoch2));
+        assert!(!sequencer1.can_write(&log_id, partition, epoch2));
+        assert!(sequencer2.can_write(&log_id, partition, epoch2));
     }
-}
 
+    #[tokio::test]
+    async fn test_roll_before_offsets_run_out() {
+        let sequencer = Sequencer::new(1, Arc::new(MemoryEpochLog::new()));
+        let log_id = LogId::new("default", "events");
+        let partition = PartitionId::new(0);
+        let capacity = EpochOffset::EPOCH_CAPACITY;
+
+        let epoch = sequencer.activate(&log_id, partition, 10).await.unwrap();
+        assert_eq!(sequencer.reserve(&log_id, partition, epoch, 10 + capacity - 2, 2).await.unwrap(), epoch);
+
+        // One more record than fits rolls over, sealing the full epoch
+        let next = sequencer.reserve(&log_id, partition, epoch, 10 + capacity - 2, 3).await.unwrap();
+        assert!(next > epoch);
+        assert!(!sequencer.can_write(&log_id, partition, epoch));
+
+        let store = sequencer.get_epoch_store(&log_id, partition).unwrap();
+        assert_eq!(store.get_epoch(epoch).unwrap().last_known_offset, Some(u32::MAX - 2));
+        assert_eq!(store.get_epoch(next).unwrap().start_offset, 10 + capacity - 2);
+    }
+}
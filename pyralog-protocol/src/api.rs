@@ -1,6 +1,9 @@
 use async_trait::async_trait;
 use bytes::Bytes;
-use pyralog_core::{LogId, LogOffset, PartitionId, Record, Result};
+use pyralog_core::{
+    Epoch, EpochOffset, GapKind, InclusionProof, LogId, LogOffset, MerkleRoot, OffsetRange,
+    PartitionId, Record, Result,
+};
 use serde::{Deserialize, Serialize};
 
 /// Request to produce records to a log
@@ -54,10 +57,44 @@ pub struct ConsumeRequest {
 pub struct ConsumeResponse {
     pub partition: PartitionId,
     pub high_watermark: LogOffset,
+
+    /// Records from the requested offset on, without skipping any;
+    /// offsets whose records were lost hold gap markers, which
+    /// `Record::gap_kind` identifies
     pub records: Vec<Record>,
     pub error: Option<String>,
 }
 
+/// Request to consume records starting at an LSN
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LsnConsumeRequest {
+    pub log_id: LogId,
+    pub partition: PartitionId,
+    pub lsn: EpochOffset,
+    pub max_records: usize,
+}
+
+/// Response to an LSN consume request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LsnConsumeResponse {
+    pub partition: PartitionId,
+    pub high_watermark: LogOffset,
+    pub events: Vec<ReadEvent>,
+}
+
+/// One step of an LSN-addressed read stream
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReadEvent {
+    /// A record and the LSN its sequencer wrote it at
+    Record { lsn: EpochOffset, record: Record },
+
+    /// An LSN holding no client data, written by epoch recovery
+    Gap { lsn: EpochOffset, kind: GapKind },
+
+    /// The records of `from` end; those of `to` follow
+    EpochBoundary { from: Epoch, to: Epoch },
+}
+
 /// Create log request
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateLogRequest {
@@ -66,6 +103,98 @@ pub struct CreateLogRequest {
     pub replication_factor: u32,
 }
 
+/// Indexes a new log maintains, sent along with its `CreateLogRequest`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LogIndexOptions {
+    /// Header keys to maintain secondary indexes on
+    pub indexed_headers: Vec<String>,
+    /// Maintain an index of the latest record per key
+    pub key_index: bool,
+}
+
+/// Request for the Merkle root of a partition at an offset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MerkleRootRequest {
+    pub log_id: LogId,
+    pub partition: PartitionId,
+    pub offset: LogOffset,
+}
+
+/// Request for a proof that a record is included under a Merkle root
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InclusionProofRequest {
+    pub log_id: LogId,
+    pub partition: PartitionId,
+    pub offset: LogOffset,
+    pub root_offset: LogOffset,
+}
+
+/// Request for records with a given header value
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeaderQueryRequest {
+    pub log_id: LogId,
+    pub partition: PartitionId,
+    pub header_key: String,
+    pub header_value: Bytes,
+    pub range: OffsetRange,
+    /// Return full records instead of only offsets
+    pub include_records: bool,
+}
+
+/// Response to a header query
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeaderQueryResponse {
+    pub partition: PartitionId,
+    pub offsets: Vec<LogOffset>,
+    pub records: Vec<Record>,
+}
+
+/// Request for the latest record with a given key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetLatestRequest {
+    pub log_id: LogId,
+    /// Partition holding the key; derived from the key if not set
+    pub partition: Option<PartitionId>,
+    pub key: Bytes,
+}
+
+/// Response to a latest-value lookup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetLatestResponse {
+    pub partition: PartitionId,
+    pub record: Option<Record>,
+}
+
+/// How current a metadata read must be
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReadConsistency {
+    /// Answer from the receiving node's metadata, which may be stale
+    #[default]
+    Local,
+
+    /// Reflect every metadata change committed before the request
+    Linearizable,
+}
+
+/// A change to the members of the metadata Raft group
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MembershipChange {
+    /// Add a node reachable at its internal cluster address
+    AddVoter { node_id: u64, address: String },
+
+    /// Remove a node
+    RemoveVoter { node_id: u64 },
+
+    /// Add a node that replicates metadata without voting
+    AddLearner { node_id: u64, address: String },
+
+    /// Turn a caught-up learner into a voter
+    PromoteLearner { node_id: u64 },
+
+    /// Remove a learner
+    RemoveLearner { node_id: u64 },
+}
+
 /// Protocol handler trait
 #[async_trait]
 pub trait ProtocolHandler: Send + Sync {
@@ -75,13 +204,37 @@ pub trait ProtocolHandler: Send + Sync {
     /// Handle consume request
     async fn consume(&self, request: ConsumeRequest) -> Result<ConsumeResponse>;
 
+    /// Consume records starting at an LSN, with gaps and epoch boundaries
+    async fn consume_lsn(&self, request: LsnConsumeRequest) -> Result<LsnConsumeResponse>;
+
     /// Create a new log
     async fn create_log(&self, request: CreateLogRequest) -> Result<()>;
 
+    /// Create a new log that maintains the given indexes
+    async fn create_log_with(&self, request: CreateLogRequest, indexes: LogIndexOptions) -> Result<()>;
+
     /// Delete a log
     async fn delete_log(&self, log_id: LogId) -> Result<()>;
 
     /// List all logs
-    async fn list_logs(&self) -> Result<Vec<LogId>>;
+    async fn list_logs(&self, consistency: ReadConsistency) -> Result<Vec<LogId>>;
+
+    /// Get the Merkle root of a partition at an offset
+    async fn merkle_root(&self, request: MerkleRootRequest) -> Result<Option<MerkleRoot>>;
+
+    /// Get an inclusion proof for a record
+    async fn inclusion_proof(&self, request: InclusionProofRequest) -> Result<Option<InclusionProof>>;
+
+    /// Find records by header value
+    async fn query_headers(&self, request: HeaderQueryRequest) -> Result<HeaderQueryResponse>;
+
+    /// Get the latest record for a key
+    async fn get_latest(&self, request: GetLatestRequest) -> Result<GetLatestResponse>;
+
+    /// Add or remove a cluster member
+    async fn change_membership(&self, change: MembershipChange) -> Result<()>;
+
+    /// Move metadata leadership to another node, e.g. before maintenance
+    async fn transfer_leadership(&self, target: u64) -> Result<()>;
 }
 
@@ -0,0 +1,55 @@
+//! Length-prefixed framing for requests and responses on a stream
+//!
+//! Each frame is a little-endian `u32` payload length followed by the
+//! bincode-encoded `Request` or `Response`.
+
+use bytes::Bytes;
+use pyralog_core::{Result, PyralogError};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Largest frame accepted from a peer
+pub const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+/// Read one frame, returning `None` if the peer closed the stream cleanly
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<Bytes>> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(PyralogError::NetworkError(e.to_string())),
+    }
+
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(PyralogError::InvalidRequest(format!("Frame too large: {} bytes", len)));
+    }
+
+    let mut payload = vec![0u8; len];
+    reader
+        .read_exact(&mut payload)
+        .await
+        .map_err(|e| PyralogError::NetworkError(e.to_string()))?;
+
+    Ok(Some(Bytes::from(payload)))
+}
+
+/// Write the length prefix of a frame whose payload is written separately
+pub async fn write_frame_len<W: AsyncWrite + Unpin>(writer: &mut W, len: usize) -> Result<()> {
+    let len = u32::try_from(len)
+        .map_err(|_| PyralogError::InvalidRequest(format!("Frame too large: {} bytes", len)))?;
+
+    writer
+        .write_all(&len.to_le_bytes())
+        .await
+        .map_err(|e| PyralogError::NetworkError(e.to_string()))
+}
+
+/// Write one frame
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, payload: &[u8]) -> Result<()> {
+    write_frame_len(writer, payload.len()).await?;
+
+    writer
+        .write_all(payload)
+        .await
+        .map_err(|e| PyralogError::NetworkError(e.to_string()))
+}
@@ -88,6 +88,8 @@ impl From<&pyralog_core::PyralogError> for KafkaErrorCode {
             pyralog_core::PyralogError::LogNotFound(_) => KafkaErrorCode::UnknownTopicOrPartition,
             pyralog_core::PyralogError::LeaderNotAvailable => KafkaErrorCode::LeaderNotAvailable,
             pyralog_core::PyralogError::NotLeader(_) => KafkaErrorCode::NotLeaderForPartition,
+            pyralog_core::PyralogError::NotLeaderHinted(_) => KafkaErrorCode::NotLeaderForPartition,
+            pyralog_core::PyralogError::EpochSealed(_) => KafkaErrorCode::NotLeaderForPartition,
             pyralog_core::PyralogError::Timeout => KafkaErrorCode::RequestTimedOut,
             pyralog_core::PyralogError::QuorumNotAvailable => KafkaErrorCode::NotEnoughReplicas,
             _ => KafkaErrorCode::NetworkException,
@@ -7,6 +7,7 @@ pub mod api;
 pub mod partitioner;
 pub mod request;
 pub mod response;
+pub mod frame;
 
 pub use api::{ProtocolHandler, ProduceRequest, ConsumeRequest, ProduceResponse, ConsumeResponse};
 pub use partitioner::{Partitioner, PartitionStrategy};
@@ -10,6 +10,20 @@ pub enum Request {
     CreateLog(crate::api::CreateLogRequest),
     DeleteLog(pyralog_core::LogId),
     ListLogs,
+    MerkleRoot(crate::api::MerkleRootRequest),
+    InclusionProof(crate::api::InclusionProofRequest),
+    QueryHeaders(crate::api::HeaderQueryRequest),
+    GetLatest(crate::api::GetLatestRequest),
+    ChangeMembership(crate::api::MembershipChange),
+    TransferLeadership(u64),
+    ConsumeLsn(crate::api::LsnConsumeRequest),
+
+    /// `ListLogs` at a chosen read consistency
+    ListLogsWith(crate::api::ReadConsistency),
+
+    /// `CreateLog` for a log that maintains indexes; answered with
+    /// `Response::CreateLog`
+    CreateLogWith(crate::api::CreateLogRequest, crate::api::LogIndexOptions),
 }
 
 impl Request {
@@ -27,3 +41,44 @@ impl Request {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{CreateLogRequest, LogIndexOptions, ReadConsistency};
+    use pyralog_core::LogId;
+
+    #[test]
+    fn test_variant_tags_are_stable() {
+        // New variants go at the end so older peers still decode these
+        let encoded = Request::ListLogs.to_bytes().unwrap();
+        assert_eq!(encoded[..], 4u32.to_le_bytes());
+
+        let encoded = Request::ListLogsWith(ReadConsistency::Linearizable).to_bytes().unwrap();
+        assert_eq!(encoded[..4], 12u32.to_le_bytes());
+
+        let request = CreateLogRequest {
+            log_id: LogId::new("default", "events"),
+            partition_count: 1,
+            replication_factor: 1,
+        };
+        let encoded = Request::CreateLog(request.clone()).to_bytes().unwrap();
+        assert_eq!(encoded[..4], 2u32.to_le_bytes());
+        let encoded = Request::CreateLogWith(request, LogIndexOptions::default()).to_bytes().unwrap();
+        assert_eq!(encoded[..4], 13u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_create_log_request_keeps_its_encoding() {
+        // Older clients send exactly these fields
+        let request = CreateLogRequest {
+            log_id: LogId::new("default", "events"),
+            partition_count: 3,
+            replication_factor: 2,
+        };
+        let mut expected = bincode::serialize(&request.log_id).unwrap();
+        expected.extend_from_slice(&3u32.to_le_bytes());
+        expected.extend_from_slice(&2u32.to_le_bytes());
+        assert_eq!(bincode::serialize(&request).unwrap(), expected);
+    }
+}
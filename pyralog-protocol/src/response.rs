@@ -1,7 +1,10 @@
-use bytes::Bytes;
-use pyralog_core::{LogId, Result, PyralogError};
+use bytes::{Bytes, BytesMut};
+use pyralog_core::{InclusionProof, LeaderHint, LogId, LogOffset, MerkleRoot, PartitionId, Result, PyralogError};
 use serde::{Deserialize, Serialize};
 
+/// Bytes following the records of an encoded successful `Response::Consume`
+pub const CONSUME_RESPONSE_TRAILER: &[u8] = &[0];
+
 /// Wire format for responses
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Response {
@@ -11,6 +14,17 @@ pub enum Response {
     DeleteLog(Result<()>),
     ListLogs(Result<Vec<LogId>>),
     Error(String),
+    MerkleRoot(Result<Option<MerkleRoot>>),
+    InclusionProof(Result<Option<InclusionProof>>),
+    QueryHeaders(Result<crate::api::HeaderQueryResponse>),
+    GetLatest(Result<crate::api::GetLatestResponse>),
+    ChangeMembership(Result<()>),
+    TransferLeadership(Result<()>),
+
+    /// The request must be served by another node, if it is known
+    NotLeader(Option<LeaderHint>),
+
+    ConsumeLsn(Result<crate::api::LsnConsumeResponse>),
 }
 
 impl Response {
@@ -26,5 +40,117 @@ impl Response {
         bincode::deserialize(bytes)
             .map_err(|e| PyralogError::SerializationError(e.to_string()))
     }
+
+    /// Error answer to a request, keeping the leader hint of `NotLeader`
+    /// so that clients can follow it
+    pub fn error(error: PyralogError) -> Self {
+        match error {
+            PyralogError::NotLeader(_) | PyralogError::NotLeaderHinted(_) => {
+                Response::NotLeader(error.leader_hint())
+            }
+            error => Response::Error(error.to_string()),
+        }
+    }
+
+    /// Leader to retry at, if this response redirects the request
+    pub fn leader_hint(&self) -> Option<LeaderHint> {
+        let error = match self {
+            Response::NotLeader(hint) => return hint.clone(),
+            Response::ConsumeLsn(Err(error))
+            | Response::CreateLog(Err(error))
+            | Response::DeleteLog(Err(error))
+            | Response::ListLogs(Err(error))
+            | Response::MerkleRoot(Err(error))
+            | Response::InclusionProof(Err(error))
+            | Response::QueryHeaders(Err(error))
+            | Response::GetLatest(Err(error))
+            | Response::ChangeMembership(Err(error))
+            | Response::TransferLeadership(Err(error)) => error,
+            _ => return None,
+        };
+        error.leader_hint()
+    }
+
+    /// Encode the bytes preceding the records of a successful `Response::Consume`
+    ///
+    /// An encoded consume response is this header, then each record in the
+    /// same encoding segments store it in, then `CONSUME_RESPONSE_TRAILER`.
+    /// This lets the server send records straight from segment files.
+    pub fn consume_header(
+        partition: PartitionId,
+        high_watermark: LogOffset,
+        record_count: u64,
+    ) -> Result<Bytes> {
+        let empty = Response::Consume(crate::api::ConsumeResponse {
+            partition,
+            high_watermark,
+            records: Vec::new(),
+            error: None,
+        })
+        .to_bytes()?;
+
+        // Drop the empty record count and trailer, then append the real count
+        let prefix_len = empty.len() - std::mem::size_of::<u64>() - CONSUME_RESPONSE_TRAILER.len();
+        let mut header = BytesMut::from(&empty[..prefix_len]);
+        header.extend_from_slice(&record_count.to_le_bytes());
+
+        Ok(header.freeze())
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyralog_core::Record;
+
+    #[test]
+    fn test_variant_tags_are_stable() {
+        // New variants go at the end so older peers still decode these
+        let tag = |response: Response| response.to_bytes().unwrap()[..4].to_vec();
+        assert_eq!(tag(Response::CreateLog(Ok(()))), 2u32.to_le_bytes());
+        assert_eq!(tag(Response::ListLogs(Ok(Vec::new()))), 4u32.to_le_bytes());
+        assert_eq!(tag(Response::Error(String::new())), 5u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_consume_header_matches_encoding() {
+        let records: Vec<Record> = (0..3)
+            .map(|i| Record::new(None, Bytes::from(format!("value-{}", i))))
+            .collect();
+
+        let mut encoded = Response::consume_header(PartitionId::new(2), LogOffset::new(3), 3)
+            .unwrap()
+            .to_vec();
+        for record in &records {
+            encoded.extend_from_slice(&bincode::serialize(record).unwrap());
+        }
+        encoded.extend_from_slice(CONSUME_RESPONSE_TRAILER);
+
+        match Response::from_bytes(&encoded).unwrap() {
+            Response::Consume(response) => {
+                assert_eq!(response.partition, PartitionId::new(2));
+                assert_eq!(response.records.len(), 3);
+                assert_eq!(response.records[2].value, records[2].value);
+                assert!(response.error.is_none());
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_leader_hint() {
+        let hint = LeaderHint::new(2).with_address("10.0.0.2:9092");
+
+        let response = Response::error(PyralogError::not_leader(Some(hint.clone())));
+        assert_eq!(response.leader_hint(), Some(hint.clone()));
+
+        let response = Response::CreateLog(Err(PyralogError::not_leader(Some(hint.clone()))));
+        assert_eq!(response.leader_hint(), Some(hint));
+
+        let response = Response::CreateLog(Err(PyralogError::NotLeader(Some(3))));
+        assert_eq!(response.leader_hint(), Some(LeaderHint::new(3)));
+
+        assert_eq!(Response::error(PyralogError::Timeout).leader_hint(), None);
+        assert_eq!(Response::CreateLog(Ok(())).leader_hint(), None);
+    }
+}
@@ -1,5 +1,6 @@
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
-use rand::Rng;
+use rand::SeedableRng;
 use std::collections::HashMap;
 
 /// CopySet represents a set of nodes that store a copy of data
@@ -30,14 +31,25 @@ pub struct CopySetSelector {
     replication_factor: usize,
     /// Track copyset usage for load balancing
     copyset_usage: HashMap<Vec<u64>, usize>,
+    rng: StdRng,
 }
 
 impl CopySetSelector {
     pub fn new(all_nodes: Vec<u64>, replication_factor: usize) -> Self {
+        Self::with_rng(all_nodes, replication_factor, StdRng::from_entropy())
+    }
+
+    /// Create a selector whose choices are fully determined by `seed`
+    pub fn with_seed(all_nodes: Vec<u64>, replication_factor: usize, seed: u64) -> Self {
+        Self::with_rng(all_nodes, replication_factor, StdRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(all_nodes: Vec<u64>, replication_factor: usize, rng: StdRng) -> Self {
         Self {
             all_nodes,
             replication_factor,
             copyset_usage: HashMap::new(),
+            rng,
         }
     }
 
@@ -48,11 +60,11 @@ impl CopySetSelector {
             return None;
         }
 
-        let mut rng = rand::thread_rng();
-        
+        let rng = &mut self.rng;
+
         // Select nodes for the copyset
         let mut nodes = self.all_nodes.clone();
-        nodes.shuffle(&mut rng);
+        nodes.shuffle(rng);
         nodes.truncate(self.replication_factor);
         nodes.sort_unstable();
 
@@ -60,7 +72,7 @@ impl CopySetSelector {
         *self.copyset_usage.entry(nodes.clone()).or_insert(0) += 1;
 
         // Select a leader from the copyset
-        let leader = *nodes.choose(&mut rng)?;
+        let leader = *nodes.choose(rng)?;
 
         Some(CopySet { nodes, leader })
     }
@@ -71,7 +83,7 @@ impl CopySetSelector {
         datacenter_map: &HashMap<u64, String>,
         preferred_dc: &str,
     ) -> Option<CopySet> {
-        let mut rng = rand::thread_rng();
+        let rng = &mut self.rng;
 
         // First, try to get at least one node from preferred DC
         let preferred_nodes: Vec<u64> = self
@@ -102,7 +114,7 @@ impl CopySetSelector {
 
         // Add at least one node from preferred DC if available
         if !preferred_nodes.is_empty() {
-            let node = *preferred_nodes.choose(&mut rng)?;
+            let node = *preferred_nodes.choose(rng)?;
             selected_nodes.push(node);
         }
 
@@ -114,7 +126,7 @@ impl CopySetSelector {
             .copied()
             .collect::<Vec<_>>();
 
-        remaining_nodes.shuffle(&mut rng);
+        remaining_nodes.shuffle(rng);
 
         while selected_nodes.len() < self.replication_factor && !remaining_nodes.is_empty() {
             selected_nodes.push(remaining_nodes.remove(0));
@@ -125,7 +137,7 @@ impl CopySetSelector {
         }
 
         selected_nodes.sort_unstable();
-        let leader = *selected_nodes.choose(&mut rng)?;
+        let leader = *selected_nodes.choose(rng)?;
 
         Some(CopySet {
             nodes: selected_nodes,
@@ -171,5 +183,18 @@ mod tests {
         assert_eq!(copyset.size(), 3);
         assert!(copyset.contains(copyset.leader));
     }
+
+    #[test]
+    fn test_seeded_selection_is_reproducible() {
+        let select = |seed| {
+            let mut selector = CopySetSelector::with_seed(vec![1, 2, 3, 4, 5], 3, seed);
+            (0..8)
+                .map(|_| selector.select_copyset().unwrap())
+                .map(|copyset| (copyset.nodes, copyset.leader))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(select(7), select(7));
+    }
 }
 
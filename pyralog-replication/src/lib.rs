@@ -7,8 +7,15 @@ pub mod quorum;
 pub mod replicator;
 pub mod copyset;
 pub mod sync;
+pub mod recovery;
+pub mod transport;
 
 pub use quorum::{QuorumConfig, QuorumSet};
 pub use replicator::{ReplicationManager, ReplicationConfig};
 pub use copyset::CopySet;
+pub use recovery::{EpochRecovery, EpochReplica, MemoryReplica, RecoveredEpoch, StorageReplica};
+pub use transport::{
+    RaftReplicaTransport, RemoteReplica, ReplicaBatch, ReplicaHandler, ReplicaRequest, ReplicaResponse,
+    ReplicaRouter, ReplicaTransport,
+};
 
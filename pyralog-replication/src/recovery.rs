@@ -0,0 +1,1036 @@
+use async_trait::async_trait;
+use parking_lot::RwLock;
+use pyralog_core::{
+    Epoch, EpochMetadata, GapKind, LogId, LogOffset, OffsetRange, PartitionId, PyralogError, Record, Result,
+    EpochLog, Sequencer, GAP_HEADER,
+};
+use pyralog_storage::LogStorage;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use crate::quorum::QuorumConfig;
+
+/// Offsets read from each replica at a time during recovery
+pub const DEFAULT_RECOVERY_CHUNK: u32 = 1024;
+
+/// One replica's copy of a partition, as seen by epoch recovery
+#[async_trait]
+pub trait EpochReplica: Send + Sync {
+    fn node_id(&self) -> u64;
+
+    /// Refuse further writes to `epoch` and every earlier epoch
+    ///
+    /// Returns the offsets the replica still stores in `epoch`, from the
+    /// first one retention left to the highest one written, or None if it
+    /// stores none.
+    async fn seal(&self, partition: PartitionId, epoch: Epoch) -> Result<Option<RangeInclusive<u32>>>;
+
+    /// Records the replica stores in `epoch` at `offsets`, by offset
+    async fn read_epoch(
+        &self,
+        partition: PartitionId,
+        epoch: Epoch,
+        offsets: RangeInclusive<u32>,
+    ) -> Result<BTreeMap<u32, Record>>;
+
+    /// Store the outcome of recovery in a sealed epoch
+    ///
+    /// Only recovery may write to a sealed epoch; it replaces whatever the
+    /// replica holds at those offsets. Each replica is written in ascending
+    /// offset order.
+    async fn store_recovered(
+        &self,
+        partition: PartitionId,
+        epoch: Epoch,
+        records: Vec<(u32, Record)>,
+    ) -> Result<()>;
+}
+
+/// Outcome of recovering one epoch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveredEpoch {
+    pub epoch: Epoch,
+
+    /// Last offset holding client data, or None for an epoch that never
+    /// held any
+    pub tail: Option<u32>,
+
+    /// Runs of offsets below the tail plugged with holes
+    pub holes: Vec<RangeInclusive<u32>>,
+
+    /// Copies written to replicas that were missing a record
+    pub rereplicated: usize,
+}
+
+/// Determines what an epoch durably holds after its sequencer is gone
+///
+/// Inspired by LogDevice's epoch recovery. The replicas are sealed first,
+/// so the old sequencer cannot complete any more writes. Every record that
+/// may have been acknowledged has a copy on at least one replica of any
+/// set that intersects every write quorum; recovery therefore waits for
+/// `replication_factor - write_quorum + 1` replicas to seal, keeps every
+/// record one of them holds, and re-replicates it to all of them. Offsets
+/// none of them hold were never acknowledged and are plugged with holes.
+/// A bridge after the last record marks the end of the epoch.
+///
+/// Replicas are read a chunk of offsets at a time, starting where
+/// retention has trimmed them, so recovering a long epoch holds only one
+/// chunk of it in memory.
+pub struct EpochRecovery {
+    quorum: QuorumConfig,
+    chunk_size: u32,
+}
+
+impl EpochRecovery {
+    pub fn new(quorum: QuorumConfig) -> Self {
+        Self {
+            quorum,
+            chunk_size: DEFAULT_RECOVERY_CHUNK,
+        }
+    }
+
+    /// Read this many offsets from each replica at a time
+    pub fn with_chunk_size(mut self, chunk_size: u32) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Replicas that must answer for recovery to see every acknowledged
+    /// record
+    pub fn required_replicas(&self) -> usize {
+        (self.quorum.replication_factor + 1)
+            .saturating_sub(self.quorum.write_quorum)
+            .max(1)
+    }
+
+    /// Recover `epoch` of a partition from its replicas
+    ///
+    /// Offsets below `from` are known to be fully replicated and are left
+    /// alone; pass 0 when nothing is known. Offsets retention trimmed from a
+    /// replica are skipped as well.
+    pub async fn recover(
+        &self,
+        partition: PartitionId,
+        epoch: Epoch,
+        from: u32,
+        replicas: &[Arc<dyn EpochReplica>],
+    ) -> Result<RecoveredEpoch> {
+        // Seal, then read: nothing can be added to a replica's copy after
+        // it answers
+        let mut sealed: Vec<Arc<dyn EpochReplica>> = Vec::new();
+        let mut start = from;
+        let mut end = u64::from(from);
+        for replica in replicas {
+            if let Ok(stored) = replica.seal(partition, epoch).await {
+                if let Some(stored) = stored {
+                    // Retention only trims records that were committed
+                    start = start.max(*stored.start());
+                    end = end.max(u64::from(*stored.end()) + 1);
+                }
+                sealed.push(Arc::clone(replica));
+            }
+        }
+        let mut sealed = self.enough(sealed)?;
+
+        let mut tail = start.checked_sub(1);
+        let mut holes = Vec::new();
+        let mut rereplicated = 0;
+
+        let mut chunk_start = u64::from(start);
+        while chunk_start < end {
+            let chunk_end = (chunk_start + u64::from(self.chunk_size)).min(end) - 1;
+            let offsets = chunk_start as u32..=chunk_end as u32;
+
+            // A replica that fails drops out, as long as enough others are
+            // left to see every acknowledged record
+            let mut reachable = Vec::with_capacity(sealed.len());
+            let mut copies = Vec::with_capacity(sealed.len());
+            for replica in &sealed {
+                if let Ok(copy) = replica.read_epoch(partition, epoch, offsets.clone()).await {
+                    reachable.push(Arc::clone(replica));
+                    copies.push(copy);
+                }
+            }
+            sealed = self.enough(reachable)?;
+
+            // Any copy of client data wins; markers from an earlier,
+            // unfinished recovery do not
+            let mut records: BTreeMap<u32, Record> = BTreeMap::new();
+            for copy in &copies {
+                for (&offset, record) in copy {
+                    if record.gap_kind().is_none() {
+                        records.entry(offset).or_insert_with(|| record.clone());
+                    }
+                }
+            }
+
+            // Offsets between two records that nobody holds were never
+            // acknowledged; those after the last record are cut off by the
+            // bridge. Each replica is written in offset order, so that
+            // append-only storage can take the records.
+            let mut stored = Vec::with_capacity(sealed.len());
+            for (replica, copy) in sealed.iter().zip(&copies) {
+                let next = tail.map_or(0, |tail| tail + 1);
+                if let Ok(count) = self.store_chunk(partition, epoch, replica, copy, &records, next).await {
+                    rereplicated += count;
+                    stored.push(Arc::clone(replica));
+                }
+            }
+            sealed = self.enough(stored)?;
+
+            for &offset in records.keys() {
+                let next = tail.map_or(0, |tail| tail + 1);
+                if next < offset {
+                    holes.push(next..=offset - 1);
+                }
+                tail = Some(offset);
+            }
+
+            chunk_start = chunk_end + 1;
+        }
+
+        // An epoch whose last offset holds data needs no bridge to end it
+        if let Some(bridge) = tail.map_or(Some(0), |tail| tail.checked_add(1)) {
+            let mut stored = Vec::with_capacity(sealed.len());
+            for replica in &sealed {
+                let marker = Record::gap(GapKind::Bridge).with_epoch(epoch);
+                if replica.store_recovered(partition, epoch, vec![(bridge, marker)]).await.is_ok() {
+                    stored.push(Arc::clone(replica));
+                }
+            }
+            self.enough(stored)?;
+        }
+
+        Ok(RecoveredEpoch {
+            epoch,
+            tail,
+            holes,
+            rereplicated,
+        })
+    }
+
+    /// Fail with `QuorumNotAvailable` unless enough replicas are left
+    fn enough(&self, replicas: Vec<Arc<dyn EpochReplica>>) -> Result<Vec<Arc<dyn EpochReplica>>> {
+        if replicas.len() < self.required_replicas() {
+            return Err(PyralogError::QuorumNotAvailable);
+        }
+        Ok(replicas)
+    }
+
+    /// Bring one replica's copy of a chunk in line with the records
+    /// recovery kept, the first of which may follow offset `next`
+    ///
+    /// Returns how many records were copied to it.
+    async fn store_chunk(
+        &self,
+        partition: PartitionId,
+        epoch: Epoch,
+        replica: &Arc<dyn EpochReplica>,
+        copy: &BTreeMap<u32, Record>,
+        records: &BTreeMap<u32, Record>,
+        mut next: u32,
+    ) -> Result<usize> {
+        let mut missing = Vec::new();
+        let mut copied = 0;
+        for (&offset, record) in records {
+            if next < offset {
+                self.store_missing(partition, epoch, replica, &mut missing).await?;
+                self.plug_holes(partition, epoch, next..=offset - 1, std::slice::from_ref(replica))
+                    .await?;
+            }
+            if copy.get(&offset).is_none_or(|stored| stored.gap_kind().is_some()) {
+                missing.push((offset, record.clone()));
+                copied += 1;
+            }
+            next = offset + 1;
+        }
+        self.store_missing(partition, epoch, replica, &mut missing).await?;
+        Ok(copied)
+    }
+
+    /// Store the records a replica was missing, if there are any
+    async fn store_missing(
+        &self,
+        partition: PartitionId,
+        epoch: Epoch,
+        replica: &Arc<dyn EpochReplica>,
+        missing: &mut Vec<(u32, Record)>,
+    ) -> Result<()> {
+        if missing.is_empty() {
+            return Ok(());
+        }
+        replica.store_recovered(partition, epoch, std::mem::take(missing)).await
+    }
+
+    /// Write hole markers at `offsets` on every replica, a chunk at a time
+    async fn plug_holes(
+        &self,
+        partition: PartitionId,
+        epoch: Epoch,
+        offsets: RangeInclusive<u32>,
+        replicas: &[Arc<dyn EpochReplica>],
+    ) -> Result<()> {
+        let (first, last) = offsets.into_inner();
+        let mut chunk_start = u64::from(first);
+        while chunk_start <= u64::from(last) {
+            let chunk_end = (chunk_start + u64::from(self.chunk_size) - 1).min(u64::from(last));
+            for replica in replicas {
+                let markers = (chunk_start as u32..=chunk_end as u32)
+                    .map(|offset| (offset, Record::gap(GapKind::Hole).with_epoch(epoch)))
+                    .collect();
+                replica.store_recovered(partition, epoch, markers).await?;
+            }
+            chunk_start = chunk_end + 1;
+        }
+        Ok(())
+    }
+
+    /// Bring `replica`'s copy of a sealed epoch in line with how recovery
+    /// ended it, copying what the replica lacks from `sources`
+    ///
+    /// `tail` is the last offset the epoch holds client data at. An offset
+    /// up to it that the replica lacks, or holds only a marker at, gets the
+    /// record one of the sources holds, or a hole if none of them does; the
+    /// bridge after the tail follows. Sources that fail are skipped, but
+    /// unless a write quorum of copies answers for every offset, a missing
+    /// record could still exist and repair fails instead.
+    ///
+    /// Returns how many records were copied to the replica.
+    pub async fn repair(
+        &self,
+        partition: PartitionId,
+        epoch: Epoch,
+        tail: Option<u32>,
+        replica: &Arc<dyn EpochReplica>,
+        sources: &[Arc<dyn EpochReplica>],
+    ) -> Result<usize> {
+        let start = replica
+            .seal(partition, epoch)
+            .await?
+            .map_or(0, |stored| *stored.start());
+        let required = self.quorum.write_quorum.max(self.required_replicas());
+        let mut copied = 0;
+
+        if let Some(tail) = tail {
+            let mut chunk_start = u64::from(start);
+            while chunk_start <= u64::from(tail) {
+                let chunk_end = (chunk_start + u64::from(self.chunk_size) - 1).min(u64::from(tail));
+                let offsets = chunk_start as u32..=chunk_end as u32;
+
+                let held = replica.read_epoch(partition, epoch, offsets.clone()).await?;
+                let missing: Vec<u32> = offsets
+                    .clone()
+                    .filter(|offset| {
+                        held.get(offset).is_none_or(|record| {
+                            record.gap_kind().is_some() && record.gap_kind() != Some(GapKind::Hole)
+                        })
+                    })
+                    .collect();
+
+                if !missing.is_empty() {
+                    let mut answered = 1;
+                    let mut records: BTreeMap<u32, Record> = BTreeMap::new();
+                    for source in sources.iter().filter(|source| source.node_id() != replica.node_id()) {
+                        let Ok(copy) = source.read_epoch(partition, epoch, offsets.clone()).await else {
+                            continue;
+                        };
+                        answered += 1;
+                        for (offset, record) in copy {
+                            if record.gap_kind().is_none() {
+                                records.entry(offset).or_insert(record);
+                            }
+                        }
+                    }
+                    if answered < required {
+                        return Err(PyralogError::QuorumNotAvailable);
+                    }
+
+                    let found = missing
+                        .into_iter()
+                        .map(|offset| {
+                            let record = records.remove(&offset).unwrap_or_else(|| {
+                                Record::gap(GapKind::Hole).with_epoch(epoch)
+                            });
+                            (offset, record)
+                        })
+                        .collect::<Vec<_>>();
+                    copied += found.iter().filter(|(_, record)| record.gap_kind().is_none()).count();
+                    replica.store_recovered(partition, epoch, found).await?;
+                }
+
+                chunk_start = chunk_end + 1;
+            }
+        }
+
+        if let Some(bridge) = tail.map_or(Some(0), |tail| tail.checked_add(1)) {
+            let marker = Record::gap(GapKind::Bridge).with_epoch(epoch);
+            replica.store_recovered(partition, epoch, vec![(bridge, marker)]).await?;
+        }
+        Ok(copied)
+    }
+
+    /// Start a new epoch for `sequencer` once every earlier epoch is
+    /// recovered and sealed, and `local`, the sequencer's own copy of the
+    /// partition, holds all of them
+    ///
+    /// The new epoch is allocated first so that sealing fences off the
+    /// previous sequencers, but it is only returned, and so written to,
+    /// after recovery completes. Unsealed epochs are recovered from
+    /// `replicas`, which include `local`. Sealed epochs the local copy may
+    /// have missed the end of, or missed entirely, while its node was away
+    /// are repaired from them: those from the last one it holds anything
+    /// of on, as its earlier ones were complete when it appended that.
+    pub async fn activate(
+        &self,
+        sequencer: &Sequencer,
+        log_id: &LogId,
+        partition: PartitionId,
+        start_offset: u64,
+        local: &Arc<dyn EpochReplica>,
+        replicas: &[Arc<dyn EpochReplica>],
+    ) -> Result<Epoch> {
+        let epoch = sequencer.activate(log_id, partition, start_offset).await?;
+
+        let previous: Vec<EpochMetadata> = sequencer
+            .get_epoch_store(log_id, partition)
+            .map(|store| {
+                store
+                    .epochs()
+                    .iter()
+                    .filter(|metadata| metadata.current_epoch < epoch)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut first = 0;
+        for (index, metadata) in previous.iter().enumerate().rev() {
+            if !metadata.can_write() && local.seal(partition, metadata.current_epoch).await?.is_some() {
+                first = index;
+                break;
+            }
+        }
+
+        for (index, metadata) in previous.iter().enumerate() {
+            if metadata.can_write() {
+                let recovered = self.recover(partition, metadata.current_epoch, 0, replicas).await?;
+                sequencer
+                    .seal_epoch(log_id, partition, metadata.current_epoch, recovered.tail)
+                    .await?;
+            } else if index >= first {
+                self.repair(partition, metadata.current_epoch, metadata.last_known_offset, local, replicas)
+                    .await?;
+            }
+        }
+
+        Ok(epoch)
+    }
+}
+
+/// Replica holding records in process memory, for tests and simulations
+#[derive(Default)]
+pub struct MemoryReplica {
+    node_id: u64,
+    records: RwLock<HashMap<(PartitionId, Epoch), BTreeMap<u32, Record>>>,
+    seals: RwLock<HashMap<PartitionId, Epoch>>,
+}
+
+impl MemoryReplica {
+    pub fn new(node_id: u64) -> Self {
+        Self {
+            node_id,
+            ..Self::default()
+        }
+    }
+
+    /// Store a record written by the sequencer of `epoch`
+    ///
+    /// Fails with `EpochSealed` once the epoch has been sealed. Like
+    /// `LogStorage::append`, it refuses records carrying the header
+    /// reserved for gap markers.
+    pub fn write(&self, partition: PartitionId, epoch: Epoch, offset: u32, record: Record) -> Result<()> {
+        if record.has_gap_header() {
+            return Err(PyralogError::InvalidRequest(format!(
+                "Header {} is reserved for gap markers",
+                GAP_HEADER
+            )));
+        }
+        if let Some(&sealed) = self.seals.read().get(&partition) {
+            if epoch <= sealed {
+                return Err(PyralogError::EpochSealed(sealed));
+            }
+        }
+
+        self.records
+            .write()
+            .entry((partition, epoch))
+            .or_default()
+            .insert(offset, record);
+        Ok(())
+    }
+
+    /// The record or marker stored at an offset
+    pub fn get(&self, partition: PartitionId, epoch: Epoch, offset: u32) -> Option<Record> {
+        self.records
+            .read()
+            .get(&(partition, epoch))?
+            .get(&offset)
+            .cloned()
+    }
+}
+
+#[async_trait]
+impl EpochReplica for MemoryReplica {
+    fn node_id(&self) -> u64 {
+        self.node_id
+    }
+
+    async fn seal(&self, partition: PartitionId, epoch: Epoch) -> Result<Option<RangeInclusive<u32>>> {
+        let mut seals = self.seals.write();
+        let sealed = seals.entry(partition).or_insert(epoch);
+        *sealed = (*sealed).max(epoch);
+
+        let records = self.records.read();
+        let stored = match records.get(&(partition, epoch)) {
+            Some(stored) => stored,
+            None => return Ok(None),
+        };
+        Ok(stored
+            .keys()
+            .next()
+            .zip(stored.keys().next_back())
+            .map(|(&first, &last)| first..=last))
+    }
+
+    async fn read_epoch(
+        &self,
+        partition: PartitionId,
+        epoch: Epoch,
+        offsets: RangeInclusive<u32>,
+    ) -> Result<BTreeMap<u32, Record>> {
+        Ok(self
+            .records
+            .read()
+            .get(&(partition, epoch))
+            .map(|records| {
+                records
+                    .range(offsets)
+                    .map(|(&offset, record)| (offset, record.clone()))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn store_recovered(
+        &self,
+        partition: PartitionId,
+        epoch: Epoch,
+        records: Vec<(u32, Record)>,
+    ) -> Result<()> {
+        let mut stored = self.records.write();
+        let stored = stored.entry((partition, epoch)).or_default();
+        for (offset, record) in records {
+            // Anything past the bridge was never acknowledged
+            if record.gap_kind() == Some(GapKind::Bridge) {
+                stored.split_off(&offset);
+            }
+            stored.insert(offset, record);
+        }
+        Ok(())
+    }
+}
+
+/// Replica backed by a partition's `LogStorage` on this node
+///
+/// Recovered records are appended at the offsets the epoch's sequencer
+/// gave them, so the storage must hold every earlier epoch up to where this
+/// one starts. Replacing a record the storage holds rewrites the log after
+/// it; rewriting records of a later epoch, or appending past a gap, fails
+/// recovery instead.
+pub struct StorageReplica {
+    node_id: u64,
+    partition: PartitionId,
+    storage: Arc<LogStorage>,
+    epochs: Option<(LogId, Arc<dyn EpochLog>)>,
+}
+
+impl StorageReplica {
+    pub fn new(node_id: u64, partition: PartitionId, storage: Arc<LogStorage>) -> Self {
+        Self {
+            node_id,
+            partition,
+            storage,
+            epochs: None,
+        }
+    }
+
+    /// Check against the log's epoch history that the storage ends where an
+    /// epoch it holds nothing of starts, before storing records of it
+    ///
+    /// Without the history the storage is trusted to hold every earlier
+    /// epoch in full, as the sequencer's own copy does once activation has
+    /// repaired it.
+    pub fn with_epochs(mut self, log_id: LogId, epochs: Arc<dyn EpochLog>) -> Self {
+        self.epochs = Some((log_id, epochs));
+        self
+    }
+
+    /// Whether the storage ends with the marker or record that ended the
+    /// last epoch before `epoch`, at the offset it ended at
+    async fn ends_before(&self, epoch: Epoch) -> Result<bool> {
+        let Some((log_id, epochs)) = &self.epochs else {
+            return Ok(true);
+        };
+        let previous = epochs.epochs(log_id, self.partition).and_then(|store| {
+            store
+                .epochs()
+                .iter()
+                .rev()
+                .find(|metadata| metadata.current_epoch < epoch)
+                .cloned()
+        });
+        let Some(previous) = previous else {
+            return Ok(true);
+        };
+        if previous.can_write() {
+            return Ok(false);
+        }
+
+        let range = match self.epoch_range(self.partition, previous.current_epoch)? {
+            Some(range) if range.end > range.start && range.end == self.storage.high_watermark() => range,
+            _ => return Ok(false),
+        };
+        let last = range.end.as_u64() - range.start.as_u64() - 1;
+        let ended = match previous.last_known_offset.map_or(Some(0), |tail| tail.checked_add(1)) {
+            Some(bridge) => {
+                let record = self.storage.read(LogOffset::new(range.end.as_u64() - 1)).await?;
+                last == u64::from(bridge) && record.is_some_and(|record| record.gap_kind() == Some(GapKind::Bridge))
+            }
+            None => Some(last) == previous.last_known_offset.map(u64::from),
+        };
+        Ok(ended)
+    }
+
+    /// Global offsets of the records the storage holds in `epoch`
+    fn epoch_range(&self, partition: PartitionId, epoch: Epoch) -> Result<Option<OffsetRange>> {
+        if partition != self.partition {
+            return Err(PyralogError::PartitionNotFound(partition.as_u32() as u64));
+        }
+
+        let starts = self.storage.epoch_starts();
+        let index = match starts.iter().position(|(stored, _)| *stored == epoch) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let end = starts
+            .get(index + 1)
+            .map_or(self.storage.high_watermark(), |(_, next)| *next);
+        Ok(Some(OffsetRange::new(starts[index].1, end)))
+    }
+}
+
+#[async_trait]
+impl EpochReplica for StorageReplica {
+    fn node_id(&self) -> u64 {
+        self.node_id
+    }
+
+    async fn seal(&self, partition: PartitionId, epoch: Epoch) -> Result<Option<RangeInclusive<u32>>> {
+        self.storage.seal_epoch(epoch)?;
+        // Records still in the write cache have no LSN yet
+        self.storage.flush().await?;
+
+        let range = match self.epoch_range(partition, epoch)? {
+            Some(range) if range.end > range.start => range,
+            _ => return Ok(None),
+        };
+        let last = u32::try_from(range.end.as_u64() - range.start.as_u64() - 1)
+            .map_err(|_| PyralogError::StorageError(format!("Epoch {} overflows its offsets", epoch)))?;
+        Ok(Some(0..=last))
+    }
+
+    async fn read_epoch(
+        &self,
+        partition: PartitionId,
+        epoch: Epoch,
+        offsets: RangeInclusive<u32>,
+    ) -> Result<BTreeMap<u32, Record>> {
+        let range = match self.epoch_range(partition, epoch)? {
+            Some(range) => range,
+            None => return Ok(BTreeMap::new()),
+        };
+
+        let base = range.start.as_u64();
+        let start = LogOffset::new(base + u64::from(*offsets.start()));
+        let end = LogOffset::new((base + u64::from(*offsets.end()) + 1).min(range.end.as_u64()));
+        let records = self.storage.read_range(OffsetRange::new(start, end)).await?;
+
+        Ok(records
+            .into_iter()
+            .map(|record| ((record.offset.as_u64() - base) as u32, record))
+            .collect())
+    }
+
+    async fn store_recovered(
+        &self,
+        partition: PartitionId,
+        epoch: Epoch,
+        records: Vec<(u32, Record)>,
+    ) -> Result<()> {
+        self.storage.flush().await?;
+        let later_epochs = self
+            .storage
+            .epoch_starts()
+            .last()
+            .is_some_and(|(last, _)| *last > epoch);
+        let base = match self.epoch_range(partition, epoch)? {
+            Some(range) => range.start,
+            // An epoch the storage holds nothing of starts where it ends
+            None if !later_epochs && self.ends_before(epoch).await? => self.storage.high_watermark(),
+            None if !later_epochs => {
+                return Err(PyralogError::StorageError(format!(
+                    "Partition {} does not end where sealed epoch {} starts",
+                    partition, epoch
+                )))
+            }
+            None => {
+                return Err(PyralogError::StorageError(format!(
+                    "Partition {} holds later epochs but nothing of sealed epoch {}",
+                    partition, epoch
+                )))
+            }
+        };
+
+        let mut bridge = None;
+        let mut placed = Vec::with_capacity(records.len());
+        for (offset, mut record) in records {
+            let global = LogOffset::new(base.as_u64() + u64::from(offset));
+            if record.gap_kind().is_none() && record.offset != global {
+                return Err(PyralogError::StorageError(format!(
+                    "Offset {} of epoch {} is {} in partition {}, not {}",
+                    offset, epoch, global, partition, record.offset
+                )));
+            }
+            if record.gap_kind() == Some(GapKind::Bridge) {
+                bridge = Some(global);
+            }
+            record.offset = global;
+            record.epoch = epoch;
+            placed.push(record);
+        }
+        self.storage.append_recovered(placed).await?;
+
+        // Anything past the bridge was never acknowledged
+        let Some(bridge) = bridge else {
+            return Ok(());
+        };
+        let past_bridge = self.storage.read(bridge.next()).await?;
+        if past_bridge.is_some_and(|record| record.epoch == epoch) {
+            if later_epochs {
+                return Err(PyralogError::StorageError(format!(
+                    "Cannot end epoch {} of partition {} at {} below later epochs",
+                    epoch, partition, bridge
+                )));
+            }
+            self.storage.truncate(bridge.next()).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use pyralog_core::{EpochOffset, MemoryEpochLog, RecordHeader};
+
+    fn record(value: &'static str) -> Record {
+        Record::new(None, Bytes::from_static(value.as_bytes()))
+    }
+
+    fn replicas(count: u64) -> (Vec<Arc<MemoryReplica>>, Vec<Arc<dyn EpochReplica>>) {
+        let memory: Vec<Arc<MemoryReplica>> =
+            (1..=count).map(|node_id| Arc::new(MemoryReplica::new(node_id))).collect();
+        let replicas = memory
+            .iter()
+            .map(|replica| Arc::clone(replica) as Arc<dyn EpochReplica>)
+            .collect();
+        (memory, replicas)
+    }
+
+    #[tokio::test]
+    async fn test_recovery_repairs_epoch() {
+        let partition = PartitionId::new(0);
+        let epoch = Epoch::FIRST;
+        let (memory, replicas) = replicas(3);
+
+        // Offset 0 reached everyone, 1 only node 1, 2 nobody, 3 node 3
+        for replica in &memory {
+            replica.write(partition, epoch, 0, record("a")).unwrap();
+        }
+        memory[0].write(partition, epoch, 1, record("b")).unwrap();
+        memory[2].write(partition, epoch, 3, record("d")).unwrap();
+
+        let recovery = EpochRecovery::new(QuorumConfig::majority(3));
+        let recovered = recovery.recover(partition, epoch, 0, &replicas).await.unwrap();
+
+        assert_eq!(recovered.tail, Some(3));
+        assert_eq!(recovered.holes, vec![2..=2]);
+        assert_eq!(recovered.rereplicated, 4);
+
+        for replica in &memory {
+            assert_eq!(replica.get(partition, epoch, 1).unwrap().value, Bytes::from("b"));
+            assert_eq!(replica.get(partition, epoch, 2).unwrap().gap_kind(), Some(GapKind::Hole));
+            assert_eq!(replica.get(partition, epoch, 4).unwrap().gap_kind(), Some(GapKind::Bridge));
+        }
+
+        // The old sequencer is fenced off
+        assert!(matches!(
+            memory[1].write(partition, epoch, 5, record("e")),
+            Err(PyralogError::EpochSealed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_headers_cannot_forge_gaps() {
+        let partition = PartitionId::new(0);
+        let epoch = Epoch::FIRST;
+        let (memory, _) = replicas(1);
+
+        let forged = record("a").with_headers(vec![RecordHeader::new(
+            GAP_HEADER.to_string(),
+            Bytes::from_static(b"bridge"),
+        )]);
+        assert!(matches!(
+            memory[0].write(partition, epoch, 0, forged.clone()),
+            Err(PyralogError::InvalidRequest(_))
+        ));
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let storage = LogStorage::create(temp_dir.path().to_path_buf(), Default::default())
+            .await
+            .unwrap();
+        assert!(matches!(storage.append(forged).await, Err(PyralogError::InvalidRequest(_))));
+        assert_eq!(storage.high_watermark(), LogOffset::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_recovery_needs_enough_replicas() {
+        let (_, replicas) = replicas(3);
+        let recovery = EpochRecovery::new(QuorumConfig::majority(3));
+
+        let result = recovery
+            .recover(PartitionId::new(0), Epoch::FIRST, 0, &replicas[..1])
+            .await;
+        assert!(matches!(result, Err(PyralogError::QuorumNotAvailable)));
+    }
+
+    #[tokio::test]
+    async fn test_activate_seals_previous_epoch() {
+        let log_id = LogId::new("default", "events");
+        let partition = PartitionId::new(0);
+        let (memory, replicas) = replicas(3);
+        let epochs = Arc::new(MemoryEpochLog::new());
+        let recovery = EpochRecovery::new(QuorumConfig::majority(3));
+
+        let sequencer1 = Sequencer::new(1, epochs.clone());
+        let epoch1 = sequencer1.activate(&log_id, partition, 0).await.unwrap();
+        for offset in 0..2 {
+            memory[0].write(partition, epoch1, offset, record("x")).unwrap();
+        }
+
+        // Node 1 dies; node 2 takes over
+        let sequencer2 = Sequencer::new(2, epochs);
+        let epoch2 = recovery
+            .activate(&sequencer2, &log_id, partition, 2, &replicas[1], &replicas)
+            .await
+            .unwrap();
+
+        assert!(epoch2 > epoch1);
+        let store = sequencer2.get_epoch_store(&log_id, partition).unwrap();
+        let previous = store.get_epoch(epoch1).unwrap();
+        assert!(!previous.can_write());
+        assert_eq!(previous.last_known_offset, Some(1));
+        assert!(sequencer2.can_write(&log_id, partition, epoch2));
+    }
+
+    #[tokio::test]
+    async fn test_activate_repairs_local_copy() {
+        let log_id = LogId::new("default", "events");
+        let partition = PartitionId::new(0);
+        let (memory, replicas) = replicas(3);
+        let epochs = Arc::new(MemoryEpochLog::new());
+        let recovery = EpochRecovery::new(QuorumConfig::majority(3)).with_chunk_size(2);
+
+        let epoch1 = Sequencer::new(1, epochs.clone()).activate(&log_id, partition, 0).await.unwrap();
+        for offset in 0..3 {
+            memory[0].write(partition, epoch1, offset, record("x")).unwrap();
+            memory[1].write(partition, epoch1, offset, record("x")).unwrap();
+        }
+
+        // Node 2 takes over while node 3 is away
+        let sequencer2 = Sequencer::new(2, epochs.clone());
+        recovery
+            .activate(&sequencer2, &log_id, partition, 0, &replicas[1], &replicas[..2])
+            .await
+            .unwrap();
+        assert!(memory[2].get(partition, epoch1, 0).is_none());
+
+        // Node 3 comes back and takes over without node 1
+        let sequencer3 = Sequencer::new(3, epochs);
+        recovery
+            .activate(&sequencer3, &log_id, partition, 3, &replicas[2], &replicas[1..])
+            .await
+            .unwrap();
+        for offset in 0..3 {
+            assert_eq!(memory[2].get(partition, epoch1, offset).unwrap().value, Bytes::from("x"));
+        }
+        assert_eq!(memory[2].get(partition, epoch1, 3).unwrap().gap_kind(), Some(GapKind::Bridge));
+
+        // Nothing can be repaired from a copy that has nothing to offer
+        let fresh: Arc<dyn EpochReplica> = Arc::new(MemoryReplica::new(4));
+        let result = recovery
+            .repair(partition, epoch1, Some(2), &fresh, std::slice::from_ref(&fresh))
+            .await;
+        assert!(matches!(result, Err(PyralogError::QuorumNotAvailable)));
+    }
+
+    #[tokio::test]
+    async fn test_recovery_reads_in_chunks_from_trim_point() {
+        let partition = PartitionId::new(0);
+        let epoch = Epoch::FIRST;
+        let (memory, replicas) = replicas(3);
+
+        // Retention trimmed everything below 10; offsets 13 to 16 were lost
+        for replica in &memory {
+            for offset in (10..13).chain(17..20) {
+                replica.write(partition, epoch, offset, record("x")).unwrap();
+            }
+        }
+
+        let recovery = EpochRecovery::new(QuorumConfig::majority(3)).with_chunk_size(2);
+        let recovered = recovery.recover(partition, epoch, 0, &replicas).await.unwrap();
+
+        assert_eq!(recovered.tail, Some(19));
+        assert_eq!(recovered.holes, vec![13..=16]);
+        assert!(memory[0].get(partition, epoch, 9).is_none());
+        assert_eq!(memory[0].get(partition, epoch, 14).unwrap().gap_kind(), Some(GapKind::Hole));
+        assert_eq!(memory[0].get(partition, epoch, 20).unwrap().gap_kind(), Some(GapKind::Bridge));
+    }
+
+    #[tokio::test]
+    async fn test_storage_replica_recovery() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let storage = LogStorage::create(
+            temp_dir.path().to_path_buf(),
+            pyralog_storage::log_storage::LogStorageConfig::default(),
+        )
+        .await
+        .unwrap();
+        let storage = Arc::new(storage);
+        let partition = PartitionId::new(0);
+        let epoch = Epoch::FIRST;
+        for value in ["a", "b", "c"] {
+            storage.append(record(value).with_epoch(epoch)).await.unwrap();
+        }
+
+        let replica: Arc<dyn EpochReplica> =
+            Arc::new(StorageReplica::new(1, partition, Arc::clone(&storage)));
+        let recovery = EpochRecovery::new(QuorumConfig::majority(1));
+        let recovered = recovery.recover(partition, epoch, 0, &[replica]).await.unwrap();
+
+        assert_eq!(recovered.tail, Some(2));
+        assert!(recovered.holes.is_empty());
+        assert!(matches!(
+            storage.append(record("d").with_epoch(epoch)).await,
+            Err(PyralogError::EpochSealed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_storage_replicas_store_recovered_records() {
+        let partition = PartitionId::new(0);
+        let epoch = Epoch::FIRST;
+        let temp_dirs: Vec<_> = (0..2).map(|_| tempfile::TempDir::new().unwrap()).collect();
+        let mut storages = Vec::new();
+        for temp_dir in &temp_dirs {
+            let storage = LogStorage::create(
+                temp_dir.path().to_path_buf(),
+                pyralog_storage::log_storage::LogStorageConfig::default(),
+            )
+            .await
+            .unwrap();
+            storages.push(Arc::new(storage));
+        }
+
+        // Node 1 stored offsets 0 and 1, node 2 only 0; offset 3 reached
+        // node 3 alone and 2 nobody
+        for value in ["a", "b"] {
+            storages[0].append(record(value).with_epoch(epoch)).await.unwrap();
+        }
+        storages[1].append(record("a").with_epoch(epoch)).await.unwrap();
+        let memory = Arc::new(MemoryReplica::new(3));
+        for (offset, value) in [(0, "a"), (1, "b"), (3, "d")] {
+            let mut stored = record(value).with_epoch(epoch);
+            stored.offset = LogOffset::new(u64::from(offset));
+            memory.write(partition, epoch, offset, stored).unwrap();
+        }
+
+        let mut replicas: Vec<Arc<dyn EpochReplica>> = storages
+            .iter()
+            .enumerate()
+            .map(|(i, storage)| {
+                Arc::new(StorageReplica::new(i as u64 + 1, partition, Arc::clone(storage)))
+                    as Arc<dyn EpochReplica>
+            })
+            .collect();
+        replicas.push(memory);
+        let recovery = EpochRecovery::new(QuorumConfig::majority(3));
+        let recovered = recovery.recover(partition, epoch, 0, &replicas).await.unwrap();
+        assert_eq!(recovered.tail, Some(3));
+        assert_eq!(recovered.holes, vec![2..=2]);
+
+        for storage in &storages {
+            assert_eq!(storage.high_watermark(), LogOffset::new(5));
+            let stored = storage
+                .read_range(OffsetRange::new(LogOffset::ZERO, LogOffset::new(5)))
+                .await
+                .unwrap();
+            let values: Vec<&[u8]> = stored.iter().map(|record| record.value.as_ref()).collect();
+            let gaps: Vec<Option<GapKind>> = stored.iter().map(|record| record.gap_kind()).collect();
+            assert_eq!(values, [&b"a"[..], b"b", b"", b"d", b""]);
+            assert_eq!(gaps, [None, None, Some(GapKind::Hole), None, Some(GapKind::Bridge)]);
+            assert_eq!(storage.lsn(LogOffset::new(4)), Some(EpochOffset::new(epoch, 4)));
+        }
+
+        // Recovering again finds everything in place
+        let recovered = recovery.recover(partition, epoch, 0, &replicas).await.unwrap();
+        assert_eq!(recovered.rereplicated, 0);
+        assert_eq!(storages[1].high_watermark(), LogOffset::new(5));
+    }
+
+    #[tokio::test]
+    async fn test_empty_epoch_is_sealed_without_offsets() {
+        let log_id = LogId::new("default", "events");
+        let partition = PartitionId::new(0);
+        let (memory, replicas) = replicas(3);
+        let epochs = Arc::new(MemoryEpochLog::new());
+        let recovery = EpochRecovery::new(QuorumConfig::majority(3));
+
+        // Node 1 dies before writing anything
+        let epoch1 = Sequencer::new(1, epochs.clone()).activate(&log_id, partition, 0).await.unwrap();
+
+        let sequencer2 = Sequencer::new(2, epochs);
+        recovery
+            .activate(&sequencer2, &log_id, partition, 0, &replicas[1], &replicas)
+            .await
+            .unwrap();
+
+        let store = sequencer2.get_epoch_store(&log_id, partition).unwrap();
+        let previous = store.get_epoch(epoch1).unwrap();
+        assert!(!previous.can_write());
+        assert_eq!(previous.last_known_offset, None);
+        assert_eq!(memory[0].get(partition, epoch1, 0).unwrap().gap_kind(), Some(GapKind::Bridge));
+    }
+}
@@ -1,23 +1,27 @@
-use bytes::Bytes;
 use pyralog_core::{
-    LogOffset, PartitionId, Record, RecordBatch, Result, PyralogError,
+    Epoch, LogId, LogOffset, PartitionId, RecordBatch, Result, PyralogError,
     traits::{ReplicationManager as ReplicationManagerTrait, ReplicationStatus},
 };
 use parking_lot::RwLock;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
 
 use crate::copyset::{CopySet, CopySetSelector};
 use crate::quorum::{QuorumConfig, QuorumSet};
 use crate::sync::SyncManager;
+use crate::transport::{ReplicaBatch, ReplicaRequest, ReplicaResponse, ReplicaTransport};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct ReplicationConfig {
     pub quorum: QuorumConfig,
     pub max_in_flight: usize,
     pub retry_attempts: usize,
     pub timeout_ms: u64,
+    /// Seed for copyset selection, for reproducible placement in tests and
+    /// simulations; placement is random when unset
+    pub seed: Option<u64>,
 }
 
 impl Default for ReplicationConfig {
@@ -27,6 +31,7 @@ impl Default for ReplicationConfig {
             max_in_flight: 1000,
             retry_attempts: 3,
             timeout_ms: 5000,
+            seed: None,
         }
     }
 }
@@ -36,20 +41,39 @@ pub struct ReplicationManager {
     sync_manager: Arc<SyncManager>,
     copyset_selector: Arc<RwLock<CopySetSelector>>,
     partition_copysets: Arc<RwLock<HashMap<PartitionId, CopySet>>>,
+    /// Highest sealed epoch of each partition
+    sealed_epochs: Arc<RwLock<HashMap<PartitionId, Epoch>>>,
+    /// Reaches the replicas; without one, replication is only recorded
+    transport: Option<Arc<dyn ReplicaTransport>>,
 }
 
 impl ReplicationManager {
     pub fn new(config: ReplicationConfig, cluster_nodes: Vec<u64>) -> Self {
-        let copyset_selector = CopySetSelector::new(
-            cluster_nodes,
-            config.quorum.replication_factor,
-        );
+        let replication_factor = config.quorum.replication_factor;
+        let copyset_selector = match config.seed {
+            Some(seed) => CopySetSelector::with_seed(cluster_nodes, replication_factor, seed),
+            None => CopySetSelector::new(cluster_nodes, replication_factor),
+        };
 
         Self {
             config,
             sync_manager: Arc::new(SyncManager::new()),
             copyset_selector: Arc::new(RwLock::new(copyset_selector)),
             partition_copysets: Arc::new(RwLock::new(HashMap::new())),
+            sealed_epochs: Arc::new(RwLock::new(HashMap::new())),
+            transport: None,
+        }
+    }
+
+    /// Create a manager that sends batches to replicas through `transport`
+    pub fn with_transport(
+        config: ReplicationConfig,
+        cluster_nodes: Vec<u64>,
+        transport: Arc<dyn ReplicaTransport>,
+    ) -> Self {
+        Self {
+            transport: Some(transport),
+            ..Self::new(config, cluster_nodes)
         }
     }
 
@@ -69,21 +93,83 @@ impl ReplicationManager {
         Some(copyset)
     }
 
+    /// Refuse to replicate batches from `epoch` and earlier epochs of a
+    /// partition
+    pub fn seal_epoch(&self, partition: PartitionId, epoch: Epoch) {
+        let mut sealed = self.sealed_epochs.write();
+        let sealed = sealed.entry(partition).or_insert(epoch);
+        *sealed = (*sealed).max(epoch);
+    }
+
+    /// Quorum for a copyset of `replication_factor` nodes
+    ///
+    /// The configured quorum if the copyset has the configured size, and a
+    /// majority quorum otherwise, e.g. for logs created with another
+    /// replication factor.
+    pub fn quorum(&self, replication_factor: usize) -> QuorumConfig {
+        if replication_factor == self.config.quorum.replication_factor {
+            return self.config.quorum.clone();
+        }
+        QuorumConfig::majority(replication_factor)
+    }
+
     /// Replicate to a specific set of nodes
+    ///
+    /// Returns once `write_quorum` of `nodes` have stored the batch; the
+    /// remaining sends carry on in the background. A sequencer that stored
+    /// its own copy first asks for one acknowledgment less from the others.
+    /// Fails with `EpochSealed` if the batch comes from a sealed epoch, and
+    /// with `QuorumNotAvailable` if too many replicas fail or time out.
     pub async fn replicate_to_nodes(
         &self,
-        partition: PartitionId,
-        batch: RecordBatch,
+        batch: ReplicaBatch,
         nodes: &[u64],
+        write_quorum: usize,
     ) -> Result<()> {
-        let mut quorum = QuorumSet::new(nodes.to_vec(), self.config.quorum.write_quorum);
+        let partition = batch.partition;
+        if let Some(&sealed) = self.sealed_epochs.read().get(&partition) {
+            if batch.batch.epoch <= sealed {
+                return Err(PyralogError::EpochSealed(sealed));
+            }
+        }
+
+        let mut quorum = QuorumSet::new(nodes.to_vec(), write_quorum);
 
-        // In production, this would send RPCs to nodes
-        // For now, simulate successful replication
-        for &node_id in nodes.iter().take(self.config.quorum.write_quorum) {
+        if let Some(transport) = &self.transport {
+            let (acks, mut received) = mpsc::unbounded_channel();
+            for &node_id in nodes {
+                let transport = Arc::clone(transport);
+                let sync_manager = Arc::clone(&self.sync_manager);
+                let request = ReplicaRequest::Append(batch.clone());
+                let acks = acks.clone();
+                let timeout = Duration::from_millis(self.config.timeout_ms);
+                tokio::spawn(async move {
+                    let sent = tokio::time::timeout(timeout, transport.send(node_id, request)).await;
+                    if let Ok(Ok(ReplicaResponse::Appended(offset))) = sent {
+                        sync_manager.update_offset(node_id, offset);
+                        acks.send(node_id).ok();
+                    }
+                });
+            }
+            drop(acks);
+
+            if quorum.is_satisfied() {
+                return Ok(());
+            }
+            while let Some(node_id) = received.recv().await {
+                quorum.add_response(node_id);
+                if quorum.is_satisfied() {
+                    return Ok(());
+                }
+            }
+            return Err(PyralogError::QuorumNotAvailable);
+        }
+
+        // Without a transport, replication is assumed to succeed
+        for &node_id in nodes.iter().take(write_quorum) {
             quorum.add_response(node_id);
             
-            if let Some(last_offset) = batch.last_offset() {
+            if let Some(last_offset) = batch.batch.last_offset() {
                 self.sync_manager.update_offset(node_id, last_offset);
             }
         }
@@ -115,7 +201,7 @@ impl ReplicationManager {
 impl ReplicationManagerTrait for ReplicationManager {
     async fn replicate(&mut self, batch: RecordBatch) -> Result<()> {
         // Determine partition from batch
-        // For now, use partition 0 as default
+        // For now, use partition 0 of the default log
         let partition = PartitionId::new(0);
 
         // Get copyset for this partition
@@ -124,7 +210,14 @@ impl ReplicationManagerTrait for ReplicationManager {
             .ok_or_else(|| PyralogError::ReplicationError("Failed to get copyset".to_string()))?;
 
         // Replicate to the copyset nodes
-        self.replicate_to_nodes(partition, batch, &copyset.nodes)
+        let batch = ReplicaBatch {
+            log_id: LogId::new("default", "default"),
+            partition,
+            sequencer: copyset.leader,
+            prev: None,
+            batch,
+        };
+        self.replicate_to_nodes(batch, &copyset.nodes, self.config.quorum.write_quorum)
             .await
     }
 
@@ -178,6 +271,8 @@ impl ReplicationManagerTrait for ReplicationManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bytes::Bytes;
+    use pyralog_core::Record;
 
     #[test]
     fn test_replication_manager() {
@@ -188,5 +283,90 @@ mod tests {
         let copyset = manager.get_copyset(PartitionId::new(0)).unwrap();
         assert_eq!(copyset.size(), 3);
     }
-}
 
+    fn replica_batch(batch: RecordBatch) -> ReplicaBatch {
+        ReplicaBatch {
+            log_id: LogId::new("default", "events"),
+            partition: PartitionId::new(0),
+            sequencer: 1,
+            prev: None,
+            batch,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sealed_epoch_is_not_replicated() {
+        let manager = ReplicationManager::new(ReplicationConfig::default(), vec![1, 2, 3]);
+        let partition = PartitionId::new(0);
+        let batch = |epoch| {
+            let record = Record::new(None, Bytes::from_static(b"x")).with_epoch(epoch);
+            replica_batch(RecordBatch::new(LogOffset::ZERO, vec![record]).with_epoch(epoch))
+        };
+
+        manager.seal_epoch(partition, Epoch::new(2));
+
+        let stale = manager.replicate_to_nodes(batch(Epoch::new(2)), &[1, 2, 3], 2).await;
+        assert!(matches!(stale, Err(PyralogError::EpochSealed(_))));
+        manager
+            .replicate_to_nodes(batch(Epoch::new(3)), &[1, 2, 3], 2)
+            .await
+            .unwrap();
+    }
+
+    /// Stores batches on every node except the unreachable ones
+    struct PartialTransport {
+        unreachable: Vec<u64>,
+    }
+
+    #[async_trait::async_trait]
+    impl ReplicaTransport for PartialTransport {
+        async fn send(&self, target: u64, request: ReplicaRequest) -> Result<ReplicaResponse> {
+            if self.unreachable.contains(&target) {
+                return Err(PyralogError::NetworkError(format!("Node {} is unreachable", target)));
+            }
+            match request {
+                ReplicaRequest::Append(batch) => Ok(ReplicaResponse::Appended(
+                    batch.batch.last_offset().unwrap_or(LogOffset::ZERO),
+                )),
+                _ => Err(PyralogError::InvalidRequest("Expected Append".to_string())),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replication_needs_a_write_quorum() {
+        let batch = || {
+            let mut record = Record::new(None, Bytes::from_static(b"x"));
+            record.offset = LogOffset::new(7);
+            replica_batch(RecordBatch::new(record.offset, vec![record]))
+        };
+        let manager = |unreachable| {
+            let transport = Arc::new(PartialTransport { unreachable });
+            ReplicationManager::with_transport(ReplicationConfig::default(), vec![1, 2, 3], transport)
+        };
+
+        let manager_one_down = manager(vec![3]);
+        manager_one_down
+            .replicate_to_nodes(batch(), &[1, 2, 3], 2)
+            .await
+            .unwrap();
+        assert_eq!(manager_one_down.committed_offset(), LogOffset::new(7));
+
+        let result = manager(vec![2, 3]).replicate_to_nodes(batch(), &[1, 2, 3], 2).await;
+        assert!(matches!(result, Err(PyralogError::QuorumNotAvailable)));
+
+        // A sequencer holding its own copy needs one of the other two
+        manager(vec![3]).replicate_to_nodes(batch(), &[2, 3], 1).await.unwrap();
+        let result = manager(vec![2, 3]).replicate_to_nodes(batch(), &[2, 3], 1).await;
+        assert!(matches!(result, Err(PyralogError::QuorumNotAvailable)));
+    }
+
+    #[test]
+    fn test_quorum_follows_copyset_size() {
+        let manager = ReplicationManager::new(ReplicationConfig::default(), vec![1, 2, 3, 4, 5]);
+
+        assert_eq!(manager.quorum(3).write_quorum, 2);
+        assert_eq!(manager.quorum(1).write_quorum, 1);
+        assert_eq!(manager.quorum(5).write_quorum, 3);
+    }
+}
@@ -7,7 +7,7 @@ use tokio::sync::Notify;
 /// Synchronization manager for tracking replication progress
 pub struct SyncManager {
     /// Track the highest offset replicated to each node
-    node_offsets: Arc<RwLock<HashMap<u64, LogOffset>>>,
+    pub(crate) node_offsets: Arc<RwLock<HashMap<u64, LogOffset>>>,
     
     /// Notification system for offset updates
     notifiers: Arc<RwLock<HashMap<u64, Arc<Notify>>>>,
@@ -22,9 +22,13 @@ impl SyncManager {
     }
 
     /// Update the replicated offset for a node
+    ///
+    /// Acknowledgements can arrive out of order; a lower offset than the
+    /// node already reached is ignored.
     pub fn update_offset(&self, node_id: u64, offset: LogOffset) {
         let mut offsets = self.node_offsets.write();
-        offsets.insert(node_id, offset);
+        let current = offsets.entry(node_id).or_insert(offset);
+        *current = (*current).max(offset);
         
         // Notify any waiters
         if let Some(notifier) = self.notifiers.read().get(&node_id) {
@@ -0,0 +1,294 @@
+use async_trait::async_trait;
+use pyralog_consensus::{RaftHandler, RaftRequest, RaftResponse, RaftTransport};
+use pyralog_core::{Epoch, LogId, LogOffset, PartitionId, PyralogError, Record, RecordBatch, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::recovery::EpochReplica;
+
+/// A batch a partition's sequencer sends to the partition's replicas
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicaBatch {
+    pub log_id: LogId,
+    pub partition: PartitionId,
+
+    /// Node that sequenced the batch; a replica missing the records
+    /// before it reads them from there
+    pub sequencer: u64,
+
+    /// Record the sequencer holds just before the batch, or None if it
+    /// holds none
+    pub prev: Option<Record>,
+
+    /// Records carry the offsets the sequencer gave them
+    pub batch: RecordBatch,
+}
+
+/// Request a sequencer sends to one replica of a log's partition
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplicaRequest {
+    /// Store a batch after the sequencer's `prev` record
+    Append(ReplicaBatch),
+
+    /// Up to `max_records` records from `offset` on, gap markers included,
+    /// for a replica catching up with its sequencer
+    Read {
+        log_id: LogId,
+        partition: PartitionId,
+        offset: LogOffset,
+        max_records: usize,
+    },
+
+    /// See `EpochReplica::seal`
+    Seal {
+        log_id: LogId,
+        partition: PartitionId,
+        epoch: Epoch,
+    },
+
+    /// See `EpochReplica::read_epoch`
+    ReadEpoch {
+        log_id: LogId,
+        partition: PartitionId,
+        epoch: Epoch,
+        offsets: RangeInclusive<u32>,
+    },
+
+    /// See `EpochReplica::store_recovered`
+    StoreRecovered {
+        log_id: LogId,
+        partition: PartitionId,
+        epoch: Epoch,
+        records: Vec<(u32, Record)>,
+    },
+}
+
+/// A replica's answer to a `ReplicaRequest`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplicaResponse {
+    /// The batch is stored; the highest offset the replica holds
+    Appended(LogOffset),
+    Records(Vec<Record>),
+    Sealed(Option<RangeInclusive<u32>>),
+    Epoch(BTreeMap<u32, Record>),
+    Stored,
+}
+
+/// Sends requests to the replicas of a partition
+#[async_trait]
+pub trait ReplicaTransport: Send + Sync {
+    /// Send a request to a replica and wait for its answer
+    async fn send(&self, target: u64, request: ReplicaRequest) -> Result<ReplicaResponse>;
+}
+
+/// Serves the requests of partitions' sequencers
+#[async_trait]
+pub trait ReplicaHandler: Send + Sync {
+    async fn handle(&self, request: ReplicaRequest) -> Result<ReplicaResponse>;
+}
+
+/// Copy of a log's partition held by another node, as seen by epoch
+/// recovery
+///
+/// Requests that get no answer within the timeout fail, so a replica that
+/// is down or partitioned away only delays recovery.
+pub struct RemoteReplica {
+    node_id: u64,
+    log_id: LogId,
+    transport: Arc<dyn ReplicaTransport>,
+    timeout: Duration,
+}
+
+impl RemoteReplica {
+    pub fn new(node_id: u64, log_id: LogId, transport: Arc<dyn ReplicaTransport>, timeout: Duration) -> Self {
+        Self {
+            node_id,
+            log_id,
+            transport,
+            timeout,
+        }
+    }
+
+    async fn send(&self, request: ReplicaRequest) -> Result<ReplicaResponse> {
+        tokio::time::timeout(self.timeout, self.transport.send(self.node_id, request))
+            .await
+            .map_err(|_| PyralogError::Timeout)?
+    }
+}
+
+fn unexpected(response: ReplicaResponse) -> PyralogError {
+    PyralogError::ReplicationError(format!("Unexpected replica response {:?}", response))
+}
+
+#[async_trait]
+impl EpochReplica for RemoteReplica {
+    fn node_id(&self) -> u64 {
+        self.node_id
+    }
+
+    async fn seal(&self, partition: PartitionId, epoch: Epoch) -> Result<Option<RangeInclusive<u32>>> {
+        let request = ReplicaRequest::Seal {
+            log_id: self.log_id.clone(),
+            partition,
+            epoch,
+        };
+        match self.send(request).await? {
+            ReplicaResponse::Sealed(stored) => Ok(stored),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn read_epoch(
+        &self,
+        partition: PartitionId,
+        epoch: Epoch,
+        offsets: RangeInclusive<u32>,
+    ) -> Result<BTreeMap<u32, Record>> {
+        let request = ReplicaRequest::ReadEpoch {
+            log_id: self.log_id.clone(),
+            partition,
+            epoch,
+            offsets,
+        };
+        match self.send(request).await? {
+            ReplicaResponse::Epoch(records) => Ok(records),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn store_recovered(
+        &self,
+        partition: PartitionId,
+        epoch: Epoch,
+        records: Vec<(u32, Record)>,
+    ) -> Result<()> {
+        let request = ReplicaRequest::StoreRecovered {
+            log_id: self.log_id.clone(),
+            partition,
+            epoch,
+            records,
+        };
+        match self.send(request).await? {
+            ReplicaResponse::Stored => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+}
+
+/// Replica traffic sent over a Raft transport, so it travels between the
+/// nodes' internal addresses along with Raft's
+pub struct RaftReplicaTransport {
+    raft: Arc<dyn RaftTransport>,
+}
+
+impl RaftReplicaTransport {
+    pub fn new(raft: Arc<dyn RaftTransport>) -> Self {
+        Self { raft }
+    }
+}
+
+#[async_trait]
+impl ReplicaTransport for RaftReplicaTransport {
+    async fn send(&self, target: u64, request: ReplicaRequest) -> Result<ReplicaResponse> {
+        let payload = bincode::serialize(&request)
+            .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+        match self.raft.send(target, RaftRequest::Peer(payload)).await? {
+            RaftResponse::Peer(payload) => bincode::deserialize::<Result<ReplicaResponse>>(&payload)
+                .map_err(|e| PyralogError::SerializationError(e.to_string()))?,
+            response => Err(PyralogError::ReplicationError(format!(
+                "Unexpected Raft response {:?}",
+                response
+            ))),
+        }
+    }
+}
+
+/// Handles what arrives on a node's internal address: replica traffic
+/// sent by a `RaftReplicaTransport` goes to `replicas`, the rest to `raft`
+pub struct ReplicaRouter {
+    raft: Arc<dyn RaftHandler>,
+    replicas: Arc<dyn ReplicaHandler>,
+}
+
+impl ReplicaRouter {
+    pub fn new(raft: Arc<dyn RaftHandler>, replicas: Arc<dyn ReplicaHandler>) -> Self {
+        Self { raft, replicas }
+    }
+}
+
+#[async_trait]
+impl RaftHandler for ReplicaRouter {
+    async fn handle(&self, request: RaftRequest) -> Result<RaftResponse> {
+        let RaftRequest::Peer(payload) = request else {
+            return self.raft.handle(request).await;
+        };
+        let request: ReplicaRequest = bincode::deserialize(&payload)
+            .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+
+        // Errors are part of the answer, so the sender sees them as they were
+        let response = self.replicas.handle(request).await;
+        bincode::serialize(&response)
+            .map(RaftResponse::Peer)
+            .map_err(|e| PyralogError::SerializationError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyralog_consensus::ChannelNetwork;
+
+    struct SealedReplica;
+
+    #[async_trait]
+    impl ReplicaHandler for SealedReplica {
+        async fn handle(&self, request: ReplicaRequest) -> Result<ReplicaResponse> {
+            match request {
+                ReplicaRequest::Seal { .. } => Ok(ReplicaResponse::Sealed(Some(0..=4))),
+                _ => Err(PyralogError::EpochSealed(Epoch::new(3))),
+            }
+        }
+    }
+
+    struct NoRaft;
+
+    #[async_trait]
+    impl RaftHandler for NoRaft {
+        async fn handle(&self, _request: RaftRequest) -> Result<RaftResponse> {
+            Err(PyralogError::ConsensusError("No Raft groups".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replica_traffic_shares_raft_transport() {
+        let network = ChannelNetwork::new();
+        network.register(1, Arc::new(NoRaft));
+        network.register(2, Arc::new(ReplicaRouter::new(Arc::new(NoRaft), Arc::new(SealedReplica))));
+        let transport = RaftReplicaTransport::new(Arc::new(network.transport(1)));
+        let log_id = LogId::new("test", "log");
+
+        let sealed = transport
+            .send(2, ReplicaRequest::Seal {
+                log_id: log_id.clone(),
+                partition: PartitionId(0),
+                epoch: Epoch::new(3),
+            })
+            .await
+            .unwrap();
+        assert!(matches!(sealed, ReplicaResponse::Sealed(Some(offsets)) if offsets == (0..=4)));
+
+        // The replica's own error comes back intact
+        let read = transport
+            .send(2, ReplicaRequest::Read {
+                log_id,
+                partition: PartitionId(0),
+                offset: LogOffset::ZERO,
+                max_records: 1,
+            })
+            .await;
+        assert!(matches!(read, Err(PyralogError::EpochSealed(epoch)) if epoch == Epoch::new(3)));
+    }
+}
@@ -0,0 +1,22 @@
+//! Pyralog Sim - Deterministic simulation of consensus and replication
+//!
+//! Runs a whole cluster of `RaftNode`s and `ReplicationManager`s in one
+//! process on a paused tokio clock. Election timeouts, message loss and
+//! delay, partitions, crashes and client operations are all drawn from a
+//! single seed, so a failing schedule replays exactly from its seed.
+//!
+//! Committed writes are replicated by the leader's `ReplicationManager`
+//! over the same simulated network, so batches meet the same faults as
+//! Raft traffic.
+//!
+//! After every step the simulation checks Raft's safety properties: at
+//! most one leader per term, log matching, and that committed and
+//! acknowledged entries are never lost or changed. Replicas must only hold
+//! committed entries, and every write a quorum of replicas confirmed must
+//! still be held by a write quorum once the cluster is healed.
+
+pub mod network;
+pub mod simulation;
+
+pub use network::{NetworkFaults, SimNetwork, SimTransport};
+pub use simulation::{run, SimConfig, SimReport};
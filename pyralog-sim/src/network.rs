@@ -0,0 +1,246 @@
+use async_trait::async_trait;
+use parking_lot::{Mutex, RwLock};
+use pyralog_consensus::{RaftHandler, RaftRequest, RaftResponse, RaftTransport};
+use pyralog_core::{Result, PyralogError};
+use pyralog_replication::{ReplicaHandler, ReplicaRequest, ReplicaResponse, ReplicaTransport};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Faults currently injected into a `SimNetwork`
+#[derive(Debug, Clone)]
+pub struct NetworkFaults {
+    /// Probability that a request or response is lost
+    pub drop_rate: f64,
+
+    /// Smallest one-way delivery delay
+    pub min_delay: Duration,
+
+    /// Largest one-way delivery delay; messages overtake each other when
+    /// the range is wide
+    pub max_delay: Duration,
+
+    /// Directed links that lose every message, as `(from, to)`
+    pub cut: BTreeSet<(u64, u64)>,
+}
+
+impl Default for NetworkFaults {
+    fn default() -> Self {
+        Self {
+            drop_rate: 0.0,
+            min_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+            cut: BTreeSet::new(),
+        }
+    }
+}
+
+/// In-process network that drops, delays, reorders and partitions messages
+///
+/// Carries both Raft RPCs and the record batches a `ReplicationManager`
+/// sends to replicas, under the same faults. Every decision is drawn from one seeded generator, and delays are tokio
+/// sleeps, so under a paused clock on a current-thread runtime the same
+/// seed always delivers the same messages in the same order.
+pub struct SimNetwork {
+    rng: Mutex<StdRng>,
+    faults: RwLock<NetworkFaults>,
+    handlers: RwLock<BTreeMap<u64, Arc<dyn RaftHandler>>>,
+    replicas: RwLock<BTreeMap<u64, Arc<dyn ReplicaHandler>>>,
+
+    /// Latest transport handed out for each node; older ones belong to
+    /// crashed incarnations and can no longer send
+    incarnations: RwLock<BTreeMap<u64, u64>>,
+    down: RwLock<BTreeSet<u64>>,
+}
+
+impl SimNetwork {
+    pub fn new(seed: u64, faults: NetworkFaults) -> Arc<Self> {
+        Arc::new(Self {
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+            faults: RwLock::new(faults),
+            handlers: RwLock::new(BTreeMap::new()),
+            replicas: RwLock::new(BTreeMap::new()),
+            incarnations: RwLock::new(BTreeMap::new()),
+            down: RwLock::new(BTreeSet::new()),
+        })
+    }
+
+    /// Transport for a new incarnation of `node_id`
+    ///
+    /// Transports of earlier incarnations stop working, as after a restart.
+    pub fn transport(self: &Arc<Self>, node_id: u64) -> SimTransport {
+        let mut incarnations = self.incarnations.write();
+        let incarnation = incarnations.get(&node_id).map_or(0, |latest| latest + 1);
+        incarnations.insert(node_id, incarnation);
+
+        SimTransport {
+            node_id,
+            incarnation,
+            network: Arc::clone(self),
+        }
+    }
+
+    /// Attach a node so it receives the requests addressed to it
+    pub fn register(&self, node_id: u64, handler: Arc<dyn RaftHandler>) {
+        self.handlers.write().insert(node_id, handler);
+        self.down.write().remove(&node_id);
+    }
+
+    /// Attach a node's replica so it receives the batches addressed to it
+    pub fn register_replica(&self, node_id: u64, handler: Arc<dyn ReplicaHandler>) {
+        self.replicas.write().insert(node_id, handler);
+    }
+
+    /// Stop delivering messages to and from a node until it is registered
+    /// again
+    pub fn crash(&self, node_id: u64) {
+        self.down.write().insert(node_id);
+    }
+
+    /// Split the nodes into groups that cannot reach each other
+    pub fn partition(&self, groups: &[Vec<u64>]) {
+        let mut faults = self.faults.write();
+        faults.cut.clear();
+        for (i, group) in groups.iter().enumerate() {
+            for other in groups.iter().skip(i + 1) {
+                for &a in group {
+                    for &b in other {
+                        faults.cut.insert((a, b));
+                        faults.cut.insert((b, a));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Lose every message sent from `from` to `to`, but not the reverse
+    pub fn cut_link(&self, from: u64, to: u64) {
+        self.faults.write().cut.insert((from, to));
+    }
+
+    /// Restore every link
+    pub fn heal(&self) {
+        self.faults.write().cut.clear();
+    }
+
+    pub fn set_drop_rate(&self, drop_rate: f64) {
+        self.faults.write().drop_rate = drop_rate;
+    }
+
+    pub fn faults(&self) -> NetworkFaults {
+        self.faults.read().clone()
+    }
+
+    fn delay(&self) -> Duration {
+        let (min, max) = {
+            let faults = self.faults.read();
+            (faults.min_delay, faults.max_delay)
+        };
+        if max <= min {
+            return min;
+        }
+        self.rng.lock().gen_range(min..=max)
+    }
+
+    /// Whether a message from `from` to `to` gets through right now
+    fn delivers(&self, from: u64, to: u64) -> bool {
+        let drop_rate = {
+            let down = self.down.read();
+            if down.contains(&from) || down.contains(&to) {
+                return false;
+            }
+            let faults = self.faults.read();
+            if faults.cut.contains(&(from, to)) {
+                return false;
+            }
+            faults.drop_rate
+        };
+        drop_rate <= 0.0 || !self.rng.lock().gen_bool(drop_rate.min(1.0))
+    }
+
+    fn is_current(&self, node_id: u64, incarnation: u64) -> bool {
+        self.incarnations.read().get(&node_id) == Some(&incarnation)
+    }
+
+    /// Carry one message of an exchange started by an incarnation of
+    /// `from`; returns whether it arrived
+    async fn hop(&self, from: u64, incarnation: u64, sender: u64, receiver: u64) -> bool {
+        // A lost message is noticed no sooner than a delivered one
+        sleep(self.delay()).await;
+        self.is_current(from, incarnation) && self.delivers(sender, receiver)
+    }
+
+    async fn deliver(&self, from: u64, incarnation: u64, to: u64, request: RaftRequest) -> Result<RaftResponse> {
+        let unreachable = || PyralogError::NetworkError(format!("Node {} is unreachable", to));
+
+        if !self.hop(from, incarnation, from, to).await {
+            return Err(unreachable());
+        }
+        let handler = self
+            .handlers
+            .read()
+            .get(&to)
+            .cloned()
+            .ok_or_else(unreachable)?;
+        let response = handler.handle(request).await;
+
+        if !self.hop(from, incarnation, to, from).await {
+            return Err(unreachable());
+        }
+        response
+    }
+
+    async fn deliver_replica(
+        &self,
+        from: u64,
+        incarnation: u64,
+        to: u64,
+        request: ReplicaRequest,
+    ) -> Result<ReplicaResponse> {
+        let unreachable = || PyralogError::NetworkError(format!("Node {} is unreachable", to));
+
+        if !self.hop(from, incarnation, from, to).await {
+            return Err(unreachable());
+        }
+        let handler = self
+            .replicas
+            .read()
+            .get(&to)
+            .cloned()
+            .ok_or_else(unreachable)?;
+        let response = handler.handle(request).await;
+
+        if !self.hop(from, incarnation, to, from).await {
+            return Err(unreachable());
+        }
+        response
+    }
+}
+
+/// Transport of one incarnation of a node on a `SimNetwork`
+pub struct SimTransport {
+    node_id: u64,
+    incarnation: u64,
+    network: Arc<SimNetwork>,
+}
+
+#[async_trait]
+impl RaftTransport for SimTransport {
+    async fn send(&self, target: u64, request: RaftRequest) -> Result<RaftResponse> {
+        self.network
+            .deliver(self.node_id, self.incarnation, target, request)
+            .await
+    }
+}
+
+#[async_trait]
+impl ReplicaTransport for SimTransport {
+    async fn send(&self, target: u64, request: ReplicaRequest) -> Result<ReplicaResponse> {
+        self.network
+            .deliver_replica(self.node_id, self.incarnation, target, request)
+            .await
+    }
+}
@@ -0,0 +1,702 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use parking_lot::Mutex;
+use pyralog_consensus::election::{ElectionTimeoutConfig, LeadershipConfig};
+use pyralog_consensus::{NodeRole, PipelineConfig, RaftConfig, RaftNode, SnapshotConfig, StateMachine};
+use pyralog_core::{Epoch, LogId, LogOffset, PartitionId, Record, RecordBatch, Result, PyralogError};
+use pyralog_replication::{
+    ReplicaBatch, ReplicaHandler, ReplicaRequest, ReplicaResponse, ReplicationConfig, ReplicationManager,
+};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+use tokio::time::{sleep, timeout};
+
+use crate::network::{NetworkFaults, SimNetwork};
+
+type Applied = Arc<Mutex<Vec<(u64, Vec<u8>)>>>;
+
+/// The one partition whose records the simulation replicates
+const PARTITION: PartitionId = PartitionId(0);
+
+/// Shape of one simulated run
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    /// Drives every random choice of the run
+    pub seed: u64,
+
+    /// Number of voters
+    pub nodes: u64,
+
+    /// Number of client operations and faults injected before the cluster
+    /// is healed
+    pub steps: usize,
+
+    /// Probability that a message is lost while faults are injected
+    pub drop_rate: f64,
+
+    /// Largest one-way message delay
+    pub max_delay: Duration,
+}
+
+impl SimConfig {
+    /// A configuration with cluster size, loss and delay drawn from `seed`
+    pub fn random(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        Self {
+            seed,
+            nodes: *[3, 5].choose(&mut rng).unwrap(),
+            steps: 200,
+            drop_rate: rng.gen_range(0.0..0.2),
+            max_delay: Duration::from_millis(rng.gen_range(2..=40)),
+        }
+    }
+}
+
+/// Outcome of a run that upheld every invariant
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimReport {
+    pub seed: u64,
+
+    /// Highest term that elected a leader
+    pub term: u64,
+
+    /// Leader of each term that elected one
+    pub leaders: BTreeMap<u64, u64>,
+
+    /// Writes acknowledged to clients, by log index
+    pub acknowledged: usize,
+
+    /// Acknowledged writes that also reached a write quorum of replicas
+    pub replicated: usize,
+
+    /// Highest index committed
+    pub commit_index: u64,
+
+    /// Time the run took on the simulated clock
+    pub elapsed: Duration,
+}
+
+/// Run one simulation on its own paused-clock runtime
+///
+/// Fails with a `ConsensusError` naming the seed and the violated invariant.
+/// The same configuration always produces the same schedule and outcome.
+pub fn run(config: SimConfig) -> Result<SimReport> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .start_paused(true)
+        .build()
+        .map_err(|e| PyralogError::IoError(e.to_string()))?;
+
+    let seed = config.seed;
+    runtime
+        .block_on(async move {
+            let mut simulation = Simulation::new(config).map_err(|e| e.to_string())?;
+            let report = simulation.run().await;
+            simulation.shutdown();
+            report
+        })
+        .map_err(|e| PyralogError::ConsensusError(format!("seed {}: {}", seed, e)))
+}
+
+/// Records the entries a node applies
+#[derive(Default)]
+struct Recorder(Applied);
+
+impl StateMachine for Recorder {
+    fn apply(&mut self, index: u64, data: &[u8]) {
+        self.0.lock().push((index, data.to_vec()));
+    }
+
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        bincode::serialize(&*self.0.lock())
+            .map_err(|e| PyralogError::SerializationError(e.to_string()))
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<()> {
+        *self.0.lock() = bincode::deserialize(snapshot)
+            .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+        Ok(())
+    }
+}
+
+/// Records a node stores as a replica of the partition, by offset
+///
+/// Kept across the node's restarts, like a disk.
+#[derive(Default)]
+struct Replica {
+    records: Mutex<BTreeMap<u64, Vec<u8>>>,
+}
+
+#[async_trait]
+impl ReplicaHandler for Replica {
+    async fn handle(&self, request: ReplicaRequest) -> Result<ReplicaResponse> {
+        let ReplicaRequest::Append(replica) = request else {
+            return Err(PyralogError::InvalidRequest("Replicas only take batches".to_string()));
+        };
+        let mut records = self.records.lock();
+        for record in replica.batch.records {
+            records.insert(record.offset.as_u64(), record.value.to_vec());
+        }
+        Ok(ReplicaResponse::Appended(LogOffset::new(
+            records.keys().next_back().copied().unwrap_or(0),
+        )))
+    }
+}
+
+struct SimNode {
+    raft: Arc<RaftNode>,
+    applied: Applied,
+    replication: Arc<ReplicationManager>,
+    up: bool,
+    restarts: u64,
+}
+
+/// What the checker has learned so far; anything contradicting it is a
+/// safety violation
+#[derive(Default)]
+struct History {
+    /// Leader elected in each term
+    leaders: BTreeMap<u64, u64>,
+
+    /// Data of every entry seen committed, by index
+    committed: BTreeMap<u64, Vec<u8>>,
+
+    /// Writes acknowledged to clients, by index
+    acknowledged: BTreeMap<u64, Vec<u8>>,
+
+    /// Acknowledged writes a write quorum of replicas confirmed, by index
+    replicated: BTreeMap<u64, Vec<u8>>,
+
+    /// Highest index seen committed anywhere
+    commit_index: u64,
+
+    /// Violations found by client tasks, reported at the next check
+    violations: Vec<String>,
+}
+
+struct Simulation {
+    config: SimConfig,
+    rng: StdRng,
+    dir: TempDir,
+    network: Arc<SimNetwork>,
+    nodes: BTreeMap<u64, SimNode>,
+    replicas: BTreeMap<u64, Arc<Replica>>,
+    history: Arc<Mutex<History>>,
+    next_value: u64,
+}
+
+impl Simulation {
+    fn new(config: SimConfig) -> Result<Self> {
+        let faults = NetworkFaults {
+            drop_rate: config.drop_rate,
+            max_delay: config.max_delay,
+            ..NetworkFaults::default()
+        };
+
+        Ok(Self {
+            rng: StdRng::seed_from_u64(config.seed),
+            dir: TempDir::new().map_err(|e| PyralogError::IoError(e.to_string()))?,
+            network: SimNetwork::new(config.seed, faults),
+            nodes: BTreeMap::new(),
+            replicas: BTreeMap::new(),
+            history: Arc::new(Mutex::new(History::default())),
+            next_value: 0,
+            config,
+        })
+    }
+
+    fn node_ids(&self) -> Vec<u64> {
+        (1..=self.config.nodes).collect()
+    }
+
+    async fn run(&mut self) -> std::result::Result<SimReport, String> {
+        let started = tokio::time::Instant::now();
+        for node_id in self.node_ids() {
+            self.start_node(node_id, 0).await?;
+        }
+
+        for step in 0..self.config.steps {
+            let pause = self.rng.gen_range(1..=100);
+            sleep(Duration::from_millis(pause)).await;
+
+            match self.rng.gen_range(0..100) {
+                0..=59 => self.propose(),
+                60..=69 => self.read(),
+                70..=75 => self.partition(),
+                76..=81 => self.network.heal(),
+                82..=85 => self.crash(),
+                86..=91 => self.restart().await?,
+                92..=94 => self.transfer_leadership(),
+                _ => self.snapshot(),
+            }
+
+            self.check().map_err(|e| format!("step {}: {}", step, e))?;
+        }
+
+        self.converge().await?;
+        self.check().map_err(|e| format!("after healing: {}", e))?;
+
+        let history = self.history.lock();
+        Ok(SimReport {
+            seed: self.config.seed,
+            term: history.leaders.keys().next_back().copied().unwrap_or(0),
+            leaders: history.leaders.clone(),
+            acknowledged: history.acknowledged.len(),
+            replicated: history.replicated.len(),
+            commit_index: history.commit_index,
+            elapsed: started.elapsed(),
+        })
+    }
+
+    async fn start_node(&mut self, node_id: u64, restarts: u64) -> std::result::Result<(), String> {
+        let config = RaftConfig {
+            node_id,
+            cluster_nodes: self.node_ids(),
+            data_dir: self.dir.path().to_path_buf(),
+            election_timeout: ElectionTimeoutConfig::default(),
+            leadership: LeadershipConfig {
+                lease: true,
+                ..LeadershipConfig::default()
+            },
+            snapshot: SnapshotConfig {
+                threshold: 32,
+                chunk_size: 256,
+            },
+            // Small batches keep several requests in flight, so the
+            // random delays reorder them
+            pipeline: PipelineConfig {
+                max_in_flight: 4,
+                max_entries: 4,
+                ..PipelineConfig::default()
+            },
+            seed: Some(self.config.seed ^ (node_id << 32) ^ restarts),
+        };
+
+        let applied = Applied::default();
+        let transport = Arc::new(self.network.transport(node_id));
+        let raft = RaftNode::new(config, transport.clone(), Box::new(Recorder(Arc::clone(&applied))))
+            .await
+            .map_err(|e| format!("node {} failed to open: {}", node_id, e))?;
+        let raft = Arc::new(raft);
+        self.network.register(node_id, raft.clone());
+        Arc::clone(&raft)
+            .start()
+            .await
+            .map_err(|e| format!("node {} failed to start: {}", node_id, e))?;
+
+        // Every node places partitions itself; a shared seed must make them
+        // agree. Batches go to the replicas over the simulated network.
+        let replication = Arc::new(ReplicationManager::with_transport(
+            ReplicationConfig {
+                seed: Some(self.config.seed),
+                ..ReplicationConfig::default()
+            },
+            self.node_ids(),
+            transport,
+        ));
+        let replica = self.replicas.entry(node_id).or_default();
+        self.network.register_replica(node_id, replica.clone());
+
+        self.nodes.insert(
+            node_id,
+            SimNode {
+                raft,
+                applied,
+                replication,
+                up: true,
+                restarts,
+            },
+        );
+        Ok(())
+    }
+
+    fn up_nodes(&self) -> Vec<u64> {
+        self.nodes
+            .iter()
+            .filter(|(_, node)| node.up)
+            .map(|(&node_id, _)| node_id)
+            .collect()
+    }
+
+    fn leaders(&self) -> Vec<u64> {
+        self.nodes
+            .iter()
+            .filter(|(_, node)| node.up && node.raft.is_leader())
+            .map(|(&node_id, _)| node_id)
+            .collect()
+    }
+
+    /// Send a client write to a node that believes it leads, and once it
+    /// is committed, replicate it to the partition's copyset in the
+    /// leader's term
+    fn propose(&mut self) {
+        let Some(&leader) = self.leaders().choose(&mut self.rng) else {
+            return;
+        };
+        let value = format!("{}-{}", self.config.seed, self.next_value).into_bytes();
+        self.next_value += 1;
+
+        let raft = Arc::clone(&self.nodes[&leader].raft);
+        let replication = Arc::clone(&self.nodes[&leader].replication);
+        let history = Arc::clone(&self.history);
+        tokio::spawn(async move {
+            let Ok(offset) = raft.propose(Bytes::from(value.clone())).await else {
+                return;
+            };
+            let index = offset.as_u64();
+            {
+                let mut history = history.lock();
+                if history.acknowledged.get(&index).is_some_and(|existing| *existing != value) {
+                    history
+                        .violations
+                        .push(format!("two writes acknowledged at index {}", index));
+                }
+                history.acknowledged.insert(index, value.clone());
+            }
+
+            if replicate(&raft, &replication, offset, value.clone()).await.is_ok() {
+                history.lock().replicated.insert(index, value);
+            }
+        });
+    }
+
+    /// Issue a linearizable read, which must observe every write
+    /// acknowledged before it started
+    fn read(&mut self) {
+        let Some(&node_id) = self.up_nodes().choose(&mut self.rng) else {
+            return;
+        };
+        let raft = Arc::clone(&self.nodes[&node_id].raft);
+        let history = Arc::clone(&self.history);
+        let acknowledged = history.lock().acknowledged.keys().next_back().copied().unwrap_or(0);
+
+        tokio::spawn(async move {
+            if let Ok(read_index) = raft.read_index().await {
+                if read_index < acknowledged {
+                    history.lock().violations.push(format!(
+                        "node {} read at index {} after index {} was acknowledged",
+                        node_id, read_index, acknowledged
+                    ));
+                }
+            }
+        });
+    }
+
+    fn partition(&mut self) {
+        let mut node_ids = self.node_ids();
+        node_ids.shuffle(&mut self.rng);
+        let split = self.rng.gen_range(1..node_ids.len());
+        let (a, b) = node_ids.split_at(split);
+        self.network.partition(&[a.to_vec(), b.to_vec()]);
+    }
+
+    fn crash(&mut self) {
+        let Some(&node_id) = self.up_nodes().choose(&mut self.rng) else {
+            return;
+        };
+        let node = self.nodes.get_mut(&node_id).unwrap();
+        node.raft.shutdown();
+        node.up = false;
+        self.network.crash(node_id);
+    }
+
+    async fn restart(&mut self) -> std::result::Result<(), String> {
+        let down: Vec<u64> = self
+            .nodes
+            .iter()
+            .filter(|(_, node)| !node.up)
+            .map(|(&node_id, _)| node_id)
+            .collect();
+        let Some(&node_id) = down.choose(&mut self.rng) else {
+            return Ok(());
+        };
+        let restarts = self.nodes[&node_id].restarts + 1;
+        self.start_node(node_id, restarts).await
+    }
+
+    fn transfer_leadership(&mut self) {
+        let Some(&leader) = self.leaders().first() else {
+            return;
+        };
+        let targets: Vec<u64> = self.node_ids().into_iter().filter(|&id| id != leader).collect();
+        let target = *targets.choose(&mut self.rng).unwrap();
+
+        let raft = Arc::clone(&self.nodes[&leader].raft);
+        tokio::spawn(async move {
+            raft.transfer_leadership(target).await.ok();
+        });
+    }
+
+    fn snapshot(&mut self) {
+        if let Some(&node_id) = self.up_nodes().choose(&mut self.rng) {
+            self.nodes[&node_id].raft.snapshot().ok();
+        }
+    }
+
+    /// Heal every fault, then wait for a leader to commit a final write and
+    /// for every node to apply it
+    async fn converge(&mut self) -> std::result::Result<(), String> {
+        self.network.heal();
+        self.network.set_drop_rate(0.0);
+        for node_id in self.node_ids() {
+            if !self.nodes[&node_id].up {
+                let restarts = self.nodes[&node_id].restarts + 1;
+                self.start_node(node_id, restarts).await?;
+            }
+        }
+
+        let mut final_index = None;
+        for _ in 0..100 {
+            sleep(Duration::from_millis(100)).await;
+            let Some(&leader) = self.leaders().first() else {
+                continue;
+            };
+            let raft = &self.nodes[&leader].raft;
+            if let Ok(Ok(offset)) = timeout(Duration::from_secs(1), raft.propose(Bytes::new())).await {
+                final_index = Some(offset.as_u64());
+                break;
+            }
+        }
+        let final_index = final_index.ok_or("no leader committed a write after healing")?;
+
+        for (&node_id, node) in &self.nodes {
+            timeout(Duration::from_secs(10), node.raft.wait_applied(final_index))
+                .await
+                .map_err(|_| format!("node {} did not apply index {}", node_id, final_index))?;
+        }
+
+        // Every node has applied the same entries, including every
+        // acknowledged write
+        {
+            let history = self.history.lock();
+            for (&node_id, node) in &self.nodes {
+                let applied: BTreeMap<u64, Vec<u8>> = node.applied.lock().iter().cloned().collect();
+                for (index, value) in &history.acknowledged {
+                    if applied.get(index) != Some(value) {
+                        return Err(format!("node {} lost acknowledged write at index {}", node_id, index));
+                    }
+                }
+            }
+            let first = self.nodes.values().next().unwrap().applied.lock().clone();
+            for (&node_id, node) in &self.nodes {
+                let applied = node.applied.lock().clone();
+                let common = applied.len().min(first.len());
+                if applied[..common] != first[..common] {
+                    return Err(format!("node {} applied different entries", node_id));
+                }
+            }
+        }
+
+        // With the network healed, the leader replicates the final write to
+        // the whole copyset
+        let leader = self.leaders().first().copied().ok_or("no leader after healing")?;
+        let node = &self.nodes[&leader];
+        replicate(&node.raft, &node.replication, LogOffset::new(final_index), Vec::new())
+            .await
+            .map_err(|e| format!("replicating the final write failed: {}", e))?;
+        sleep(Duration::from_secs(1)).await;
+        if node.replication.committed_offset() < LogOffset::new(final_index) {
+            return Err("replication did not catch up with the final write".to_string());
+        }
+
+        // Every write a write quorum confirmed is still held by one
+        let history = self.history.lock();
+        let copyset = node.replication.get_copyset(PARTITION).ok_or("partition has no copyset")?;
+        let write_quorum = ReplicationConfig::default().quorum.write_quorum;
+        for (index, value) in &history.replicated {
+            let holders = copyset
+                .nodes
+                .iter()
+                .filter(|node_id| self.replicas[node_id].records.lock().get(index) == Some(value))
+                .count();
+            if holders < write_quorum {
+                return Err(format!(
+                    "replicated write at index {} is held by {} replicas, short of its write quorum of {}",
+                    index, holders, write_quorum
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the safety invariants against every node
+    fn check(&mut self) -> std::result::Result<(), String> {
+        let mut history = self.history.lock();
+        if let Some(violation) = history.violations.first() {
+            return Err(violation.clone());
+        }
+
+        let mut logs = BTreeMap::new();
+        for (&node_id, node) in &self.nodes {
+            let (term, role, commit_index, log) = node.raft.with_state(|state| {
+                (
+                    state.persistent.current_term,
+                    state.role,
+                    state.volatile.commit_index,
+                    state
+                        .persistent
+                        .log
+                        .iter()
+                        .map(|entry| (entry.index, (entry.term, entry.data.clone())))
+                        .collect::<BTreeMap<_, _>>(),
+                )
+            });
+
+            // Election safety: at most one leader per term. A leader seals
+            // the epochs of earlier terms, so their batches are no longer
+            // replicated.
+            if role == NodeRole::Leader {
+                let leader = *history.leaders.entry(term).or_insert(node_id);
+                if leader != node_id {
+                    return Err(format!("nodes {} and {} both led term {}", leader, node_id, term));
+                }
+                node.replication.seal_epoch(PARTITION, Epoch::new(term - 1));
+            }
+
+            // Committed entries never change, wherever they are seen
+            history.commit_index = history.commit_index.max(commit_index);
+            let committed = log.range(..=commit_index).map(|(&index, (_, data))| (index, data.clone()));
+            let applied = node.applied.lock().clone();
+            for (index, data) in committed.chain(applied) {
+                let known = history.committed.entry(index).or_insert_with(|| data.clone());
+                if *known != data {
+                    return Err(format!("node {} changed committed entry {}", node_id, index));
+                }
+            }
+
+            logs.insert(node_id, log);
+        }
+
+        for (index, value) in &history.acknowledged {
+            if history.committed.get(index).is_some_and(|data| data != value) {
+                return Err(format!("acknowledged write at index {} was overwritten", index));
+            }
+        }
+
+        // Replicas only ever hold committed entries
+        for (&node_id, replica) in &self.replicas {
+            for (index, value) in replica.records.lock().iter() {
+                if history.committed.get(index).is_some_and(|data| data != value) {
+                    return Err(format!(
+                        "replica on node {} holds a different record at index {}",
+                        node_id, index
+                    ));
+                }
+            }
+        }
+
+        // Log matching: logs that agree on the term of an entry agree on
+        // every entry up to it
+        let logs: Vec<_> = logs.into_iter().collect();
+        for (i, (a, log_a)) in logs.iter().enumerate() {
+            for (b, log_b) in &logs[i + 1..] {
+                let mut matched = false;
+                for (index, entry_a) in log_a.iter().rev() {
+                    let Some(entry_b) = log_b.get(index) else {
+                        continue;
+                    };
+                    if entry_a.0 == entry_b.0 && entry_a.1 != entry_b.1 {
+                        return Err(format!(
+                            "nodes {} and {} hold different entries at index {} in term {}",
+                            a, b, index, entry_a.0
+                        ));
+                    }
+                    if matched && entry_a.0 != entry_b.0 {
+                        return Err(format!("logs of nodes {} and {} diverge at index {}", a, b, index));
+                    }
+                    matched |= entry_a.0 == entry_b.0;
+                }
+            }
+        }
+
+        // Replicas are placed the same way on every node, and replication
+        // never reports more than Raft has committed
+        let placements: Vec<_> = self
+            .nodes
+            .values()
+            .map(|node| node.replication.get_copyset(PARTITION).map(|copyset| copyset.nodes))
+            .collect();
+        if placements.windows(2).any(|pair| pair[0] != pair[1]) {
+            return Err("nodes disagree on the copyset of partition 0".to_string());
+        }
+        for node in self.nodes.values() {
+            if node.replication.committed_offset().as_u64() > history.commit_index {
+                return Err("replication reports an uncommitted offset as committed".to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn shutdown(&self) {
+        for node in self.nodes.values() {
+            node.raft.shutdown();
+        }
+    }
+}
+
+/// Replicate a committed entry to the partition's copyset, in the epoch
+/// of the term `raft` is in
+async fn replicate(
+    raft: &RaftNode,
+    replication: &ReplicationManager,
+    offset: LogOffset,
+    value: Vec<u8>,
+) -> Result<()> {
+    let (node_id, term) = raft.with_state(|state| (state.node_id, state.persistent.current_term));
+    let epoch = Epoch::new(term);
+    let mut record = Record::new(None, Bytes::from(value)).with_epoch(epoch);
+    record.offset = offset;
+    let batch = RecordBatch::new(offset, vec![record]).with_epoch(epoch);
+
+    let copyset = replication
+        .get_copyset(PARTITION)
+        .ok_or_else(|| PyralogError::ReplicationError("Failed to get copyset".to_string()))?;
+    let batch = ReplicaBatch {
+        log_id: LogId::new("sim", "entries"),
+        partition: PARTITION,
+        sequencer: node_id,
+        prev: None,
+        batch,
+    };
+    let write_quorum = replication.quorum(copyset.nodes.len()).write_quorum;
+    replication.replicate_to_nodes(batch, &copyset.nodes, write_quorum).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(name: &str) -> Option<u64> {
+        std::env::var(name).ok().and_then(|value| value.parse().ok())
+    }
+
+    #[test]
+    fn test_same_seed_same_outcome() {
+        let first = run(SimConfig::random(42)).unwrap();
+        let second = run(SimConfig::random(42)).unwrap();
+
+        assert_eq!(first, second);
+        assert!(first.acknowledged > 0);
+        assert!(first.replicated > 0);
+    }
+
+    /// Run `PYRALOG_SIM_SCHEDULES` random schedules starting at
+    /// `PYRALOG_SIM_SEED`; a failure names the seed that reproduces it
+    #[test]
+    fn test_random_schedules() {
+        let schedules = env("PYRALOG_SIM_SCHEDULES").unwrap_or(8);
+        let first_seed = env("PYRALOG_SIM_SEED").unwrap_or(0);
+
+        for seed in first_seed..first_seed + schedules {
+            if let Err(e) = run(SimConfig::random(seed)) {
+                panic!("{}", e);
+            }
+        }
+    }
+}
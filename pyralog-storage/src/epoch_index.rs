@@ -0,0 +1,196 @@
+use pyralog_core::{Epoch, LogOffset, Record, Result, PyralogError};
+use parking_lot::RwLock;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+const EPOCH_ENTRY_SIZE: usize = 16; // 8 + 8 bytes
+
+/// Where each epoch starts within a segment
+///
+/// Persisted next to the segment as `<base>.epochs`, one entry per epoch
+/// change: the epoch and the offset of its first record. An epoch carried
+/// over from the previous segment keeps its start there, so LSNs survive
+/// retention deleting that segment. Epochs only increase, so the records of
+/// an epoch are contiguous.
+pub struct EpochIndex {
+    path: PathBuf,
+    file: RwLock<File>,
+    starts: RwLock<Vec<(Epoch, LogOffset)>>,
+}
+
+impl EpochIndex {
+    /// Create a new, empty epoch index for a segment
+    pub fn create(segment_path: &Path) -> Result<Self> {
+        let path = segment_path.with_extension("epochs");
+
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+
+        Ok(Self {
+            path,
+            file: RwLock::new(file),
+            starts: RwLock::new(Vec::new()),
+        })
+    }
+
+    /// Open an existing epoch index
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+
+        let mut starts = Vec::new();
+        let mut buffer = [0u8; EPOCH_ENTRY_SIZE];
+
+        loop {
+            match file.read_exact(&mut buffer) {
+                Ok(_) => {
+                    let epoch = u64::from_le_bytes(buffer[0..8].try_into().unwrap());
+                    let offset = u64::from_le_bytes(buffer[8..16].try_into().unwrap());
+                    starts.push((Epoch::new(epoch), LogOffset::new(offset)));
+                }
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(PyralogError::StorageError(e.to_string())),
+            }
+        }
+
+        Ok(Self {
+            path,
+            file: RwLock::new(file),
+            starts: RwLock::new(starts),
+        })
+    }
+
+    /// Note a newly appended record, recording a new entry if it starts an
+    /// epoch
+    pub fn append(&self, record: &Record) -> Result<()> {
+        self.record_start(record.epoch, record.offset)
+    }
+
+    /// Record that the segment continues `epoch`, which started at `start`
+    /// in an earlier segment
+    pub fn carry(&self, epoch: Epoch, start: LogOffset) -> Result<()> {
+        self.record_start(epoch, start)
+    }
+
+    fn record_start(&self, epoch: Epoch, start: LogOffset) -> Result<()> {
+        let mut starts = self.starts.write();
+        if starts.last().is_some_and(|(last, _)| *last == epoch) {
+            return Ok(());
+        }
+
+        let mut buffer = [0u8; EPOCH_ENTRY_SIZE];
+        buffer[0..8].copy_from_slice(&epoch.as_u64().to_le_bytes());
+        buffer[8..16].copy_from_slice(&start.as_u64().to_le_bytes());
+
+        self.file
+            .write()
+            .write_all(&buffer)
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+
+        starts.push((epoch, start));
+
+        Ok(())
+    }
+
+    /// Each epoch in the segment with the offset of its first record
+    pub fn starts(&self) -> Vec<(Epoch, LogOffset)> {
+        self.starts.read().clone()
+    }
+
+    /// Sync the epoch index to disk
+    pub fn sync(&self) -> Result<()> {
+        self.file
+            .read()
+            .sync_all()
+            .map_err(|e| PyralogError::StorageError(e.to_string()))
+    }
+
+    /// Get the path to this epoch index
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::log_storage::{LogStorage, LogStorageConfig};
+    use bytes::Bytes;
+    use pyralog_core::{Epoch, EpochOffset, LogOffset, Record};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_lsns_survive_reopen() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = LogStorageConfig::default();
+        config.cache_config.enabled = false;
+        config.segment_config.max_size = 256;
+
+        let storage = LogStorage::create(temp_dir.path().to_path_buf(), config.clone())
+            .await
+            .unwrap();
+        for (i, epoch) in [1, 1, 1, 3, 3, 4, 4, 4].into_iter().enumerate() {
+            let record = Record::new(None, Bytes::from(format!("event-{}", i)));
+            storage.append(record.with_epoch(Epoch::new(epoch))).await.unwrap();
+        }
+        drop(storage);
+
+        let storage = LogStorage::open(temp_dir.path().to_path_buf(), config)
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.epoch_starts(),
+            vec![
+                (Epoch::new(1), LogOffset::new(0)),
+                (Epoch::new(3), LogOffset::new(3)),
+                (Epoch::new(4), LogOffset::new(5)),
+            ]
+        );
+
+        let lsn = EpochOffset::new(Epoch::new(4), 1);
+        assert_eq!(storage.lsn(LogOffset::new(6)), Some(lsn));
+        assert_eq!(storage.offset_for_lsn(lsn), Some(LogOffset::new(6)));
+        assert_eq!(storage.offset_for_lsn(EpochOffset::new(Epoch::new(3), 2)), None);
+        assert_eq!(storage.offset_for_lsn(EpochOffset::new(Epoch::new(2), 0)), None);
+    }
+
+    #[tokio::test]
+    async fn test_lsns_survive_retention() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = LogStorageConfig::default();
+        config.cache_config.enabled = false;
+        config.segment_config.max_size = 256;
+
+        let storage = LogStorage::create(temp_dir.path().to_path_buf(), config.clone())
+            .await
+            .unwrap();
+        for (i, epoch) in [1, 1, 1, 1, 1, 1, 2, 2].into_iter().enumerate() {
+            let record = Record::new(None, Bytes::from(format!("event-{}", i)));
+            storage.append(record.with_epoch(Epoch::new(epoch))).await.unwrap();
+        }
+        let lsn = storage.lsn(LogOffset::new(5)).unwrap();
+        let root = storage.merkle_root(LogOffset::new(7)).unwrap().unwrap();
+
+        let low_watermark = storage.delete_segments_below(LogOffset::new(5)).await.unwrap();
+        assert!(low_watermark > LogOffset::ZERO && low_watermark <= LogOffset::new(5));
+        assert!(storage.read(LogOffset::ZERO).await.unwrap().is_none());
+        drop(storage);
+
+        let storage = LogStorage::open(temp_dir.path().to_path_buf(), config)
+            .await
+            .unwrap();
+        assert_eq!(storage.low_watermark(), low_watermark);
+        assert_eq!(storage.lsn(LogOffset::new(5)), Some(lsn));
+        assert_eq!(lsn, EpochOffset::new(Epoch::new(1), 5));
+        assert_eq!(storage.offset_for_lsn(lsn), Some(LogOffset::new(5)));
+        assert_eq!(storage.merkle_root(LogOffset::new(7)).unwrap().unwrap(), root);
+    }
+}
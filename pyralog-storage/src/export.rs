@@ -0,0 +1,241 @@
+use arrow::array::{
+    ArrayRef, BinaryBuilder, MapBuilder, StringBuilder, TimestampMicrosecondArray, UInt64Array,
+};
+use arrow::datatypes::{DataType, Field, Fields, Schema, SchemaRef, TimeUnit};
+use arrow::ipc::writer::StreamWriter;
+use arrow::record_batch::RecordBatch as ArrowBatch;
+use parquet::arrow::ArrowWriter;
+use pyralog_core::{OffsetRange, Record, Result, PyralogError};
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of records per Arrow record batch when exporting
+pub const DEFAULT_EXPORT_BATCH_SIZE: usize = 8192;
+
+/// Output format for exported log ranges
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Arrow IPC streaming format
+    ArrowIpc,
+
+    /// Apache Parquet file
+    Parquet,
+}
+
+/// Range of a partition to export
+#[derive(Debug, Clone, Copy)]
+pub enum ExportRange {
+    /// Records with offsets in [start, end)
+    Offsets(OffsetRange),
+
+    /// Records with timestamps in [start, end)
+    Time { start: SystemTime, end: SystemTime },
+}
+
+impl ExportRange {
+    /// Check whether a record falls inside this range
+    pub fn contains(&self, record: &Record) -> bool {
+        match self {
+            ExportRange::Offsets(range) => range.contains(record.offset),
+            ExportRange::Time { start, end } => {
+                record.timestamp >= *start && record.timestamp < *end
+            }
+        }
+    }
+}
+
+/// Arrow schema used for exported records
+///
+/// Columns: offset, epoch, timestamp, key, value and headers (as a map
+/// from header key to header value).
+pub fn record_schema() -> SchemaRef {
+    let header_entries = Fields::from(vec![
+        Field::new("keys", DataType::Utf8, false),
+        Field::new("values", DataType::Binary, true),
+    ]);
+
+    Arc::new(Schema::new(vec![
+        Field::new("offset", DataType::UInt64, false),
+        Field::new("epoch", DataType::UInt64, false),
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("key", DataType::Binary, true),
+        Field::new("value", DataType::Binary, false),
+        Field::new(
+            "headers",
+            DataType::Map(
+                Arc::new(Field::new("entries", DataType::Struct(header_entries), false)),
+                false,
+            ),
+            false,
+        ),
+    ]))
+}
+
+/// Convert records into a single Arrow record batch
+pub fn records_to_batch(records: &[Record]) -> Result<ArrowBatch> {
+    let offsets = UInt64Array::from_iter_values(records.iter().map(|r| r.offset.as_u64()));
+    let epochs = UInt64Array::from_iter_values(records.iter().map(|r| r.epoch.as_u64()));
+    let timestamps = TimestampMicrosecondArray::from_iter_values(
+        records.iter().map(|r| timestamp_micros(r.timestamp)),
+    )
+    .with_timezone("UTC");
+
+    let mut keys = BinaryBuilder::new();
+    let mut values = BinaryBuilder::new();
+    let mut headers = MapBuilder::new(None, StringBuilder::new(), BinaryBuilder::new());
+
+    for record in records {
+        match &record.key {
+            Some(key) => keys.append_value(key),
+            None => keys.append_null(),
+        }
+        values.append_value(&record.value);
+
+        for header in &record.headers {
+            headers.keys().append_value(&header.key);
+            headers.values().append_value(&header.value);
+        }
+        headers
+            .append(true)
+            .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+    }
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(offsets),
+        Arc::new(epochs),
+        Arc::new(timestamps),
+        Arc::new(keys.finish()),
+        Arc::new(values.finish()),
+        Arc::new(headers.finish()),
+    ];
+
+    ArrowBatch::try_new(record_schema(), columns)
+        .map_err(|e| PyralogError::SerializationError(e.to_string()))
+}
+
+/// Incremental writer of exported records
+///
+/// Records are converted and written one batch at a time, so a large range
+/// can be exported without holding it in memory.
+pub enum ExportWriter<W: Write + Send> {
+    ArrowIpc(StreamWriter<W>),
+    Parquet(ArrowWriter<W>),
+}
+
+impl<W: Write + Send> ExportWriter<W> {
+    /// Start writing records in the given format
+    pub fn new(writer: W, format: ExportFormat) -> Result<Self> {
+        let schema = record_schema();
+        match format {
+            ExportFormat::ArrowIpc => StreamWriter::try_new(writer, &schema)
+                .map(ExportWriter::ArrowIpc)
+                .map_err(|e| PyralogError::SerializationError(e.to_string())),
+            ExportFormat::Parquet => ArrowWriter::try_new(writer, schema, None)
+                .map(ExportWriter::Parquet)
+                .map_err(|e| PyralogError::SerializationError(e.to_string())),
+        }
+    }
+
+    /// Write records as one record batch
+    pub fn write(&mut self, records: &[Record]) -> Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        let batch = records_to_batch(records)?;
+        let result = match self {
+            ExportWriter::ArrowIpc(stream) => stream.write(&batch).map_err(|e| e.to_string()),
+            ExportWriter::Parquet(parquet) => parquet.write(&batch).map_err(|e| e.to_string()),
+        };
+        result.map_err(PyralogError::SerializationError)
+    }
+
+    /// Finish the stream or file
+    pub fn finish(self) -> Result<()> {
+        let result = match self {
+            ExportWriter::ArrowIpc(mut stream) => stream.finish().map_err(|e| e.to_string()),
+            ExportWriter::Parquet(parquet) => parquet.close().map(|_| ()).map_err(|e| e.to_string()),
+        };
+        result.map_err(PyralogError::SerializationError)
+    }
+}
+
+/// Write records as an Arrow IPC stream
+pub fn write_ipc<W: Write + Send>(records: &[Record], writer: W, batch_size: usize) -> Result<()> {
+    write_records(records, writer, ExportFormat::ArrowIpc, batch_size)
+}
+
+/// Write records as a Parquet file
+pub fn write_parquet<W: Write + Send>(records: &[Record], writer: W, batch_size: usize) -> Result<()> {
+    write_records(records, writer, ExportFormat::Parquet, batch_size)
+}
+
+/// Write records in the given format
+pub fn write_records<W: Write + Send>(
+    records: &[Record],
+    writer: W,
+    format: ExportFormat,
+    batch_size: usize,
+) -> Result<()> {
+    let mut export = ExportWriter::new(writer, format)?;
+    for chunk in records.chunks(batch_size.max(1)) {
+        export.write(chunk)?;
+    }
+    export.finish()
+}
+
+fn timestamp_micros(timestamp: SystemTime) -> i64 {
+    match timestamp.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_micros() as i64,
+        Err(before) => -(before.duration().as_micros() as i64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{Array, MapArray};
+    use arrow::ipc::reader::StreamReader;
+    use bytes::Bytes;
+    use pyralog_core::{LogOffset, RecordHeader};
+
+    fn test_records() -> Vec<Record> {
+        (0..3)
+            .map(|i| {
+                let mut record = Record::new(
+                    if i % 2 == 0 { Some(Bytes::from(format!("key-{}", i))) } else { None },
+                    Bytes::from(format!("value-{}", i)),
+                )
+                .with_headers(vec![RecordHeader::new("trace".to_string(), Bytes::from("abc"))]);
+                record.offset = LogOffset::new(i);
+                record
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_records_to_batch() {
+        let batch = records_to_batch(&test_records()).unwrap();
+        assert_eq!(batch.num_rows(), 3);
+        assert_eq!(batch.num_columns(), 6);
+        assert!(batch.column(3).is_null(1));
+
+        let headers = batch.column(5).as_any().downcast_ref::<MapArray>().unwrap();
+        assert_eq!(headers.value(0).len(), 1);
+    }
+
+    #[test]
+    fn test_ipc_roundtrip() {
+        let mut buffer = Vec::new();
+        write_ipc(&test_records(), &mut buffer, 2).unwrap();
+
+        let reader = StreamReader::try_new(buffer.as_slice(), None).unwrap();
+        let rows: usize = reader.map(|batch| batch.unwrap().num_rows()).sum();
+        assert_eq!(rows, 3);
+    }
+}
@@ -0,0 +1,97 @@
+use pyralog_core::{Epoch, PyralogError, Result};
+use parking_lot::RwLock;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const FENCE_FILE: &str = "sealed_epoch";
+
+/// Highest sealed epoch of a partition replica
+///
+/// Persisted as `sealed_epoch` in the log directory, so a replica keeps
+/// refusing a deposed sequencer's writes after a restart.
+pub struct EpochFence {
+    path: PathBuf,
+    sealed: RwLock<Epoch>,
+}
+
+impl EpochFence {
+    /// Load the fence of a log directory; nothing is sealed if it has none
+    pub fn open(dir: &Path) -> Result<Self> {
+        let path = dir.join(FENCE_FILE);
+
+        let sealed = match fs::read(&path) {
+            Ok(data) => {
+                let bytes: [u8; 8] = data.as_slice().try_into().map_err(|_| {
+                    PyralogError::StorageError(format!("Corrupt fence file {}", path.display()))
+                })?;
+                Epoch::new(u64::from_le_bytes(bytes))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Epoch::INVALID,
+            Err(e) => return Err(PyralogError::StorageError(e.to_string())),
+        };
+
+        Ok(Self {
+            path,
+            sealed: RwLock::new(sealed),
+        })
+    }
+
+    /// Highest sealed epoch; `Epoch::INVALID` if none is
+    pub fn sealed(&self) -> Epoch {
+        *self.sealed.read()
+    }
+
+    /// Durably seal `epoch` and every earlier one
+    ///
+    /// Seals never move back; sealing an already sealed epoch does nothing.
+    pub fn seal(&self, epoch: Epoch) -> Result<()> {
+        let mut sealed = self.sealed.write();
+        if epoch <= *sealed {
+            return Ok(());
+        }
+
+        let temp_path = self.path.with_extension("tmp");
+        let mut file = File::create(&temp_path)
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+        file.write_all(&epoch.as_u64().to_le_bytes())
+            .and_then(|_| file.sync_all())
+            .and_then(|_| fs::rename(&temp_path, &self.path))
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+
+        *sealed = epoch;
+        Ok(())
+    }
+
+    /// Fail with `EpochSealed` if a write from `epoch` must be refused
+    pub fn check(&self, epoch: Epoch) -> Result<()> {
+        let sealed = self.sealed();
+        if sealed.is_valid() && epoch <= sealed {
+            return Err(PyralogError::EpochSealed(sealed));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_fence_survives_reopen() {
+        let dir = TempDir::new().unwrap();
+
+        let fence = EpochFence::open(dir.path()).unwrap();
+        assert!(fence.check(Epoch::INVALID).is_ok());
+
+        fence.seal(Epoch::new(3)).unwrap();
+        fence.seal(Epoch::new(2)).unwrap();
+        assert!(matches!(fence.check(Epoch::new(3)), Err(PyralogError::EpochSealed(_))));
+        assert!(fence.check(Epoch::new(4)).is_ok());
+
+        let fence = EpochFence::open(dir.path()).unwrap();
+        assert_eq!(fence.sealed(), Epoch::new(3));
+        assert!(fence.check(Epoch::new(1)).is_err());
+    }
+}
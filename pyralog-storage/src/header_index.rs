@@ -0,0 +1,340 @@
+use bytes::Bytes;
+use pyralog_core::{LogOffset, OffsetRange, Record, Result, PyralogError};
+use parking_lot::RwLock;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Secondary index from header values to record offsets within a segment
+///
+/// Only headers whose key is in `keys` are indexed. The file starts with the
+/// list of indexed keys so a segment can be reindexed when the configured
+/// keys change; it is followed by one entry per indexed header:
+/// `[key len: u16][key][value len: u32][value][offset: u64]`.
+pub struct HeaderIndex {
+    path: PathBuf,
+    file: RwLock<File>,
+    keys: Vec<String>,
+    entries: RwLock<HashMap<String, HashMap<Bytes, Vec<LogOffset>>>>,
+}
+
+impl HeaderIndex {
+    /// Create a new, empty header index for a segment
+    pub fn create(segment_path: &Path, keys: &[String]) -> Result<Self> {
+        let path = segment_path.with_extension("hindex");
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&(keys.len() as u16).to_le_bytes());
+        for key in keys {
+            header.extend_from_slice(&(key.len() as u16).to_le_bytes());
+            header.extend_from_slice(key.as_bytes());
+        }
+        file.write_all(&header)
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+
+        Ok(Self {
+            path,
+            file: RwLock::new(file),
+            keys: keys.to_vec(),
+            entries: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// Open an existing header index
+    ///
+    /// Returns `None` if the index was built for a different set of keys or
+    /// its list of keys is incomplete. Entries for offsets at or past `end`,
+    /// which were indexed before their record reached the segment, and a
+    /// partially written trailing entry are cut off.
+    pub fn open(path: PathBuf, keys: &[String], end: LogOffset) -> Result<Option<Self>> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+        let mut reader = EntryReader { data: &data, position: 0 };
+
+        let Some(indexed_keys) = reader.keys() else {
+            return Ok(None);
+        };
+        if indexed_keys != keys {
+            return Ok(None);
+        }
+
+        let mut entries: HashMap<String, HashMap<Bytes, Vec<LogOffset>>> = HashMap::new();
+        let mut complete = reader.position;
+        while let Some((key, value, offset)) = reader.entry()? {
+            if offset >= end {
+                break;
+            }
+            entries
+                .entry(key)
+                .or_default()
+                .entry(value)
+                .or_default()
+                .push(offset);
+            complete = reader.position;
+        }
+
+        file.set_len(complete as u64)
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+
+        Ok(Some(Self {
+            path,
+            file: RwLock::new(file),
+            keys: keys.to_vec(),
+            entries: RwLock::new(entries),
+        }))
+    }
+
+    /// Index the headers of a newly appended record
+    pub fn append(&self, record: &Record) -> Result<()> {
+        let indexed: Vec<_> = record
+            .headers
+            .iter()
+            .filter(|header| self.keys.contains(&header.key))
+            .collect();
+
+        if indexed.is_empty() {
+            return Ok(());
+        }
+
+        let mut buffer = Vec::new();
+        for header in &indexed {
+            buffer.extend_from_slice(&(header.key.len() as u16).to_le_bytes());
+            buffer.extend_from_slice(header.key.as_bytes());
+            buffer.extend_from_slice(&(header.value.len() as u32).to_le_bytes());
+            buffer.extend_from_slice(&header.value);
+            buffer.extend_from_slice(&record.offset.as_u64().to_le_bytes());
+        }
+
+        self.file
+            .write()
+            .write_all(&buffer)
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+
+        let mut entries = self.entries.write();
+        for header in indexed {
+            entries
+                .entry(header.key.clone())
+                .or_default()
+                .entry(header.value.clone())
+                .or_default()
+                .push(record.offset);
+        }
+
+        Ok(())
+    }
+
+    /// Check whether a header key is indexed in this segment
+    pub fn is_indexed(&self, key: &str) -> bool {
+        self.keys.iter().any(|k| k == key)
+    }
+
+    /// Offsets in `range` of records with a header `key` equal to `value`
+    pub fn lookup(&self, key: &str, value: &[u8], range: OffsetRange) -> Vec<LogOffset> {
+        let entries = self.entries.read();
+
+        let mut offsets: Vec<LogOffset> = entries
+            .get(key)
+            .and_then(|values| values.get(value))
+            .map(|offsets| {
+                offsets
+                    .iter()
+                    .copied()
+                    .filter(|offset| range.contains(*offset))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        offsets.sort_unstable();
+        offsets.dedup();
+        offsets
+    }
+
+    /// Sync the header index to disk
+    pub fn sync(&self) -> Result<()> {
+        self.file
+            .read()
+            .sync_all()
+            .map_err(|e| PyralogError::StorageError(e.to_string()))
+    }
+
+    /// Get the path to this header index
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Parses the bytes of a header index file
+struct EntryReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl EntryReader<'_> {
+    /// Take the next `len` bytes, or `None` if the file ends first
+    fn take(&mut self, len: usize) -> Option<&[u8]> {
+        let bytes = self.data.get(self.position..self.position.checked_add(len)?)?;
+        self.position += len;
+        Some(bytes)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|bytes| u16::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    /// The indexed keys at the start of the file
+    fn keys(&mut self) -> Option<Vec<String>> {
+        let count = self.u16()?;
+        (0..count)
+            .map(|_| {
+                let len = self.u16()?;
+                String::from_utf8(self.take(len as usize)?.to_vec()).ok()
+            })
+            .collect()
+    }
+
+    /// The next complete entry, or `None` at the end of the file or at a
+    /// partially written entry
+    fn entry(&mut self) -> Result<Option<(String, Bytes, LogOffset)>> {
+        let Some((key, value, offset)) = self.raw_entry() else {
+            return Ok(None);
+        };
+        let key = String::from_utf8(key.to_vec())
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+        Ok(Some((key, Bytes::copy_from_slice(value), LogOffset::new(offset))))
+    }
+
+    fn raw_entry(&mut self) -> Option<(&[u8], &[u8], u64)> {
+        let key_len = self.u16()?;
+        let key_start = self.position;
+        self.take(key_len as usize)?;
+        let value_len = self.u32()?;
+        let value_start = self.position;
+        self.take(value_len as usize)?;
+        let offset = self.u64()?;
+
+        let key = &self.data[key_start..key_start + key_len as usize];
+        let value = &self.data[value_start..value_start + value_len as usize];
+        Some((key, value, offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::log_storage::{LogStorage, LogStorageConfig};
+    use bytes::Bytes;
+    use pyralog_core::{LogOffset, OffsetRange, Record, RecordHeader};
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    fn traced(trace: &str, value: &str) -> Record {
+        Record::new(None, Bytes::from(value.to_string()))
+            .with_headers(vec![RecordHeader::new("trace".to_string(), Bytes::from(trace.to_string()))])
+    }
+
+    #[tokio::test]
+    async fn test_find_by_header() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = LogStorageConfig {
+            indexed_headers: vec!["trace".to_string()],
+            ..Default::default()
+        };
+
+        let storage = LogStorage::create(temp_dir.path().to_path_buf(), config.clone())
+            .await
+            .unwrap();
+        for (trace, value) in [("a", "1"), ("b", "2"), ("a", "3"), ("c", "4"), ("a", "5")] {
+            storage.append(traced(trace, value)).await.unwrap();
+        }
+
+        let all = OffsetRange::new(LogOffset::ZERO, LogOffset::new(5));
+        let offsets = storage.find_by_header("trace", b"a", all).await.unwrap();
+        assert_eq!(offsets, vec![LogOffset::new(0), LogOffset::new(2), LogOffset::new(4)]);
+
+        let partial = OffsetRange::new(LogOffset::new(1), LogOffset::new(4));
+        let records = storage.read_by_header("trace", b"a", partial).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].value, Bytes::from("3"));
+
+        drop(storage);
+        let reopened = LogStorage::open(temp_dir.path().to_path_buf(), config)
+            .await
+            .unwrap();
+        let offsets = reopened.find_by_header("trace", b"c", all).await.unwrap();
+        assert_eq!(offsets, vec![LogOffset::new(3)]);
+    }
+
+    #[tokio::test]
+    async fn test_open_cuts_torn_and_unstored_entries() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = LogStorageConfig {
+            indexed_headers: vec!["trace".to_string()],
+            ..Default::default()
+        };
+
+        let storage = LogStorage::create(temp_dir.path().to_path_buf(), config.clone())
+            .await
+            .unwrap();
+        storage.append(traced("a", "1")).await.unwrap();
+        storage.append(traced("a", "2")).await.unwrap();
+        storage.flush().await.unwrap();
+        drop(storage);
+
+        // An entry for a record that never reached the segment, then a torn one
+        let hindex_path = std::fs::read_dir(temp_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "hindex"))
+            .unwrap();
+        let mut unstored = Vec::new();
+        unstored.extend_from_slice(&5u16.to_le_bytes());
+        unstored.extend_from_slice(b"trace");
+        unstored.extend_from_slice(&1u32.to_le_bytes());
+        unstored.extend_from_slice(b"a");
+        unstored.extend_from_slice(&2u64.to_le_bytes());
+        let mut file = std::fs::OpenOptions::new().append(true).open(&hindex_path).unwrap();
+        file.write_all(&unstored).unwrap();
+        file.write_all(&unstored[..9]).unwrap();
+        drop(file);
+
+        let all = OffsetRange::new(LogOffset::ZERO, LogOffset::new(10));
+        let reopened = LogStorage::open(temp_dir.path().to_path_buf(), config.clone())
+            .await
+            .unwrap();
+        let offsets = reopened.find_by_header("trace", b"a", all).await.unwrap();
+        assert_eq!(offsets, vec![LogOffset::new(0), LogOffset::new(1)]);
+
+        // Entries appended after the cut are read back on the next open
+        reopened.append(traced("a", "3")).await.unwrap();
+        reopened.flush().await.unwrap();
+        drop(reopened);
+        let reopened = LogStorage::open(temp_dir.path().to_path_buf(), config)
+            .await
+            .unwrap();
+        let offsets = reopened.find_by_header("trace", b"a", all).await.unwrap();
+        assert_eq!(offsets, vec![LogOffset::new(0), LogOffset::new(1), LogOffset::new(2)]);
+    }
+}
@@ -43,9 +43,18 @@ impl Index {
 
     /// Open an existing index
     pub fn open(path: PathBuf) -> Result<Self> {
-        let mut file = OpenOptions::new()
-            .read(true)
-            .write(true)
+        Self::open_with(path, OpenOptions::new().read(true).write(true))
+    }
+
+    /// Open an existing index for reading only; appends fail
+    ///
+    /// A partially written trailing entry is ignored.
+    pub fn open_read_only(path: PathBuf) -> Result<Self> {
+        Self::open_with(path, OpenOptions::new().read(true))
+    }
+
+    fn open_with(path: PathBuf, options: &OpenOptions) -> Result<Self> {
+        let mut file = options
             .open(&path)
             .map_err(|e| PyralogError::StorageError(e.to_string()))?;
 
@@ -122,6 +131,16 @@ impl Index {
             .map(|(_, entry)| (entry.offset, entry.position, entry.size))
     }
 
+    /// Get up to `max` entries starting at the given offset
+    pub fn entries_from(&self, offset: LogOffset, max: usize) -> Vec<(LogOffset, u64, u32)> {
+        self.entries
+            .read()
+            .range(offset.as_u64()..)
+            .take(max)
+            .map(|(_, entry)| (entry.offset, entry.position, entry.size))
+            .collect()
+    }
+
     /// Get all entries in the index
     pub fn entries(&self) -> Vec<(LogOffset, u64, u32)> {
         self.entries
@@ -0,0 +1,137 @@
+use bytes::Bytes;
+use pyralog_core::{LogOffset, Record};
+use parking_lot::RwLock;
+use std::collections::HashMap;
+
+/// Index from record key to the offset of the latest record with that key
+///
+/// Lets keyed logs be read as tables. A record with a key and an empty
+/// value is a tombstone and removes the key, matching compaction semantics.
+/// The index lives in memory and is rebuilt from segments on open.
+pub struct KeyIndex {
+    entries: RwLock<HashMap<Bytes, LogOffset>>,
+}
+
+impl KeyIndex {
+    pub fn new() -> Self {
+        Self {
+            entries: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Update the index with a newly written record
+    pub fn update(&self, record: &Record) {
+        let key = match &record.key {
+            Some(key) => key,
+            None => return,
+        };
+
+        let mut entries = self.entries.write();
+
+        // Ignore records older than the one already indexed
+        if entries.get(key).is_some_and(|latest| *latest > record.offset) {
+            return;
+        }
+
+        if record.value.is_empty() {
+            entries.remove(key);
+        } else {
+            entries.insert(key.clone(), record.offset);
+        }
+    }
+
+    /// Get the offset of the latest record for a key
+    pub fn lookup(&self, key: &[u8]) -> Option<LogOffset> {
+        self.entries.read().get(key).copied()
+    }
+
+    /// Drop entries pointing below `offset`
+    ///
+    /// Called when compaction or retention removes the records before
+    /// `offset`. Returns the number of pruned keys.
+    pub fn prune_below(&self, offset: LogOffset) -> usize {
+        let mut entries = self.entries.write();
+        let before = entries.len();
+        entries.retain(|_, latest| *latest >= offset);
+        before - entries.len()
+    }
+
+    /// Drop every entry, before the index is rebuilt
+    pub fn clear(&self) {
+        self.entries.write().clear();
+    }
+
+    /// Number of indexed keys
+    pub fn len(&self) -> usize {
+        self.entries.read().len()
+    }
+
+    /// Check if the index is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.read().is_empty()
+    }
+}
+
+impl Default for KeyIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_storage::{LogStorage, LogStorageConfig};
+    use tempfile::TempDir;
+
+    fn keyed(offset: u64, key: &str, value: &str) -> Record {
+        let mut record = Record::new(Some(Bytes::from(key.to_string())), Bytes::from(value.to_string()));
+        record.offset = LogOffset::new(offset);
+        record
+    }
+
+    #[test]
+    fn test_latest_and_tombstone() {
+        let index = KeyIndex::new();
+        index.update(&keyed(0, "user-1", "a"));
+        index.update(&keyed(1, "user-2", "b"));
+        index.update(&keyed(2, "user-1", "c"));
+        assert_eq!(index.lookup(b"user-1"), Some(LogOffset::new(2)));
+
+        // Late write of an older record does not win
+        index.update(&keyed(1, "user-1", "stale"));
+        assert_eq!(index.lookup(b"user-1"), Some(LogOffset::new(2)));
+
+        index.update(&keyed(3, "user-2", ""));
+        assert_eq!(index.lookup(b"user-2"), None);
+
+        assert_eq!(index.prune_below(LogOffset::new(3)), 1);
+        assert!(index.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_latest_value_in_write_cache() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = LogStorageConfig {
+            key_index: true,
+            ..Default::default()
+        };
+        config.cache_config.enabled = true;
+        config.cache_config.max_buffer_time = std::time::Duration::from_secs(3600);
+
+        let storage = LogStorage::create(temp_dir.path().to_path_buf(), config)
+            .await
+            .unwrap();
+        storage.append(keyed(0, "user-1", "a")).await.unwrap();
+        storage.flush().await.unwrap();
+
+        // Both stay in the write cache
+        storage.append(keyed(0, "user-1", "b")).await.unwrap();
+        storage.append(keyed(0, "user-2", "c")).await.unwrap();
+
+        let latest = storage.get_latest(b"user-1").await.unwrap().unwrap();
+        assert_eq!(latest.value, Bytes::from("b"));
+        let latest = storage.get_latest(b"user-2").await.unwrap().unwrap();
+        assert_eq!(latest.value, Bytes::from("c"));
+    }
+}
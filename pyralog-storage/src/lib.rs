@@ -6,14 +6,32 @@
 //! - Write-ahead logging
 //! - Zero-copy operations
 //! - Compression support
+//! - Arrow IPC and Parquet export
+//! - BLAKE3 Merkle trees for tamper-evident segments
+//! - Secondary indexes on record headers
+//! - Latest-value lookup by record key
+//! - Epoch fencing of deposed sequencers
+//! - Epoch boundaries for addressing records by LSN
 
 pub mod segment;
 pub mod index;
 pub mod log_storage;
 pub mod write_cache;
 pub mod tiered;
+pub mod export;
+pub mod merkle;
+pub mod zero_copy;
+pub mod header_index;
+pub mod key_index;
+pub mod fence;
+pub mod epoch_index;
+pub mod reader;
 
-pub use log_storage::LogStorage;
+pub use log_storage::{LogStorage, LogStorageConfig};
 pub use segment::{Segment, SegmentConfig};
-pub use write_cache::WriteCache;
+pub use write_cache::{WriteCache, WriteCacheConfig};
+pub use export::{ExportFormat, ExportRange, ExportWriter};
+pub use zero_copy::SegmentRange;
+pub use fence::EpochFence;
+pub use reader::LogReader;
 
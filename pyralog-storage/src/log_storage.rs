@@ -1,13 +1,29 @@
 use bytes::Bytes;
-use pyralog_core::{LogOffset, Record, RecordBatch, Result, PyralogError, OffsetRange};
+use pyralog_core::merkle::{self, Hash, GENESIS_CHAIN};
+use pyralog_core::log::RetentionPolicy;
+use pyralog_core::{
+    Epoch, EpochOffset, InclusionProof, LogOffset, MerkleRoot, OffsetRange, PyralogError, Record, RecordBatch, Result,
+    GAP_HEADER,
+};
 use parking_lot::RwLock;
+use std::collections::BTreeMap;
+use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tokio::sync::mpsc;
 
+use crate::epoch_index::EpochIndex;
+use crate::fence::EpochFence;
+use crate::export::{ExportFormat, ExportRange};
 use crate::segment::{Segment, SegmentConfig};
+use crate::header_index::HeaderIndex;
 use crate::index::Index;
+use crate::key_index::KeyIndex;
+use crate::merkle::MerkleLog;
+use crate::reader::LogReader;
 use crate::write_cache::{WriteCache, WriteCacheConfig};
+use crate::zero_copy::SegmentRange;
 
 /// Main log storage implementation
 pub struct LogStorage {
@@ -16,17 +32,31 @@ pub struct LogStorage {
     write_cache: WriteCache,
     config: LogStorageConfig,
     current_offset: Arc<RwLock<LogOffset>>,
+    key_index: Option<KeyIndex>,
+    fence: EpochFence,
 }
 
 struct SegmentWithIndex {
     segment: Segment,
     index: Index,
+    merkle: MerkleLog,
+    headers: HeaderIndex,
+    epochs: EpochIndex,
+
+    /// Chain hash of the roots of all earlier segments
+    prev_chain: Hash,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct LogStorageConfig {
     pub segment_config: SegmentConfig,
     pub cache_config: WriteCacheConfig,
+
+    /// Header keys to maintain a secondary index on
+    pub indexed_headers: Vec<String>,
+
+    /// Maintain an index of the latest record per key
+    pub key_index: bool,
 }
 
 impl Default for LogStorageConfig {
@@ -34,6 +64,8 @@ impl Default for LogStorageConfig {
         Self {
             segment_config: SegmentConfig::default(),
             cache_config: WriteCacheConfig::default(),
+            indexed_headers: Vec::new(),
+            key_index: false,
         }
     }
 }
@@ -51,20 +83,35 @@ impl LogStorage {
         )?;
 
         let index = Index::create(segment.path())?;
+        let merkle = MerkleLog::create(segment.path())?;
+        let headers = HeaderIndex::create(segment.path(), &config.indexed_headers)?;
+        let epochs = EpochIndex::create(segment.path())?;
 
-        let segment_with_index = Arc::new(SegmentWithIndex { segment, index });
+        let segment_with_index = Arc::new(SegmentWithIndex {
+            segment,
+            index,
+            merkle,
+            headers,
+            epochs,
+            prev_chain: GENESIS_CHAIN,
+        });
+        let fence = EpochFence::open(&base_path)?;
 
         Ok(Self {
             base_path,
             segments: Arc::new(RwLock::new(vec![segment_with_index])),
             write_cache: WriteCache::new(config.cache_config.clone()),
+            key_index: config.key_index.then(KeyIndex::new),
             config,
             current_offset: Arc::new(RwLock::new(LogOffset::ZERO)),
+            fence,
         })
     }
 
     /// Open an existing log storage
     pub async fn open(base_path: PathBuf, config: LogStorageConfig) -> Result<Self> {
+        finish_rewrite(&base_path)?;
+
         let mut segment_files = std::fs::read_dir(&base_path)
             .map_err(|e| PyralogError::StorageError(e.to_string()))?
             .filter_map(|entry| entry.ok())
@@ -80,10 +127,16 @@ impl LogStorage {
             return Self::create(base_path, config).await;
         }
 
-        let mut segments = Vec::new();
+        let mut segments: Vec<Arc<SegmentWithIndex>> = Vec::new();
         let mut max_offset = LogOffset::ZERO;
 
         for segment_path in segment_files {
+            // Every segment but the last is sealed; chain its root
+            let prev_chain = match segments.last() {
+                Some(prev) => merkle::chain_hash(&prev.prev_chain, &prev.merkle.seal()),
+                None => read_chain(&segment_path)?,
+            };
+
             let segment = Segment::open(segment_path.clone(), config.segment_config.clone())?;
             let index_path = segment_path.with_extension("index");
             let index = if index_path.exists() {
@@ -92,24 +145,114 @@ impl LogStorage {
                 Index::create(&segment_path)?
             };
 
-            if let Some((offset, _, _)) = index.entries().last() {
-                max_offset = offset.next();
+            // A segment is named after the offset it was rolled for, so an
+            // empty one left by retention still resumes the log there
+            let entries = index.entries();
+            max_offset = max_offset.max(segment.base_offset());
+            if let Some((offset, _, _)) = entries.last() {
+                max_offset = max_offset.max(offset.next());
+            }
+
+            // The index is authoritative. Leaves missing after a crash, or
+            // for segments written before hashing was added, are hashed on
+            // open; a leaf log that disagrees with the index is rebuilt.
+            let merkle_path = segment_path.with_extension("merkle");
+            let mut merkle = if merkle_path.exists() {
+                MerkleLog::open(merkle_path)?
+            } else {
+                MerkleLog::create(&segment_path)?
+            };
+            let consistent = merkle.len() <= entries.len()
+                && merkle.last_offset() == merkle.len().checked_sub(1).map(|last| entries[last].0);
+            if !consistent {
+                merkle = MerkleLog::create(&segment_path)?;
             }
+            for (offset, position, size) in entries.iter().skip(merkle.len()) {
+                merkle.append(*offset, &segment.read(*position, *size as usize)?)?;
+            }
+
+            // Headers are only trusted for records that reached the segment
+            let stored_end = entries
+                .iter()
+                .take_while(|(_, position, size)| position + *size as u64 <= segment.size())
+                .last()
+                .map_or(segment.base_offset(), |(offset, _, _)| offset.next());
+            let headers_path = segment_path.with_extension("hindex");
+            let headers = if headers_path.exists() {
+                HeaderIndex::open(headers_path, &config.indexed_headers, stored_end)?
+            } else {
+                None
+            };
+            let headers = match headers {
+                Some(headers) => headers,
+                None => {
+                    // Missing, or built for different keys: reindex the segment
+                    let headers = HeaderIndex::create(&segment_path, &config.indexed_headers)?;
+                    for (_, position, size) in index.entries() {
+                        let data = segment.read(position, size as usize)?;
+                        let record: Record = bincode::deserialize(&data)
+                            .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+                        headers.append(&record)?;
+                    }
+                    headers
+                }
+            };
 
-            segments.push(Arc::new(SegmentWithIndex { segment, index }));
+            let epochs_path = segment_path.with_extension("epochs");
+            let epochs = if epochs_path.exists() {
+                EpochIndex::open(epochs_path)?
+            } else {
+                // Segments written before epochs were indexed are scanned on open
+                let epochs = EpochIndex::create(&segment_path)?;
+                for (_, position, size) in index.entries() {
+                    let data = segment.read(position, size as usize)?;
+                    let record: Record = bincode::deserialize(&data)
+                        .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+                    epochs.append(&record)?;
+                }
+                epochs
+            };
+
+            segments.push(Arc::new(SegmentWithIndex {
+                segment,
+                index,
+                merkle,
+                headers,
+                epochs,
+                prev_chain,
+            }));
         }
 
+        let key_index = if config.key_index {
+            let key_index = KeyIndex::new();
+            index_keys(&key_index, &segments)?;
+            Some(key_index)
+        } else {
+            None
+        };
+
+        let fence = EpochFence::open(&base_path)?;
+
         Ok(Self {
             base_path,
             segments: Arc::new(RwLock::new(segments)),
             write_cache: WriteCache::new(config.cache_config.clone()),
             config,
             current_offset: Arc::new(RwLock::new(max_offset)),
+            key_index,
+            fence,
         })
     }
 
     /// Append a record to the log
+    ///
+    /// Fails with `EpochSealed` if the record's epoch has been sealed, and
+    /// with `InvalidRequest` if it carries the header reserved for gap
+    /// markers.
     pub async fn append(&self, mut record: Record) -> Result<LogOffset> {
+        self.fence.check(record.epoch)?;
+        check_not_gap(&record)?;
+
         // Assign offset
         let offset = {
             let mut current = self.current_offset.write();
@@ -121,6 +264,8 @@ impl LogStorage {
 
         // Try to add to write cache
         if self.write_cache.push(record.clone())? {
+            self.index_key(&record);
+
             // Check if we should flush
             if self.write_cache.should_flush() {
                 self.flush_cache().await?;
@@ -130,13 +275,24 @@ impl LogStorage {
 
         // Cache is full, flush and write directly
         self.flush_cache().await?;
-        self.write_record(record).await?;
+        self.write_record(&record).await?;
+        self.index_key(&record);
 
         Ok(offset)
     }
 
     /// Append a batch of records
+    ///
+    /// Fails with `EpochSealed`, writing nothing, if the epoch of the batch
+    /// or of any of its records has been sealed, and with `InvalidRequest`
+    /// if a record carries the header reserved for gap markers.
     pub async fn append_batch(&self, mut batch: RecordBatch) -> Result<LogOffset> {
+        self.fence.check(batch.epoch)?;
+        for record in &batch.records {
+            self.fence.check(record.epoch)?;
+            check_not_gap(record)?;
+        }
+
         let base_offset = {
             let mut current = self.current_offset.write();
             let offset = *current;
@@ -151,17 +307,117 @@ impl LogStorage {
             record.offset = LogOffset::new(base_offset.as_u64() + i as u64);
         }
 
-        self.write_batch(batch).await?;
+        for record in &batch.records {
+            self.write_record(record).await?;
+        }
+        for record in &batch.records {
+            self.index_key(record);
+        }
 
         Ok(base_offset)
     }
 
+    /// Write records that recovery settled for sealed epochs
+    ///
+    /// Unlike `append`, records keep the offsets they carry. Records the
+    /// log already holds are skipped; one that differs replaces what the log
+    /// holds at its offset, and the records after it are written back. The
+    /// rest must continue the log from the high watermark. Only recovery
+    /// writes to a sealed epoch, so every record's epoch must be sealed.
+    /// Records bypass the write cache and are synced. Returns the new high
+    /// watermark.
+    pub async fn append_recovered(&self, records: Vec<Record>) -> Result<LogOffset> {
+        let sealed = self.fence.sealed();
+        if let Some(record) = records.iter().find(|record| record.epoch > sealed) {
+            return Err(PyralogError::InvalidRequest(format!(
+                "Epoch {} is not sealed",
+                record.epoch
+            )));
+        }
+        self.flush_cache().await?;
+
+        let mut records: BTreeMap<LogOffset, Record> =
+            records.into_iter().map(|record| (record.offset, record)).collect();
+        let high_watermark = self.high_watermark();
+        let low_watermark = self.low_watermark();
+        let mut replaced = None;
+        for record in records.range(low_watermark..high_watermark).map(|(_, record)| record) {
+            let held = self.read(record.offset).await?;
+            if held.is_none_or(|held| !same_record(&held, record)) {
+                replaced = Some(record.offset);
+                break;
+            }
+        }
+
+        if let Some(offset) = replaced {
+            let held = self.read_range(OffsetRange::new(offset, high_watermark)).await?;
+            self.truncate(offset).await?;
+            for record in held {
+                records.entry(record.offset).or_insert(record);
+            }
+        }
+        self.append_at(records.into_values().collect()).await
+    }
+
+    /// Write records a replica copies from the sequencer of `epoch`
+    ///
+    /// Like `append_recovered`, records keep the offsets they carry and gap
+    /// markers are taken as they are, so records of earlier epochs can be
+    /// copied while catching up. Records the log already holds are
+    /// skipped; at the first one that differs the log is cut, as whatever
+    /// it holds from there on is a tail the sequencer never kept. Fails
+    /// with `EpochSealed` once `epoch` is sealed. Returns the new high
+    /// watermark.
+    pub async fn append_replicated(&self, epoch: Epoch, records: Vec<Record>) -> Result<LogOffset> {
+        self.fence.check(epoch)?;
+        if let Some(record) = records.iter().find(|record| record.epoch > epoch) {
+            return Err(PyralogError::InvalidRequest(format!(
+                "Record of epoch {} in a batch of epoch {}",
+                record.epoch, epoch
+            )));
+        }
+        self.flush_cache().await?;
+
+        let high_watermark = self.high_watermark();
+        let low_watermark = self.low_watermark();
+        for record in records
+            .iter()
+            .filter(|record| record.offset >= low_watermark && record.offset < high_watermark)
+        {
+            if !self.holds(record).await? {
+                self.truncate(record.offset).await?;
+                break;
+            }
+        }
+        self.append_at(records).await
+    }
+
+    /// Whether the log holds `record` at its offset
+    ///
+    /// Gap markers match a marker of the same kind and epoch.
+    pub async fn holds(&self, record: &Record) -> Result<bool> {
+        let held = self.read(record.offset).await?;
+        Ok(held.is_some_and(|held| same_record(&held, record)))
+    }
+
+    /// Refuse all further appends from `epoch` and every earlier epoch
+    ///
+    /// The seal is durable and never moves back.
+    pub fn seal_epoch(&self, epoch: Epoch) -> Result<()> {
+        self.fence.seal(epoch)
+    }
+
+    /// Highest sealed epoch; `Epoch::INVALID` if none is
+    pub fn sealed_epoch(&self) -> Epoch {
+        self.fence.sealed()
+    }
+
     /// Read a record at the given offset
     pub async fn read(&self, offset: LogOffset) -> Result<Option<Record>> {
         let segments = self.segments.read();
 
         for seg in segments.iter().rev() {
-            if offset >= seg.segment.base_offset() {
+            if offset >= Self::first_offset(seg) {
                 if let Some((position, size)) = seg.index.lookup(offset) {
                     let data = seg.segment.read(position, size as usize)?;
                     let record: Record = bincode::deserialize(&data)
@@ -187,6 +443,545 @@ impl LogStorage {
         Ok(records)
     }
 
+    /// Read records starting from an offset up to a maximum count
+    pub async fn read_from(&self, offset: LogOffset, max_count: usize) -> Result<Vec<Record>> {
+        let end = LogOffset::new(
+            offset
+                .as_u64()
+                .saturating_add(max_count as u64)
+                .min(self.high_watermark().as_u64()),
+        );
+
+        self.read_range(OffsetRange::new(offset, end)).await
+    }
+
+    /// Locate the on-disk bytes of consecutive records starting at `offset`
+    ///
+    /// The range stops at the end of the segment, at the first gap, or when
+    /// `max_records` or `max_bytes` would be exceeded (at least one record is
+    /// always included). Returns `None` if `offset` is not in a segment.
+    ///
+    /// The segment file is opened while the segments are locked, so the
+    /// range stays readable even if retention or `truncate` replaces the
+    /// segment before it is sent.
+    pub fn segment_range(
+        &self,
+        offset: LogOffset,
+        max_records: usize,
+        max_bytes: usize,
+    ) -> Result<Option<SegmentRange>> {
+        let segments = self.segments.read();
+        let Some(seg) = segments
+            .iter()
+            .rev()
+            .find(|seg| offset >= Self::first_offset(seg))
+        else {
+            return Ok(None);
+        };
+
+        let entries = seg.index.entries_from(offset, max_records);
+        let Some(&(first_offset, position, _)) = entries.first() else {
+            return Ok(None);
+        };
+        if first_offset != offset {
+            return Ok(None);
+        }
+
+        let mut length = 0u64;
+        let mut next_offset = offset;
+        let mut record_count = 0u64;
+
+        for (entry_offset, entry_position, size) in entries {
+            let contiguous = entry_offset == next_offset && entry_position == position + length;
+            let fits = record_count == 0 || length + size as u64 <= max_bytes as u64;
+            if !contiguous || !fits {
+                break;
+            }
+
+            length += size as u64;
+            next_offset = entry_offset.next();
+            record_count += 1;
+        }
+
+        let file = File::open(seg.segment.path())
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+        let file_len = file
+            .metadata()
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?
+            .len();
+        if file_len < position + length {
+            return Ok(None);
+        }
+
+        Ok(Some(SegmentRange {
+            file: Arc::new(file),
+            position,
+            length,
+            record_count,
+            next_offset,
+        }))
+    }
+
+    /// Get the latest record with the given key
+    ///
+    /// Requires the key index to be enabled. Returns `None` if the key was
+    /// never written or its latest record is a tombstone.
+    pub async fn get_latest(&self, key: &[u8]) -> Result<Option<Record>> {
+        let key_index = self.key_index.as_ref().ok_or_else(|| {
+            PyralogError::InvalidRequest("Key index is not enabled for this log".to_string())
+        })?;
+
+        let Some(offset) = key_index.lookup(key) else {
+            return Ok(None);
+        };
+        if let Some(record) = self.read(offset).await? {
+            return Ok(Some(record));
+        }
+
+        // Keys are indexed on append, so the record may still be cached
+        self.flush_cache().await?;
+        self.read(offset).await
+    }
+
+    /// Drop key index entries for records below `offset`
+    ///
+    /// Called by compaction or retention after removing those records.
+    pub fn prune_key_index(&self, offset: LogOffset) -> usize {
+        self.key_index
+            .as_ref()
+            .map_or(0, |key_index| key_index.prune_below(offset))
+    }
+
+    /// First offset still stored
+    ///
+    /// Starts at zero and moves forward as retention deletes segments.
+    pub fn low_watermark(&self) -> LogOffset {
+        self.segments
+            .read()
+            .first()
+            .map_or(LogOffset::ZERO, |seg| Self::first_offset(seg))
+    }
+
+    /// Delete the oldest sealed segments that `policy` no longer retains
+    ///
+    /// With both a time and a size limit, a segment goes once it breaks
+    /// either. Returns the new low watermark.
+    pub async fn apply_retention(&self, policy: &RetentionPolicy) -> Result<LogOffset> {
+        let (max_age, max_size) = match *policy {
+            RetentionPolicy::Time(seconds) => (Some(seconds), None),
+            RetentionPolicy::Size(bytes) => (None, Some(bytes)),
+            RetentionPolicy::TimeAndSize { time_seconds, size_bytes } => {
+                (Some(time_seconds), Some(size_bytes))
+            }
+            RetentionPolicy::Forever => return Ok(self.low_watermark()),
+        };
+        self.flush_cache().await?;
+
+        let expired_before = max_age
+            .and_then(|seconds| SystemTime::now().checked_sub(Duration::from_secs(seconds)));
+        let segments = self.segments.read().clone();
+        let mut size: u64 = segments.iter().map(|seg| seg.segment.size()).sum();
+        let mut cutoff = self.low_watermark();
+
+        // Every segment but the last is sealed
+        for (seg, next) in segments.iter().zip(segments.iter().skip(1)) {
+            let oversized = max_size.is_some_and(|max_size| size > max_size);
+            let expired = match expired_before {
+                Some(before) => Self::last_record(seg)?.is_none_or(|record| record.timestamp < before),
+                None => false,
+            };
+            if !oversized && !expired {
+                break;
+            }
+            size -= seg.segment.size();
+            cutoff = Self::first_offset(next);
+        }
+
+        self.delete_segments_below(cutoff).await
+    }
+
+    /// Delete the sealed segments whose records all lie below `offset`
+    ///
+    /// The active segment is always kept. Returns the new low watermark.
+    pub async fn delete_segments_below(&self, offset: LogOffset) -> Result<LogOffset> {
+        self.flush_cache().await?;
+
+        let deleted: Vec<Arc<SegmentWithIndex>> = {
+            let mut segments = self.segments.write();
+            let count = segments[1..].partition_point(|seg| Self::first_offset(seg) <= offset);
+            if count == 0 {
+                return Ok(Self::first_offset(&segments[0]));
+            }
+
+            // Roots of the remaining segments still chain from the deleted ones
+            let oldest = &segments[count];
+            write_chain(oldest.segment.path(), &oldest.prev_chain)?;
+            segments.drain(..count).collect()
+        };
+
+        for seg in deleted {
+            remove_segment_files(seg.segment.path(), SEGMENT_FILES)?;
+        }
+
+        let low_watermark = self.low_watermark();
+        self.prune_key_index(low_watermark);
+        Ok(low_watermark)
+    }
+
+    /// Remove the records at `offset` and after
+    ///
+    /// Lets a replica drop the end of its copy where it diverged from the
+    /// log it follows. The segment holding `offset` is rewritten with the
+    /// records before it and later segments are deleted. Offsets below the
+    /// low watermark cannot be truncated.
+    pub async fn truncate(&self, offset: LogOffset) -> Result<()> {
+        self.flush_cache().await?;
+        if offset >= self.high_watermark() {
+            return Ok(());
+        }
+        let low_watermark = self.low_watermark();
+        if offset < low_watermark {
+            return Err(PyralogError::InvalidRequest(format!(
+                "Cannot truncate at {} below the low watermark {}",
+                offset, low_watermark
+            )));
+        }
+
+        {
+            let mut segments = self.segments.write();
+            let kept = segments
+                .partition_point(|seg| Self::first_offset(seg) < offset)
+                .max(1);
+            for seg in segments.drain(kept..) {
+                remove_segment_files(seg.segment.path(), SEGMENT_FILES)?;
+            }
+            let last = segments.last_mut().expect("the first segment is always kept");
+            if let Some(rewritten) = self.rewrite_segment(last, offset)? {
+                *last = Arc::new(rewritten);
+            }
+        }
+        *self.current_offset.write() = offset;
+
+        if let Some(key_index) = &self.key_index {
+            key_index.clear();
+            index_keys(key_index, &self.segments.read())?;
+        }
+        Ok(())
+    }
+
+    /// Recreate a segment with only its records below `offset`
+    ///
+    /// The new files are staged and moved over the old ones, so a crash
+    /// leaves either the old segment or the rewritten one. Returns None if
+    /// it holds nothing at or past `offset`.
+    fn rewrite_segment(&self, seg: &SegmentWithIndex, offset: LogOffset) -> Result<Option<SegmentWithIndex>> {
+        if !self.stage_rewrite(seg, offset)? {
+            return Ok(None);
+        }
+        finish_rewrite(&self.base_path)?;
+
+        let path = seg.segment.path().to_path_buf();
+        let segment = Segment::open(path.clone(), self.config.segment_config.clone())?;
+        let headers = HeaderIndex::open(path.with_extension("hindex"), &self.config.indexed_headers, offset)?
+            .ok_or_else(|| {
+                PyralogError::StorageError(format!("Rewritten header index of {} is unreadable", path.display()))
+            })?;
+        Ok(Some(SegmentWithIndex {
+            index: Index::open(path.with_extension("index"))?,
+            merkle: MerkleLog::open(path.with_extension("merkle"))?,
+            headers,
+            epochs: EpochIndex::open(path.with_extension("epochs"))?,
+            segment,
+            prev_chain: seg.prev_chain,
+        }))
+    }
+
+    /// Write a segment's records below `offset` to `REWRITE_STAGING`, then
+    /// rename it to `REWRITE_STAGED`
+    ///
+    /// Returns false, writing nothing, if the segment holds nothing at or
+    /// past `offset`.
+    fn stage_rewrite(&self, seg: &SegmentWithIndex, offset: LogOffset) -> Result<bool> {
+        let entries = seg.index.entries();
+        if entries.last().is_none_or(|(last, _, _)| *last < offset) {
+            return Ok(false);
+        }
+        let mut kept = Vec::new();
+        for (_, position, size) in entries.into_iter().filter(|(entry, _, _)| *entry < offset) {
+            kept.push(seg.segment.read(position, size as usize)?);
+        }
+
+        let staging = self.base_path.join(REWRITE_STAGING);
+        if staging.exists() {
+            std::fs::remove_dir_all(&staging).map_err(|e| PyralogError::StorageError(e.to_string()))?;
+        }
+        std::fs::create_dir(&staging).map_err(|e| PyralogError::StorageError(e.to_string()))?;
+
+        // The chain the segment continues from stays
+        let segment = Segment::create(seg.segment.base_offset(), &staging, self.config.segment_config.clone())?;
+        let index = Index::create(segment.path())?;
+        let merkle = MerkleLog::create(segment.path())?;
+        let headers = HeaderIndex::create(segment.path(), &self.config.indexed_headers)?;
+        let epochs = EpochIndex::create(segment.path())?;
+
+        // Includes an epoch carried over from the previous segment
+        for (epoch, start) in seg.epochs.starts() {
+            if start < offset {
+                epochs.carry(epoch, start)?;
+            }
+        }
+        for data in kept {
+            let record: Record = bincode::deserialize(&data)
+                .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+            let position = segment.append(&data)?;
+            index.append(record.offset, position, data.len() as u32)?;
+            merkle.append(record.offset, &data)?;
+            headers.append(&record)?;
+        }
+
+        segment.sync()?;
+        index.sync()?;
+        merkle.sync()?;
+        headers.sync()?;
+        epochs.sync()?;
+        sync_dir(&staging)?;
+
+        std::fs::rename(&staging, self.base_path.join(REWRITE_STAGED))
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+        sync_dir(&self.base_path)?;
+        Ok(true)
+    }
+
+    /// Offset of a segment's first record, or where its first record will go
+    fn first_offset(seg: &SegmentWithIndex) -> LogOffset {
+        seg.merkle.first_offset().unwrap_or_else(|| seg.segment.base_offset())
+    }
+
+    /// The newest record in a segment
+    fn last_record(seg: &SegmentWithIndex) -> Result<Option<Record>> {
+        let Some((position, size)) = seg.merkle.last_offset().and_then(|offset| seg.index.lookup(offset))
+        else {
+            return Ok(None);
+        };
+        let data = seg.segment.read(position, size as usize)?;
+        let record = bincode::deserialize(&data)
+            .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+        Ok(Some(record))
+    }
+
+    /// Find offsets in `range` of records with header `key` equal to `value`
+    ///
+    /// Uses the secondary index where `key` is indexed and scans the
+    /// remaining segments.
+    pub async fn find_by_header(
+        &self,
+        key: &str,
+        value: &[u8],
+        range: OffsetRange,
+    ) -> Result<Vec<LogOffset>> {
+        // Cached records are not indexed until they are written
+        self.flush_cache().await?;
+
+        let segments = self.segments.read();
+        let mut offsets = Vec::new();
+
+        for seg in segments.iter() {
+            if seg.headers.is_indexed(key) {
+                offsets.extend(seg.headers.lookup(key, value, range));
+                continue;
+            }
+
+            for (offset, position, size) in seg.index.entries() {
+                if !range.contains(offset) {
+                    continue;
+                }
+                let data = seg.segment.read(position, size as usize)?;
+                let record: Record = bincode::deserialize(&data)
+                    .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+                if record
+                    .headers
+                    .iter()
+                    .any(|header| header.key == key && header.value.as_ref() == value)
+                {
+                    offsets.push(offset);
+                }
+            }
+        }
+
+        offsets.sort_unstable();
+        Ok(offsets)
+    }
+
+    /// Read records in `range` with header `key` equal to `value`
+    pub async fn read_by_header(
+        &self,
+        key: &str,
+        value: &[u8],
+        range: OffsetRange,
+    ) -> Result<Vec<Record>> {
+        let mut records = Vec::new();
+
+        for offset in self.find_by_header(key, value, range).await? {
+            if let Some(record) = self.read(offset).await? {
+                records.push(record);
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Export a range of records to a file in Arrow IPC or Parquet format
+    ///
+    /// Flushes the write cache and exports from a `LogReader` over the
+    /// segments. Returns the number of exported records.
+    pub async fn export(
+        &self,
+        range: ExportRange,
+        format: ExportFormat,
+        path: &Path,
+    ) -> Result<usize> {
+        self.flush_cache().await?;
+        LogReader::open(&self.base_path, self.config.segment_config.clone())?.export(range, format, path)
+    }
+
+    /// Get the chained Merkle root covering every record up to `offset`
+    ///
+    /// Only flushed records are covered; returns `None` if `offset` has not
+    /// been written to a segment yet.
+    pub fn merkle_root(&self, offset: LogOffset) -> Result<Option<MerkleRoot>> {
+        let segments = self.segments.read();
+
+        let (seg_idx, leaf_idx) = match Self::locate(&segments, offset) {
+            Some(location) => location,
+            None => return Ok(None),
+        };
+
+        let seg = &segments[seg_idx];
+        let root = seg.merkle.root(leaf_idx + 1);
+
+        Ok(Some(MerkleRoot {
+            offset,
+            hash: merkle::chain_hash(&seg.prev_chain, &root),
+        }))
+    }
+
+    /// Get a proof that the record at `offset` is included under the root at
+    /// `root_offset`
+    pub fn inclusion_proof(
+        &self,
+        offset: LogOffset,
+        root_offset: LogOffset,
+    ) -> Result<Option<InclusionProof>> {
+        if root_offset < offset {
+            return Err(PyralogError::InvalidOffset(root_offset.as_u64()));
+        }
+
+        let segments = self.segments.read();
+
+        let (seg_idx, leaf_idx) = match Self::locate(&segments, offset) {
+            Some(location) => location,
+            None => return Ok(None),
+        };
+        let (root_seg_idx, root_leaf_idx) = match Self::locate(&segments, root_offset) {
+            Some(location) => location,
+            None => return Ok(None),
+        };
+
+        if root_seg_idx < seg_idx || (root_seg_idx == seg_idx && root_leaf_idx < leaf_idx) {
+            return Err(PyralogError::InvalidOffset(root_offset.as_u64()));
+        }
+
+        let seg = &segments[seg_idx];
+        let tree_size = if root_seg_idx == seg_idx {
+            root_leaf_idx + 1
+        } else {
+            seg.merkle.len()
+        };
+
+        let mut later_segment_roots = Vec::new();
+        for (i, later) in segments.iter().enumerate().take(root_seg_idx + 1).skip(seg_idx + 1) {
+            let size = if i == root_seg_idx {
+                root_leaf_idx + 1
+            } else {
+                later.merkle.len()
+            };
+            later_segment_roots.push(later.merkle.root(size));
+        }
+
+        Ok(Some(InclusionProof {
+            offset,
+            leaf_index: leaf_idx as u64,
+            tree_size: tree_size as u64,
+            path: seg.merkle.audit_path(leaf_idx, tree_size),
+            prev_chain: seg.prev_chain,
+            later_segment_roots,
+            root_offset,
+        }))
+    }
+
+    /// Each epoch with the offset of its first record, oldest first
+    ///
+    /// Only flushed records are covered.
+    pub fn epoch_starts(&self) -> Vec<(Epoch, LogOffset)> {
+        let mut starts: Vec<(Epoch, LogOffset)> = Vec::new();
+        for seg in self.segments.read().iter() {
+            for (epoch, offset) in seg.epochs.starts() {
+                // An epoch may continue from the previous segment
+                if starts.last().map(|(last, _)| *last) != Some(epoch) {
+                    starts.push((epoch, offset));
+                }
+            }
+        }
+        starts
+    }
+
+    /// LSN of the record at `offset`: its epoch and position in the epoch
+    ///
+    /// Returns `None` if `offset` has not been written to a segment yet.
+    pub fn lsn(&self, offset: LogOffset) -> Option<EpochOffset> {
+        let stored = self
+            .segments
+            .read()
+            .iter()
+            .rev()
+            .find(|seg| offset >= Self::first_offset(seg))
+            .is_some_and(|seg| seg.index.lookup(offset).is_some());
+        if !stored {
+            return None;
+        }
+
+        let (epoch, start) = self
+            .epoch_starts()
+            .into_iter()
+            .rev()
+            .find(|(_, start)| *start <= offset)?;
+        let position = u32::try_from(offset.as_u64() - start.as_u64()).ok()?;
+        Some(EpochOffset::new(epoch, position))
+    }
+
+    /// Offset of the record written at `lsn`
+    ///
+    /// Returns `None` if no flushed record has that LSN.
+    pub fn offset_for_lsn(&self, lsn: EpochOffset) -> Option<LogOffset> {
+        let starts = self.epoch_starts();
+        let index = starts.iter().position(|(epoch, _)| *epoch == lsn.epoch)?;
+
+        let offset = LogOffset::new(starts[index].1.as_u64() + lsn.offset as u64);
+        if starts.get(index + 1).is_some_and(|(_, next)| offset >= *next) {
+            return None;
+        }
+        self.lsn(offset).map(|_| offset)
+    }
+
+    /// Find the segment and leaf position holding an offset
+    fn locate(segments: &[Arc<SegmentWithIndex>], offset: LogOffset) -> Option<(usize, usize)> {
+        let seg_idx = segments
+            .partition_point(|seg| seg.merkle.first_offset().is_some_and(|first| first <= offset))
+            .checked_sub(1)?;
+        let leaf_idx = segments[seg_idx].merkle.leaf_index(offset)?;
+        Some((seg_idx, leaf_idx))
+    }
+
     /// Flush the write cache
     pub async fn flush(&self) -> Result<()> {
         self.flush_cache().await
@@ -197,40 +992,81 @@ impl LogStorage {
         *self.current_offset.read()
     }
 
+    /// Point the key index at a newly appended record
+    fn index_key(&self, record: &Record) {
+        if let Some(key_index) = &self.key_index {
+            key_index.update(record);
+        }
+    }
+
+    /// Write records at the offsets they carry, skipping those already held
+    ///
+    /// Callers serialize writes to the log.
+    async fn append_at(&self, records: Vec<Record>) -> Result<LogOffset> {
+        self.flush_cache().await?;
+
+        for record in records {
+            let high_watermark = self.high_watermark();
+            if record.offset < high_watermark {
+                // Below the low watermark, records were committed and trimmed
+                if record.offset < self.low_watermark() {
+                    continue;
+                }
+                let held = self.read(record.offset).await?;
+                if held.is_some_and(|held| same_record(&held, &record)) {
+                    continue;
+                }
+                return Err(PyralogError::StorageError(format!(
+                    "Offset {} already holds a different record",
+                    record.offset
+                )));
+            }
+            if record.offset > high_watermark {
+                return Err(PyralogError::StorageError(format!(
+                    "Offset {} would leave a gap after the high watermark {}",
+                    record.offset, high_watermark
+                )));
+            }
+
+            self.write_record(&record).await?;
+            *self.current_offset.write() = record.offset.next();
+            self.index_key(&record);
+        }
+
+        self.flush_cache().await?;
+        Ok(self.high_watermark())
+    }
+
     /// Write a single record directly to storage
-    async fn write_record(&self, record: Record) -> Result<()> {
-        let data = bincode::serialize(&record)
+    async fn write_record(&self, record: &Record) -> Result<()> {
+        let data = bincode::serialize(record)
             .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
 
+        let fits = self.segments.read().last()
+            .ok_or_else(|| PyralogError::StorageError("No segments available".to_string()))?
+            .segment.can_fit(data.len() as u64);
+        if !fits {
+            self.roll_segment(record.offset).await?;
+        }
+
         let segments = self.segments.read();
         let current_segment = segments.last()
             .ok_or_else(|| PyralogError::StorageError("No segments available".to_string()))?;
 
-        if !current_segment.segment.can_fit(data.len() as u64) {
-            drop(segments);
-            self.roll_segment().await?;
-            return self.write_record(record).await;
-        }
-
         let position = current_segment.segment.append(&data)?;
         current_segment.index.append(record.offset, position, data.len() as u32)?;
+        current_segment.merkle.append(record.offset, &data)?;
+        current_segment.headers.append(record)?;
+        current_segment.epochs.append(record)?;
 
         Ok(())
     }
 
-    /// Write a batch of records
-    async fn write_batch(&self, batch: RecordBatch) -> Result<()> {
-        for record in batch.records {
-            self.write_record(record).await?;
-        }
-        Ok(())
-    }
-
     /// Flush the write cache to storage
     async fn flush_cache(&self) -> Result<()> {
         let records = self.write_cache.drain();
         
-        for record in records {
+        for record in &records {
             self.write_record(record).await?;
         }
 
@@ -238,15 +1074,19 @@ impl LogStorage {
         if let Some(seg) = segments.last() {
             seg.segment.sync()?;
             seg.index.sync()?;
+            seg.merkle.sync()?;
+            seg.headers.sync()?;
+            seg.epochs.sync()?;
         }
 
         Ok(())
     }
 
-    /// Create a new segment
-    async fn roll_segment(&self) -> Result<()> {
-        let base_offset = *self.current_offset.read();
-        
+    /// Create a new segment, named after the first record it will hold
+    ///
+    /// Not the high watermark: that is already past any records still in
+    /// the write cache.
+    async fn roll_segment(&self, base_offset: LogOffset) -> Result<()> {
         let segment = Segment::create(
             base_offset,
             &self.base_path,
@@ -254,10 +1094,364 @@ impl LogStorage {
         )?;
 
         let index = Index::create(segment.path())?;
+        let merkle = MerkleLog::create(segment.path())?;
+        let headers = HeaderIndex::create(segment.path(), &self.config.indexed_headers)?;
+        let epochs = EpochIndex::create(segment.path())?;
 
-        self.segments.write().push(Arc::new(SegmentWithIndex { segment, index }));
+        let mut segments = self.segments.write();
+        let prev_chain = match segments.last() {
+            Some(prev) => {
+                if let Some((epoch, start)) = prev.epochs.starts().last() {
+                    epochs.carry(*epoch, *start)?;
+                }
+                merkle::chain_hash(&prev.prev_chain, &prev.merkle.seal())
+            }
+            None => GENESIS_CHAIN,
+        };
+        segments.push(Arc::new(SegmentWithIndex {
+            segment,
+            index,
+            merkle,
+            headers,
+            epochs,
+            prev_chain,
+        }));
 
         Ok(())
     }
 }
 
+/// Files making up a segment, the segment itself first so that a partial
+/// delete leaves only side files
+const SEGMENT_FILES: &[&str] = &["log", "index", "merkle", "hindex", "epochs", "chain"];
+
+/// Directory a segment is rewritten in; discarded if found on open
+const REWRITE_STAGING: &str = "rewrite.tmp";
+
+/// A completely written rewrite; its files replace the segment's, and one
+/// found on open is moved into place then
+const REWRITE_STAGED: &str = "rewrite";
+
+/// Move the files of a staged rewrite over the segment they replace
+///
+/// Each file is renamed on its own, so this may be repeated after a crash
+/// partway through.
+fn finish_rewrite(base_path: &Path) -> Result<()> {
+    let staging = base_path.join(REWRITE_STAGING);
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging).map_err(|e| PyralogError::StorageError(e.to_string()))?;
+    }
+
+    let staged = base_path.join(REWRITE_STAGED);
+    if !staged.exists() {
+        return Ok(());
+    }
+    let files = std::fs::read_dir(&staged).map_err(|e| PyralogError::StorageError(e.to_string()))?;
+    for file in files {
+        let file = file.map_err(|e| PyralogError::StorageError(e.to_string()))?;
+        std::fs::rename(file.path(), base_path.join(file.file_name()))
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+    }
+    sync_dir(base_path)?;
+    std::fs::remove_dir(&staged).map_err(|e| PyralogError::StorageError(e.to_string()))?;
+    sync_dir(base_path)
+}
+
+/// Make renames and deletions in a directory durable
+fn sync_dir(path: &Path) -> Result<()> {
+    File::open(path)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|e| PyralogError::StorageError(e.to_string()))
+}
+
+fn remove_segment_files(segment_path: &Path, extensions: &[&str]) -> Result<()> {
+    for extension in extensions {
+        let path = segment_path.with_extension(extension);
+        if path.exists() {
+            std::fs::remove_file(&path).map_err(|e| PyralogError::StorageError(e.to_string()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Only recovery, through `append_recovered`, may write gap markers
+fn check_not_gap(record: &Record) -> Result<()> {
+    if record.has_gap_header() {
+        return Err(PyralogError::InvalidRequest(format!(
+            "Header {} is reserved for gap markers",
+            GAP_HEADER
+        )));
+    }
+    Ok(())
+}
+
+/// Point `key_index` at the latest record of each key in `segments`
+fn index_keys(key_index: &KeyIndex, segments: &[Arc<SegmentWithIndex>]) -> Result<()> {
+    for seg in segments {
+        for (_, position, size) in seg.index.entries() {
+            let data = seg.segment.read(position, size as usize)?;
+            let record: Record = bincode::deserialize(&data)
+                .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+            key_index.update(&record);
+        }
+    }
+    Ok(())
+}
+
+/// Whether `held` is the record the log already holds for `record`
+///
+/// Markers are recreated by every recovery, so only their kind and epoch
+/// are compared.
+fn same_record(held: &Record, record: &Record) -> bool {
+    match (held.gap_kind(), record.gap_kind()) {
+        (Some(held_kind), Some(kind)) => held_kind == kind && held.epoch == record.epoch,
+        _ => held == record,
+    }
+}
+
+/// Chain hash the oldest segment continues from
+///
+/// `GENESIS_CHAIN` unless retention deleted the segments before it.
+fn read_chain(segment_path: &Path) -> Result<Hash> {
+    let chain_path = segment_path.with_extension("chain");
+    if !chain_path.exists() {
+        return Ok(GENESIS_CHAIN);
+    }
+
+    let bytes = std::fs::read(&chain_path).map_err(|e| PyralogError::StorageError(e.to_string()))?;
+    bytes.try_into().map_err(|_| {
+        PyralogError::StorageError(format!("Corrupt chain file {}", chain_path.display()))
+    })
+}
+
+/// Record the chain hash a segment continues from, before the segments
+/// before it are deleted
+fn write_chain(segment_path: &Path, chain: &Hash) -> Result<()> {
+    std::fs::write(segment_path.with_extension("chain"), chain)
+        .map_err(|e| PyralogError::StorageError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_offsets_resume_after_retention_deletes_every_record() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = LogStorageConfig::default();
+        config.cache_config.enabled = false;
+        config.segment_config.max_size = 256;
+
+        let storage = LogStorage::create(temp_dir.path().to_path_buf(), config.clone())
+            .await
+            .unwrap();
+        for i in 0..8 {
+            let record = Record::new(None, Bytes::from(format!("event-{}", i)));
+            storage.append(record).await.unwrap();
+        }
+
+        // Too large for any segment: rolls to an empty one named after its
+        // offset, then fails without writing there
+        let failed_at = storage.high_watermark();
+        let oversized = Record::new(None, Bytes::from(vec![0u8; 512]));
+        assert!(storage.append(oversized).await.is_err());
+        let low_watermark = storage.delete_segments_below(storage.high_watermark()).await.unwrap();
+        assert_eq!(low_watermark, failed_at);
+        drop(storage);
+
+        let storage = LogStorage::open(temp_dir.path().to_path_buf(), config)
+            .await
+            .unwrap();
+        assert_eq!(storage.high_watermark(), failed_at);
+        let offset = storage.append(Record::new(None, Bytes::from("next"))).await.unwrap();
+        assert_eq!(offset, failed_at);
+        assert_eq!(storage.low_watermark(), failed_at);
+    }
+
+    #[tokio::test]
+    async fn test_truncate_rewrites_segment_and_drops_later_ones() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = LogStorageConfig::default();
+        config.cache_config.enabled = false;
+        config.segment_config.max_size = 256;
+        config.key_index = true;
+
+        let storage = LogStorage::create(temp_dir.path().to_path_buf(), config.clone())
+            .await
+            .unwrap();
+        for i in 0..12 {
+            let record = Record::new(Some(Bytes::from("key")), Bytes::from(format!("event-{}", i)));
+            storage.append(record).await.unwrap();
+        }
+        assert!(storage.segments.read().len() > 2);
+
+        storage.truncate(LogOffset::new(3)).await.unwrap();
+        assert_eq!(storage.high_watermark(), LogOffset::new(3));
+        assert!(storage.read(LogOffset::new(3)).await.unwrap().is_none());
+        let latest = storage.get_latest(b"key").await.unwrap().unwrap();
+        assert_eq!(latest.value, Bytes::from("event-2"));
+
+        let offset = storage.append(Record::new(None, Bytes::from("again"))).await.unwrap();
+        assert_eq!(offset, LogOffset::new(3));
+        drop(storage);
+
+        let storage = LogStorage::open(temp_dir.path().to_path_buf(), config)
+            .await
+            .unwrap();
+        assert_eq!(storage.high_watermark(), LogOffset::new(4));
+        let values: Vec<Bytes> = storage
+            .read_range(OffsetRange::new(LogOffset::ZERO, LogOffset::new(4)))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|record| record.value)
+            .collect();
+        assert_eq!(values, ["event-0", "event-1", "event-2", "again"].map(Bytes::from));
+    }
+
+    #[tokio::test]
+    async fn test_open_finishes_interrupted_truncate() {
+        let temp_dir = TempDir::new().unwrap();
+        let base_path = temp_dir.path().to_path_buf();
+        let mut config = LogStorageConfig::default();
+        config.cache_config.enabled = false;
+        config.segment_config.max_size = 256;
+
+        let storage = LogStorage::create(base_path.clone(), config.clone()).await.unwrap();
+        for i in 0..12 {
+            let record = Record::new(None, Bytes::from(format!("event-{}", i)));
+            storage.append(record).await.unwrap();
+        }
+        let high_watermark = storage.high_watermark();
+
+        // Interrupted while staging: the segment is left as it was
+        let first = Arc::clone(&storage.segments.read()[0]);
+        assert!(storage.stage_rewrite(&first, LogOffset::new(3)).unwrap());
+        std::fs::rename(base_path.join(REWRITE_STAGED), base_path.join(REWRITE_STAGING)).unwrap();
+        drop(first);
+        drop(storage);
+
+        let storage = LogStorage::open(base_path.clone(), config.clone()).await.unwrap();
+        assert!(!base_path.join(REWRITE_STAGING).exists());
+        assert_eq!(storage.high_watermark(), high_watermark);
+
+        // Interrupted after the later segments were deleted and the
+        // rewritten segment file was moved into place, but not its index
+        let first = {
+            let mut segments = storage.segments.write();
+            for seg in segments.drain(1..) {
+                remove_segment_files(seg.segment.path(), SEGMENT_FILES).unwrap();
+            }
+            Arc::clone(&segments[0])
+        };
+        assert!(storage.stage_rewrite(&first, LogOffset::new(3)).unwrap());
+        let log = first.segment.path().file_name().unwrap().to_owned();
+        std::fs::rename(base_path.join(REWRITE_STAGED).join(&log), base_path.join(&log)).unwrap();
+        drop(first);
+        drop(storage);
+
+        let storage = LogStorage::open(base_path.clone(), config).await.unwrap();
+        assert!(!base_path.join(REWRITE_STAGED).exists());
+        assert_eq!(storage.high_watermark(), LogOffset::new(3));
+        let values: Vec<Bytes> = storage
+            .read_range(OffsetRange::new(LogOffset::ZERO, LogOffset::new(3)))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|record| record.value)
+            .collect();
+        assert_eq!(values, ["event-0", "event-1", "event-2"].map(Bytes::from));
+    }
+
+    #[tokio::test]
+    async fn test_append_replicated_cuts_diverged_tail() {
+        let temp_dir = TempDir::new().unwrap();
+        let storage = LogStorage::create(temp_dir.path().to_path_buf(), LogStorageConfig::default())
+            .await
+            .unwrap();
+        let epoch = Epoch::FIRST;
+        for value in ["a", "b", "stale"] {
+            storage.append(Record::new(None, Bytes::from(value)).with_epoch(epoch)).await.unwrap();
+        }
+        storage.flush().await.unwrap();
+
+        // The sequencer of the next epoch kept a and b, then wrote c
+        let next = epoch.next();
+        let mut kept = storage.read(LogOffset::new(1)).await.unwrap().unwrap();
+        let mut written = Record::new(None, Bytes::from("c")).with_epoch(next);
+        written.offset = LogOffset::new(2);
+        assert!(storage.holds(&kept).await.unwrap());
+        let high_watermark = storage
+            .append_replicated(next, vec![kept.clone(), written.clone()])
+            .await
+            .unwrap();
+        assert_eq!(high_watermark, LogOffset::new(3));
+        assert_eq!(storage.read(LogOffset::new(2)).await.unwrap(), Some(written));
+
+        // Batches of a sealed epoch are refused
+        storage.seal_epoch(next).unwrap();
+        kept.offset = LogOffset::new(3);
+        assert!(matches!(
+            storage.append_replicated(next, vec![kept]).await,
+            Err(PyralogError::EpochSealed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_open_segment_written_before_gap_markers() {
+        use pyralog_core::{GapKind, RecordHeader};
+
+        /// `Record` as segments stored it before recovery wrote gap markers
+        #[derive(serde::Serialize, serde::Deserialize)]
+        struct StoredRecord {
+            offset: LogOffset,
+            epoch: Epoch,
+            timestamp: SystemTime,
+            key: Option<Bytes>,
+            value: Bytes,
+            headers: Vec<RecordHeader>,
+        }
+
+        let temp_dir = TempDir::new().unwrap();
+        let config = LogStorageConfig {
+            indexed_headers: vec!["trace".to_string()],
+            ..Default::default()
+        };
+
+        // Only the segment and its offset index, as they were written then
+        let segment = Segment::create(LogOffset::ZERO, temp_dir.path(), config.segment_config.clone())
+            .unwrap();
+        let index = Index::create(segment.path()).unwrap();
+        for i in 0..3u64 {
+            let stored = StoredRecord {
+                offset: LogOffset::new(i),
+                epoch: Epoch::FIRST,
+                timestamp: SystemTime::now(),
+                key: None,
+                value: Bytes::from(format!("event-{}", i)),
+                headers: vec![RecordHeader::new("trace".to_string(), Bytes::from("t"))],
+            };
+            let data = bincode::serialize(&stored).unwrap();
+            let position = segment.append(&data).unwrap();
+            index.append(LogOffset::new(i), position, data.len() as u32).unwrap();
+        }
+        drop((segment, index));
+
+        let storage = LogStorage::open(temp_dir.path().to_path_buf(), config)
+            .await
+            .unwrap();
+        assert_eq!(storage.high_watermark(), LogOffset::new(3));
+        let record = storage.read(LogOffset::new(1)).await.unwrap().unwrap();
+        assert_eq!(record.value, Bytes::from("event-1"));
+        assert_eq!(record.gap_kind(), None);
+        let all = OffsetRange::new(LogOffset::ZERO, LogOffset::new(3));
+        assert_eq!(storage.find_by_header("trace", b"t", all).await.unwrap().len(), 3);
+
+        // Markers keep the same layout
+        let marker = bincode::serialize(&Record::gap(GapKind::Bridge)).unwrap();
+        let stored: StoredRecord = bincode::deserialize(&marker).unwrap();
+        assert!(stored.value.is_empty());
+    }
+}
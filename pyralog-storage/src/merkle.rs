@@ -0,0 +1,274 @@
+use pyralog_core::merkle::{self, Hash};
+use pyralog_core::{LogOffset, Result, PyralogError};
+use parking_lot::RwLock;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+const LEAF_ENTRY_SIZE: usize = 40; // 8 + 32 bytes
+
+/// Leaf hashes of the records stored in a segment, in append order
+///
+/// Persisted next to the segment as `<base>.merkle` so segment roots and
+/// proofs can be served without rehashing segment data. Once the segment
+/// is sealed its root is computed once and cached.
+pub struct MerkleLog {
+    path: PathBuf,
+    file: RwLock<File>,
+    leaves: RwLock<Leaves>,
+    sealed_root: RwLock<Option<Hash>>,
+}
+
+#[derive(Default)]
+struct Leaves {
+    offsets: Vec<LogOffset>,
+    hashes: Vec<Hash>,
+}
+
+impl MerkleLog {
+    /// Create a new, empty leaf log for a segment
+    pub fn create(segment_path: &Path) -> Result<Self> {
+        let path = segment_path.with_extension("merkle");
+
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+
+        Ok(Self {
+            path,
+            file: RwLock::new(file),
+            leaves: RwLock::new(Leaves::default()),
+            sealed_root: RwLock::new(None),
+        })
+    }
+
+    /// Open an existing leaf log
+    ///
+    /// A partially written trailing entry is cut off.
+    pub fn open(path: PathBuf) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+
+        let mut leaves = Leaves::default();
+        let mut buffer = [0u8; LEAF_ENTRY_SIZE];
+
+        loop {
+            match file.read_exact(&mut buffer) {
+                Ok(_) => {
+                    let offset = u64::from_le_bytes(buffer[0..8].try_into().unwrap());
+                    let hash: Hash = buffer[8..40].try_into().unwrap();
+                    leaves.offsets.push(LogOffset::new(offset));
+                    leaves.hashes.push(hash);
+                }
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(PyralogError::StorageError(e.to_string())),
+            }
+        }
+
+        file.set_len((leaves.hashes.len() * LEAF_ENTRY_SIZE) as u64)
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+
+        Ok(Self {
+            path,
+            file: RwLock::new(file),
+            leaves: RwLock::new(leaves),
+            sealed_root: RwLock::new(None),
+        })
+    }
+
+    /// Record the stored bytes of a newly appended record
+    pub fn append(&self, offset: LogOffset, data: &[u8]) -> Result<()> {
+        let hash = merkle::leaf_hash(data);
+
+        let mut buffer = [0u8; LEAF_ENTRY_SIZE];
+        buffer[0..8].copy_from_slice(&offset.as_u64().to_le_bytes());
+        buffer[8..40].copy_from_slice(&hash);
+
+        self.file
+            .write()
+            .write_all(&buffer)
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+
+        let mut leaves = self.leaves.write();
+        leaves.offsets.push(offset);
+        leaves.hashes.push(hash);
+
+        Ok(())
+    }
+
+    /// Position of an offset among the leaves
+    ///
+    /// Offsets within a segment are consecutive, so the position is found
+    /// from the distance to the first leaf; out of order leaves fall back to
+    /// a binary search.
+    pub fn leaf_index(&self, offset: LogOffset) -> Option<usize> {
+        let leaves = self.leaves.read();
+        let first = *leaves.offsets.first()?;
+        let index = usize::try_from(offset.as_u64().checked_sub(first.as_u64())?).ok()?;
+
+        match leaves.offsets.get(index) {
+            Some(found) if *found == offset => Some(index),
+            _ => leaves.offsets.binary_search(&offset).ok(),
+        }
+    }
+
+    /// Offset of the first leaf
+    pub fn first_offset(&self) -> Option<LogOffset> {
+        self.leaves.read().offsets.first().copied()
+    }
+
+    /// Offset of the last leaf
+    pub fn last_offset(&self) -> Option<LogOffset> {
+        self.leaves.read().offsets.last().copied()
+    }
+
+    /// Number of leaves
+    pub fn len(&self) -> usize {
+        self.leaves.read().hashes.len()
+    }
+
+    /// Check if the segment has no leaves
+    pub fn is_empty(&self) -> bool {
+        self.leaves.read().hashes.is_empty()
+    }
+
+    /// Root over the first `size` leaves
+    pub fn root(&self, size: usize) -> Hash {
+        let leaves = self.leaves.read();
+        let size = size.min(leaves.hashes.len());
+        if size == leaves.hashes.len() {
+            if let Some(root) = *self.sealed_root.read() {
+                return root;
+            }
+        }
+        merkle::merkle_root(&leaves.hashes[..size])
+    }
+
+    /// Audit path for a leaf within the first `size` leaves
+    pub fn audit_path(&self, index: usize, size: usize) -> Vec<Hash> {
+        let leaves = self.leaves.read();
+        merkle::audit_path(index, &leaves.hashes[..size.min(leaves.hashes.len())])
+    }
+
+    /// Mark the segment as complete and cache its root
+    ///
+    /// Called when the segment rolls; no leaves are appended afterwards.
+    pub fn seal(&self) -> Hash {
+        let root = merkle::merkle_root(&self.leaves.read().hashes);
+        *self.sealed_root.write() = Some(root);
+        root
+    }
+
+    /// Sync the leaf log to disk
+    pub fn sync(&self) -> Result<()> {
+        self.file
+            .read()
+            .sync_all()
+            .map_err(|e| PyralogError::StorageError(e.to_string()))
+    }
+
+    /// Get the path to this leaf log
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::log_storage::{LogStorage, LogStorageConfig};
+    use bytes::Bytes;
+    use pyralog_core::{LogOffset, Record};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_inclusion_proof_across_segments() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = LogStorageConfig::default();
+        config.cache_config.enabled = false;
+        config.segment_config.max_size = 256;
+
+        let storage = LogStorage::create(temp_dir.path().to_path_buf(), config)
+            .await
+            .unwrap();
+
+        for i in 0..8 {
+            storage
+                .append(Record::new(None, Bytes::from(format!("event-{}", i))))
+                .await
+                .unwrap();
+        }
+
+        let root = storage.merkle_root(LogOffset::new(7)).unwrap().unwrap();
+        let proof = storage
+            .inclusion_proof(LogOffset::new(1), LogOffset::new(7))
+            .unwrap()
+            .unwrap();
+        assert!(!proof.later_segment_roots.is_empty());
+
+        let mut record = storage.read(LogOffset::new(1)).await.unwrap().unwrap();
+        assert!(proof.verify_record(&record, &root));
+
+        record.value = Bytes::from("rewritten");
+        assert!(!proof.verify_record(&record, &root));
+    }
+
+    #[tokio::test]
+    async fn test_roots_survive_reopen() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = LogStorageConfig::default();
+        config.cache_config.enabled = false;
+        config.segment_config.max_size = 256;
+
+        let storage = LogStorage::create(temp_dir.path().to_path_buf(), config.clone())
+            .await
+            .unwrap();
+        for i in 0..8 {
+            storage
+                .append(Record::new(None, Bytes::from(format!("event-{}", i))))
+                .await
+                .unwrap();
+        }
+        let roots: Vec<_> = (0..8)
+            .map(|i| storage.merkle_root(LogOffset::new(i)).unwrap().unwrap())
+            .collect();
+        drop(storage);
+
+        // Lose the last leaf of the active segment, as a crash might
+        let last_merkle = std::fs::read_dir(temp_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "merkle"))
+            .max()
+            .unwrap();
+        let len = std::fs::metadata(&last_merkle).unwrap().len();
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&last_merkle)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+
+        let storage = LogStorage::open(temp_dir.path().to_path_buf(), config)
+            .await
+            .unwrap();
+        for (i, root) in roots.iter().enumerate() {
+            assert_eq!(storage.merkle_root(LogOffset::new(i as u64)).unwrap().as_ref(), Some(root));
+        }
+
+        let offset = storage
+            .append(Record::new(None, Bytes::from("after restart")))
+            .await
+            .unwrap();
+        let root = storage.merkle_root(offset).unwrap().unwrap();
+        let proof = storage.inclusion_proof(LogOffset::new(2), offset).unwrap().unwrap();
+        let record = storage.read(LogOffset::new(2)).await.unwrap().unwrap();
+        assert!(proof.verify_record(&record, &root));
+    }
+}
@@ -0,0 +1,211 @@
+use pyralog_core::{LogOffset, OffsetRange, PyralogError, Record, Result};
+use std::path::Path;
+
+use crate::export::{ExportFormat, ExportRange, ExportWriter, DEFAULT_EXPORT_BATCH_SIZE};
+use crate::index::Index;
+use crate::segment::{Segment, SegmentConfig};
+
+/// Read-only view of the records in a log directory
+///
+/// Segments and their offset indexes are opened for reading only, and
+/// nothing is created, truncated or rebuilt, so a partition can be read
+/// while a server has it open. Only records indexed when the view was
+/// opened are seen; a segment without an index holds none.
+pub struct LogReader {
+    /// Segments with the offset of their first indexed record, oldest first
+    segments: Vec<(LogOffset, Segment, Index)>,
+    high_watermark: LogOffset,
+}
+
+impl LogReader {
+    /// Open a view of the segments in `base_path`
+    pub fn open(base_path: &Path, config: SegmentConfig) -> Result<Self> {
+        let mut segment_files = std::fs::read_dir(base_path)
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().and_then(|s| s.to_str()) == Some("log"))
+            .collect::<Vec<_>>();
+        segment_files.sort();
+
+        let mut segments = Vec::new();
+        let mut high_watermark = LogOffset::ZERO;
+        for segment_path in segment_files {
+            let index_path = segment_path.with_extension("index");
+            if !index_path.exists() {
+                continue;
+            }
+
+            // The index first: a server writes records before indexing
+            // them, so every indexed record is then in the segment
+            let index = Index::open_read_only(index_path)?;
+            let segment = Segment::open_read_only(segment_path, config.clone())?;
+            let entries = index.entries();
+            let (Some((first, _, _)), Some((last, _, _))) = (entries.first(), entries.last()) else {
+                continue;
+            };
+            high_watermark = last.next();
+            segments.push((*first, segment, index));
+        }
+
+        Ok(Self {
+            segments,
+            high_watermark,
+        })
+    }
+
+    /// Read the record at `offset`
+    pub fn read(&self, offset: LogOffset) -> Result<Option<Record>> {
+        // Segments rolled while records were cached used to be named after
+        // an offset past their first record, so they are found by their
+        // first indexed offset, not their names
+        let count = self.segments.partition_point(|(first, _, _)| *first <= offset);
+        let Some((_, segment, index)) = count.checked_sub(1).map(|i| &self.segments[i]) else {
+            return Ok(None);
+        };
+        let Some((position, size)) = index.lookup(offset) else {
+            return Ok(None);
+        };
+        let data = segment.read(position, size as usize)?;
+        let record = bincode::deserialize(&data)
+            .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+        Ok(Some(record))
+    }
+
+    /// Read the records in `range`
+    pub fn read_range(&self, range: OffsetRange) -> Result<Vec<Record>> {
+        let mut records = Vec::new();
+        for offset in range.start.as_u64()..range.end.as_u64() {
+            if let Some(record) = self.read(LogOffset::new(offset))? {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    /// Offset after the last indexed record
+    pub fn high_watermark(&self) -> LogOffset {
+        self.high_watermark
+    }
+
+    /// Export a range of records to a file in Arrow IPC or Parquet format
+    ///
+    /// Records are read and written `DEFAULT_EXPORT_BATCH_SIZE` offsets at a
+    /// time. A time range scans every offset, since timestamps are not
+    /// indexed. Gap markers are skipped, as they hold no data. Returns the
+    /// number of exported records.
+    pub fn export(&self, range: ExportRange, format: ExportFormat, path: &Path) -> Result<usize> {
+        let scan = match range {
+            ExportRange::Offsets(offsets) => offsets,
+            ExportRange::Time { .. } => OffsetRange::new(LogOffset::ZERO, self.high_watermark),
+        };
+        let end = scan.end.min(self.high_watermark);
+
+        let file = std::fs::File::create(path)
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+        let mut writer = ExportWriter::new(std::io::BufWriter::new(file), format)?;
+        let mut exported = 0;
+
+        let mut start = scan.start;
+        while start < end {
+            let chunk_end = LogOffset::new(
+                start
+                    .as_u64()
+                    .saturating_add(DEFAULT_EXPORT_BATCH_SIZE as u64)
+                    .min(end.as_u64()),
+            );
+
+            let mut records = self.read_range(OffsetRange::new(start, chunk_end))?;
+            records.retain(|record| record.gap_kind().is_none() && range.contains(record));
+            writer.write(&records)?;
+
+            exported += records.len();
+            start = chunk_end;
+        }
+
+        writer.finish()?;
+        Ok(exported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_storage::{LogStorage, LogStorageConfig};
+    use bytes::Bytes;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_reads_without_touching_side_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = LogStorageConfig::default();
+        config.segment_config.max_size = 256;
+
+        let storage = LogStorage::create(temp_dir.path().to_path_buf(), config.clone())
+            .await
+            .unwrap();
+        for i in 0..8 {
+            let record = Record::new(None, Bytes::from(format!("event-{}", i)));
+            storage.append(record).await.unwrap();
+        }
+        storage.flush().await.unwrap();
+
+        // A missing Merkle leaf log would be rebuilt by a read-write open
+        let merkle_path = temp_dir.path().join(format!("{:020}.merkle", 0));
+        std::fs::remove_file(&merkle_path).unwrap();
+        let files = std::fs::read_dir(temp_dir.path()).unwrap().count();
+
+        let reader = LogReader::open(temp_dir.path(), config.segment_config).unwrap();
+        assert_eq!(reader.high_watermark(), LogOffset::new(8));
+        let records = reader
+            .read_range(OffsetRange::new(LogOffset::new(2), LogOffset::new(6)))
+            .unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[0].value, Bytes::from("event-2"));
+
+        let output = TempDir::new().unwrap();
+        let range = ExportRange::Offsets(OffsetRange::new(LogOffset::ZERO, LogOffset::new(8)));
+        let exported = reader
+            .export(range, ExportFormat::ArrowIpc, &output.path().join("events.arrow"))
+            .unwrap();
+        assert_eq!(exported, 8);
+
+        assert!(!merkle_path.exists());
+        assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), files);
+    }
+
+    #[tokio::test]
+    async fn test_export_skips_gap_markers() {
+        use pyralog_core::{Epoch, GapKind};
+
+        let temp_dir = TempDir::new().unwrap();
+        let config = LogStorageConfig::default();
+        let storage = LogStorage::create(temp_dir.path().to_path_buf(), config.clone())
+            .await
+            .unwrap();
+        let epoch = Epoch::FIRST;
+        let mut records = Vec::new();
+        for (i, value) in ["a", "b", "", "c"].into_iter().enumerate() {
+            let mut record = match value {
+                "" => Record::gap(GapKind::Hole),
+                value => Record::new(None, Bytes::from(value)),
+            }
+            .with_epoch(epoch);
+            record.offset = LogOffset::new(i as u64);
+            records.push(record);
+        }
+        storage.append_replicated(epoch, records).await.unwrap();
+        storage.flush().await.unwrap();
+
+        let reader = LogReader::open(temp_dir.path(), config.segment_config).unwrap();
+        let marker = reader.read(LogOffset::new(2)).unwrap().unwrap();
+        assert_eq!(marker.gap_kind(), Some(GapKind::Hole));
+
+        let output = TempDir::new().unwrap();
+        let range = ExportRange::Offsets(OffsetRange::new(LogOffset::ZERO, LogOffset::new(4)));
+        let exported = reader
+            .export(range, ExportFormat::ArrowIpc, &output.path().join("events.arrow"))
+            .unwrap();
+        assert_eq!(exported, 3);
+    }
+}
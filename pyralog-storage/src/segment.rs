@@ -8,7 +8,7 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 /// Configuration for segment files
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct SegmentConfig {
     pub max_size: u64,
     pub use_mmap: bool,
@@ -44,12 +44,15 @@ impl Segment {
     ) -> Result<Self> {
         let path = directory.join(format!("{:020}.log", base_offset.as_u64()));
         
+        // Appending keeps writes at the end even after reads move the cursor
         let file = OpenOptions::new()
             .create(true)
             .read(true)
-            .write(true)
+            .append(true)
             .open(&path)
             .map_err(|e| PyralogError::StorageError(e.to_string()))?;
+        file.set_len(0)
+            .map_err(|e| PyralogError::StorageError(e.to_string()))?;
 
         Ok(Self {
             base_offset,
@@ -63,6 +66,15 @@ impl Segment {
 
     /// Open an existing segment
     pub fn open(path: PathBuf, config: SegmentConfig) -> Result<Self> {
+        Self::open_with(path, config, OpenOptions::new().read(true).append(true))
+    }
+
+    /// Open an existing segment for reading only; appends fail
+    pub fn open_read_only(path: PathBuf, config: SegmentConfig) -> Result<Self> {
+        Self::open_with(path, config, OpenOptions::new().read(true))
+    }
+
+    fn open_with(path: PathBuf, config: SegmentConfig, options: &OpenOptions) -> Result<Self> {
         let filename = path
             .file_stem()
             .and_then(|s| s.to_str())
@@ -72,9 +84,7 @@ impl Segment {
             .parse::<u64>()
             .map_err(|e| PyralogError::StorageError(format!("Invalid offset in filename: {}", e)))?;
 
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
+        let file = options
             .open(&path)
             .map_err(|e| PyralogError::StorageError(e.to_string()))?;
 
@@ -132,11 +142,14 @@ impl Segment {
             return Err(PyralogError::InvalidOffset(offset));
         }
 
-        // Try to read from mmap first
+        // Try to read from mmap first; it only covers the data present
+        // when the segment was opened
         if let Some(mmap) = self.mmap.read().as_ref() {
             let start = offset as usize;
             let end = start + length;
-            return Ok(Bytes::copy_from_slice(&mmap[start..end]));
+            if end <= mmap.len() {
+                return Ok(Bytes::copy_from_slice(&mmap[start..end]));
+            }
         }
 
         // Fallback to file read
@@ -18,7 +18,7 @@ struct CacheBuffer {
     last_flush: Instant,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct WriteCacheConfig {
     /// Maximum size of the cache in bytes
     pub max_size: usize,
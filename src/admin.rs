@@ -0,0 +1,84 @@
+//! Administrative commands that operate directly on a node's data directory
+//!
+//! They only read it, so they are safe to run while the node is up.
+
+use crate::config::PyralogConfig;
+use pyralog_core::{LogId, LogOffset, OffsetRange, PartitionId, Result, PyralogError};
+use pyralog_storage::{ExportFormat, ExportRange, LogReader};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Export a range of a partition to an Arrow IPC stream or Parquet file
+///
+/// Reads the partition's segments through a `LogReader`, which never writes
+/// to the data directory. Returns the number of exported records.
+pub async fn export_partition(
+    config: &PyralogConfig,
+    log_id: &LogId,
+    partition: PartitionId,
+    range: ExportRange,
+    format: ExportFormat,
+    output: &Path,
+) -> Result<usize> {
+    let path = config.partition_dir(log_id, partition);
+    if !path.exists() {
+        return Err(PyralogError::PartitionNotFound(partition.as_u32() as u64));
+    }
+
+    let reader = LogReader::open(&path, config.storage.segment_config.clone())?;
+    reader.export(range, format, output)
+}
+
+/// Run the `export` admin command
+///
+/// Usage: `export <namespace/name> <partition> <from>..<to> <ipc|parquet> <output> [--by-time]`
+///
+/// The range is a half-open offset range, or a range of unix timestamps in
+/// milliseconds when `--by-time` is given.
+pub async fn run_export(config: &PyralogConfig, args: &[String]) -> Result<usize> {
+    if args.len() < 5 {
+        return Err(PyralogError::InvalidRequest(
+            "usage: export <namespace/name> <partition> <from>..<to> <ipc|parquet> <output> [--by-time]"
+                .to_string(),
+        ));
+    }
+
+    let (namespace, name) = args[0]
+        .split_once('/')
+        .ok_or_else(|| PyralogError::InvalidRequest(format!("Invalid log id: {}", args[0])))?;
+    let log_id = LogId::new(namespace, name);
+
+    let partition = u32::try_from(parse_number(&args[1])?)
+        .map(PartitionId::new)
+        .map_err(|_| PyralogError::InvalidRequest(format!("Invalid partition: {}", args[1])))?;
+
+    let (from, to) = args[2]
+        .split_once("..")
+        .ok_or_else(|| PyralogError::InvalidRequest(format!("Invalid range: {}", args[2])))?;
+    let (from, to) = (parse_number(from)?, parse_number(to)?);
+
+    let range = if args.iter().any(|arg| arg == "--by-time") {
+        ExportRange::Time {
+            start: UNIX_EPOCH + Duration::from_millis(from),
+            end: UNIX_EPOCH + Duration::from_millis(to),
+        }
+    } else {
+        ExportRange::Offsets(OffsetRange::new(LogOffset::new(from), LogOffset::new(to)))
+    };
+
+    let format = match args[3].as_str() {
+        "ipc" | "arrow" => ExportFormat::ArrowIpc,
+        "parquet" => ExportFormat::Parquet,
+        other => {
+            return Err(PyralogError::InvalidRequest(format!("Unknown export format: {}", other)))
+        }
+    };
+
+    export_partition(config, &log_id, partition, range, format, Path::new(&args[4])).await
+}
+
+fn parse_number(value: &str) -> Result<u64> {
+    value
+        .parse::<u64>()
+        .map_err(|e| PyralogError::InvalidRequest(format!("Invalid number {}: {}", value, e)))
+}
@@ -1,27 +1,74 @@
 use bytes::Bytes;
-use pyralog_core::{LogId, LogOffset, PartitionId, Record, Result, PyralogError};
-use pyralog_protocol::{api::*, request::Request, response::Response};
-use std::sync::Arc;
+use parking_lot::Mutex;
+use pyralog_core::{EpochOffset, InclusionProof, LogId, LogOffset, MerkleRoot, OffsetRange, PartitionId, Record, Result, PyralogError};
+use pyralog_protocol::{api::*, frame, request::Request, response::Response};
 use tokio::net::TcpStream;
 
+/// Most redirects followed by one request before giving up
+const MAX_REDIRECTS: usize = 3;
+
 /// Pyralog client for connecting to a Pyralog cluster
 pub struct PyralogClient {
-    address: String,
-    // In production, this would maintain connection pools
+    /// Node requests are sent to, moved to the leader whenever a node
+    /// redirects the client
+    address: Mutex<String>,
 }
 
 impl PyralogClient {
     /// Create a new client
     pub fn new(address: impl Into<String>) -> Self {
         Self {
-            address: address.into(),
+            address: Mutex::new(address.into()),
         }
     }
 
-    /// Connect to the server
+    /// Node the next request will be sent to
+    pub fn address(&self) -> String {
+        self.address.lock().clone()
+    }
+
+    /// Check that the server can be reached
     pub async fn connect(&self) -> Result<()> {
-        // In production, establish connection
-        Ok(())
+        TcpStream::connect(self.address())
+            .await
+            .map(|_| ())
+            .map_err(|e| PyralogError::NetworkError(e.to_string()))
+    }
+
+    /// Send a request, following `NotLeader` redirects to the leader
+    ///
+    /// A redirect without the leader's address is returned to the caller,
+    /// as is one past `MAX_REDIRECTS`.
+    async fn call(&self, request: Request) -> Result<Response> {
+        let payload = request.to_bytes()?;
+
+        let mut redirects = 0;
+        loop {
+            let address = self.address();
+            let response = Self::send(&address, &payload).await?;
+
+            let leader = response.leader_hint().and_then(|hint| hint.address);
+            match leader {
+                Some(leader) if redirects < MAX_REDIRECTS && leader != address => {
+                    tracing::debug!("Redirected from {} to leader at {}", address, leader);
+                    *self.address.lock() = leader;
+                    redirects += 1;
+                }
+                _ => return Ok(response),
+            }
+        }
+    }
+
+    async fn send(address: &str, payload: &[u8]) -> Result<Response> {
+        let mut socket = TcpStream::connect(address)
+            .await
+            .map_err(|e| PyralogError::NetworkError(e.to_string()))?;
+        frame::write_frame(&mut socket, payload).await?;
+
+        let response = frame::read_frame(&mut socket).await?.ok_or_else(|| {
+            PyralogError::NetworkError(format!("{} closed the connection", address))
+        })?;
+        Response::from_bytes(&response)
     }
 
     /// Produce records to a log
@@ -42,9 +89,7 @@ impl PyralogClient {
             acks: AckMode::Leader,
         };
 
-        // In production, send request over network
-        // For now, return mock offset
-        Ok(LogOffset::new(0))
+        self.call_produce(request).await
     }
 
     /// Produce a batch of records
@@ -67,8 +112,17 @@ impl PyralogClient {
             acks: AckMode::Leader,
         };
 
-        // In production, send request over network
-        Ok(LogOffset::new(0))
+        self.call_produce(request).await
+    }
+
+    async fn call_produce(&self, request: ProduceRequest) -> Result<LogOffset> {
+        match self.call(Request::Produce(request)).await? {
+            Response::Produce(ProduceResponse { error: Some(error), .. }) => {
+                Err(PyralogError::InvalidRequest(error))
+            }
+            Response::Produce(response) => Ok(response.base_offset),
+            response => Err(unexpected(response)),
+        }
     }
 
     /// Consume records from a log
@@ -87,8 +141,37 @@ impl PyralogClient {
             max_bytes: 1024 * 1024, // 1MB
         };
 
-        // In production, send request over network
-        Ok(Vec::new())
+        match self.call(Request::Consume(request)).await? {
+            Response::Consume(ConsumeResponse { error: Some(error), .. }) => {
+                Err(PyralogError::InvalidRequest(error))
+            }
+            Response::Consume(response) => Ok(response.records),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Consume a partition from an LSN
+    ///
+    /// Besides records, the stream reports the gaps epoch recovery left and
+    /// where one sequencer's epoch hands over to the next.
+    pub async fn consume_lsn(
+        &self,
+        log_id: LogId,
+        partition: PartitionId,
+        lsn: EpochOffset,
+        max_records: usize,
+    ) -> Result<Vec<ReadEvent>> {
+        let request = LsnConsumeRequest {
+            log_id,
+            partition,
+            lsn,
+            max_records,
+        };
+
+        match self.call(Request::ConsumeLsn(request)).await? {
+            Response::ConsumeLsn(result) => result.map(|response| response.events),
+            response => Err(unexpected(response)),
+        }
     }
 
     /// Create a new log
@@ -104,20 +187,238 @@ impl PyralogClient {
             replication_factor,
         };
 
-        // In production, send request over network
-        Ok(())
+        match self.call(Request::CreateLog(request)).await? {
+            Response::CreateLog(result) => result,
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Create a new log with secondary indexes on the given header keys
+    pub async fn create_log_with_header_indexes(
+        &self,
+        log_id: LogId,
+        partition_count: u32,
+        replication_factor: u32,
+        indexed_headers: Vec<String>,
+    ) -> Result<()> {
+        let indexes = LogIndexOptions {
+            indexed_headers,
+            key_index: false,
+        };
+
+        self.create_indexed_log(log_id, partition_count, replication_factor, indexes).await
+    }
+
+    /// Create a new log that maintains the latest record per key
+    pub async fn create_keyed_log(
+        &self,
+        log_id: LogId,
+        partition_count: u32,
+        replication_factor: u32,
+    ) -> Result<()> {
+        let indexes = LogIndexOptions {
+            indexed_headers: Vec::new(),
+            key_index: true,
+        };
+
+        self.create_indexed_log(log_id, partition_count, replication_factor, indexes).await
+    }
+
+    async fn create_indexed_log(
+        &self,
+        log_id: LogId,
+        partition_count: u32,
+        replication_factor: u32,
+        indexes: LogIndexOptions,
+    ) -> Result<()> {
+        let request = CreateLogRequest {
+            log_id,
+            partition_count,
+            replication_factor,
+        };
+
+        match self.call(Request::CreateLogWith(request, indexes)).await? {
+            Response::CreateLog(result) => result,
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Get the latest record for a key
+    pub async fn get_latest(&self, log_id: LogId, key: Bytes) -> Result<Option<Record>> {
+        let request = GetLatestRequest {
+            log_id,
+            partition: None,
+            key,
+        };
+
+        match self.call(Request::GetLatest(request)).await? {
+            Response::GetLatest(result) => result.map(|response| response.record),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Find records in a partition with a given header value
+    pub async fn query_headers(
+        &self,
+        log_id: LogId,
+        partition: PartitionId,
+        header_key: impl Into<String>,
+        header_value: Bytes,
+        range: OffsetRange,
+    ) -> Result<Vec<Record>> {
+        let request = HeaderQueryRequest {
+            log_id,
+            partition,
+            header_key: header_key.into(),
+            header_value,
+            range,
+            include_records: true,
+        };
+
+        match self.call(Request::QueryHeaders(request)).await? {
+            Response::QueryHeaders(result) => result.map(|response| response.records),
+            response => Err(unexpected(response)),
+        }
     }
 
     /// Delete a log
     pub async fn delete_log(&self, log_id: LogId) -> Result<()> {
-        // In production, send request over network
-        Ok(())
+        match self.call(Request::DeleteLog(log_id)).await? {
+            Response::DeleteLog(result) => result,
+            response => Err(unexpected(response)),
+        }
     }
 
     /// List all logs
     pub async fn list_logs(&self) -> Result<Vec<LogId>> {
-        // In production, send request over network
-        Ok(Vec::new())
+        match self.call(Request::ListLogs).await? {
+            Response::ListLogs(result) => result,
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// List all logs, optionally including every change committed before the call
+    pub async fn list_logs_with_consistency(
+        &self,
+        consistency: ReadConsistency,
+    ) -> Result<Vec<LogId>> {
+        match self.call(Request::ListLogsWith(consistency)).await? {
+            Response::ListLogs(result) => result,
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Get the Merkle root of a partition at an offset
+    pub async fn merkle_root(
+        &self,
+        log_id: LogId,
+        partition: PartitionId,
+        offset: LogOffset,
+    ) -> Result<Option<MerkleRoot>> {
+        let request = MerkleRootRequest {
+            log_id,
+            partition,
+            offset,
+        };
+
+        match self.call(Request::MerkleRoot(request)).await? {
+            Response::MerkleRoot(result) => result,
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Get a proof that the record at `offset` is included under the root at `root_offset`
+    pub async fn inclusion_proof(
+        &self,
+        log_id: LogId,
+        partition: PartitionId,
+        offset: LogOffset,
+        root_offset: LogOffset,
+    ) -> Result<Option<InclusionProof>> {
+        let request = InclusionProofRequest {
+            log_id,
+            partition,
+            offset,
+            root_offset,
+        };
+
+        match self.call(Request::InclusionProof(request)).await? {
+            Response::InclusionProof(result) => result,
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Add a node to the cluster's metadata group as a voter
+    pub async fn add_voter(&self, node_id: u64, address: impl Into<String>) -> Result<()> {
+        let request = Request::ChangeMembership(MembershipChange::AddVoter {
+            node_id,
+            address: address.into(),
+        });
+
+        self.change_membership(request).await
+    }
+
+    /// Remove a node from the cluster's metadata group
+    pub async fn remove_voter(&self, node_id: u64) -> Result<()> {
+        let request = Request::ChangeMembership(MembershipChange::RemoveVoter { node_id });
+
+        self.change_membership(request).await
+    }
+
+    /// Add a node that replicates the cluster's metadata without voting
+    pub async fn add_learner(&self, node_id: u64, address: impl Into<String>) -> Result<()> {
+        let request = Request::ChangeMembership(MembershipChange::AddLearner {
+            node_id,
+            address: address.into(),
+        });
+
+        self.change_membership(request).await
+    }
+
+    /// Turn a learner that has caught up into a voter
+    pub async fn promote_learner(&self, node_id: u64) -> Result<()> {
+        let request = Request::ChangeMembership(MembershipChange::PromoteLearner { node_id });
+
+        self.change_membership(request).await
+    }
+
+    /// Remove a learner from the cluster's metadata group
+    pub async fn remove_learner(&self, node_id: u64) -> Result<()> {
+        let request = Request::ChangeMembership(MembershipChange::RemoveLearner { node_id });
+
+        self.change_membership(request).await
+    }
+
+    /// Move metadata leadership to another node before taking the leader down
+    pub async fn transfer_leadership(&self, target: u64) -> Result<()> {
+        match self.call(Request::TransferLeadership(target)).await? {
+            Response::TransferLeadership(result) => result,
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn change_membership(&self, request: Request) -> Result<()> {
+        match self.call(request).await? {
+            Response::ChangeMembership(result) => result,
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Verify that a record is included under a trusted Merkle root
+    ///
+    /// The root should come from a trusted source (e.g. an auditor's
+    /// previously recorded root), not from the same response as the proof.
+    pub fn verify_inclusion(record: &Record, proof: &InclusionProof, root: &MerkleRoot) -> bool {
+        proof.verify_record(record, root)
+    }
+}
+
+/// Error for a response that does not answer the request it was sent for
+fn unexpected(response: Response) -> PyralogError {
+    match response {
+        Response::Error(error) => PyralogError::InvalidRequest(error),
+        Response::NotLeader(hint) => PyralogError::not_leader(hint),
+        _ => PyralogError::SerializationError("Response does not match the request".to_string()),
     }
 }
 
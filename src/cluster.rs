@@ -1,59 +1,696 @@
-use pyralog_core::{LogId, LogMetadata, PartitionId, Result, PyralogError};
-use pyralog_consensus::{RaftNode, RaftConfig};
+use bytes::Bytes;
+use pyralog_core::{
+    Epoch, EpochLog, EpochMetadata, EpochStore, LeaderHint, LogId, LogMetadata, PartitionId,
+    Result, PyralogError,
+};
+use crate::config::NetworkConfig;
+use pyralog_consensus::{
+    GroupId, MultiRaft, RaftHandler, RaftNode, RaftConfig, RaftTransport, StateMachine, TcpTransport,
+};
+use pyralog_replication::{ReplicaHandler, ReplicaRouter};
 use pyralog_storage::LogStorage;
-use parking_lot::RwLock;
-use std::collections::HashMap;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+
+type LogMap = Arc<RwLock<HashMap<LogId, LogMetadata>>>;
+type AssignmentMap = Arc<RwLock<HashMap<(LogId, PartitionId), Vec<u64>>>>;
+type GroupMap = Arc<RwLock<HashMap<(LogId, PartitionId), GroupId>>>;
+type EpochMap = Arc<RwLock<HashMap<(LogId, PartitionId), EpochStore>>>;
+
+/// Raft group holding the cluster metadata
+const METADATA_GROUP: GroupId = 0;
+
+/// How often partition groups are checked against their assignments when
+/// no metadata changes arrive
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A change to cluster metadata, replicated through the Raft log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum MetadataCommand {
+    /// Register a log along with the nodes hosting each of its partitions
+    ///
+    /// Each partition is given its own Raft group when the command applies.
+    CreateLog {
+        metadata: LogMetadata,
+        assignments: Vec<(PartitionId, Vec<u64>)>,
+    },
+    
+    /// Move a partition to a new set of nodes
+    AssignPartition {
+        log_id: LogId,
+        partition: PartitionId,
+        nodes: Vec<u64>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct MetadataSnapshot {
+    logs: Vec<LogMetadata>,
+    partition_assignments: Vec<((LogId, PartitionId), Vec<u64>)>,
+    partition_voters: Vec<((LogId, PartitionId), Vec<u64>)>,
+    partition_groups: Vec<((LogId, PartitionId), GroupId)>,
+}
+
+/// Applies `MetadataCommand`s to the maps read by `ClusterManager`
+struct MetadataStateMachine {
+    logs: LogMap,
+    partition_assignments: AssignmentMap,
+    partition_voters: AssignmentMap,
+    partition_groups: GroupMap,
+}
+
+impl MetadataStateMachine {
+    fn assign(&self, log_id: LogId, partition: PartitionId, nodes: Vec<u64>) {
+        let key = (log_id, partition);
+        // The first nodes assigned a partition bootstrap its Raft group;
+        // later ones join it
+        self.partition_voters
+            .write()
+            .entry(key.clone())
+            .or_insert_with(|| nodes.clone());
+        self.partition_assignments.write().insert(key, nodes);
+    }
+
+    /// Give every partition of a new log a Raft group of its own
+    ///
+    /// Commands apply in the same order everywhere, so every node allocates
+    /// the same IDs.
+    fn allocate_groups(&self, log_id: &LogId, partition_count: u32) {
+        let mut groups = self.partition_groups.write();
+        let first = groups.values().max().map_or(METADATA_GROUP, |&group| group) + 1;
+        for partition in 0..partition_count {
+            let group = first + GroupId::from(partition);
+            groups.insert((log_id.clone(), PartitionId::new(partition)), group);
+        }
+    }
+}
+
+impl StateMachine for MetadataStateMachine {
+    fn apply(&mut self, _index: u64, data: &[u8]) {
+        // Entries are only ever written by `ClusterManager::propose`
+        let command: MetadataCommand = match bincode::deserialize(data) {
+            Ok(command) => command,
+            Err(_) => return,
+        };
+
+        match command {
+            MetadataCommand::CreateLog { metadata, assignments } => {
+                // The first registration of a log wins
+                let mut logs = self.logs.write();
+                if logs.contains_key(&metadata.id) {
+                    return;
+                }
+                let log_id = metadata.id.clone();
+                self.allocate_groups(&log_id, metadata.partition_count);
+                logs.insert(log_id.clone(), metadata);
+                for (partition, nodes) in assignments {
+                    self.assign(log_id.clone(), partition, nodes);
+                }
+            }
+            MetadataCommand::AssignPartition { log_id, partition, nodes } => {
+                // Only partitions of registered logs have a group to move
+                if self.partition_groups.read().contains_key(&(log_id.clone(), partition)) {
+                    self.assign(log_id, partition, nodes);
+                }
+            }
+        }
+    }
+
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        let snapshot = MetadataSnapshot {
+            logs: self.logs.read().values().cloned().collect(),
+            partition_assignments: self
+                .partition_assignments
+                .read()
+                .iter()
+                .map(|(key, nodes)| (key.clone(), nodes.clone()))
+                .collect(),
+            partition_voters: self
+                .partition_voters
+                .read()
+                .iter()
+                .map(|(key, nodes)| (key.clone(), nodes.clone()))
+                .collect(),
+            partition_groups: self
+                .partition_groups
+                .read()
+                .iter()
+                .map(|(key, group)| (key.clone(), *group))
+                .collect(),
+        };
+
+        bincode::serialize(&snapshot)
+            .map_err(|e| PyralogError::SerializationError(e.to_string()))
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<()> {
+        let snapshot: MetadataSnapshot = bincode::deserialize(snapshot)
+            .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+
+        *self.logs.write() = snapshot
+            .logs
+            .into_iter()
+            .map(|metadata| (metadata.id.clone(), metadata))
+            .collect();
+        *self.partition_assignments.write() = snapshot.partition_assignments.into_iter().collect();
+        *self.partition_voters.write() = snapshot.partition_voters.into_iter().collect();
+        *self.partition_groups.write() = snapshot.partition_groups.into_iter().collect();
+
+        Ok(())
+    }
+}
+
+/// A change to a partition's epochs, replicated through its Raft group
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum EpochCommand {
+    /// Hand the partition to a new sequencer; the epoch is at least the
+    /// term of the leader proposing it
+    Start {
+        epoch: Epoch,
+        sequencer_node: u64,
+        start_offset: u64,
+    },
+
+    /// Close an epoch to writes; `last_offset` is None if it holds no
+    /// records
+    Seal { epoch: Epoch, last_offset: Option<u32> },
+
+    /// Forget sealed epochs that retention has moved past
+    Compact { low_watermark: u64 },
+}
+
+/// Partition groups elect leaders and record the partition's epochs; the
+/// records themselves live in the partition's `LogStorage`
+struct PartitionStateMachine {
+    log_id: LogId,
+    partition: PartitionId,
+    epochs: EpochMap,
+}
+
+impl StateMachine for PartitionStateMachine {
+    fn apply(&mut self, _index: u64, data: &[u8]) {
+        // Entries are only ever written by `ClusterManager::propose_epoch`
+        let command: EpochCommand = match bincode::deserialize(data) {
+            Ok(command) => command,
+            Err(_) => return,
+        };
+
+        let mut epochs = self.epochs.write();
+        let store = epochs.entry((self.log_id.clone(), self.partition)).or_default();
+        match command {
+            EpochCommand::Start { epoch, sequencer_node, start_offset } => {
+                // A deposed leader's proposal may commit after a newer one
+                store.record_epoch(EpochMetadata::new(epoch, sequencer_node, start_offset));
+            }
+            EpochCommand::Seal { epoch, last_offset } => {
+                store.seal_epoch(epoch, last_offset);
+            }
+            EpochCommand::Compact { low_watermark } => {
+                store.compact(low_watermark);
+            }
+        }
+    }
+
+    fn snapshot(&self) -> Result<Vec<u8>> {
+        let epochs = self.epochs.read();
+        let store = epochs
+            .get(&(self.log_id.clone(), self.partition))
+            .cloned()
+            .unwrap_or_default();
+
+        bincode::serialize(&store)
+            .map_err(|e| PyralogError::SerializationError(e.to_string()))
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<()> {
+        let store: EpochStore = bincode::deserialize(snapshot)
+            .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+
+        self.epochs.write().insert((self.log_id.clone(), self.partition), store);
+        Ok(())
+    }
+}
 
 /// Cluster manager handles log metadata and partition assignments
+///
+/// Metadata and every partition of every log each have their own Raft
+/// group, all hosted by one multi-Raft runtime per node. A partition is led by whichever of
+/// its nodes wins its group's election. A new leader records an epoch, at
+/// least its term, in the group before writing, so epochs are unique,
+/// durable, and recovered from the group's log on restart.
 pub struct ClusterManager {
     node_id: u64,
+    multi_raft: Arc<MultiRaft>,
     raft: Arc<RaftNode>,
-    logs: Arc<RwLock<HashMap<LogId, LogMetadata>>>,
-    partition_assignments: Arc<RwLock<HashMap<PartitionId, Vec<u64>>>>,
+    raft_config: RaftConfig,
+    internal_address: String,
+    cluster_addresses: BTreeMap<u64, String>,
+    client_addresses: BTreeMap<u64, String>,
+    logs: LogMap,
+    partition_assignments: AssignmentMap,
+    partition_voters: AssignmentMap,
+    partition_groups: GroupMap,
+    epochs: EpochMap,
+    /// For each partition, the epoch this node started and the term it
+    /// led the partition in at the time
+    active_epochs: RwLock<HashMap<(LogId, PartitionId), (u64, Epoch)>>,
+    /// Listener and reconciler, stopped on shutdown
+    tasks: Mutex<Vec<JoinHandle<()>>>,
 }
 
 impl ClusterManager {
-    pub async fn new(config: RaftConfig) -> Result<Self> {
+    pub async fn new(config: RaftConfig, network: &NetworkConfig) -> Result<Self> {
+        // A Raft RPC that takes longer than an election timeout is useless
+        let transport = Arc::new(TcpTransport::new(
+            network.cluster_addresses.clone(),
+            Duration::from_millis(config.election_timeout.min_ms),
+        ));
+        Self::with_transport(config, network, transport).await
+    }
+
+    /// Create a cluster manager sending Raft traffic through `transport`
+    ///
+    /// Peers must deliver requests to `raft_handler`; with a transport
+    /// other than TCP, start the manager with `run` rather than `start`.
+    pub async fn with_transport(
+        config: RaftConfig,
+        network: &NetworkConfig,
+        transport: Arc<dyn RaftTransport>,
+    ) -> Result<Self> {
         let node_id = config.node_id;
-        let raft = Arc::new(RaftNode::new(config).await?);
+        let logs: LogMap = Arc::new(RwLock::new(HashMap::new()));
+        let partition_assignments: AssignmentMap = Arc::new(RwLock::new(HashMap::new()));
+        let partition_voters: AssignmentMap = Arc::new(RwLock::new(HashMap::new()));
+        let partition_groups: GroupMap = Arc::new(RwLock::new(HashMap::new()));
+
+        let state_machine = Box::new(MetadataStateMachine {
+            logs: Arc::clone(&logs),
+            partition_assignments: Arc::clone(&partition_assignments),
+            partition_voters: Arc::clone(&partition_voters),
+            partition_groups: Arc::clone(&partition_groups),
+        });
+        let multi_raft = MultiRaft::new(node_id, transport);
+        let raft = multi_raft
+            .create_group(METADATA_GROUP, config.clone(), state_machine)
+            .await?;
         
         Ok(Self {
             node_id,
+            multi_raft,
             raft,
-            logs: Arc::new(RwLock::new(HashMap::new())),
-            partition_assignments: Arc::new(RwLock::new(HashMap::new())),
+            raft_config: config,
+            internal_address: network.internal_address.clone(),
+            cluster_addresses: network.cluster_addresses.clone(),
+            client_addresses: network.client_addresses.clone(),
+            logs,
+            partition_assignments,
+            partition_voters,
+            partition_groups,
+            epochs: Arc::new(RwLock::new(HashMap::new())),
+            active_epochs: RwLock::new(HashMap::new()),
+            tasks: Mutex::new(Vec::new()),
         })
     }
 
-    pub async fn start(self: Arc<Self>) -> Result<()> {
-        Arc::clone(&self.raft).start().await?;
+    /// Accept Raft traffic on the internal address and start the groups
+    ///
+    /// Replica traffic sent over Raft's connections is passed to `replicas`.
+    pub async fn start(self: Arc<Self>, replicas: Arc<dyn ReplicaHandler>) -> Result<()> {
+        let handler = Arc::new(ReplicaRouter::new(self.raft_handler(), replicas));
+        let address = self.internal_address.clone();
+        let listener = tokio::spawn(async move {
+            if let Err(e) = TcpTransport::serve(&address, handler).await {
+                tracing::error!("Raft listener on {} failed: {}", address, e);
+            }
+        });
+        self.tasks.lock().push(listener);
+
+        self.run();
+        Ok(())
+    }
+
+    /// Start the groups, with Raft traffic arriving through `raft_handler`
+    pub fn run(self: Arc<Self>) {
+        self.multi_raft.start();
+        let reconciler = tokio::spawn(Arc::clone(&self).run_reconciler());
+        self.tasks.lock().push(reconciler);
+    }
+
+    /// Where Raft requests addressed to this node are handled
+    pub fn raft_handler(&self) -> Arc<dyn RaftHandler> {
+        self.multi_raft.clone()
+    }
+
+    /// Stop serving Raft traffic and shut down every group
+    pub fn shutdown(&self) {
+        for task in self.tasks.lock().drain(..) {
+            task.abort();
+        }
+        self.multi_raft.shutdown();
+    }
+
+    /// Keep the partition groups hosted here in line with the assignments
+    async fn run_reconciler(self: Arc<Self>) {
+        let mut applied = self.raft.subscribe();
+        loop {
+            tokio::select! {
+                received = applied.recv() => {
+                    if let Err(RecvError::Closed) = received {
+                        return;
+                    }
+                }
+                _ = sleep(RECONCILE_INTERVAL) => {}
+            }
+
+            self.reconcile().await;
+        }
+    }
+
+    /// Open groups of partitions assigned here, drop those this node has
+    /// left, and have partition leaders move their group one membership
+    /// change closer to the assignment
+    ///
+    /// A partition that fails is logged and retried on the next pass, so
+    /// it does not hold up the others.
+    async fn reconcile(&self) {
+        let assignments: Vec<((LogId, PartitionId), Vec<u64>)> = self
+            .partition_assignments
+            .read()
+            .iter()
+            .map(|(key, nodes)| (key.clone(), nodes.clone()))
+            .collect();
+
+        for ((log_id, partition), nodes) in assignments {
+            if let Err(e) = self.reconcile_partition(&log_id, partition, &nodes).await {
+                tracing::warn!(
+                    "Failed to reconcile partition {} of log {}: {}",
+                    partition.as_u32(),
+                    log_id,
+                    e
+                );
+            }
+        }
+    }
+
+    async fn reconcile_partition(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        nodes: &[u64],
+    ) -> Result<()> {
+        let group_id = match self.partition_group(log_id, partition) {
+            Some(group_id) => group_id,
+            None => return Ok(()),
+        };
+        let assigned = nodes.contains(&self.node_id);
+        let group = match self.multi_raft.group(group_id) {
+            Some(group) => group,
+            None if assigned => self.open_partition_group(log_id, partition, group_id).await?,
+            None => return Ok(()),
+        };
+
+        if !assigned && !group.membership().is_voter(self.node_id) {
+            self.multi_raft.remove_group(group_id);
+            return Ok(());
+        }
+        if group.is_leader() {
+            self.sync_partition_voters(&group, nodes).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn open_partition_group(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        group: GroupId,
+    ) -> Result<Arc<RaftNode>> {
+        // Nodes assigned after the group was bootstrapped start without
+        // members and are added by its leader
+        let bootstrap = self
+            .partition_voters
+            .read()
+            .get(&(log_id.clone(), partition))
+            .cloned()
+            .unwrap_or_default();
+        let cluster_nodes = if bootstrap.contains(&self.node_id) {
+            bootstrap
+        } else {
+            Vec::new()
+        };
+
+        let config = RaftConfig {
+            cluster_nodes,
+            data_dir: self.raft_config.data_dir.join(format!(
+                "{}/{}/partition-{}",
+                log_id.namespace,
+                log_id.name,
+                partition.as_u32()
+            )),
+            ..self.raft_config.clone()
+        };
+        let state_machine = Box::new(PartitionStateMachine {
+            log_id: log_id.clone(),
+            partition,
+            epochs: Arc::clone(&self.epochs),
+        });
+        self.multi_raft.create_group(group, config, state_machine).await
+    }
+
+    async fn sync_partition_voters(&self, group: &RaftNode, nodes: &[u64]) -> Result<()> {
+        let voters = group.membership().voters;
+
+        if let Some(&node_id) = nodes.iter().find(|node_id| !voters.contains(node_id)) {
+            let address = self.cluster_addresses.get(&node_id).cloned().ok_or_else(|| {
+                PyralogError::ConfigError(format!("No address for node {}", node_id))
+            })?;
+            return group.add_voter(node_id, address).await;
+        }
+        if let Some(&node_id) = voters.iter().find(|node_id| !nodes.contains(node_id)) {
+            return group.remove_voter(node_id).await;
+        }
+
         Ok(())
     }
 
     /// Register a new log in the cluster
+    ///
+    /// Partitions are spread over the cluster nodes round-robin. Returns once
+    /// the log is committed and visible in this node's metadata.
     pub async fn create_log(&self, metadata: LogMetadata) -> Result<()> {
-        // In production, this would go through Raft consensus
-        self.logs.write().insert(metadata.id.clone(), metadata);
+        if self.logs.read().contains_key(&metadata.id) {
+            return Err(PyralogError::InvalidRequest(format!(
+                "Log already exists: {}/{}",
+                metadata.id.namespace, metadata.id.name
+            )));
+        }
+
+        let assignments = (0..metadata.partition_count)
+            .map(|partition| {
+                let partition = PartitionId::new(partition);
+                (partition, self.assign_replicas(partition, metadata.replication_factor))
+            })
+            .collect();
+
+        self.propose(MetadataCommand::CreateLog { metadata, assignments }).await
+    }
+
+    /// Move a partition to a new set of nodes
+    ///
+    /// The partition's Raft group adds the new nodes before removing the old
+    /// ones, and its leader is elected among them. Every node must be a
+    /// member of the cluster.
+    pub async fn assign_partition(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        nodes: Vec<u64>,
+    ) -> Result<()> {
+        if self.partition_group(log_id, partition).is_none() {
+            return Err(PyralogError::PartitionNotFound(partition.as_u32() as u64));
+        }
+        let members = self.members();
+        if let Some(node_id) = nodes.iter().find(|id| !members.contains(id)) {
+            return Err(PyralogError::InvalidRequest(format!("Unknown node {}", node_id)));
+        }
+
+        let log_id = log_id.clone();
+        self.propose(MetadataCommand::AssignPartition { log_id, partition, nodes }).await
+    }
+
+    /// Add a node to the metadata Raft group as a voter
+    ///
+    /// The node should be started without cluster nodes of its own so that
+    /// it learns the membership from the leader.
+    pub async fn add_voter(&self, node_id: u64, address: String) -> Result<()> {
+        self.raft.add_voter(node_id, address).await.map_err(|e| self.redirect(e))
+    }
+
+    /// Remove a node from the metadata Raft group
+    pub async fn remove_voter(&self, node_id: u64) -> Result<()> {
+        self.raft.remove_voter(node_id).await.map_err(|e| self.redirect(e))
+    }
+
+    /// Add a node that replicates metadata without voting, e.g. a read-only
+    /// replica in a remote region or a node still catching up
+    pub async fn add_learner(&self, node_id: u64, address: String) -> Result<()> {
+        self.raft.add_learner(node_id, address).await.map_err(|e| self.redirect(e))
+    }
+
+    /// Turn a learner that has caught up into a voter
+    pub async fn promote_learner(&self, node_id: u64) -> Result<()> {
+        self.raft.promote_learner(node_id).await.map_err(|e| self.redirect(e))
+    }
+
+    /// Remove a learner from the metadata Raft group
+    pub async fn remove_learner(&self, node_id: u64) -> Result<()> {
+        self.raft.remove_learner(node_id).await.map_err(|e| self.redirect(e))
+    }
+
+    /// Hand metadata leadership to another node
+    ///
+    /// Metadata changes are refused for the few milliseconds the handover
+    /// takes. Returns once this node is no longer the leader.
+    pub async fn transfer_leadership(&self, target: u64) -> Result<()> {
+        self.raft.transfer_leadership(target).await.map_err(|e| self.redirect(e))
+    }
+
+    /// Current voters of the metadata Raft group
+    pub fn members(&self) -> Vec<u64> {
+        self.raft.membership().voters.into_iter().collect()
+    }
+
+    /// Commit a metadata command and wait until it is applied locally
+    async fn propose(&self, command: MetadataCommand) -> Result<()> {
+        let data = bincode::serialize(&command)
+            .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+
+        let index = self
+            .raft
+            .propose(Bytes::from(data))
+            .await
+            .map_err(|e| self.redirect(e))?;
+        self.raft.wait_applied(index.as_u64()).await;
+
         Ok(())
     }
 
+    fn assign_replicas(&self, partition: PartitionId, replication_factor: u32) -> Vec<u64> {
+        let nodes = &self.members();
+        let count = (replication_factor as usize).clamp(1, nodes.len().max(1)).min(nodes.len());
+        let start = partition.as_u32() as usize;
+
+        (0..count).map(|i| nodes[(start + i) % nodes.len()]).collect()
+    }
+
+    /// Wait until this node's metadata reflects every committed change
+    ///
+    /// Metadata read afterwards is linearizable; without it a follower or a
+    /// deposed leader may answer from stale state.
+    pub async fn read_barrier(&self) -> Result<()> {
+        self.raft
+            .read_index()
+            .await
+            .map(|_| ())
+            .map_err(|e| self.redirect(e))
+    }
+
+    /// Add the leader's client address to a `NotLeader` error
+    fn redirect(&self, error: PyralogError) -> PyralogError {
+        match error.leader_hint() {
+            Some(hint) => PyralogError::not_leader(Some(self.leader_hint(hint.node_id))),
+            None => error,
+        }
+    }
+
+    /// Where clients should send requests meant for `leader_id`
+    fn leader_hint(&self, leader_id: u64) -> LeaderHint {
+        let hint = LeaderHint::new(leader_id);
+        match self.client_addresses.get(&leader_id) {
+            Some(address) => hint.with_address(address.clone()),
+            None => hint,
+        }
+    }
+
     /// Get log metadata
     pub fn get_log(&self, log_id: &LogId) -> Option<LogMetadata> {
         self.logs.read().get(log_id).cloned()
     }
 
     /// Get partition assignment (which nodes host this partition)
-    pub fn get_partition_nodes(&self, partition: PartitionId) -> Option<Vec<u64>> {
-        self.partition_assignments.read().get(&partition).cloned()
+    pub fn get_partition_nodes(&self, log_id: &LogId, partition: PartitionId) -> Option<Vec<u64>> {
+        self.partition_assignments
+            .read()
+            .get(&(log_id.clone(), partition))
+            .cloned()
+    }
+
+    /// Raft group electing the leader of a partition
+    fn partition_group(&self, log_id: &LogId, partition: PartitionId) -> Option<GroupId> {
+        self.partition_groups
+            .read()
+            .get(&(log_id.clone(), partition))
+            .copied()
+    }
+
+    /// The partition's Raft group, if this node hosts it
+    fn partition_raft(&self, log_id: &LogId, partition: PartitionId) -> Option<Arc<RaftNode>> {
+        self.multi_raft.group(self.partition_group(log_id, partition)?)
     }
 
     /// Check if this node is the leader for a partition
-    pub fn is_partition_leader(&self, partition: PartitionId) -> bool {
-        self.get_partition_nodes(partition)
-            .map(|nodes| nodes.first() == Some(&self.node_id))
-            .unwrap_or(false)
+    pub fn is_partition_leader(&self, log_id: &LogId, partition: PartitionId) -> bool {
+        self.partition_raft(log_id, partition)
+            .is_some_and(|group| group.is_leader())
+    }
+
+    /// Node leading a partition and its client address, if known here
+    pub fn partition_leader(&self, log_id: &LogId, partition: PartitionId) -> Option<LeaderHint> {
+        let leader_id = self.partition_raft(log_id, partition)?.leader_id()?;
+        Some(self.leader_hint(leader_id))
+    }
+
+    /// Epoch of the partition while this node leads it
+    ///
+    /// None until the leader has recorded an epoch in its term with
+    /// `EpochLog::start_epoch`, or once a newer epoch is recorded.
+    pub fn partition_epoch(&self, log_id: &LogId, partition: PartitionId) -> Option<Epoch> {
+        let key = (log_id.clone(), partition);
+        let term = self.partition_raft(log_id, partition)?.leader_term()?;
+        let (started_term, epoch) = *self.active_epochs.read().get(&key)?;
+        let current = self.epochs.read().get(&key)?.current_epoch()?;
+        (started_term == term && current == epoch).then_some(epoch)
+    }
+
+    /// Commit an epoch command to a partition's group and wait until it is
+    /// applied locally
+    async fn propose_epoch(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        command: EpochCommand,
+    ) -> Result<()> {
+        let group = self
+            .partition_raft(log_id, partition)
+            .ok_or_else(|| PyralogError::not_leader(self.partition_leader(log_id, partition)))?;
+
+        let data = bincode::serialize(&command)
+            .map_err(|e| PyralogError::SerializationError(e.to_string()))?;
+        let index = group
+            .propose(Bytes::from(data))
+            .await
+            .map_err(|e| self.redirect(e))?;
+        group.wait_applied(index.as_u64()).await;
+
+        Ok(())
     }
 
     /// List all logs in the cluster
@@ -66,3 +703,83 @@ impl ClusterManager {
     }
 }
 
+#[async_trait::async_trait]
+impl EpochLog for ClusterManager {
+    /// Record a new epoch for this node's leadership of the partition
+    ///
+    /// Only the partition's leader may start an epoch, and only for itself.
+    /// The epoch is the leader's term, or one past the current epoch if
+    /// that is already higher, e.g. after the offsets of an epoch ran out.
+    async fn start_epoch(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        sequencer_node: u64,
+        start_offset: u64,
+    ) -> Result<Epoch> {
+        if sequencer_node != self.node_id {
+            return Err(PyralogError::InvalidRequest(format!(
+                "Node {} cannot start an epoch for node {}",
+                self.node_id, sequencer_node
+            )));
+        }
+
+        let group = self.partition_raft(log_id, partition);
+        let term = group
+            .as_ref()
+            .and_then(|group| group.leader_term())
+            .ok_or_else(|| PyralogError::not_leader(self.partition_leader(log_id, partition)))?;
+        let key = (log_id.clone(), partition);
+        let current = self
+            .epochs
+            .read()
+            .get(&key)
+            .and_then(|store| store.current_epoch());
+        let epoch = current.map_or(Epoch::new(term), |current| current.next().max(Epoch::new(term)));
+
+        let command = EpochCommand::Start { epoch, sequencer_node, start_offset };
+        self.propose_epoch(log_id, partition, command).await?;
+
+        // Leadership may have moved on, or a newer epoch committed first
+        let recorded = self
+            .epochs
+            .read()
+            .get(&key)
+            .and_then(|store| store.get_epoch(epoch).map(|metadata| metadata.sequencer_node));
+        let leading = group.and_then(|group| group.leader_term()) == Some(term);
+        if !leading || recorded != Some(self.node_id) {
+            return Err(PyralogError::not_leader(self.partition_leader(log_id, partition)));
+        }
+
+        self.active_epochs.write().insert(key, (term, epoch));
+        Ok(epoch)
+    }
+
+    async fn seal_epoch(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        epoch: Epoch,
+        last_offset: Option<u32>,
+    ) -> Result<()> {
+        self.propose_epoch(log_id, partition, EpochCommand::Seal { epoch, last_offset })
+            .await
+    }
+
+    async fn compact_epochs(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        low_watermark: u64,
+    ) -> Result<usize> {
+        let before = self.epochs(log_id, partition).map_or(0, |store| store.epochs().len());
+        self.propose_epoch(log_id, partition, EpochCommand::Compact { low_watermark })
+            .await?;
+        let after = self.epochs(log_id, partition).map_or(0, |store| store.epochs().len());
+        Ok(before.saturating_sub(after))
+    }
+
+    fn epochs(&self, log_id: &LogId, partition: PartitionId) -> Option<EpochStore> {
+        self.epochs.read().get(&(log_id.clone(), partition)).cloned()
+    }
+}
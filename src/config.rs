@@ -1,7 +1,9 @@
 use pyralog_consensus::RaftConfig;
+use pyralog_core::{LogId, PartitionId};
 use pyralog_replication::ReplicationConfig;
 use pyralog_storage::{LogStorageConfig, SegmentConfig, WriteCacheConfig};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,6 +31,11 @@ pub struct NodeConfig {
     
     /// Cluster nodes (for consensus)
     pub cluster_nodes: Vec<u64>,
+    
+    /// Seed of the node's Raft timeouts, so a test can replay a run;
+    /// unset, they are drawn from entropy
+    #[serde(default)]
+    pub seed: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -39,6 +46,15 @@ pub struct NetworkConfig {
     /// Listen address for internal cluster communication
     pub internal_address: String,
     
+    /// Internal addresses of the cluster nodes, by node ID
+    #[serde(default)]
+    pub cluster_addresses: BTreeMap<u64, String>,
+    
+    /// Client addresses of the cluster nodes, by node ID, which clients
+    /// are redirected to when a request reaches a node that does not lead
+    #[serde(default)]
+    pub client_addresses: BTreeMap<u64, String>,
+    
     /// Maximum concurrent connections
     pub max_connections: usize,
     
@@ -53,6 +69,7 @@ impl Default for PyralogConfig {
                 node_id: 1,
                 data_dir: PathBuf::from("./data"),
                 cluster_nodes: vec![1],
+                seed: None,
             },
             storage: LogStorageConfig {
                 segment_config: SegmentConfig {
@@ -65,11 +82,15 @@ impl Default for PyralogConfig {
                     max_buffer_time: tokio::time::Duration::from_millis(10),
                     enabled: true,
                 },
+                indexed_headers: Vec::new(),
+                key_index: false,
             },
             replication: ReplicationConfig::default(),
             network: NetworkConfig {
                 listen_address: "0.0.0.0:9092".to_string(),
                 internal_address: "0.0.0.0:9093".to_string(),
+                cluster_addresses: BTreeMap::new(),
+                client_addresses: BTreeMap::new(),
                 max_connections: 10000,
                 request_timeout_ms: 30000,
             },
@@ -85,6 +106,13 @@ impl PyralogConfig {
         Ok(config)
     }
 
+    /// Directory holding the storage for a log partition
+    pub fn partition_dir(&self, log_id: &LogId, partition: PartitionId) -> PathBuf {
+        self.node
+            .data_dir
+            .join(format!("{}/{}/partition-{}", log_id.namespace, log_id.name, partition.as_u32()))
+    }
+
     /// Save configuration to a file
     pub fn to_file(&self, path: &str) -> anyhow::Result<()> {
         let content = serde_json::to_string_pretty(self)?;
@@ -15,6 +15,7 @@ pub mod server;
 pub mod client;
 pub mod cluster;
 pub mod config;
+pub mod admin;
 
 pub use pyralog_core as core;
 pub use pyralog_storage as storage;
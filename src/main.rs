@@ -11,8 +11,24 @@ async fn main() -> anyhow::Result<()> {
         .with_level(true)
         .init();
 
-    // Load configuration
-    let config = PyralogConfig::default();
+    // Load configuration from `--config <path>`, or use the defaults
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let config = match args.iter().position(|arg| arg == "--config") {
+        Some(i) if i + 1 < args.len() => {
+            let path = args.remove(i + 1);
+            args.remove(i);
+            PyralogConfig::from_file(&path)?
+        }
+        Some(_) => anyhow::bail!("--config requires a path"),
+        None => PyralogConfig::default(),
+    };
+
+    // Admin commands run against the local data directory and exit
+    if args.first().map(String::as_str) == Some("export") {
+        let count = pyralog::admin::run_export(&config, &args[1..]).await?;
+        tracing::info!("Exported {} records", count);
+        return Ok(());
+    }
 
     tracing::info!("Starting Pyralog server with node_id={}", config.node.node_id);
 
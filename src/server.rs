@@ -1,52 +1,131 @@
 use crate::cluster::ClusterManager;
 use crate::config::PyralogConfig;
-use pyralog_consensus::RaftConfig;
-use pyralog_core::{LogId, LogMetadata, LogConfig, PartitionId, Record, RecordHeader, Result, PyralogError, RetentionPolicy};
+use pyralog_consensus::{RaftConfig, RaftTransport, TcpTransport};
+use pyralog_core::{Epoch, EpochLog, InclusionProof, LogId, LogMetadata, LogConfig, LogOffset, MerkleRoot, PartitionId, Record, RecordBatch, RecordHeader, Result, PyralogError, RetentionPolicy, Sequencer};
 use pyralog_protocol::{
-    api::*, Partitioner, PartitionStrategy,
+    api::*, frame, request::Request, response::{Response, CONSUME_RESPONSE_TRAILER},
+    Partitioner, PartitionStrategy,
 };
-use pyralog_replication::ReplicationManager;
-use pyralog_storage::LogStorage;
-use parking_lot::RwLock;
+use pyralog_replication::{
+    EpochRecovery, EpochReplica, RaftReplicaTransport, RemoteReplica, ReplicaBatch, ReplicaHandler,
+    ReplicaRequest, ReplicaResponse, ReplicaTransport, ReplicationManager, StorageReplica,
+};
+use pyralog_storage::log_storage::LogStorageConfig;
+use pyralog_storage::{zero_copy, LogStorage};
+use parking_lot::{Mutex, RwLock};
 use std::collections::HashMap;
 use std::sync::Arc;
-use tokio::net::TcpListener;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
 use bytes::Bytes;
 
+/// How often each log's retention policy is applied
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Serializes the writes to one partition
+type WriteLock = Arc<tokio::sync::Mutex<()>>;
+
+/// Records a replica catching up reads from its sequencer at a time
+const CATCH_UP_CHUNK: usize = 1024;
+
 /// Main Pyralog server
 pub struct PyralogServer {
     config: PyralogConfig,
     cluster: Arc<ClusterManager>,
     storage: Arc<RwLock<HashMap<(LogId, PartitionId), Arc<LogStorage>>>>,
+    /// Held by a produce from choosing its epoch until its records are
+    /// replicated, so a concurrent one cannot roll or replace the epoch in
+    /// between, and by a replica while it stores a batch
+    write_locks: RwLock<HashMap<(LogId, PartitionId), WriteLock>>,
+    /// For partitions this node sequences, the offset below which a write
+    /// quorum of replicas holds every record; consumers read no further
+    committed: RwLock<HashMap<(LogId, PartitionId), LogOffset>>,
     replication: Arc<ReplicationManager>,
+    /// Reaches the other replicas of the partitions this node sequences
+    replicas: Arc<dyn ReplicaTransport>,
+    sequencer: Sequencer,
+    tasks: Mutex<Vec<JoinHandle<()>>>,
 }
 
 impl PyralogServer {
     /// Create a new Pyralog server
     pub async fn new(config: PyralogConfig) -> Result<Self> {
+        let raft_config = Self::raft_config(&config)?;
+        let cluster = ClusterManager::new(raft_config, &config.network).await?;
+
+        // Replica traffic goes to the same internal addresses as Raft's,
+        // but may take longer than an election timeout
+        let internal = Arc::new(TcpTransport::new(
+            config.network.cluster_addresses.clone(),
+            Duration::from_millis(config.replication.timeout_ms),
+        ));
+        let replicas = Arc::new(RaftReplicaTransport::new(internal));
+        Ok(Self::with_cluster(config, cluster, replicas))
+    }
+
+    /// Create a server whose Raft traffic goes through `transport` and
+    /// replica traffic through `replicas`, e.g. an in-process network
+    ///
+    /// Peers must deliver requests to the cluster's `raft_handler` and to
+    /// the server as a `ReplicaHandler`. The server does not listen for
+    /// clients; call `run` to start it.
+    pub async fn with_transport(
+        config: PyralogConfig,
+        transport: Arc<dyn RaftTransport>,
+        replicas: Arc<dyn ReplicaTransport>,
+    ) -> Result<Self> {
+        let raft_config = Self::raft_config(&config)?;
+        let cluster =
+            ClusterManager::with_transport(raft_config, &config.network, transport).await?;
+        Ok(Self::with_cluster(config, cluster, replicas))
+    }
+
+    fn raft_config(config: &PyralogConfig) -> Result<RaftConfig> {
         let raft_config = RaftConfig {
             node_id: config.node.node_id,
             cluster_nodes: config.node.cluster_nodes.clone(),
             data_dir: config.node.data_dir.join("raft"),
             election_timeout: pyralog_consensus::election::ElectionTimeoutConfig::default(),
+            leadership: pyralog_consensus::election::LeadershipConfig::default(),
+            snapshot: pyralog_consensus::SnapshotConfig::default(),
+            pipeline: pyralog_consensus::PipelineConfig::default(),
+            seed: config.node.seed,
         };
 
         std::fs::create_dir_all(&config.node.data_dir)
             .map_err(|e| PyralogError::ConfigError(e.to_string()))?;
 
-        let cluster = Arc::new(ClusterManager::new(raft_config).await?);
-        
-        let replication = Arc::new(ReplicationManager::new(
+        Ok(raft_config)
+    }
+
+    fn with_cluster(
+        config: PyralogConfig,
+        cluster: ClusterManager,
+        replicas: Arc<dyn ReplicaTransport>,
+    ) -> Self {
+        let cluster = Arc::new(cluster);
+        let sequencer = Sequencer::new(config.node.node_id, cluster.clone());
+
+        let replication = Arc::new(ReplicationManager::with_transport(
             config.replication.clone(),
             config.node.cluster_nodes.clone(),
+            Arc::clone(&replicas),
         ));
 
-        Ok(Self {
+        Self {
             config,
             cluster,
             storage: Arc::new(RwLock::new(HashMap::new())),
+            write_locks: RwLock::new(HashMap::new()),
+            committed: RwLock::new(HashMap::new()),
             replication,
-        })
+            replicas,
+            sequencer,
+            tasks: Mutex::new(Vec::new()),
+        }
     }
 
     /// Start the server
@@ -54,7 +133,8 @@ impl PyralogServer {
         tracing::info!("Starting Pyralog server on {}", self.config.network.listen_address);
 
         // Start cluster manager
-        Arc::clone(&self.cluster).start().await?;
+        Arc::clone(&self.cluster).start(Arc::clone(&self) as Arc<dyn ReplicaHandler>).await?;
+        self.tasks.lock().push(tokio::spawn(Arc::clone(&self).run_retention()));
 
         // Start network listeners
         let listener = TcpListener::bind(&self.config.network.listen_address)
@@ -82,43 +162,584 @@ impl PyralogServer {
         }
     }
 
+    /// Start the cluster without listening for clients
+    ///
+    /// For servers created `with_transport`, which are driven through their
+    /// `ProtocolHandler` methods.
+    pub fn run(self: &Arc<Self>) {
+        Arc::clone(&self.cluster).run();
+        self.tasks.lock().push(tokio::spawn(Arc::clone(self).run_retention()));
+    }
+
+    /// Cluster membership and partition leadership as seen by this node
+    pub fn cluster(&self) -> &Arc<ClusterManager> {
+        &self.cluster
+    }
+
+    /// Stop the cluster and flush every partition's storage
+    pub async fn shutdown(&self) -> Result<()> {
+        for task in self.tasks.lock().drain(..) {
+            task.abort();
+        }
+        self.cluster.shutdown();
+
+        let storages: Vec<Arc<LogStorage>> = self.storage.read().values().cloned().collect();
+        for storage in storages {
+            storage.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Apply each log's retention policy to the partitions stored here
+    ///
+    /// Segments past the policy are deleted, and the partition's leader
+    /// drops the epochs whose records went with them.
+    pub async fn enforce_retention(&self) -> Result<()> {
+        let storages: Vec<((LogId, PartitionId), Arc<LogStorage>)> = self
+            .storage
+            .read()
+            .iter()
+            .map(|(key, storage)| (key.clone(), Arc::clone(storage)))
+            .collect();
+
+        for ((log_id, partition), storage) in storages {
+            let Some(metadata) = self.cluster.get_log(&log_id) else {
+                continue;
+            };
+            if matches!(metadata.retention_policy, RetentionPolicy::Forever) {
+                continue;
+            }
+
+            let low_watermark = storage.apply_retention(&metadata.retention_policy).await?;
+            if self.cluster.is_partition_leader(&log_id, partition) {
+                self.sequencer
+                    .compact_epochs(&log_id, partition, low_watermark.as_u64())
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn run_retention(self: Arc<Self>) {
+        loop {
+            sleep(RETENTION_INTERVAL).await;
+            if let Err(e) = self.enforce_retention().await {
+                tracing::warn!("Failed to apply retention: {}", e);
+            }
+        }
+    }
+
     /// Handle a client connection
-    async fn handle_connection(&self, socket: tokio::net::TcpStream) -> Result<()> {
-        // In production, this would implement the full protocol handler
-        // For now, this is a placeholder
+    async fn handle_connection(&self, mut socket: TcpStream) -> Result<()> {
+        while let Some(payload) = frame::read_frame(&mut socket).await? {
+            let response = match Request::from_bytes(&payload) {
+                Ok(Request::Consume(request)) => {
+                    self.serve_consume(request, &mut socket).await?;
+                    continue;
+                }
+                Ok(request) => self.dispatch(request).await,
+                Err(e) => Response::error(e),
+            };
+
+            frame::write_frame(&mut socket, &response.to_bytes()?).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Dispatch a request to the protocol handler
+    async fn dispatch(&self, request: Request) -> Response {
+        match request {
+            Request::Produce(request) => match self.produce(request).await {
+                Ok(response) => Response::Produce(response),
+                Err(e) => Response::error(e),
+            },
+            Request::Consume(request) => match self.consume(request).await {
+                Ok(response) => Response::Consume(response),
+                Err(e) => Response::error(e),
+            },
+            Request::ConsumeLsn(request) => Response::ConsumeLsn(self.consume_lsn(request).await),
+            Request::CreateLog(request) => Response::CreateLog(self.create_log(request).await),
+            Request::CreateLogWith(request, indexes) => {
+                Response::CreateLog(self.create_log_with(request, indexes).await)
+            }
+            Request::DeleteLog(log_id) => Response::DeleteLog(self.delete_log(log_id).await),
+            Request::ListLogs => {
+                Response::ListLogs(self.list_logs(ReadConsistency::Local).await)
+            }
+            Request::ListLogsWith(consistency) => {
+                Response::ListLogs(self.list_logs(consistency).await)
+            }
+            Request::MerkleRoot(request) => Response::MerkleRoot(self.merkle_root(request).await),
+            Request::InclusionProof(request) => {
+                Response::InclusionProof(self.inclusion_proof(request).await)
+            }
+            Request::QueryHeaders(request) => {
+                Response::QueryHeaders(self.query_headers(request).await)
+            }
+            Request::GetLatest(request) => Response::GetLatest(self.get_latest(request).await),
+            Request::ChangeMembership(change) => {
+                Response::ChangeMembership(self.change_membership(change).await)
+            }
+            Request::TransferLeadership(target) => {
+                Response::TransferLeadership(self.transfer_leadership(target).await)
+            }
+        }
+    }
+
+    /// Answer a consume request, sending records straight from segment files
+    ///
+    /// Segments store records in their wire encoding, so a run of consecutive
+    /// records is sent with `sendfile` between a prebuilt response header and
+    /// trailer. Falls back to a regular encoded response otherwise. Only
+    /// the partition's leader answers, and it never creates storage for a
+    /// partition nothing has been written to.
+    async fn serve_consume(&self, request: ConsumeRequest, socket: &mut TcpStream) -> Result<()> {
+        let storage = match self.check_partition_leader(&request.log_id, request.partition) {
+            Ok(()) => self.get_storage(&request.log_id, request.partition).await,
+            Err(e) => Err(e),
+        };
+        let storage = match storage {
+            Ok(storage) => storage,
+            Err(e) => return frame::write_frame(socket, &Response::error(e).to_bytes()?).await,
+        };
+
+        // Only records a write quorum holds are served
+        let range = match &storage {
+            Some(storage) => match self.readable(&request.log_id, request.partition, storage).await {
+                Ok(high_watermark) if request.offset < high_watermark => {
+                    let readable = (high_watermark.as_u64() - request.offset.as_u64()) as usize;
+                    storage
+                        .segment_range(request.offset, request.max_records.min(readable), request.max_bytes)
+                        .map(|range| range.map(|range| (high_watermark, range)))
+                }
+                Ok(_) => Ok(None),
+                Err(e) => Err(e),
+            },
+            None => Ok(None),
+        };
+        let range = match range {
+            Ok(range) => range,
+            Err(e) => return frame::write_frame(socket, &Response::error(e).to_bytes()?).await,
+        };
+        let (high_watermark, range) = match range {
+            Some(range) => range,
+            _ => {
+                let response = match self.consume(request).await {
+                    Ok(response) => Response::Consume(response),
+                    Err(e) => Response::error(e),
+                };
+                return frame::write_frame(socket, &response.to_bytes()?).await;
+            }
+        };
+
+        let header = Response::consume_header(request.partition, high_watermark, range.record_count)?;
+
+        frame::write_frame_len(
+            socket,
+            header.len() + range.length as usize + CONSUME_RESPONSE_TRAILER.len(),
+        )
+        .await?;
+        socket
+            .write_all(&header)
+            .await
+            .map_err(|e| PyralogError::NetworkError(e.to_string()))?;
+        zero_copy::send_segment_range(&range, socket).await?;
+        socket
+            .write_all(CONSUME_RESPONSE_TRAILER)
+            .await
+            .map_err(|e| PyralogError::NetworkError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    /// Epoch of this node's leadership of a partition
+    ///
+    /// The first write after an election records the new epoch through the
+    /// partition's Raft group, and so does a write of `count` records that
+    /// would run past the epoch's 32-bit offsets. Epochs of earlier
+    /// sequencers are recovered and sealed over the partition's replicas
+    /// before the first write, and the local copy is repaired from them, so
+    /// it holds every record they acknowledged. Earlier epochs are sealed in
+    /// the local storage, so a deposed sequencer on this node cannot append.
+    async fn activate_epoch(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        storage: &Arc<LogStorage>,
+        count: u64,
+    ) -> Result<Epoch> {
+        let key = (log_id.clone(), partition);
+        let activated = self.committed.read().contains_key(&key);
+        let epoch = match self.cluster.partition_epoch(log_id, partition) {
+            Some(epoch) if activated && self.sequencer.can_write(log_id, partition, epoch) => epoch,
+            _ => {
+                // Until recovery completes, nothing is known to be committed
+                // and the next request activates again
+                self.committed.write().remove(&key);
+                let node_id = self.config.node.node_id;
+                let local: Arc<dyn EpochReplica> = Arc::new(StorageReplica::new(
+                    node_id,
+                    partition,
+                    Arc::clone(storage),
+                ));
+                let timeout = Duration::from_millis(self.config.replication.timeout_ms);
+                let mut replicas = vec![Arc::clone(&local)];
+                for follower in self.followers(log_id, partition) {
+                    replicas.push(Arc::new(RemoteReplica::new(
+                        follower,
+                        log_id.clone(),
+                        Arc::clone(&self.replicas),
+                        timeout,
+                    )));
+                }
+
+                let recovery = EpochRecovery::new(self.replication.quorum(replicas.len()));
+                let next_offset = storage.high_watermark().as_u64();
+                let epoch = recovery
+                    .activate(&self.sequencer, log_id, partition, next_offset, &local, &replicas)
+                    .await?;
+
+                // Recovery left every record it kept on enough replicas
+                self.committed.write().insert(key, storage.high_watermark());
+                epoch
+            }
+        };
+        let next_offset = storage.high_watermark().as_u64();
+        let epoch = self
+            .sequencer
+            .reserve(log_id, partition, epoch, next_offset, count)
+            .await?;
+
+        if !self.sequencer.can_write(log_id, partition, epoch) {
+            return Err(PyralogError::not_leader(self.cluster.partition_leader(log_id, partition)));
+        }
+        storage.seal_epoch(Epoch::new(epoch.as_u64() - 1))?;
+
+        Ok(epoch)
+    }
+
+    /// Nodes other than this one that hold copies of a partition
+    fn followers(&self, log_id: &LogId, partition: PartitionId) -> Vec<u64> {
+        let node_id = self.config.node.node_id;
+        self.cluster
+            .get_partition_nodes(log_id, partition)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|&node| node != node_id)
+            .collect()
+    }
+
+    /// Send records this node appended to a partition to its followers
+    ///
+    /// Returns once enough of them stored the batch for `acks`: none for
+    /// `AckMode::None`, enough to complete a write quorum with the local
+    /// copy for `AckMode::Leader`, and all of them for `AckMode::All`.
+    /// Returns whether a write quorum is known to hold the batch.
+    async fn replicate(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        prev: Option<Record>,
+        batch: RecordBatch,
+        acks: AckMode,
+    ) -> Result<bool> {
+        let followers = self.followers(log_id, partition);
+        let quorum = self
+            .replication
+            .quorum(followers.len() + 1)
+            .write_quorum
+            .saturating_sub(1);
+        let write_quorum = match acks {
+            AckMode::None => 0,
+            AckMode::Leader => quorum,
+            AckMode::All => followers.len(),
+        };
+        let batch = ReplicaBatch {
+            log_id: log_id.clone(),
+            partition,
+            sequencer: self.config.node.node_id,
+            prev,
+            batch,
+        };
+        self.replication
+            .replicate_to_nodes(batch, &followers, write_quorum)
+            .await?;
+        Ok(write_quorum >= quorum)
+    }
+
+    /// Sequence produced records into a partition this node leads and
+    /// replicate them; returns the offset of the first
+    async fn append_produced(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+        produced: Vec<ProduceRecord>,
+        acks: AckMode,
+    ) -> Result<LogOffset> {
+        // Get storage
+        let storage = self.get_or_create_storage(log_id, partition).await?;
+        let count = produced.len() as u64;
+        let write_lock = self.write_lock(log_id, partition);
+        let writing = write_lock.lock().await;
+        let epoch = self.activate_epoch(log_id, partition, &storage, count).await?;
+
+        // Followers check that the batch continues their copy from the
+        // record before it
+        storage.flush().await?;
+        let prev = match storage.high_watermark().as_u64().checked_sub(1) {
+            Some(last) => storage.read(LogOffset::new(last)).await?,
+            None => None,
+        };
+
+        // Convert records
+        let mut records = Vec::with_capacity(produced.len());
+        for produce_record in produced {
+            let headers: Vec<RecordHeader> = produce_record
+                .headers
+                .into_iter()
+                .map(|(k, v)| RecordHeader::new(k, v))
+                .collect();
+
+            let mut record = Record::new(produce_record.key, produce_record.value)
+                .with_epoch(epoch)
+                .with_headers(headers);
+
+            record.offset = storage.append(record.clone()).await?;
+            records.push(record);
+        }
+
+        let base_offset = records
+            .first()
+            .map(|record| record.offset)
+            .ok_or_else(|| PyralogError::InvalidRequest("No records written".to_string()))?;
+
+        // Followers catching up read the batch from segments, and recovery
+        // only sees flushed records
+        storage.flush().await?;
+        let end = storage.high_watermark();
+        let batch = RecordBatch::new(base_offset, records).with_epoch(epoch);
+        if self.replicate(log_id, partition, prev, batch, acks).await? {
+            // A batch only continues a replica's copy, so the records before
+            // it are held by the same replicas
+            self.committed.write().insert((log_id.clone(), partition), end);
+        }
+        drop(writing);
+
+        Ok(base_offset)
+    }
+
+    /// Offset consumers of a partition this node leads may read up to
+    ///
+    /// Records past it may not survive a change of sequencer. A leader
+    /// that has not activated its epoch yet does so first, so that it
+    /// serves every record earlier sequencers acknowledged.
+    async fn readable(&self, log_id: &LogId, partition: PartitionId, storage: &Arc<LogStorage>) -> Result<LogOffset> {
+        let key = (log_id.clone(), partition);
+        let activated = self.committed.read().contains_key(&key);
+        if !activated || self.cluster.partition_epoch(log_id, partition).is_none() {
+            let write_lock = self.write_lock(log_id, partition);
+            let _writing = write_lock.lock().await;
+            self.activate_epoch(log_id, partition, storage, 0).await?;
+        }
+        let committed = self.committed.read().get(&key).copied();
+        Ok(committed.unwrap_or(LogOffset::ZERO).min(storage.high_watermark()))
+    }
+
+    /// Store a batch from a partition's sequencer
+    ///
+    /// The batch only continues this copy if the copy holds the record the
+    /// sequencer holds before it. Records are never stored at the same
+    /// offset in the same epoch by two sequencers, so the copies then agree
+    /// up to the batch. Otherwise the replica first catches up.
+    async fn append_replica(&self, replica: ReplicaBatch) -> Result<LogOffset> {
+        let storage = self.get_or_create_storage(&replica.log_id, replica.partition).await?;
+        let write_lock = self.write_lock(&replica.log_id, replica.partition);
+        let _writing = write_lock.lock().await;
+
+        let epoch = replica.batch.epoch;
+        storage.seal_epoch(Epoch::new(epoch.as_u64().saturating_sub(1)))?;
+        storage.flush().await?;
+
+        let base_offset = replica.batch.base_offset;
+        let follows = match &replica.prev {
+            Some(prev) => storage.holds(prev).await?,
+            None => true,
+        };
+        if !follows || storage.high_watermark() < base_offset {
+            self.catch_up(&storage, &replica).await?;
+        }
+
+        storage.append_replicated(epoch, replica.batch.records).await?;
+        storage.flush().await?;
+        Ok(storage.high_watermark())
+    }
+
+    /// Copy the records before a batch from its sequencer
+    ///
+    /// This copy is searched backwards, an epoch at a time, for a record
+    /// the sequencer holds at the same offset. Everything before it agrees;
+    /// the sequencer's records after it replace this copy's.
+    async fn catch_up(&self, storage: &Arc<LogStorage>, replica: &ReplicaBatch) -> Result<()> {
+        let base_offset = replica.batch.base_offset;
+        let low_watermark = storage.low_watermark();
+        let starts = storage.epoch_starts();
+
+        let mut from = storage.high_watermark().min(base_offset);
+        while from > low_watermark {
+            let last = LogOffset::new(from.as_u64() - 1);
+            let theirs = self.read_sequencer(replica, last, 1).await?;
+            let held = match theirs.first() {
+                Some(record) if record.offset == last => storage.holds(record).await?,
+                _ => false,
+            };
+            if held {
+                break;
+            }
+            from = starts
+                .iter()
+                .rev()
+                .map(|(_, start)| *start)
+                .find(|start| *start < from)
+                .unwrap_or(low_watermark)
+                .max(low_watermark);
+        }
+
+        let epoch = replica.batch.epoch;
+        while from < base_offset {
+            let max_records = CATCH_UP_CHUNK.min((base_offset.as_u64() - from.as_u64()) as usize);
+            let records = self.read_sequencer(replica, from, max_records).await?;
+            if records.first().map(|record| record.offset) != Some(from) {
+                return Err(PyralogError::ReplicationError(format!(
+                    "Sequencer {} does not hold offset {}",
+                    replica.sequencer, from
+                )));
+            }
+            from = storage.append_replicated(epoch, records).await?;
+        }
+        Ok(())
+    }
+
+    /// Records a batch's sequencer holds from `offset` on
+    async fn read_sequencer(
+        &self,
+        replica: &ReplicaBatch,
+        offset: LogOffset,
+        max_records: usize,
+    ) -> Result<Vec<Record>> {
+        let request = ReplicaRequest::Read {
+            log_id: replica.log_id.clone(),
+            partition: replica.partition,
+            offset,
+            max_records,
+        };
+        let timeout = Duration::from_millis(self.config.replication.timeout_ms);
+        let response = tokio::time::timeout(timeout, self.replicas.send(replica.sequencer, request))
+            .await
+            .map_err(|_| PyralogError::Timeout)??;
+        match response {
+            ReplicaResponse::Records(records) => Ok(records),
+            response => Err(PyralogError::ReplicationError(format!(
+                "Unexpected replica response {:?}",
+                response
+            ))),
+        }
+    }
+
+    /// This node's copy of a partition, as seen by epoch recovery
+    async fn storage_replica(&self, log_id: &LogId, partition: PartitionId) -> Result<StorageReplica> {
+        let storage = self.get_or_create_storage(log_id, partition).await?;
+        let epochs: Arc<dyn EpochLog> = self.cluster.clone();
+        Ok(StorageReplica::new(self.config.node.node_id, partition, storage)
+            .with_epochs(log_id.clone(), epochs))
+    }
+
+    /// Fail with `NotLeader` unless this node leads the partition
+    ///
+    /// Only the partition's sequencer serves its records, as followers
+    /// may not hold every record it acknowledged yet.
+    fn check_partition_leader(&self, log_id: &LogId, partition: PartitionId) -> Result<()> {
+        if !self.cluster.is_partition_leader(log_id, partition) {
+            return Err(PyralogError::not_leader(self.cluster.partition_leader(log_id, partition)));
+        }
         Ok(())
     }
 
+    /// Lock serializing the writes to a partition
+    fn write_lock(&self, log_id: &LogId, partition: PartitionId) -> WriteLock {
+        self.write_locks
+            .write()
+            .entry((log_id.clone(), partition))
+            .or_default()
+            .clone()
+    }
+
     /// Get or create storage for a log partition
     async fn get_or_create_storage(
         &self,
         log_id: &LogId,
         partition: PartitionId,
     ) -> Result<Arc<LogStorage>> {
-        let key = (log_id.clone(), partition);
+        if let Some(storage) = self.get_storage(log_id, partition).await? {
+            return Ok(storage);
+        }
 
-        // Check if storage already exists
-        {
-            let storage = self.storage.read();
-            if let Some(s) = storage.get(&key) {
-                return Ok(Arc::clone(s));
-            }
+        // Create the partition's storage on first use
+        let path = self.config.partition_dir(log_id, partition);
+        let storage = LogStorage::create(path, self.storage_config(log_id)).await?;
+        Ok(self.cache_storage(log_id, partition, storage))
+    }
+
+    /// Get storage for a log partition, opening it if it is on disk
+    ///
+    /// Returns `None` for a partition this node has never stored.
+    async fn get_storage(
+        &self,
+        log_id: &LogId,
+        partition: PartitionId,
+    ) -> Result<Option<Arc<LogStorage>>> {
+        if let Some(storage) = self.storage.read().get(&(log_id.clone(), partition)) {
+            return Ok(Some(Arc::clone(storage)));
         }
 
-        // Create new storage
-        let path = self
-            .config
-            .node
-            .data_dir
-            .join(format!("{}/{}/partition-{}", log_id.namespace, log_id.name, partition.as_u32()));
+        let path = self.config.partition_dir(log_id, partition);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let storage = LogStorage::open(path, self.storage_config(log_id)).await?;
+        Ok(Some(self.cache_storage(log_id, partition, storage)))
+    }
 
-        let storage = Arc::new(
-            LogStorage::create(path, self.config.storage.clone()).await?
-        );
+    /// Storage of a partition this node leads, for a read that creates
+    /// nothing on disk
+    ///
+    /// Fails with `LogNotFound` for an unknown log and `NotLeader` on any
+    /// other node. Returns `None` if nothing was written to the partition.
+    async fn leader_storage(&self, log_id: &LogId, partition: PartitionId) -> Result<Option<Arc<LogStorage>>> {
+        if self.cluster.get_log(log_id).is_none() {
+            return Err(PyralogError::LogNotFound(log_id.to_string()));
+        }
+        self.check_partition_leader(log_id, partition)?;
+        self.get_storage(log_id, partition).await
+    }
 
-        self.storage.write().insert(key, Arc::clone(&storage));
+    /// Keep opened storage, or the storage a concurrent request opened first
+    fn cache_storage(&self, log_id: &LogId, partition: PartitionId, storage: LogStorage) -> Arc<LogStorage> {
+        self.storage
+            .write()
+            .entry((log_id.clone(), partition))
+            .or_insert_with(|| Arc::new(storage))
+            .clone()
+    }
 
-        Ok(storage)
+    /// Storage configuration for a log's partitions
+    fn storage_config(&self, log_id: &LogId) -> LogStorageConfig {
+        let mut storage_config = self.config.storage.clone();
+        if let Some(metadata) = self.cluster.get_log(log_id) {
+            storage_config.indexed_headers = metadata.config.indexed_headers;
+            storage_config.key_index |= metadata.config.key_index_enabled;
+        }
+        storage_config
     }
 }
 
@@ -150,40 +771,35 @@ impl ProtocolHandler for PyralogServer {
             )
         };
 
-        // Check if we're the leader for this partition
-        if !self.cluster.is_partition_leader(partition) {
-            return Err(PyralogError::NotLeader(None));
+        // Keyed records go to their key's partition, where `get_latest`
+        // looks for them; records without a key follow the first record
+        let mut groups: Vec<(PartitionId, Vec<ProduceRecord>)> = Vec::new();
+        for record in request.records {
+            let target = match (&record.key, request.partition) {
+                (Some(key), None) => partitioner.partition(Some(key), &record.value),
+                _ => partition,
+            };
+            match groups.iter_mut().find(|(group, _)| *group == target) {
+                Some((_, records)) => records.push(record),
+                None => groups.push((target, vec![record])),
+            }
         }
 
-        // Get storage
-        let storage = self.get_or_create_storage(&request.log_id, partition).await?;
+        // Nothing is written unless every partition is led here
+        for (partition, _) in &groups {
+            self.check_partition_leader(&request.log_id, *partition)?;
+        }
 
-        // Convert records
         let mut base_offset = None;
-        for produce_record in request.records {
-            let headers: Vec<RecordHeader> = produce_record
-                .headers
-                .into_iter()
-                .map(|(k, v)| RecordHeader::new(k, v))
-                .collect();
-
-            let record = Record::new(produce_record.key, produce_record.value)
-                .with_headers(headers);
-
-            let offset = storage.append(record).await?;
-            if base_offset.is_none() {
-                base_offset = Some(offset);
-            }
+        for (partition, records) in groups {
+            let offset = self
+                .append_produced(&request.log_id, partition, records, request.acks)
+                .await?;
+            base_offset.get_or_insert(offset);
         }
-
         let base_offset = base_offset
             .ok_or_else(|| PyralogError::InvalidRequest("No records written".to_string()))?;
 
-        // Flush if required
-        if matches!(request.acks, AckMode::Leader | AckMode::All) {
-            storage.flush().await?;
-        }
-
         Ok(ProduceResponse {
             partition,
             base_offset,
@@ -192,18 +808,20 @@ impl ProtocolHandler for PyralogServer {
     }
 
     async fn consume(&self, request: ConsumeRequest) -> Result<ConsumeResponse> {
+        self.check_partition_leader(&request.log_id, request.partition)?;
+
         // Get storage
         let storage = self
             .get_or_create_storage(&request.log_id, request.partition)
             .await?;
 
         // Read records
+        let high_watermark = self.readable(&request.log_id, request.partition, &storage).await?;
+        let readable = high_watermark.as_u64().saturating_sub(request.offset.as_u64()) as usize;
         let records = storage
-            .read_from(request.offset, request.max_records)
+            .read_from(request.offset, request.max_records.min(readable))
             .await?;
 
-        let high_watermark = storage.high_watermark();
-
         Ok(ConsumeResponse {
             partition: request.partition,
             high_watermark,
@@ -212,13 +830,67 @@ impl ProtocolHandler for PyralogServer {
         })
     }
 
+    async fn consume_lsn(&self, request: LsnConsumeRequest) -> Result<LsnConsumeResponse> {
+        self.check_partition_leader(&request.log_id, request.partition)?;
+        let storage = self
+            .get_or_create_storage(&request.log_id, request.partition)
+            .await?;
+        let high_watermark = self.readable(&request.log_id, request.partition, &storage).await?;
+
+        // Nothing has been written at the LSN yet
+        let offset = match storage.offset_for_lsn(request.lsn) {
+            Some(offset) if offset < high_watermark => offset,
+            _ => {
+                return Ok(LsnConsumeResponse {
+                    partition: request.partition,
+                    high_watermark,
+                    events: Vec::new(),
+                })
+            }
+        };
+
+        let readable = (high_watermark.as_u64() - offset.as_u64()) as usize;
+        let records = storage.read_from(offset, request.max_records.min(readable)).await?;
+
+        let mut events = Vec::with_capacity(records.len());
+        let mut epoch = request.lsn.epoch;
+        for record in records {
+            let lsn = match storage.lsn(record.offset) {
+                Some(lsn) => lsn,
+                None => break,
+            };
+            if lsn.epoch != epoch {
+                events.push(ReadEvent::EpochBoundary { from: epoch, to: lsn.epoch });
+                epoch = lsn.epoch;
+            }
+            match record.gap_kind() {
+                Some(kind) => events.push(ReadEvent::Gap { lsn, kind }),
+                None => events.push(ReadEvent::Record { lsn, record }),
+            }
+        }
+
+        Ok(LsnConsumeResponse {
+            partition: request.partition,
+            high_watermark,
+            events,
+        })
+    }
+
     async fn create_log(&self, request: CreateLogRequest) -> Result<()> {
+        self.create_log_with(request, LogIndexOptions::default()).await
+    }
+
+    async fn create_log_with(&self, request: CreateLogRequest, indexes: LogIndexOptions) -> Result<()> {
         let metadata = LogMetadata {
             id: request.log_id,
             partition_count: request.partition_count,
             replication_factor: request.replication_factor,
             retention_policy: RetentionPolicy::Forever,
-            config: LogConfig::default(),
+            config: LogConfig {
+                indexed_headers: indexes.indexed_headers,
+                key_index_enabled: indexes.key_index,
+                ..LogConfig::default()
+            },
         };
 
         self.cluster.create_log(metadata).await
@@ -230,8 +902,383 @@ impl ProtocolHandler for PyralogServer {
         Ok(())
     }
 
-    async fn list_logs(&self) -> Result<Vec<LogId>> {
+    async fn list_logs(&self, consistency: ReadConsistency) -> Result<Vec<LogId>> {
+        if consistency == ReadConsistency::Linearizable {
+            self.cluster.read_barrier().await?;
+        }
         Ok(self.cluster.list_logs())
     }
+
+    async fn merkle_root(&self, request: MerkleRootRequest) -> Result<Option<MerkleRoot>> {
+        match self.leader_storage(&request.log_id, request.partition).await? {
+            Some(storage) => storage.merkle_root(request.offset),
+            None => Ok(None),
+        }
+    }
+
+    async fn inclusion_proof(&self, request: InclusionProofRequest) -> Result<Option<InclusionProof>> {
+        match self.leader_storage(&request.log_id, request.partition).await? {
+            Some(storage) => storage.inclusion_proof(request.offset, request.root_offset),
+            None => Ok(None),
+        }
+    }
+
+    async fn query_headers(&self, request: HeaderQueryRequest) -> Result<HeaderQueryResponse> {
+        let Some(storage) = self.leader_storage(&request.log_id, request.partition).await? else {
+            return Ok(HeaderQueryResponse {
+                partition: request.partition,
+                offsets: Vec::new(),
+                records: Vec::new(),
+            });
+        };
+
+        let (offsets, records) = if request.include_records {
+            let records = storage
+                .read_by_header(&request.header_key, &request.header_value, request.range)
+                .await?;
+            (records.iter().map(|r| r.offset).collect(), records)
+        } else {
+            let offsets = storage
+                .find_by_header(&request.header_key, &request.header_value, request.range)
+                .await?;
+            (offsets, Vec::new())
+        };
+
+        Ok(HeaderQueryResponse {
+            partition: request.partition,
+            offsets,
+            records,
+        })
+    }
+
+    async fn get_latest(&self, request: GetLatestRequest) -> Result<GetLatestResponse> {
+        let metadata = self
+            .cluster
+            .get_log(&request.log_id)
+            .ok_or_else(|| PyralogError::LogNotFound(request.log_id.to_string()))?;
+
+        // `produce` places every keyed record by its key's hash
+        let partition = match request.partition {
+            Some(partition) => partition,
+            None => Partitioner::new(PartitionStrategy::KeyHash, metadata.partition_count)
+                .partition(Some(&request.key), &Bytes::new()),
+        };
+        self.check_partition_leader(&request.log_id, partition)?;
+
+        let record = match self.get_storage(&request.log_id, partition).await? {
+            Some(storage) => storage.get_latest(&request.key).await?,
+            None => None,
+        };
+
+        Ok(GetLatestResponse { partition, record })
+    }
+
+    async fn change_membership(&self, change: MembershipChange) -> Result<()> {
+        match change {
+            MembershipChange::AddVoter { node_id, address } => {
+                self.cluster.add_voter(node_id, address).await
+            }
+            MembershipChange::RemoveVoter { node_id } => self.cluster.remove_voter(node_id).await,
+            MembershipChange::AddLearner { node_id, address } => {
+                self.cluster.add_learner(node_id, address).await
+            }
+            MembershipChange::PromoteLearner { node_id } => {
+                self.cluster.promote_learner(node_id).await
+            }
+            MembershipChange::RemoveLearner { node_id } => {
+                self.cluster.remove_learner(node_id).await
+            }
+        }
+    }
+
+    async fn transfer_leadership(&self, target: u64) -> Result<()> {
+        self.cluster.transfer_leadership(target).await
+    }
 }
 
+
+#[async_trait::async_trait]
+impl ReplicaHandler for PyralogServer {
+    async fn handle(&self, request: ReplicaRequest) -> Result<ReplicaResponse> {
+        match request {
+            ReplicaRequest::Append(batch) => self.append_replica(batch).await.map(ReplicaResponse::Appended),
+            // Served without the write lock: the replica asking holds its
+            // own while it catches up
+            ReplicaRequest::Read { log_id, partition, offset, max_records } => {
+                let records = match self.get_storage(&log_id, partition).await? {
+                    Some(storage) => storage.read_from(offset, max_records).await?,
+                    None => Vec::new(),
+                };
+                Ok(ReplicaResponse::Records(records))
+            }
+            ReplicaRequest::Seal { log_id, partition, epoch } => {
+                let replica = self.storage_replica(&log_id, partition).await?;
+                let write_lock = self.write_lock(&log_id, partition);
+                let _writing = write_lock.lock().await;
+                replica.seal(partition, epoch).await.map(ReplicaResponse::Sealed)
+            }
+            ReplicaRequest::ReadEpoch { log_id, partition, epoch, offsets } => {
+                let replica = self.storage_replica(&log_id, partition).await?;
+                replica
+                    .read_epoch(partition, epoch, offsets)
+                    .await
+                    .map(ReplicaResponse::Epoch)
+            }
+            ReplicaRequest::StoreRecovered { log_id, partition, epoch, records } => {
+                let replica = self.storage_replica(&log_id, partition).await?;
+                let write_lock = self.write_lock(&log_id, partition);
+                let _writing = write_lock.lock().await;
+                replica.store_recovered(partition, epoch, records).await?;
+                Ok(ReplicaResponse::Stored)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyralog_consensus::ChannelNetwork;
+    use pyralog_core::{EpochOffset, LogOffset};
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn config(data_dir: &std::path::Path) -> PyralogConfig {
+        let mut config = PyralogConfig::default();
+        config.node.data_dir = data_dir.to_path_buf();
+        config
+    }
+
+    async fn start_server(config: PyralogConfig) -> Arc<PyralogServer> {
+        let network = ChannelNetwork::new();
+        let replicas = Arc::new(RaftReplicaTransport::new(Arc::new(network.transport(1))));
+        let server = PyralogServer::with_transport(config, Arc::new(network.transport(1)), replicas)
+            .await
+            .unwrap();
+        network.register(1, server.cluster().raft_handler());
+        let server = Arc::new(server);
+        server.run();
+        server
+    }
+
+    /// Retry `attempt` while the node is still electing leaders
+    async fn retry<T, F, Fut>(mut attempt: F) -> T
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        for _ in 0..100 {
+            match attempt().await {
+                Ok(value) => return value,
+                Err(PyralogError::NotLeader(_) | PyralogError::NotLeaderHinted(_) | PyralogError::LeaderNotAvailable) => {
+                    tokio::time::sleep(Duration::from_millis(50)).await
+                }
+                Err(e) => panic!("request failed: {}", e),
+            }
+        }
+        panic!("no leader elected");
+    }
+
+    fn produce(log_id: &LogId, value: &'static str) -> ProduceRequest {
+        ProduceRequest {
+            log_id: log_id.clone(),
+            partition: Some(PartitionId::new(0)),
+            records: vec![ProduceRecord {
+                key: None,
+                value: Bytes::from_static(value.as_bytes()),
+                headers: Vec::new(),
+            }],
+            acks: AckMode::Leader,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lsn_reads_survive_restart() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_id = LogId::new("default", "events");
+        let partition = PartitionId::new(0);
+
+        let server = start_server(config(temp_dir.path())).await;
+        retry(|| {
+            server.create_log(CreateLogRequest {
+                log_id: log_id.clone(),
+                partition_count: 1,
+                replication_factor: 1,
+            })
+        })
+        .await;
+        for value in ["a", "b"] {
+            retry(|| server.produce(produce(&log_id, value))).await;
+        }
+        let storage = server.get_or_create_storage(&log_id, partition).await.unwrap();
+        let first = storage.lsn(LogOffset::ZERO).unwrap();
+        drop(storage);
+        server.shutdown().await.unwrap();
+        drop(server);
+
+        // The restarted node reads the old epoch and writes in a new one
+        let server = start_server(config(temp_dir.path())).await;
+        retry(|| server.cluster().read_barrier()).await;
+        retry(|| server.produce(produce(&log_id, "c"))).await;
+
+        let response = server
+            .consume_lsn(LsnConsumeRequest {
+                log_id: log_id.clone(),
+                partition,
+                lsn: first,
+                max_records: 10,
+            })
+            .await
+            .unwrap();
+
+        let values: Vec<(EpochOffset, Bytes)> = response
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                ReadEvent::Record { lsn, record } => Some((*lsn, record.value.clone())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(values.len(), 3);
+        assert_eq!(values[0], (first, Bytes::from("a")));
+        assert_eq!(values[1], (EpochOffset::new(first.epoch, 1), Bytes::from("b")));
+        assert!(values[2].0.epoch > first.epoch);
+        assert_eq!(values[2].1, Bytes::from("c"));
+        assert!(response
+            .events
+            .iter()
+            .any(|event| matches!(event, ReadEvent::EpochBoundary { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_mixed_key_batch_is_placed_by_key() {
+        let temp_dir = TempDir::new().unwrap();
+        let log_id = LogId::new("default", "profiles");
+        let server = start_server(config(temp_dir.path())).await;
+        retry(|| {
+            server.create_log_with(
+                CreateLogRequest {
+                    log_id: log_id.clone(),
+                    partition_count: 4,
+                    replication_factor: 1,
+                },
+                LogIndexOptions {
+                    indexed_headers: Vec::new(),
+                    key_index: true,
+                },
+            )
+        })
+        .await;
+
+        let partitioner = Partitioner::new(PartitionStrategy::KeyHash, 4);
+        let first = Bytes::from("key-0");
+        let second = (1..)
+            .map(|i| Bytes::from(format!("key-{}", i)))
+            .find(|key| partitioner.partition(Some(key), key) != partitioner.partition(Some(&first), &first))
+            .unwrap();
+        let request = ProduceRequest {
+            log_id: log_id.clone(),
+            partition: None,
+            records: [(&first, "a"), (&second, "b")]
+                .map(|(key, value)| ProduceRecord {
+                    key: Some(key.clone()),
+                    value: Bytes::from(value),
+                    headers: Vec::new(),
+                })
+                .to_vec(),
+            acks: AckMode::Leader,
+        };
+        retry(|| server.produce(request.clone())).await;
+
+        for (key, value) in [(first, "a"), (second, "b")] {
+            let request = GetLatestRequest {
+                log_id: log_id.clone(),
+                partition: None,
+                key,
+            };
+            let response = retry(|| server.get_latest(request.clone())).await;
+            assert_eq!(response.record.unwrap().value, Bytes::from(value));
+        }
+
+        let unknown = LogId::new("default", "unknown");
+        let request = GetLatestRequest {
+            log_id: unknown.clone(),
+            partition: None,
+            key: Bytes::from("key-0"),
+        };
+        assert!(matches!(server.get_latest(request).await, Err(PyralogError::LogNotFound(_))));
+        assert!(!server.config.partition_dir(&unknown, PartitionId::new(0)).exists());
+    }
+
+    #[tokio::test]
+    async fn test_merkle_requests_need_a_known_log() {
+        let temp_dir = TempDir::new().unwrap();
+        let server = start_server(config(temp_dir.path())).await;
+        let unknown = LogId::new("default", "unknown");
+        let partition = PartitionId::new(0);
+
+        let root = server
+            .merkle_root(MerkleRootRequest {
+                log_id: unknown.clone(),
+                partition,
+                offset: LogOffset::ZERO,
+            })
+            .await;
+        assert!(matches!(root, Err(PyralogError::LogNotFound(_))));
+        let proof = server
+            .inclusion_proof(InclusionProofRequest {
+                log_id: unknown.clone(),
+                partition,
+                offset: LogOffset::ZERO,
+                root_offset: LogOffset::ZERO,
+            })
+            .await;
+        assert!(matches!(proof, Err(PyralogError::LogNotFound(_))));
+        assert!(!server.config.partition_dir(&unknown, partition).exists());
+    }
+
+    #[tokio::test]
+    async fn test_retention_compacts_epochs() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut config = config(temp_dir.path());
+        config.storage.cache_config.enabled = false;
+        config.storage.segment_config.max_size = 256;
+        let log_id = LogId::new("default", "events");
+        let partition = PartitionId::new(0);
+
+        let server = start_server(config.clone()).await;
+        retry(|| {
+            server.cluster().create_log(LogMetadata {
+                id: log_id.clone(),
+                partition_count: 1,
+                replication_factor: 1,
+                retention_policy: RetentionPolicy::Size(0),
+                config: LogConfig::default(),
+            })
+        })
+        .await;
+        for value in ["a", "b"] {
+            retry(|| server.produce(produce(&log_id, value))).await;
+        }
+        server.shutdown().await.unwrap();
+        drop(server);
+
+        // The restart seals the first epoch and writes in a new one
+        let server = start_server(config).await;
+        retry(|| server.cluster().read_barrier()).await;
+        for value in ["c", "d", "e", "f"] {
+            retry(|| server.produce(produce(&log_id, value))).await;
+        }
+        let epochs = server.sequencer.get_epoch_store(&log_id, partition).unwrap();
+        let current = epochs.current_epoch();
+        assert!(epochs.get_epoch(Epoch::new(1)).is_some());
+
+        server.enforce_retention().await.unwrap();
+
+        let storage = server.get_or_create_storage(&log_id, partition).await.unwrap();
+        assert!(storage.low_watermark() >= LogOffset::new(2));
+        let epochs = server.sequencer.get_epoch_store(&log_id, partition).unwrap();
+        assert!(epochs.get_epoch(Epoch::new(1)).is_none());
+        assert_eq!(epochs.current_epoch(), current);
+    }
+}